@@ -1,8 +1,9 @@
 use core::{
-    cartridge::Cartridge,
+    cartridge::{self, Cartridge, LoadError},
     dev_read, dev_write,
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
+    state::State,
 };
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -21,6 +22,18 @@ impl Sensor for () {
     fn capture(&mut self, _buffer: &mut Buffer) {}
 }
 
+/// Like `core::cartridge::load`, but also creates Pocket Camera cartridges
+/// (type FC), capturing images from the given sensor.
+pub fn load_cartridge<S: Sensor + 'static>(
+    file: Box<[u8]>,
+    sensor: S,
+) -> Result<Box<dyn Cartridge>, LoadError> {
+    match file.get(0x147) {
+        Some(0xfc) => Ok(Box::new(PocketCamera::new(file, sensor))),
+        _ => cartridge::load(file),
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum Mode {
     Ram,
@@ -239,4 +252,61 @@ impl<S: Sensor> Device for PocketCamera<S> {
     }
 }
 
-impl<S: Sensor> Cartridge for PocketCamera<S> {}
+// The ROM, the sensor and the captured image are not part of the state.
+impl<S: Sensor> State for PocketCamera<S> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        (self.mode == Mode::Cam).save(buf);
+        self.rom_bank.save(buf);
+        self.ram.save(buf);
+        self.ram_bank.save(buf);
+        self.ram_enabled.save(buf);
+        let r = &self.registers;
+        [r.a000, r.a001, r.a002, r.a003, r.a004, r.a005].save(buf);
+        r.a006.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut cam = false;
+        cam.load(buf)?;
+        self.mode = if cam { Mode::Cam } else { Mode::Ram };
+        self.rom_bank.load(buf)?;
+        self.ram.load(buf)?;
+        self.ram_bank.load(buf)?;
+        self.ram_enabled.load(buf)?;
+        let mut regs = [0u8; 6];
+        regs.load(buf)?;
+        let r = &mut self.registers;
+        [r.a000, r.a001, r.a002, r.a003, r.a004, r.a005] = regs;
+        r.a006.load(buf)?;
+        Ok(())
+    }
+}
+
+impl<S: Sensor> Cartridge for PocketCamera<S> {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn reset(&mut self) {
+        self.mode = Mode::Ram;
+        self.rom_bank = 0;
+        self.ram_bank = 0;
+        self.ram_enabled = false;
+    }
+}
@@ -1,13 +1,331 @@
+//! Audio Processing Unit.
+//!
+//! The four channels are synthesized at the CPU clock, mixed according to
+//! NR50 & NR51, and sampled at the rate of the connected [`AudioOutput`].
 use crate::{
     device::Device,
-    error::{Component, ReadError, WriteError},
+    error::{ReadError, WriteError},
+    irq, Update, CLOCK,
 };
+use educe::Educe;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 
-// https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Registers
+pub mod wav;
+
+// frame sequencer clock (512Hz)
+const SEQUENCER_TICKS: u32 = 8192;
+
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const NOISE_DIVISOR: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// fraction of the (DC) capacitor charge kept after each CPU tick
+const CHARGE: f64 = 0.999958;
+
+/// The destination of the audio samples.
+///
+/// Implement this trait to play (or record) the audio output of the
+/// emulator.
+pub trait AudioOutput {
+    /// Samples per second.
+    fn sample_rate(&self) -> u32 {
+        44_100
+    }
+
+    /// Called with every stereo sample.
+    fn sample(&mut self, left: i16, right: i16);
+
+    /// Return `true` to also receive the output of each channel (see
+    /// `stem`).
+    fn stems(&self) -> bool {
+        false
+    }
+
+    /// Called with the stereo sample of each of the four channels, before
+    /// `sample`. The samples of the channels add up to the mixed one (give or
+    /// take the clipping).
+    #[allow(unused_variables)]
+    fn stem(&mut self, channel: usize, left: i16, right: i16) {}
+}
+
+/// An empty tuple represents the absence of an output (samples are dropped).
+impl AudioOutput for () {
+    fn sample(&mut self, _left: i16, _right: i16) {}
+}
+
+/// A shared output, to keep a handle to it once connected (to finish a
+/// recording, for example).
+impl<A: AudioOutput + ?Sized> AudioOutput for Arc<Mutex<A>> {
+    fn sample_rate(&self) -> u32 {
+        self.lock().unwrap().sample_rate()
+    }
+
+    fn sample(&mut self, left: i16, right: i16) {
+        self.lock().unwrap().sample(left, right);
+    }
+
+    fn stems(&self) -> bool {
+        self.lock().unwrap().stems()
+    }
+
+    fn stem(&mut self, channel: usize, left: i16, right: i16) {
+        self.lock().unwrap().stem(channel, left, right);
+    }
+}
+
+fn default_output() -> Box<dyn AudioOutput + Send> {
+    Box::new(())
+}
+
+// Length counter (all channels).
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Length {
+    counter: u16,
+}
+
+crate::state::impl_state!(Length { counter });
+
+impl Length {
+    // Returns true if the channel must be disabled.
+    fn clock(&mut self, nrx4: u8) -> bool {
+        if nrx4 & 0x40 != 0 && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+}
+
+// Volume envelope (channels 1, 2 and 4).
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Envelope {
+    volume: u8,
+    timer: u8,
+}
+
+crate::state::impl_state!(Envelope { volume, timer });
+
+impl Envelope {
+    fn clock(&mut self, nrx2: u8) {
+        let period = nrx2 & 0x7;
+        if period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if nrx2 & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if nrx2 & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn trigger(&mut self, nrx2: u8) {
+        self.volume = nrx2 >> 4;
+        self.timer = nrx2 & 0x7;
+    }
+}
+
+// Square wave (channels 1 and 2).
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Square {
+    enabled: bool,
+    timer: u32,
+    step: u8,
+    length: Length,
+    envelope: Envelope,
+}
+
+crate::state::impl_state!(Square {
+    enabled,
+    timer,
+    step,
+    length,
+    envelope
+});
+
+impl Square {
+    fn update(&mut self, ticks: u32, freq: u16) {
+        let period = (2048 - freq as u32) * 4;
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = period;
+            self.step = (self.step + 1) % 8;
+        }
+        self.timer -= ticks;
+    }
+
+    fn output(&self, nrx1: u8) -> u8 {
+        let duty = DUTY[nrx1 as usize >> 6];
+        if self.enabled && duty & (0x80 >> self.step) != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn trigger(&mut self, nrx2: u8, freq: u16) {
+        self.enabled = nrx2 & 0xf8 != 0;
+        self.timer = (2048 - freq as u32) * 4;
+        self.length.trigger(64);
+        self.envelope.trigger(nrx2);
+    }
+}
+
+// Frequency sweep (channel 1).
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Sweep {
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+crate::state::impl_state!(Sweep {
+    enabled,
+    shadow,
+    timer
+});
+
+impl Sweep {
+    // Returns the next frequency (above 2047 disables the channel).
+    fn next(&self, nr10: u8) -> u16 {
+        let delta = self.shadow >> (nr10 & 0x7);
+        if nr10 & 0x08 != 0 {
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn period(nr10: u8) -> u8 {
+        match (nr10 >> 4) & 0x7 {
+            0 => 8,
+            period => period,
+        }
+    }
+}
+
+// Wave output (channel 3).
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Wave {
+    enabled: bool,
+    timer: u32,
+    position: u8,
+    length: Length,
+}
+
+crate::state::impl_state!(Wave {
+    enabled,
+    timer,
+    position,
+    length
+});
+
+impl Wave {
+    fn update(&mut self, ticks: u32, freq: u16) {
+        let period = (2048 - freq as u32) * 2;
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = period;
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= ticks;
+    }
+
+    fn output(&self, nr32: u8, wave_ram: &[u8; 0x10]) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = wave_ram[self.position as usize / 2];
+        let sample = if self.position % 2 == 0 {
+            byte >> 4
+        } else {
+            byte & 0xf
+        };
+        match (nr32 >> 5) & 0x3 {
+            0 => 0,
+            shift => sample >> (shift - 1),
+        }
+    }
+}
+
+// Noise (channel 4).
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Noise {
+    enabled: bool,
+    timer: u32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope,
+}
+
+crate::state::impl_state!(Noise {
+    enabled,
+    timer,
+    lfsr,
+    length,
+    envelope
+});
+
+impl Noise {
+    fn period(nr43: u8) -> u32 {
+        NOISE_DIVISOR[nr43 as usize & 0x7] << (nr43 >> 4)
+    }
+
+    fn update(&mut self, ticks: u32, nr43: u8) {
+        let period = Self::period(nr43);
+        let mut ticks = ticks;
+        while ticks >= self.timer {
+            ticks -= self.timer;
+            self.timer = period;
+            let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            // 7-bit mode
+            if nr43 & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (xor << 6);
+            }
+        }
+        self.timer -= ticks;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn trigger(&mut self, nr42: u8, nr43: u8) {
+        self.enabled = nr42 & 0xf8 != 0;
+        self.timer = Self::period(nr43);
+        self.lfsr = 0x7fff;
+        self.length.trigger(64);
+        self.envelope.trigger(nr42);
+    }
+}
+
+// https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Registers
+#[derive(Educe)]
+#[educe(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct APU {
     // Sound Channel 1 - Tone & Sweep
     nr10: u8,
@@ -51,9 +369,164 @@ pub struct APU {
     // Bit 1 - Sound 2 ON flag (Read Only)
     // Bit 0 - Sound 1 ON flag (Read Only)
     nr52: u8,
+    // channels
+    square1: Square,
+    sweep: Sweep,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // frame sequencer
+    sequencer_step: u8,
+    sequencer_timer: u32,
+    // CPU ticks times the sample rate since the last sample
+    sample_clock: u64,
+    // charge of the high-pass filter capacitors (left & right)
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    capacitor: [f64; 2],
+    // same, for each channel on its own
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stem_capacitors: [[f64; 2]; 4],
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_output"))]
+    output: Box<dyn AudioOutput + Send>,
+}
+
+// The audio output and the state of the filters are not part of the state.
+crate::state::impl_state!(APU {
+    nr10,
+    nr11,
+    nr12,
+    nr13,
+    nr14,
+    nr20,
+    nr21,
+    nr22,
+    nr23,
+    nr24,
+    nr30,
+    nr31,
+    nr32,
+    nr33,
+    nr34,
+    wave_ram,
+    nr40,
+    nr41,
+    nr42,
+    nr43,
+    nr44,
+    nr50,
+    nr51,
+    nr52,
+    square1,
+    sweep,
+    square2,
+    wave,
+    noise,
+    sequencer_step,
+    sequencer_timer,
+    sample_clock
+});
+
+impl Default for APU {
+    fn default() -> Self {
+        Self {
+            nr10: 0,
+            nr11: 0,
+            nr12: 0,
+            nr13: 0,
+            nr14: 0,
+            nr20: 0,
+            nr21: 0,
+            nr22: 0,
+            nr23: 0,
+            nr24: 0,
+            nr30: 0,
+            nr31: 0,
+            nr32: 0,
+            nr33: 0,
+            nr34: 0,
+            wave_ram: [0; 0x10],
+            nr40: 0,
+            nr41: 0,
+            nr42: 0,
+            nr43: 0,
+            nr44: 0,
+            nr50: 0,
+            nr51: 0,
+            nr52: 0,
+            square1: Default::default(),
+            sweep: Default::default(),
+            square2: Default::default(),
+            wave: Default::default(),
+            noise: Default::default(),
+            sequencer_step: 0,
+            sequencer_timer: SEQUENCER_TICKS,
+            sample_clock: 0,
+            capacitor: [0.0; 2],
+            stem_capacitors: [[0.0; 2]; 4],
+            output: default_output(),
+        }
+    }
 }
 
 impl APU {
+    pub fn output(&self) -> &dyn AudioOutput {
+        self.output.as_ref()
+    }
+
+    /// Replace the audio output. Returns the previous one.
+    pub fn set_output(
+        &mut self,
+        output: Box<dyn AudioOutput + Send>,
+    ) -> Box<dyn AudioOutput + Send> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    // Reset the APU (the output stays connected).
+    pub(crate) fn reset(&mut self) {
+        let output = std::mem::replace(&mut self.output, default_output());
+        *self = Self {
+            output,
+            ..Default::default()
+        };
+    }
+
+    // Register writes that bring a (powered off) APU to the current state, for
+    // logs of the writes that start mid-emulation. Channels that are on are
+    // retriggered.
+    pub(crate) fn register_writes(&self) -> Vec<(u16, u8)> {
+        let mut writes = vec![(0xff26, self.nr52 & 0x80)];
+        writes.extend((0xff30..).zip(self.wave_ram));
+        if self.nr52 & 0x80 != 0 {
+            let trigger = |on: bool| if on { 0x80 } else { 0 };
+            writes.extend([
+                (0xff10, self.nr10),
+                (0xff11, self.nr11),
+                (0xff12, self.nr12),
+                (0xff13, self.nr13),
+                (0xff14, self.nr14 & 0x7f | trigger(self.square1.enabled)),
+                (0xff16, self.nr21),
+                (0xff17, self.nr22),
+                (0xff18, self.nr23),
+                (0xff19, self.nr24 & 0x7f | trigger(self.square2.enabled)),
+                (0xff1a, self.nr30),
+                (0xff1b, self.nr31),
+                (0xff1c, self.nr32),
+                (0xff1d, self.nr33),
+                (0xff1e, self.nr34 & 0x7f | trigger(self.wave.enabled)),
+                (0xff20, self.nr41),
+                (0xff21, self.nr42),
+                (0xff22, self.nr43),
+                (0xff23, self.nr44 & 0x7f | trigger(self.noise.enabled)),
+                (0xff24, self.nr50),
+                (0xff25, self.nr51),
+            ]);
+        }
+        writes
+    }
+
     fn clear_reg(&mut self) {
         self.nr10 = 0;
         self.nr11 = 0;
@@ -82,6 +555,190 @@ impl APU {
         self.nr50 = 0;
         self.nr51 = 0;
         self.nr52 = 0;
+
+        self.square1 = Default::default();
+        self.sweep = Default::default();
+        self.square2 = Default::default();
+        self.wave = Default::default();
+        self.noise = Default::default();
+    }
+
+    fn freq1(&self) -> u16 {
+        u16::from_le_bytes([self.nr13, self.nr14 & 0x7])
+    }
+
+    fn freq2(&self) -> u16 {
+        u16::from_le_bytes([self.nr23, self.nr24 & 0x7])
+    }
+
+    fn freq3(&self) -> u16 {
+        u16::from_le_bytes([self.nr33, self.nr34 & 0x7])
+    }
+
+    fn trigger_square1(&mut self) {
+        self.square1.trigger(self.nr12, self.freq1());
+        self.sweep.shadow = self.freq1();
+        self.sweep.timer = Sweep::period(self.nr10);
+        self.sweep.enabled = self.nr10 & 0x77 != 0;
+        if self.nr10 & 0x7 != 0 && self.sweep.next(self.nr10) > 2047 {
+            self.square1.enabled = false;
+        }
+    }
+
+    fn trigger_wave(&mut self) {
+        self.wave.enabled = self.nr30 & 0x80 != 0;
+        self.wave.timer = (2048 - self.freq3() as u32) * 2;
+        self.wave.position = 0;
+        self.wave.length.trigger(256);
+    }
+
+    fn clock_sweep(&mut self) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer != 0 {
+            return;
+        }
+        self.sweep.timer = Sweep::period(self.nr10);
+        if !self.sweep.enabled || self.nr10 & 0x70 == 0 {
+            return;
+        }
+        let freq = self.sweep.next(self.nr10);
+        if freq > 2047 {
+            self.square1.enabled = false;
+        } else if self.nr10 & 0x7 != 0 {
+            self.sweep.shadow = freq;
+            self.nr13 = freq as u8;
+            self.nr14 = (self.nr14 & !0x7) | (freq >> 8) as u8;
+            // overflow check with the new frequency
+            if self.sweep.next(self.nr10) > 2047 {
+                self.square1.enabled = false;
+            }
+        }
+    }
+
+    fn clock_sequencer(&mut self) {
+        let step = self.sequencer_step;
+        if step % 2 == 0 {
+            if self.square1.length.clock(self.nr14) {
+                self.square1.enabled = false;
+            }
+            if self.square2.length.clock(self.nr24) {
+                self.square2.enabled = false;
+            }
+            if self.wave.length.clock(self.nr34) {
+                self.wave.enabled = false;
+            }
+            if self.noise.length.clock(self.nr44) {
+                self.noise.enabled = false;
+            }
+        }
+        if step == 2 || step == 6 {
+            self.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.clock(self.nr12);
+            self.square2.envelope.clock(self.nr22);
+            self.noise.envelope.clock(self.nr42);
+        }
+        self.sequencer_step = (step + 1) % 8;
+    }
+
+    // Returns the digital output of each channel (0 to 15), or None if the
+    // DAC of the channel is off.
+    fn channels(&self) -> [Option<u8>; 4] {
+        let dac = |on: bool, output: u8| if on { Some(output) } else { None };
+        [
+            dac(self.nr12 & 0xf8 != 0, self.square1.output(self.nr11)),
+            dac(self.nr22 & 0xf8 != 0, self.square2.output(self.nr21)),
+            dac(
+                self.nr30 & 0x80 != 0,
+                self.wave.output(self.nr32, &self.wave_ram),
+            ),
+            dac(self.nr42 & 0xf8 != 0, self.noise.output()),
+        ]
+    }
+
+    // Pan the channels (NR51) and apply the master volume (NR50). Each side
+    // of each channel is between -1 and 1.
+    fn pan(&self) -> [[f64; 2]; 4] {
+        let left = ((self.nr50 >> 4) & 0x7) as f64 + 1.0;
+        let right = (self.nr50 & 0x7) as f64 + 1.0;
+        let mut channels = [[0.0; 2]; 4];
+        for (i, channel) in self.channels().into_iter().enumerate() {
+            let Some(output) = channel else {
+                continue;
+            };
+            let analog = output as f64 / 7.5 - 1.0;
+            if self.nr51 & (0x10 << i) != 0 {
+                channels[i][0] = analog * left / 8.0;
+            }
+            if self.nr51 & (0x01 << i) != 0 {
+                channels[i][1] = analog * right / 8.0;
+            }
+        }
+        channels
+    }
+
+    fn sample(&mut self, ticks: u64) {
+        let rate = self.output.sample_rate() as u64;
+        self.sample_clock += ticks * rate;
+        if self.sample_clock < CLOCK {
+            return;
+        }
+        let channels = self.pan();
+        let mix = channels
+            .iter()
+            .fold([0.0; 2], |mix, c| [mix[0] + c[0], mix[1] + c[1]]);
+        let charge = CHARGE.powf(CLOCK as f64 / rate as f64);
+        let out = filter(&mut self.capacitor, mix, charge);
+        let stems = self.output.stems().then(|| {
+            let mut stems = [[0; 2]; 4];
+            for (i, channel) in channels.into_iter().enumerate() {
+                stems[i] = filter(&mut self.stem_capacitors[i], channel, charge);
+            }
+            stems
+        });
+        while self.sample_clock >= CLOCK {
+            self.sample_clock -= CLOCK;
+            for (i, stem) in stems.iter().flatten().enumerate() {
+                self.output.stem(i, stem[0], stem[1]);
+            }
+            self.output.sample(out[0], out[1]);
+        }
+    }
+}
+
+// Remove the DC offset of the DACs (high-pass filter), and scale the mix of
+// the four channels (between -4 and 4) to 16 bits.
+fn filter(capacitor: &mut [f64; 2], mix: [f64; 2], charge: f64) -> [i16; 2] {
+    let mut out = [0; 2];
+    for i in 0..2 {
+        let filtered = mix[i] - capacitor[i];
+        capacitor[i] = mix[i] - filtered * charge;
+        out[i] = (filtered / 4.0 * i16::MAX as f64).clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+    }
+    out
+}
+
+impl Update for APU {
+    fn update(&mut self, ticks: u64, _: &mut irq::Flags) {
+        if self.nr52 & 0x80 != 0 {
+            let mut remaining = ticks as u32;
+            while remaining > 0 {
+                let chunk = remaining.min(self.sequencer_timer);
+                let (freq1, freq2, freq3) = (self.freq1(), self.freq2(), self.freq3());
+                self.square1.update(chunk, freq1);
+                self.square2.update(chunk, freq2);
+                self.wave.update(chunk, freq3);
+                self.noise.update(chunk, self.nr43);
+                self.sequencer_timer -= chunk;
+                if self.sequencer_timer == 0 {
+                    self.sequencer_timer = SEQUENCER_TICKS;
+                    self.clock_sequencer();
+                }
+                remaining -= chunk;
+            }
+        }
+        self.sample(ticks);
     }
 }
 
@@ -125,7 +782,19 @@ impl Device for APU {
                 // Sound Control Registers
                 0xff24 => Ok(self.nr50),
                 0xff25 => Ok(self.nr51),
-                0xff26 => Ok(self.nr52 | 0x70),
+                0xff26 => {
+                    let status = [
+                        self.square1.enabled,
+                        self.square2.enabled,
+                        self.wave.enabled,
+                        self.noise.enabled,
+                    ];
+                    let status = status
+                        .iter()
+                        .enumerate()
+                        .fold(0, |bits, (i, on)| bits | ((*on as u8) << i));
+                    Ok(self.nr52 | 0x70 | status)
+                }
                 // $FF27-$FF2F always read back as $FF
                 0xff27..=0xff2f => Ok(0xff),
                 0xff30..=0xff3f => Ok(self.wave_ram[address as usize - 0xff30]),
@@ -140,28 +809,81 @@ impl Device for APU {
                 address, data {
                     // Channel 1 sweep
                     0xff10 => self.nr10 = data,
-                    0xff11 => self.nr11 = data,
-                    0xff12 => self.nr12 = data,
+                    0xff11 => {
+                        self.nr11 = data;
+                        self.square1.length.counter = 64 - (data & 0x3f) as u16;
+                    }
+                    0xff12 => {
+                        self.nr12 = data;
+                        if data & 0xf8 == 0 {
+                            self.square1.enabled = false;
+                        }
+                    }
                     0xff13 => self.nr13 = data,
-                    0xff14 => self.nr14 = data,
+                    0xff14 => {
+                        self.nr14 = data;
+                        if data & 0x80 != 0 {
+                            self.trigger_square1();
+                        }
+                    }
                     // Channel 2 - Tone
                     0xff15 => self.nr20 = data,
-                    0xff16 => self.nr21 = data,
-                    0xff17 => self.nr22 = data,
+                    0xff16 => {
+                        self.nr21 = data;
+                        self.square2.length.counter = 64 - (data & 0x3f) as u16;
+                    }
+                    0xff17 => {
+                        self.nr22 = data;
+                        if data & 0xf8 == 0 {
+                            self.square2.enabled = false;
+                        }
+                    }
                     0xff18 => self.nr23 = data,
-                    0xff19 => self.nr24 = data,
+                    0xff19 => {
+                        self.nr24 = data;
+                        if data & 0x80 != 0 {
+                            let freq = self.freq2();
+                            self.square2.trigger(self.nr22, freq);
+                        }
+                    }
                     // Channel 3 - Wave RAM
-                    0xff1a => self.nr30 = data,
-                    0xff1b => self.nr31 = data,
+                    0xff1a => {
+                        self.nr30 = data;
+                        if data & 0x80 == 0 {
+                            self.wave.enabled = false;
+                        }
+                    }
+                    0xff1b => {
+                        self.nr31 = data;
+                        self.wave.length.counter = 256 - data as u16;
+                    }
                     0xff1c => self.nr32 = data,
                     0xff1d => self.nr33 = data,
-                    0xff1e => self.nr34 = data,
+                    0xff1e => {
+                        self.nr34 = data;
+                        if data & 0x80 != 0 {
+                            self.trigger_wave();
+                        }
+                    }
                     // Channel 4 - Noise
                     0xff1f => self.nr40 = data,
-                    0xff20 => self.nr41 = data,
-                    0xff21 => self.nr42 = data,
+                    0xff20 => {
+                        self.nr41 = data;
+                        self.noise.length.counter = 64 - (data & 0x3f) as u16;
+                    }
+                    0xff21 => {
+                        self.nr42 = data;
+                        if data & 0xf8 == 0 {
+                            self.noise.enabled = false;
+                        }
+                    }
                     0xff22 => self.nr43 = data,
-                    0xff23 => self.nr44 = data,
+                    0xff23 => {
+                        self.nr44 = data;
+                        if data & 0x80 != 0 {
+                            self.noise.trigger(self.nr42, self.nr43);
+                        }
+                    }
                     // Sound Control Registers
                     0xff24 => self.nr50 = data,
                     0xff25 => self.nr51 = data,
@@ -179,11 +901,16 @@ impl Device for APU {
 
         // so is NR52
         if address == 0xff26 {
+            let on = self.nr52 & 0x80 != 0;
             self.nr52 &= 0x7f;
             self.nr52 |= data & 0x80;
 
             if self.nr52 & 0x80 == 0 {
                 self.clear_reg();
+            } else if !on {
+                // the frame sequencer restarts when the APU is switched on
+                self.sequencer_step = 0;
+                self.sequencer_timer = SEQUENCER_TICKS;
             }
         }
 
@@ -192,4 +919,246 @@ impl Device for APU {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::{AudioOutput, Noise, APU};
+    use crate::{device::Device, irq, Update, CLOCK};
+    use std::sync::{Arc, Mutex};
+
+    // switched on, with the frame sequencer at step 0
+    fn power_on() -> APU {
+        let mut apu = APU::default();
+        apu.write(0xff26, 0x80).unwrap();
+        apu.write(0xff24, 0x77).unwrap();
+        apu.write(0xff25, 0xff).unwrap();
+        apu
+    }
+
+    fn clock_sequencer(apu: &mut APU, steps: usize) {
+        for _ in 0..steps {
+            apu.clock_sequencer();
+        }
+    }
+
+    struct Samples(Arc<Mutex<Vec<(i16, i16)>>>);
+
+    impl AudioOutput for Samples {
+        fn sample(&mut self, left: i16, right: i16) {
+            self.0.lock().unwrap().push((left, right));
+        }
+    }
+
+    #[test]
+    fn square() {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let mut apu = APU::default();
+        apu.set_output(Box::new(Samples(Arc::clone(&samples))));
+        let mut flags = irq::Flags::empty();
+        apu.write(0xff26, 0x80).unwrap();
+        apu.write(0xff24, 0x77).unwrap();
+        apu.write(0xff25, 0x11).unwrap(); // channel 1 on both sides
+        apu.write(0xff11, 0x80 | 0x3f).unwrap(); // 50% duty, 1 length unit
+        apu.write(0xff12, 0xf0).unwrap(); // max volume
+        apu.write(0xff13, 0x00).unwrap();
+        apu.write(0xff14, 0xc7).unwrap(); // trigger, length enabled, 2048 - 1792 (~512Hz)
+        assert_eq!(0xf1, apu.read(0xff26).unwrap());
+
+        for _ in 0..CLOCK / 1000 {
+            apu.update(4, &mut flags);
+        }
+        // one second = 44100 samples
+        assert_eq!(44_100 * 4 / 1000, samples.lock().unwrap().len());
+        let peak = samples
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(l, _)| l.abs())
+            .max()
+            .unwrap();
+        assert!(peak > 0x1000);
+        assert!(samples.lock().unwrap().iter().all(|(l, r)| l == r));
+        // the length counter ran out (1/256 of a second)
+        assert_eq!(0xf0, apu.read(0xff26).unwrap());
+    }
+
+    // mixed sample (left) and the left side of each channel
+    #[derive(Default)]
+    struct Stems {
+        stems: [i16; 4],
+        samples: Vec<(i16, [i16; 4])>,
+    }
+
+    impl AudioOutput for Stems {
+        fn sample(&mut self, left: i16, _: i16) {
+            self.samples.push((left, self.stems));
+        }
+
+        fn stems(&self) -> bool {
+            true
+        }
+
+        fn stem(&mut self, channel: usize, left: i16, _: i16) {
+            self.stems[channel] = left;
+        }
+    }
+
+    #[test]
+    fn stems() {
+        let stems = Arc::new(Mutex::new(Stems::default()));
+        let mut apu = APU::default();
+        apu.set_output(Box::new(Arc::clone(&stems)));
+        let mut flags = irq::Flags::empty();
+        apu.write(0xff26, 0x80).unwrap();
+        apu.write(0xff24, 0x77).unwrap();
+        apu.write(0xff25, 0x33).unwrap(); // channels 1 & 2 on both sides
+        apu.write(0xff12, 0xf0).unwrap();
+        apu.write(0xff14, 0x87).unwrap();
+        apu.write(0xff17, 0xa0).unwrap();
+        apu.write(0xff19, 0x86).unwrap();
+
+        for _ in 0..CLOCK / 1000 {
+            apu.update(4, &mut flags);
+        }
+        let stems = stems.lock().unwrap();
+        assert_eq!(44_100 * 4 / 1000, stems.samples.len());
+        for (mix, [ch1, ch2, ch3, ch4]) in &stems.samples {
+            assert_eq!((0, 0), (*ch3, *ch4));
+            assert!((mix - ch1 - ch2).abs() <= 1);
+        }
+        assert!(stems.samples.iter().any(|(_, stems)| stems[0] != stems[1]));
+    }
+
+    #[test]
+    fn sweep() {
+        // the overflow check on trigger disables the channel
+        let mut apu = power_on();
+        apu.write(0xff10, 0x01).unwrap(); // period 0, increase, shift 1
+        apu.write(0xff12, 0xf0).unwrap();
+        apu.write(0xff13, 0xff).unwrap();
+        apu.write(0xff14, 0x87).unwrap(); // trigger, frequency 2047
+        assert_eq!(0xf0, apu.read(0xff26).unwrap());
+
+        // 0x500 -> 0x780, then the check with the new frequency overflows
+        let mut apu = power_on();
+        apu.write(0xff10, 0x11).unwrap(); // period 1, increase, shift 1
+        apu.write(0xff12, 0xf0).unwrap();
+        apu.write(0xff13, 0x00).unwrap();
+        apu.write(0xff14, 0x85).unwrap();
+        assert_eq!(0xf1, apu.read(0xff26).unwrap());
+        // the sweep is clocked on steps 2 and 6
+        clock_sequencer(&mut apu, 2);
+        assert_eq!(0x500, apu.freq1());
+        clock_sequencer(&mut apu, 1);
+        assert_eq!(0x780, apu.freq1());
+        assert_eq!(0xf0, apu.read(0xff26).unwrap());
+
+        // decrease
+        let mut apu = power_on();
+        apu.write(0xff10, 0x19).unwrap(); // period 1, decrease, shift 1
+        apu.write(0xff12, 0xf0).unwrap();
+        apu.write(0xff14, 0x84).unwrap();
+        clock_sequencer(&mut apu, 3);
+        for freq in [0x200, 0x100, 0x80] {
+            assert_eq!(freq, apu.freq1());
+            clock_sequencer(&mut apu, 4);
+        }
+        assert_eq!(0xf1, apu.read(0xff26).unwrap());
+
+        // period 0 or sweep disabled: the frequency doesn't change
+        for nr10 in [0x01, 0x00] {
+            let mut apu = power_on();
+            apu.write(0xff10, nr10).unwrap();
+            apu.write(0xff12, 0xf0).unwrap();
+            apu.write(0xff14, 0x84).unwrap();
+            clock_sequencer(&mut apu, 64);
+            assert_eq!(0x400, apu.freq1());
+            assert_eq!(0xf1, apu.read(0xff26).unwrap());
+        }
+    }
+
+    #[test]
+    fn wave() {
+        let mut apu = power_on();
+        // samples 0 to 15, twice
+        for i in 0..0x10 {
+            let data = ((2 * i) % 16) << 4 | (2 * i + 1) % 16;
+            apu.write(0xff30 + i as u16, data).unwrap();
+        }
+        apu.write(0xff1a, 0x80).unwrap();
+        apu.write(0xff1c, 0x20).unwrap();
+        apu.write(0xff1d, 0xff).unwrap();
+        apu.write(0xff1e, 0x87).unwrap(); // trigger, frequency 2047 (2 ticks per sample)
+        assert_eq!(0xf4, apu.read(0xff26).unwrap());
+        apu.update(10, &mut irq::Flags::empty());
+        assert_eq!(Some(5), apu.channels()[2]);
+
+        // output level: mute, 100%, 50% & 25%
+        for (nr32, shift) in [(0x20, 0), (0x40, 1), (0x60, 2)] {
+            apu.write(0xff1c, nr32).unwrap();
+            for position in 0..32 {
+                apu.wave.position = position;
+                assert_eq!(Some((position % 16) >> shift), apu.channels()[2]);
+            }
+        }
+        apu.write(0xff1c, 0x00).unwrap();
+        assert_eq!(Some(0), apu.channels()[2]);
+
+        // DAC off
+        apu.write(0xff1a, 0x00).unwrap();
+        assert_eq!(None, apu.channels()[2]);
+        assert_eq!(0xf0, apu.read(0xff26).unwrap());
+    }
+
+    #[test]
+    fn noise() {
+        // 15-bit & 7-bit LFSR (maximal length sequences)
+        for (nr43, mask, period) in [(0x00, 0x7fff, 32767), (0x08, 0x7f, 127)] {
+            let mut apu = power_on();
+            apu.write(0xff21, 0xf0).unwrap();
+            apu.write(0xff22, nr43).unwrap();
+            apu.write(0xff23, 0x80).unwrap();
+            let mut steps = 0;
+            let mut on = 0;
+            loop {
+                apu.noise.update(Noise::period(nr43), nr43);
+                steps += 1;
+                if apu.channels()[3] == Some(15) {
+                    on += 1;
+                }
+                if apu.noise.lfsr & mask == mask {
+                    break;
+                }
+            }
+            assert_eq!(period, steps);
+            assert_eq!(period / 2, on);
+        }
+        assert_eq!(8, Noise::period(0x00));
+        assert_eq!(112 << 3, Noise::period(0x37));
+    }
+
+    #[test]
+    fn length_envelope() {
+        let mut apu = power_on();
+        apu.write(0xff16, 0x3e).unwrap(); // length 2
+        apu.write(0xff17, 0xf0).unwrap();
+        apu.write(0xff19, 0xc0).unwrap(); // trigger, length enabled
+                                          // the length is clocked on steps 0, 2, 4 & 6
+        clock_sequencer(&mut apu, 1);
+        assert_eq!(0xf2, apu.read(0xff26).unwrap());
+        clock_sequencer(&mut apu, 2);
+        assert_eq!(0xf0, apu.read(0xff26).unwrap());
+
+        // volume down every envelope clock, and up every 2 clocks
+        apu.write(0xff12, 0xf1).unwrap();
+        apu.write(0xff14, 0x80).unwrap();
+        apu.write(0xff21, 0x0a).unwrap();
+        apu.write(0xff23, 0x80).unwrap();
+        // the envelope is clocked on step 7
+        for clocks in 1..=32 {
+            clock_sequencer(&mut apu, 8);
+            assert_eq!(15u8.saturating_sub(clocks), apu.square1.envelope.volume);
+            assert_eq!((clocks / 2).min(15), apu.noise.envelope.volume);
+        }
+        // length counters are disabled (64 would have run out)
+        assert_eq!(0xf9, apu.read(0xff26).unwrap());
+    }
+}
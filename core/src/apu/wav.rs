@@ -0,0 +1,107 @@
+//! WAV recording of the audio output.
+use super::AudioOutput;
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+use utils::wav::WavWriter;
+
+type Writer = WavWriter<BufWriter<File>>;
+
+/// Audio output that records 16-bit stereo PCM WAV files.
+///
+/// The headers are kept up to date (see `utils::wav::WavWriter`), so the files
+/// are valid even if the recording is interrupted. In stem mode, each of the
+/// four channels is also recorded to its own file (see `stem_path`).
+///
+/// Dropping the recorder (or replacing the audio output) closes the files.
+/// Writing stops at the first error, which is logged.
+pub struct WavRecorder {
+    mix: Writer,
+    stems: Vec<Writer>,
+    failed: bool,
+}
+
+impl WavRecorder {
+    /// Record the mixed output to the given path.
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        Ok(Self {
+            mix: writer(path.as_ref(), sample_rate)?,
+            stems: Vec::new(),
+            failed: false,
+        })
+    }
+
+    /// Record the mixed output to the given path, and each channel to its own
+    /// file.
+    pub fn with_stems<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        let path = path.as_ref();
+        let stems = (0..4)
+            .map(|channel| writer(&stem_path(path, channel), sample_rate))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            stems,
+            ..Self::create(path, sample_rate)?
+        })
+    }
+
+    /// Returns the number of samples recorded.
+    pub fn samples(&self) -> u32 {
+        self.mix.samples()
+    }
+
+    /// Close the files, returning the error of the recording if there was one.
+    pub fn finish(self) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other("WAV recording failed"));
+        }
+        for stem in self.stems {
+            stem.finish()?;
+        }
+        self.mix.finish()?;
+        Ok(())
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            log::error!("WAV recording failed: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Path of the file of the given channel (0 to 3) in stem mode:
+/// `song.wav` → `song.ch1.wav`, ..., `song.ch4.wav`.
+pub fn stem_path<P: AsRef<Path>>(path: P, channel: usize) -> PathBuf {
+    path.as_ref()
+        .with_extension(format!("ch{}.wav", channel + 1))
+}
+
+fn writer(path: &Path, sample_rate: u32) -> io::Result<Writer> {
+    WavWriter::new(BufWriter::new(File::create(path)?), 2, sample_rate)
+}
+
+impl AudioOutput for WavRecorder {
+    fn sample_rate(&self) -> u32 {
+        self.mix.sample_rate()
+    }
+
+    fn sample(&mut self, left: i16, right: i16) {
+        if !self.failed {
+            let result = self.mix.write(&[left, right]);
+            self.check(result);
+        }
+    }
+
+    fn stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    fn stem(&mut self, channel: usize, left: i16, right: i16) {
+        if !self.failed {
+            let result = self.stems[channel].write(&[left, right]);
+            self.check(result);
+        }
+    }
+}
@@ -17,6 +17,8 @@ pub struct Boot {
     enabled: bool,
 }
 
+crate::state::impl_state!(Boot { enabled });
+
 impl Default for Boot {
     fn default() -> Self {
         Self {
@@ -0,0 +1,156 @@
+//! Screenshots and video capture.
+//!
+//! Wrap the LCD output with `Capture` to keep a copy of the frames in RGB. While
+//! recording (`LR35902::start_recording`), every frame is passed to a
+//! `utils::capture::Recorder`, and the audio goes to the WAV file next to the
+//! video (as well as to the audio output connected before the recording).
+use crate::{
+    apu::AudioOutput,
+    cartridge::Cartridge,
+    ppu::{lcd, Color, LCD, LCD_HEIGHT, LCD_WIDTH},
+    LR35902,
+};
+use educe::Educe;
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use utils::capture::{self, Recorder};
+
+/// LCD adapter that captures the frames of another LCD output.
+#[derive(Educe)]
+#[educe(Debug)]
+pub struct Capture<O: LCD> {
+    #[educe(Debug(ignore))]
+    output: O,
+    // frame being drawn, and the last complete one (RGB)
+    #[educe(Debug(ignore))]
+    lines: Box<[u8]>,
+    #[educe(Debug(ignore))]
+    frame: Box<[u8]>,
+    #[educe(Debug(ignore))]
+    recording: Option<Arc<Mutex<Recording>>>,
+    // frames emulated again when rewinding aren't recorded
+    replaying: bool,
+}
+
+struct Recording {
+    recorder: Recorder,
+    // audio output connected before the recording
+    output: Box<dyn AudioOutput + Send>,
+}
+
+impl<O: LCD> Capture<O> {
+    pub fn new(output: O) -> Self {
+        Self {
+            output,
+            lines: vec![0; LCD_WIDTH * LCD_HEIGHT * 3].into_boxed_slice(),
+            frame: vec![0; LCD_WIDTH * LCD_HEIGHT * 3].into_boxed_slice(),
+            recording: None,
+            replaying: false,
+        }
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    /// Returns the last complete frame, in RGB.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Save the last complete frame as PNG, scaled up by the given factor.
+    pub fn screenshot<P: AsRef<Path>>(&self, path: P, scale: usize) -> io::Result<()> {
+        let scale = scale.max(1);
+        let frame = capture::scale(&self.frame, LCD_WIDTH, scale);
+        capture::save_png(path, LCD_WIDTH * scale, LCD_HEIGHT * scale, &frame)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+impl<O: LCD> LCD for Capture<O> {
+    fn output_line(&mut self, ly: u8, data: &[Color; LCD_WIDTH]) {
+        self.output.output_line(ly, data);
+        let offset = LCD_WIDTH * 3 * ly as usize;
+        let line = &mut self.lines[offset..offset + LCD_WIDTH * 3];
+        for (pixel, color) in line.chunks_mut(3).zip(data) {
+            pixel.copy_from_slice(&[lcd::r(color), lcd::g(color), lcd::b(color)]);
+        }
+        if ly as usize == LCD_HEIGHT - 1 {
+            self.frame.copy_from_slice(&self.lines);
+            if let Some(recording) = self.recording.as_ref().filter(|_| !self.replaying) {
+                recording.lock().unwrap().recorder.frame(&self.frame);
+            }
+        }
+    }
+
+    fn replay(&mut self, replaying: bool) {
+        self.replaying = replaying;
+        self.output.replay(replaying);
+    }
+}
+
+struct Audio(Arc<Mutex<Recording>>);
+
+impl AudioOutput for Audio {
+    fn sample_rate(&self) -> u32 {
+        self.0.lock().unwrap().output.sample_rate()
+    }
+
+    fn sample(&mut self, left: i16, right: i16) {
+        let mut recording = self.0.lock().unwrap();
+        recording.recorder.sample(left, right);
+        recording.output.sample(left, right);
+    }
+
+    fn stems(&self) -> bool {
+        self.0.lock().unwrap().output.stems()
+    }
+
+    fn stem(&mut self, channel: usize, left: i16, right: i16) {
+        self.0.lock().unwrap().output.stem(channel, left, right);
+    }
+}
+
+impl<C: Cartridge, O: LCD> LR35902<C, Capture<O>> {
+    /// Start recording the video (and audio) to the given path. See
+    /// `utils::capture` for the formats.
+    ///
+    /// The audio is recorded at the sample rate of the connected audio
+    /// output, and still passed on to it.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P, scale: usize) -> io::Result<()> {
+        self.stop_recording();
+        let rate = self.apu.output().sample_rate();
+        let recorder = Recorder::create(path, LCD_WIDTH, LCD_HEIGHT, scale, Some(rate))?;
+        let output = self.apu.set_output(Box::new(()));
+        let recording = Arc::new(Mutex::new(Recording { recorder, output }));
+        self.apu.set_output(Box::new(Audio(Arc::clone(&recording))));
+        self.ppu.output_mut().recording = Some(recording);
+        Ok(())
+    }
+
+    /// Stop recording. Returns the number of frames recorded, or `None` if
+    /// there was no recording.
+    pub fn stop_recording(&mut self) -> Option<io::Result<u64>> {
+        let recording = self.ppu.output_mut().recording.take()?;
+        // drops the other reference to the recording
+        self.apu.set_output(Box::new(()));
+        let Recording { recorder, output } = Arc::try_unwrap(recording)
+            .ok()
+            .unwrap()
+            .into_inner()
+            .unwrap();
+        self.apu.set_output(output);
+        let frames = recorder.frames();
+        Some(recorder.finish().map(|_| frames))
+    }
+}
@@ -1,9 +1,11 @@
-use crate::device::Device;
+use crate::{device::Device, state::State};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 // re-exports
 use crate::error::{ReadError, WriteError};
+#[cfg(feature = "gbs")]
+pub use gbs::{GbsCartridge, GbsHeader};
 #[cfg(feature = "mbc1")]
 pub use mbc1::MBC1;
 #[cfg(feature = "mbc2")]
@@ -13,6 +15,8 @@ pub use mbc3::MBC3;
 #[cfg(feature = "mbc5")]
 pub use mbc5::MBC5;
 
+#[cfg(feature = "gbs")]
+pub mod gbs;
 #[cfg(feature = "mbc1")]
 mod mbc1;
 #[cfg(feature = "mbc2")]
@@ -22,6 +26,44 @@ mod mbc3;
 #[cfg(feature = "mbc5")]
 mod mbc5;
 
+/// Errors loading a cartridge.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum LoadError {
+    #[error("ROM file too small for the cartridge header")]
+    Header,
+
+    #[error("Unsupported cartridge type {0:02X}")]
+    Type(u8),
+
+    #[cfg(feature = "gbs")]
+    #[error(transparent)]
+    Gbs(#[from] gbs::GbsError),
+}
+
+/// Creates the cartridge for the contents of a ROM (or GBS) file, from the
+/// cartridge type in its header.
+///
+/// Cartridge types behind a disabled feature are reported as unsupported.
+pub fn load(file: Box<[u8]>) -> Result<Box<dyn Cartridge>, LoadError> {
+    #[cfg(feature = "gbs")]
+    if gbs::is_gbs(&file) {
+        return Ok(Box::new(GbsCartridge::new(&file)?));
+    }
+    let cartridge: Box<dyn Cartridge> = match *file.get(0x147).ok_or(LoadError::Header)? {
+        0x00 | 0x08 | 0x09 => Box::new(ROM::new(file)),
+        #[cfg(feature = "mbc1")]
+        0x01..=0x03 => Box::new(MBC1::new(file)),
+        #[cfg(feature = "mbc2")]
+        0x05 | 0x06 => Box::new(MBC2::new(file)),
+        #[cfg(feature = "mbc3")]
+        0x0f..=0x13 => Box::new(MBC3::new(file)),
+        #[cfg(feature = "mbc5")]
+        0x19..=0x1e => Box::new(MBC5::new(file)),
+        kind => return Err(LoadError::Type(kind)),
+    };
+    Ok(cartridge)
+}
+
 fn decode_ram_banks(banks: u8) -> usize {
     match banks {
         0x00 => 0,
@@ -33,7 +75,49 @@ fn decode_ram_banks(banks: u8) -> usize {
 }
 
 /// An empty touple represents the absence of cartride.
-pub trait Cartridge: Device {}
+///
+/// The state of the cartridge (RAM and controller registers, but not the ROM)
+/// is included in the save states.
+pub trait Cartridge: Device + State {
+    /// Returns the contents of the cartridge ROM (empty if there is none).
+    fn rom(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Returns the ROM bank mapped at 0x4000-0x7fff.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Returns the RAM bank mapped at 0xa000-0xbfff.
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    /// Returns the contents of the cartridge RAM, for all banks (empty if
+    /// there is none).
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Returns the contents of the cartridge RAM as mutable (see `ram`).
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// Returns the RTC registers, if the cartridge has a real time clock.
+    fn rtc(&self) -> Option<[u8; 5]> {
+        None
+    }
+
+    /// Set the RTC registers.
+    /// It has no effect if the cartridge doesn't have a real time clock.
+    fn set_rtc(&mut self, _rtc: [u8; 5]) {}
+
+    /// Reset the registers of the memory controller.
+    /// The contents of the cartridge RAM are preserved.
+    fn reset(&mut self) {}
+}
 
 impl Device for Box<dyn Cartridge> {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
@@ -53,7 +137,39 @@ impl Device for Box<dyn Cartridge> {
     }
 }
 
-impl Cartridge for Box<dyn Cartridge> {}
+impl Cartridge for Box<dyn Cartridge> {
+    fn rom(&self) -> &[u8] {
+        self.as_ref().rom()
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.as_ref().rom_bank()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.as_ref().ram_bank()
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.as_ref().ram()
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.as_mut().ram_mut()
+    }
+
+    fn rtc(&self) -> Option<[u8; 5]> {
+        self.as_ref().rtc()
+    }
+
+    fn set_rtc(&mut self, rtc: [u8; 5]) {
+        self.as_mut().set_rtc(rtc)
+    }
+
+    fn reset(&mut self) {
+        self.as_mut().reset()
+    }
+}
 
 impl Device for () {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
@@ -87,6 +203,9 @@ pub struct ROM {
     ram: Box<[u8]>,
 }
 
+// the ROM is not part of the state
+crate::state::impl_state!(ROM { ram });
+
 impl ROM {
     pub fn new(rom: Box<[u8]>) -> Self {
         Self {
@@ -96,7 +215,19 @@ impl ROM {
     }
 }
 
-impl Cartridge for ROM {}
+impl Cartridge for ROM {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
 
 impl Device for ROM {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
@@ -123,3 +254,20 @@ impl Device for ROM {
         self.read_exact_fallback(address, buf)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{load, Cartridge, LoadError};
+
+    #[test]
+    fn load_header() {
+        let rom = |kind: u8| {
+            let mut rom = vec![0; 0x8000];
+            rom[0x147] = kind;
+            rom.into_boxed_slice()
+        };
+        assert_eq!(0x8000, load(rom(0x00)).unwrap().rom().len());
+        assert_eq!(Some(LoadError::Type(0xfc)), load(rom(0xfc)).err());
+        assert_eq!(Some(LoadError::Header), load(Box::new([0; 0x100])).err());
+    }
+}
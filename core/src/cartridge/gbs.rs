@@ -0,0 +1,321 @@
+//! GBS (Game Boy Sound System) files.
+//!
+//! A GBS file holds the music code & data ripped from a game, along with the
+//! addresses of its init and play routines. `GbsCartridge` maps the data into
+//! a ROM, next to a small driver that calls the init routine with the song
+//! number, then the play routine from the VBlank or the timer interrupt:
+//!
+//! ```text
+//! 0x0000 - 0x003f  RST vectors (jump to the load address + vector)
+//! 0x0040           VBlank interrupt: CALL play; RETI
+//! 0x0050           Timer interrupt: CALL play; RETI
+//! 0x0100           DI; LD SP,sp; CALL init; (TMA, TAC & IE setup); EI
+//!                  HALT loop
+//! ```
+//!
+//! Songs are started with `GameBoy::play_song`.
+use crate::{
+    cartridge::Cartridge,
+    device::Device,
+    error::{Error, ReadError, WriteError},
+    gb::GameBoy,
+    ppu::LCD,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+const HEADER_LEN: usize = 0x70;
+
+// lowest load address (the driver goes below it)
+const DRIVER_LEN: u16 = 0x400;
+
+/// Errors parsing a GBS file.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum GbsError {
+    #[error("Not a GBS file")]
+    Magic,
+
+    #[error("Unsupported GBS version {0}")]
+    Version(u8),
+
+    #[error("Load address {0:04X} overlaps the player (must be 0400 or above)")]
+    LoadAddress(u16),
+
+    #[error("No songs in GBS file")]
+    NoSongs,
+}
+
+/// Header of a GBS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GbsHeader {
+    /// Number of songs.
+    pub songs: u8,
+    /// Song to play first (starting at 1).
+    pub first_song: u8,
+    /// Address of the code & data.
+    pub load: u16,
+    /// Address of the routine that starts a song (song number in A, starting
+    /// at 0).
+    pub init: u16,
+    /// Address of the routine called at the rate of the interrupt.
+    pub play: u16,
+    /// Initial stack pointer.
+    pub sp: u16,
+    /// Timer modulo & control. If bit 2 of the control is set, the play
+    /// routine is called from the timer interrupt (VBlank otherwise).
+    pub tma: u8,
+    pub tac: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    /// Parse the header of a GBS file.
+    pub fn parse(file: &[u8]) -> Result<Self, GbsError> {
+        if file.len() < HEADER_LEN || &file[..3] != b"GBS" {
+            return Err(GbsError::Magic);
+        }
+        if file[3] != 1 {
+            return Err(GbsError::Version(file[3]));
+        }
+        let word = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+        let text = |offset: usize| {
+            let text = &file[offset..offset + 0x20];
+            let len = text.iter().position(|c| *c == 0).unwrap_or(text.len());
+            String::from_utf8_lossy(&text[..len]).into_owned()
+        };
+        let header = Self {
+            songs: file[0x04],
+            first_song: file[0x05].max(1),
+            load: word(0x06),
+            init: word(0x08),
+            play: word(0x0a),
+            sp: word(0x0c),
+            tma: file[0x0e],
+            tac: file[0x0f],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+        };
+        if header.songs == 0 {
+            return Err(GbsError::NoSongs);
+        }
+        if header.load < DRIVER_LEN {
+            return Err(GbsError::LoadAddress(header.load));
+        }
+        Ok(header)
+    }
+
+    /// Returns true if the play routine is called from the timer interrupt.
+    pub fn timer(&self) -> bool {
+        self.tac & 0x04 != 0
+    }
+}
+
+/// Returns true if the file starts like a GBS file.
+pub fn is_gbs(file: &[u8]) -> bool {
+    file.starts_with(b"GBS")
+}
+
+/// Cartridge with the contents of a GBS file.
+///
+/// Writes to 0x2000-0x3fff select the ROM bank mapped at 0x4000-0x7fff (as in
+/// MBC1), and there are 8KiB of RAM at 0xa000-0xbfff.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GbsCartridge {
+    header: GbsHeader,
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    rom_bank: usize,
+}
+
+// the ROM is not part of the state
+crate::state::impl_state!(GbsCartridge { ram, rom_bank });
+
+impl GbsCartridge {
+    pub fn new(file: &[u8]) -> Result<Self, GbsError> {
+        let header = GbsHeader::parse(file)?;
+        let data = &file[HEADER_LEN..];
+        let len = (header.load as usize + data.len()).max(0x8000);
+        let mut rom = vec![0xff; (len + 0x3fff) & !0x3fff];
+        rom[header.load as usize..][..data.len()].copy_from_slice(data);
+        driver(&header, &mut rom);
+        Ok(Self {
+            header,
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; 0x2000].into_boxed_slice(),
+            rom_bank: 1,
+        })
+    }
+
+    pub fn header(&self) -> &GbsHeader {
+        &self.header
+    }
+
+    fn banks(&self) -> usize {
+        self.rom.len() / 0x4000
+    }
+}
+
+// Write the driver below the load address.
+fn driver(header: &GbsHeader, rom: &mut [u8]) {
+    let [init_lo, init_hi] = header.init.to_le_bytes();
+    let [play_lo, play_hi] = header.play.to_le_bytes();
+    let [sp_lo, sp_hi] = header.sp.to_le_bytes();
+    // RST vectors
+    for vector in (0x00..0x40).step_by(8) {
+        let [lo, hi] = (header.load + vector as u16).to_le_bytes();
+        rom[vector..vector + 3].copy_from_slice(&[0xc3, lo, hi]); // JP load+vector
+    }
+    // interrupt vectors
+    for vector in [0x40, 0x48, 0x50, 0x58, 0x60] {
+        rom[vector] = 0xd9; // RETI
+    }
+    let play = [0xcd, play_lo, play_hi, 0xd9]; // CALL play; RETI
+    let ie = if header.timer() {
+        rom[0x50..0x54].copy_from_slice(&play);
+        0x04
+    } else {
+        rom[0x40..0x44].copy_from_slice(&play);
+        0x01
+    };
+    #[rustfmt::skip]
+    let main = [
+        0xf3,                       // DI
+        0x31, sp_lo, sp_hi,         // LD SP,sp
+        0xcd, init_lo, init_hi,     // CALL init (song in A)
+        0x3e, header.tma,           // LD A,tma
+        0xe0, 0x06,                 // LDH (TMA),A
+        0x3e, header.tac,           // LD A,tac
+        0xe0, 0x07,                 // LDH (TAC),A
+        0x3e, ie,                   // LD A,ie
+        0xe0, 0xff,                 // LDH (IE),A
+        0xaf,                       // XOR A
+        0xe0, 0x0f,                 // LDH (IF),A
+        0xfb,                       // EI
+        0x76,                       // HALT
+        0x18, 0xfd,                 // JR -3
+    ];
+    rom[0x100..0x100 + main.len()].copy_from_slice(&main);
+}
+
+impl Cartridge for GbsCartridge {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 1;
+    }
+}
+
+impl Device for GbsCartridge {
+    fn read(&self, address: u16) -> Result<u8, ReadError> {
+        dev_read! {
+            address {
+                0x0000..=0x3fff => Ok(self.rom[address as usize]),
+                0x4000..=0x7fff => Ok(self.rom[0x4000 * self.rom_bank + address as usize - 0x4000]),
+                0xa000..=0xbfff => Ok(self.ram[address as usize - 0xa000]),
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        dev_write! {
+            address, data {
+                0x2000..=0x3fff => self.rom_bank = (data as usize % self.banks()).max(1),
+                // RAM enable & other MBC registers
+                0x0000..=0x1fff | 0x4000..=0x7fff => {}
+                0xa000..=0xbfff => self.ram[address as usize - 0xa000] = data,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: Cartridge, O: LCD> GameBoy<C, O> {
+    /// Start playing a song of the GBS file in the cartridge (starting at 0).
+    ///
+    /// The system is reset (skipping the boot sequence), and the driver calls
+    /// the init routine with the song number. Outputs are preserved.
+    pub fn play_song(&mut self, song: u8) -> Result<(), Error> {
+        self.reset();
+        self.boot()?;
+        self.soc_mut().cpu_mut().registers_mut().a = song;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GbsCartridge, GbsError, GbsHeader};
+    use crate::{device::Device, gb::GameBoy};
+
+    // GBS file that counts the calls to play at 0xc000, with the song
+    // number at 0xc001
+    fn gbs(tac: u8) -> Vec<u8> {
+        let mut file = vec![0; 0x70];
+        file[..4].copy_from_slice(b"GBS\x01");
+        file[0x04] = 3;
+        file[0x05] = 1;
+        file[0x06..0x0e].copy_from_slice(&[0x00, 0x04, 0x00, 0x04, 0x08, 0x04, 0xfe, 0xff]);
+        file[0x0e] = 0x00;
+        file[0x0f] = tac;
+        file[0x10..0x14].copy_from_slice(b"Test");
+        #[rustfmt::skip]
+        file.extend([
+            // init: LD ($C001),A; XOR A; LD ($C000),A; RET
+            0xea, 0x01, 0xc0, 0xaf, 0xea, 0x00, 0xc0, 0xc9,
+            // play: LD HL,$C000; INC (HL); RET
+            0x21, 0x00, 0xc0, 0x34, 0xc9,
+        ]);
+        file
+    }
+
+    #[test]
+    fn header() {
+        let header = GbsHeader::parse(&gbs(0)).unwrap();
+        assert_eq!((3, 1), (header.songs, header.first_song));
+        assert_eq!(
+            (0x400, 0x400, 0x408),
+            (header.load, header.init, header.play)
+        );
+        assert_eq!(0xfffe, header.sp);
+        assert_eq!("Test", header.title);
+        assert!(!header.timer());
+        assert_eq!(Err(GbsError::Magic), GbsHeader::parse(b"GBZ"));
+        let mut file = gbs(0);
+        file[0x07] = 0x02;
+        assert_eq!(Err(GbsError::LoadAddress(0x200)), GbsHeader::parse(&file));
+    }
+
+    #[test]
+    fn play() {
+        // VBlank (~60Hz) and timer (4096Hz / 256 = 16Hz) driven songs
+        for (tac, calls) in [(0x00, 59..=60), (0x04, 15..=16)] {
+            let cartridge = GbsCartridge::new(&gbs(tac)).unwrap();
+            let mut gb = GameBoy::new(cartridge, ());
+            gb.play_song(2).unwrap();
+            for _ in 0..60 {
+                gb.next_frame().unwrap();
+            }
+            assert_eq!(Ok(2), gb.soc().read(0xc001));
+            assert!(calls.contains(&gb.soc().read(0xc000).unwrap()));
+        }
+    }
+}
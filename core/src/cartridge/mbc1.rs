@@ -1,7 +1,8 @@
 use crate::{
     cartridge::{decode_ram_banks, Cartridge},
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
+    state::State,
 };
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,19 @@ enum Mode {
     Ram,
 }
 
+impl State for Mode {
+    fn save(&self, buf: &mut Vec<u8>) {
+        matches!(self, Mode::Ram).save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut ram = false;
+        ram.load(buf)?;
+        *self = if ram { Mode::Ram } else { Mode::Rom };
+        Ok(())
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug)]
 pub struct MBC1 {
@@ -24,6 +38,15 @@ pub struct MBC1 {
     mode: Mode,
 }
 
+// the ROM is not part of the state
+crate::state::impl_state!(MBC1 {
+    ram,
+    rom_bank,
+    ram_bank,
+    ram_enable,
+    mode
+});
+
 impl MBC1 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = decode_ram_banks(rom[0x149]);
@@ -47,7 +70,34 @@ impl MBC1 {
     }
 }
 
-impl Cartridge for MBC1 {}
+impl Cartridge for MBC1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 0;
+        self.ram_bank = 0;
+        self.ram_enable = false;
+        self.mode = Mode::Rom;
+    }
+}
 
 impl Device for MBC1 {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
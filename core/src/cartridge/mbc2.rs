@@ -11,6 +11,13 @@ pub struct MBC2 {
     ram_enabled: bool,
 }
 
+// the ROM is not part of the state
+crate::state::impl_state!(MBC2 {
+    ram,
+    rom_bank,
+    ram_enabled
+});
+
 impl MBC2 {
     pub fn new(rom: Box<[u8]>) -> Self {
         Self {
@@ -26,7 +33,28 @@ impl MBC2 {
     }
 }
 
-impl Cartridge for MBC2 {}
+impl Cartridge for MBC2 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 0;
+        self.ram_enabled = false;
+    }
+}
 
 impl Device for MBC2 {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
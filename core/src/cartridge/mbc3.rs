@@ -1,7 +1,8 @@
 use crate::{
     cartridge::{decode_ram_banks, Cartridge},
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
+    state::State,
 };
 use log::info;
 #[cfg(feature = "serde")]
@@ -13,6 +14,19 @@ enum Mode {
     Rtc,
 }
 
+impl State for Mode {
+    fn save(&self, buf: &mut Vec<u8>) {
+        matches!(self, Mode::Rtc).save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut rtc = false;
+        rtc.load(buf)?;
+        *self = if rtc { Mode::Rtc } else { Mode::Ram };
+        Ok(())
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MBC3 {
     rom: Box<[u8]>,
@@ -34,6 +48,17 @@ pub struct MBC3 {
     mode: Mode,
 }
 
+// the ROM is not part of the state
+crate::state::impl_state!(MBC3 {
+    ram,
+    rtc,
+    rtc_select,
+    rom_bank,
+    ram_bank,
+    ram_timer_enabled,
+    mode
+});
+
 impl MBC3 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = decode_ram_banks(rom[0x149]);
@@ -59,7 +84,43 @@ impl MBC3 {
     }
 }
 
-impl Cartridge for MBC3 {}
+impl Cartridge for MBC3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn rtc(&self) -> Option<[u8; 5]> {
+        Some(self.rtc)
+    }
+
+    fn set_rtc(&mut self, rtc: [u8; 5]) {
+        self.rtc = rtc;
+    }
+
+    fn reset(&mut self) {
+        self.rtc_select = 0;
+        self.rom_bank = 0;
+        self.ram_bank = 0;
+        self.ram_timer_enabled = false;
+        self.mode = Mode::Ram;
+    }
+}
 
 impl Device for MBC3 {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
@@ -16,6 +16,14 @@ pub struct MBC5 {
     ram_enabled: bool,
 }
 
+// the ROM is not part of the state
+crate::state::impl_state!(MBC5 {
+    rom_bank,
+    ram,
+    ram_bank,
+    ram_enabled
+});
+
 impl MBC5 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = decode_ram_banks(rom[0x149]);
@@ -37,7 +45,33 @@ impl MBC5 {
     }
 }
 
-impl Cartridge for MBC5 {}
+impl Cartridge for MBC5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 0;
+        self.ram_bank = 0;
+        self.ram_enabled = true;
+    }
+}
 
 impl Device for MBC5 {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
@@ -0,0 +1,409 @@
+//! Cheat codes.
+//!
+//! Two kinds of codes are supported:
+//!
+//! - Game Genie codes (`ABC-DEF-GHI` or `ABC-DEF`) patch the cartridge ROM.
+//!   When the optional compare byte is present, the patch only applies if the
+//!   original byte matches (this is how codes target a particular ROM bank).
+//! - GameShark codes (`ttvvaaaa`) write `vv` to the address `aaaa` (little
+//!   endian) on every frame. `tt` selects the RAM bank (see `Code::GameShark`).
+//!
+//! Cheats are a named group of codes and they can be enabled individually.
+//! They are stored in a text file (`.cht`), alongside the hash of the ROM they
+//! are meant for:
+//!
+//! ```text
+//! rom = 90DA0DB7
+//! on Infinite lives = 01FF10C0
+//! off Start on level 4 = 004-0AA-C49 014-0BA-3BE
+//! ```
+use crate::utils;
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+/// Cheat errors.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The code is not a valid Game Genie or GameShark code.
+    #[error("Invalid code: {0}")]
+    InvalidCode(String),
+
+    /// Cheat names can't be empty or contain `=`.
+    #[error("Invalid cheat name: {0}")]
+    InvalidName(String),
+
+    /// Syntax error in a cheat file.
+    #[error("Invalid cheat file (line {0})")]
+    InvalidFile(usize),
+
+    /// The cheats are meant for a different ROM.
+    #[error("ROM hash mismatch (expected {expected:08X}, found {found:08X})")]
+    Rom { expected: u32, found: u32 },
+}
+
+/// A single cheat code.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Code {
+    /// ROM patch. Reads from `address` return `data` (if the original byte
+    /// matches `compare`).
+    GameGenie {
+        address: u16,
+        data: u8,
+        compare: Option<u8>,
+    },
+
+    /// RAM write, applied on every VBlank.
+    ///
+    /// Codes with a `bank` of `0x01` write through the memory bus (to the
+    /// currently mapped bank). Codes with bank `0x8X` or `0x9X` write to the
+    /// work RAM bank `X` (CGB), and any other value selects the bank of the
+    /// cartridge RAM.
+    GameShark { bank: u8, address: u16, data: u8 },
+}
+
+impl Code {
+    /// Parse a Game Genie or GameShark code.
+    pub fn parse(code: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidCode(code.to_string());
+        let nibbles = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let byte = |i: usize| (nibbles[i] << 4) | nibbles[i + 1];
+        let dashes = code.matches('-').count();
+        match (nibbles.len(), dashes) {
+            (6, 1) | (9, 2) => {
+                let [a, b, c, d, e, f] = [0, 1, 2, 3, 4, 5].map(|i| nibbles[i] as u16);
+                let compare = if nibbles.len() == 9 {
+                    let gi = (nibbles[6] << 4) | nibbles[8];
+                    Some(gi.rotate_right(2) ^ 0xba)
+                } else {
+                    None
+                };
+                Ok(Self::GameGenie {
+                    address: (((f ^ 0xf) << 12) | (c << 8) | (d << 4) | e),
+                    data: ((a << 4) | b) as u8,
+                    compare,
+                })
+            }
+            (8, 0) => Ok(Self::GameShark {
+                bank: byte(0),
+                data: byte(2),
+                address: u16::from_le_bytes([byte(4), byte(6)]),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A named group of codes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cheat {
+    name: String,
+    // the codes as they were entered
+    source: String,
+    codes: Vec<Code>,
+    enabled: bool,
+}
+
+impl Cheat {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "{state} {} = {}", self.name, self.source)
+    }
+}
+
+/// The cheats of a ROM.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Cheats {
+    rom: u32,
+    cheats: Vec<Cheat>,
+    // enabled Game Genie codes (address, data, compare)
+    patches: Vec<(u16, u8, Option<u8>)>,
+}
+
+impl Cheats {
+    /// Create an empty list of cheats for the ROM with the given hash.
+    pub fn new(rom: u32) -> Self {
+        Self {
+            rom,
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty list of cheats for the given ROM.
+    pub fn for_rom(rom: &[u8]) -> Self {
+        Self::new(utils::crc32(rom))
+    }
+
+    /// Returns the hash of the ROM the cheats are meant for.
+    pub fn rom_hash(&self) -> u32 {
+        self.rom
+    }
+
+    /// Add a cheat (enabled), replacing any cheat with the same name.
+    /// Codes are separated by whitespace or commas.
+    pub fn add(&mut self, name: &str, codes: &str) -> Result<(), Error> {
+        let name = name.trim();
+        if name.is_empty() || name.contains('=') {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        let parsed = codes
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|code| !code.is_empty())
+            .map(Code::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if parsed.is_empty() {
+            return Err(Error::InvalidCode(codes.to_string()));
+        }
+        let cheat = Cheat {
+            name: name.to_string(),
+            source: parsed_source(codes),
+            codes: parsed,
+            enabled: true,
+        };
+        match self.cheats.iter_mut().find(|c| c.name == cheat.name) {
+            Some(c) => *c = cheat,
+            None => self.cheats.push(cheat),
+        }
+        self.update_patches();
+        Ok(())
+    }
+
+    /// Remove a cheat. Returns false if there is no cheat with that name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|c| c.name != name);
+        self.update_patches();
+        len != self.cheats.len()
+    }
+
+    /// Enable or disable a cheat. Returns false if there is no cheat with
+    /// that name.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let found = match self.cheats.iter_mut().find(|c| c.name == name) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        };
+        self.update_patches();
+        found
+    }
+
+    /// Toggle a cheat. Returns whether it is now enabled, or `None` if there
+    /// is no cheat with that name.
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let enabled = !self.get(name)?.enabled;
+        self.set_enabled(name, enabled);
+        Some(enabled)
+    }
+
+    /// Returns the cheat with the given name.
+    pub fn get(&self, name: &str) -> Option<&Cheat> {
+        self.cheats.iter().find(|c| c.name == name)
+    }
+
+    /// Returns an iterator over all the cheats.
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    /// Read cheats from a `.cht` file.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut cheats: Option<Cheats> = None;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidFile(i + 1);
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let key = key.trim();
+            match (&mut cheats, key.split_once(' ')) {
+                (None, _) if key == "rom" => {
+                    let rom = u32::from_str_radix(value.trim(), 16).map_err(|_| invalid())?;
+                    cheats = Some(Self::new(rom));
+                }
+                (Some(cheats), Some((state @ ("on" | "off"), name))) => {
+                    cheats.add(name, value)?;
+                    cheats.set_enabled(name.trim(), state == "on");
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        cheats.ok_or(Error::InvalidFile(0))
+    }
+
+    /// Write the cheats in the `.cht` format.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "rom = {:08X}", self.rom)?;
+        for cheat in &self.cheats {
+            writeln!(writer, "{cheat}")?;
+        }
+        Ok(())
+    }
+
+    fn update_patches(&mut self) {
+        self.patches.clear();
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            for code in &cheat.codes {
+                if let Code::GameGenie {
+                    address,
+                    data,
+                    compare,
+                } = *code
+                {
+                    self.patches.push((address, data, compare));
+                }
+            }
+        }
+    }
+
+    // Patch a byte read from the cartridge ROM.
+    pub(crate) fn patch(&self, address: u16, data: u8) -> u8 {
+        for (addr, patch, compare) in &self.patches {
+            if *addr == address && compare.map_or(true, |c| c == data) {
+                return *patch;
+            }
+        }
+        data
+    }
+
+    // Enabled GameShark codes (bank, address, data).
+    pub(crate) fn writes(&self) -> impl Iterator<Item = (u8, u16, u8)> + '_ {
+        self.cheats
+            .iter()
+            .filter(|c| c.enabled)
+            .flat_map(|c| c.codes.iter())
+            .filter_map(|code| match *code {
+                Code::GameShark {
+                    bank,
+                    address,
+                    data,
+                } => Some((bank, address, data)),
+                _ => None,
+            })
+    }
+}
+
+fn parsed_source(codes: &str) -> String {
+    codes
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|code| !code.is_empty())
+        .map(|code| code.to_uppercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cheats, Code, Error};
+    use crate::{cartridge::ROM, device::Device, gb::GameBoy};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Code::GameShark {
+                bank: 0x01,
+                address: 0xc010,
+                data: 0xff
+            },
+            Code::parse("01FF10C0").unwrap()
+        );
+        assert_eq!(
+            Code::GameGenie {
+                address: 0x4a17,
+                data: 0x00,
+                compare: Some(0xc8)
+            },
+            Code::parse("00A-17B-C49").unwrap()
+        );
+        assert_eq!(
+            Code::GameGenie {
+                address: 0x0a17,
+                data: 0x3e,
+                compare: None
+            },
+            Code::parse("3ea-17f").unwrap()
+        );
+        assert!(Code::parse("01FF10C").is_err());
+        assert!(Code::parse("00A-17B-C4").is_err());
+        assert!(Code::parse("01FF10CX").is_err());
+    }
+
+    #[test]
+    fn patch() {
+        let mut cheats = Cheats::new(0);
+        cheats.add("foo", "00A-17B-C49, 3EA-17F").unwrap();
+        assert_eq!(0x00, cheats.patch(0x4a17, 0xc8));
+        assert_eq!(0x12, cheats.patch(0x4a17, 0x12));
+        assert_eq!(0x3e, cheats.patch(0x0a17, 0x12));
+        assert_eq!(Some(false), cheats.toggle("foo"));
+        assert_eq!(0xc8, cheats.patch(0x4a17, 0xc8));
+        assert_eq!(None, cheats.toggle("bar"));
+    }
+
+    #[test]
+    fn apply() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]); // JR -2
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let mut cheats = Cheats::for_rom(&[]);
+        cheats.add("foo", "01FF10C0").unwrap();
+        assert!(matches!(gb.set_cheats(cheats), Err(Error::Rom { .. })));
+
+        let cheats = gb.cheats_mut();
+        cheats.add("foo", "01AB10C0 004210A0 025510A0").unwrap();
+        cheats.add("bar", "3EA-17F").unwrap();
+        assert_eq!(0x3e, gb.soc().read(0x0a17).unwrap());
+        gb.next_frame().unwrap();
+        assert_eq!(0xab, gb.soc().read(0xc010).unwrap());
+        // cartridge RAM bank 2 doesn't exist (the write is ignored)
+        assert_eq!(0x42, gb.soc().read(0xa010).unwrap());
+    }
+
+    #[test]
+    fn file() {
+        let mut cheats = Cheats::new(0x1234abcd);
+        cheats.add("Infinite lives", "01ff10c0").unwrap();
+        cheats.add("Level 4", "004-0AA-C49 014-0BA-3BE").unwrap();
+        cheats.set_enabled("Level 4", false);
+
+        let mut file = Vec::new();
+        cheats.write(&mut file).unwrap();
+        assert_eq!(
+            "rom = 1234ABCD\n\
+             on Infinite lives = 01FF10C0\n\
+             off Level 4 = 004-0AA-C49 014-0BA-3BE\n",
+            String::from_utf8_lossy(&file)
+        );
+        assert_eq!(cheats, Cheats::read(&file[..]).unwrap());
+        assert!(Cheats::read(&b"on foo = 01ff10c0"[..]).is_err());
+    }
+}
@@ -1,4 +1,9 @@
 use crate::{
+    debug::{
+        profile::Profiler,
+        stack::{CallKind, CallStack, Frame},
+        Address,
+    },
     device::{Device, MemoryBus},
     error::Error,
 };
@@ -16,8 +21,19 @@ pub struct CPU {
     registers: Registers,
     ime: bool,
     halt: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    call_stack: CallStack,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    profiler: Option<Box<Profiler>>,
 }
 
+// The call stack is saved separately, at the end of the state.
+crate::state::impl_state!(CPU {
+    registers,
+    ime,
+    halt
+});
+
 impl CPU {
     /// Returns the current state of the CPU registers.
     pub fn registers(&self) -> &Registers {
@@ -31,6 +47,29 @@ impl CPU {
         &mut self.registers
     }
 
+    /// Returns the shadow call stack.
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    pub(crate) fn call_stack_mut(&mut self) -> &mut CallStack {
+        &mut self.call_stack
+    }
+
+    /// Set the cycle profiler. Returns the previous one.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler.map(Box::new)).map(|p| *p)
+    }
+
+    /// Returns the cycle profiler.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    pub(crate) fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_deref_mut()
+    }
+
     /// Returns true id the CPU is currently halted, or false otherwise.
     /// If halted, the CPU will remain so until the next interrupt is
     /// acknowledged.
@@ -47,6 +86,13 @@ impl CPU {
 
     pub(super) fn update<D: MemoryBus>(&mut self, memory: &mut D) -> Result<u64, Error> {
         let int = self.int(memory)?;
+        // after an interrupt dispatch, the PC is the interrupt vector
+        let pc = self.registers.pc;
+        let halted = int == 0 && self.halt;
+        // the call stack before the instruction calls or returns
+        if let Some(profiler) = &mut self.profiler {
+            profiler.set_stack(self.call_stack.frames());
+        }
         let cycles = if int != 0 {
             int
         } else if !self.halt {
@@ -54,12 +100,20 @@ impl CPU {
         } else {
             4
         };
+        if let Some(profiler) = &mut self.profiler {
+            let pc = Address {
+                bank: memory.bank(pc),
+                address: pc,
+            };
+            profiler.record(pc, cycles, halted);
+        }
         Ok(cycles)
     }
 
     fn int<D: MemoryBus>(&mut self, memory: &mut D) -> Result<u64, Error> {
-        let ie = <D as MemoryBus>::read(memory, 0xffff)?;
-        let if_ = <D as MemoryBus>::read(memory, 0xff0f)?;
+        // polled on every step, so they bypass the bus (and the watchpoints)
+        let ie = <D as Device>::read(memory, 0xffff)?;
+        let if_ = <D as Device>::read(memory, 0xff0f)?;
         let tr = (ie & if_).trailing_zeros() as u8;
         if tr <= 4 {
             self.halt = false;
@@ -75,9 +129,8 @@ impl CPU {
     }
 
     fn int_v<D: MemoryBus>(&mut self, v: u16, memory: &mut D) -> Result<(), Error> {
-        self.stack_push(self.registers.pc, memory)?;
-        self.registers.pc = v;
-        Ok(())
+        let pc = self.registers.pc;
+        self.call(CallKind::Interrupt, pc, v, memory)
     }
 
     fn exec_opcode_cb<D: MemoryBus>(&mut self, memory: &mut D, opcode: u8) -> Result<u64, Error> {
@@ -821,7 +874,7 @@ impl CPU {
             0xbf => self.cp_n(self.registers.a),
             0xc0 => {
                 if !flag!(self.registers, Z) {
-                    self.registers.pc = self.stack_pop(memory)?;
+                    self.ret(memory)?;
                     branch = true;
                 }
             }
@@ -840,11 +893,11 @@ impl CPU {
             0xc7 => self.rst_n(0x00, memory)?,
             0xc8 => {
                 if flag!(self.registers, Z) {
-                    self.registers.pc = self.stack_pop(memory)?;
+                    self.ret(memory)?;
                     branch = true;
                 }
             }
-            0xc9 => self.registers.pc = self.stack_pop(memory)?,
+            0xc9 => self.ret(memory)?,
             0xca => branch = self.jp_c_n(flag!(self.registers, Z), memory)?,
             0xcb => {
                 let opcode = self.fetch(memory)?;
@@ -859,7 +912,7 @@ impl CPU {
             0xcf => self.rst_n(0x08, memory)?,
             0xd0 => {
                 if !flag!(self.registers, C) {
-                    self.registers.pc = self.stack_pop(memory)?;
+                    self.ret(memory)?;
                     branch = true;
                 }
             }
@@ -878,13 +931,13 @@ impl CPU {
             0xd7 => self.rst_n(0x10, memory)?,
             0xd8 => {
                 if flag!(self.registers, C) {
-                    self.registers.pc = self.stack_pop(memory)?;
+                    self.ret(memory)?;
                     branch = true;
                 }
             }
             0xd9 => {
                 self.ime = true;
-                self.registers.pc = self.stack_pop(memory)?;
+                self.ret(memory)?;
             }
             0xda => branch = self.jp_c_n(flag!(self.registers, C), memory)?,
             0xdb => return Err(Error::UnknownOp(opcode)),
@@ -988,15 +1041,13 @@ impl CPU {
     }
 
     fn exec<D: MemoryBus>(&mut self, memory: &mut D) -> Result<u64, Error> {
-        // let pc = self.registers.pc;
-        // let op = <D as crate::device::Device>::read(device, pc).unwrap();
-        // println!("{pc:04X} {op:02X}");
+        memory.instruction(&self.registers);
         let opcode = self.fetch(memory)?;
         self.exec_opcode(memory, opcode)
     }
 
     fn fetch<D: MemoryBus>(&mut self, device: &D) -> Result<u8, Error> {
-        let opcode = <D as MemoryBus>::read(device, self.registers.pc)?;
+        let opcode = <D as MemoryBus>::fetch(device, self.registers.pc)?;
         // TODO(german) remove this once Shantae! emulation is fixed
         if self.registers.pc == 0xffff {
             return Err(Error::ProgramCounterOverflow);
@@ -1006,8 +1057,8 @@ impl CPU {
     }
 
     fn fetch_word<D: MemoryBus>(&mut self, device: &D) -> Result<u16, Error> {
-        let lo = <D as MemoryBus>::read(device, self.registers.pc)? as u16;
-        let hi = <D as MemoryBus>::read(device, self.registers.pc + 1)? as u16;
+        let lo = <D as MemoryBus>::fetch(device, self.registers.pc)? as u16;
+        let hi = <D as MemoryBus>::fetch(device, self.registers.pc + 1)? as u16;
         self.registers.pc += 2;
         Ok((hi << 8) | lo)
     }
@@ -1029,6 +1080,43 @@ impl CPU {
         Ok(())
     }
 
+    // Pushes the return address (PC) and jumps to the called address.
+    // The call is recorded in the shadow call stack.
+    fn call<D: MemoryBus>(
+        &mut self,
+        kind: CallKind,
+        from: u16,
+        to: u16,
+        memory: &mut D,
+    ) -> Result<(), Error> {
+        let ret = self.registers.pc;
+        self.stack_push(ret, memory)?;
+        self.registers.pc = to;
+        self.call_stack.push(Frame {
+            kind,
+            from: Address {
+                bank: memory.bank(from),
+                address: from,
+            },
+            to: Address {
+                bank: memory.bank(to),
+                address: to,
+            },
+            ret,
+            sp: self.registers.sp,
+        });
+        Ok(())
+    }
+
+    // Pops the return address into PC.
+    // The return is recorded in the shadow call stack.
+    fn ret<D: MemoryBus>(&mut self, memory: &D) -> Result<(), Error> {
+        let sp = self.registers.sp;
+        self.registers.pc = self.stack_pop(memory)?;
+        self.call_stack.ret(sp, self.registers.pc);
+        Ok(())
+    }
+
     // Pops word from the stack
     // Increments SP by 2
     fn stack_pop<D: MemoryBus>(&mut self, device: &D) -> Result<u16, Error> {
@@ -1337,9 +1425,8 @@ impl CPU {
     // Jump to address $000 + n
     // n = 00,$08,$10,$18,$20,$28,$30,$38
     fn rst_n<D: MemoryBus>(&mut self, n: u8, device: &mut D) -> Result<(), Error> {
-        self.stack_push(self.registers.pc, device)?;
-        self.registers.pc = n as u16;
-        Ok(())
+        let from = self.registers.pc.wrapping_sub(1);
+        self.call(CallKind::Rst, from, n as u16, device)
     }
 
     // Call Address n if following condition is true:
@@ -1350,8 +1437,8 @@ impl CPU {
     fn call_c_n<D: MemoryBus>(&mut self, branch: bool, device: &mut D) -> Result<bool, Error> {
         let n = self.fetch_word(device)?;
         if branch {
-            self.stack_push(self.registers.pc, device)?;
-            self.registers.pc = n;
+            let from = self.registers.pc.wrapping_sub(3);
+            self.call(CallKind::Call, from, n, device)?;
         }
         Ok(branch)
     }
@@ -1359,9 +1446,8 @@ impl CPU {
     // Push address of next instruction onto the stack and then jump to address n.
     fn call_n<D: MemoryBus>(&mut self, device: &mut D) -> Result<(), Error> {
         let n = self.fetch_word(device)?;
-        self.stack_push(self.registers.pc, device)?;
-        self.registers.pc = n;
-        Ok(())
+        let from = self.registers.pc.wrapping_sub(3);
+        self.call(CallKind::Call, from, n, device)
     }
 
     // Jump to address n if following condition is true:
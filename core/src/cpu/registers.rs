@@ -16,6 +16,19 @@ pub struct Registers {
     pub sp: u16,
 }
 
+crate::state::impl_state!(Registers {
+    a,
+    f,
+    b,
+    c,
+    d,
+    e,
+    h,
+    l,
+    pc,
+    sp
+});
+
 // Internal macro to check the state of the flags register.
 #[rustfmt::skip]
 macro_rules! flag {
@@ -1,4 +1,16 @@
 use crate::{cartridge::Cartridge, device::Device, ppu::LCD, LR35902};
+use expr::Expr;
+use std::{
+    cell::{Ref, RefCell},
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+};
+use utils::symbols::Symbols;
+
+pub mod expr;
+pub mod profile;
+pub mod stack;
 
 pub trait Breakpoint {
     /// Called right before stepping the emulation.
@@ -7,13 +19,13 @@ pub trait Breakpoint {
     /// Called right after stepping the emulation, to determine if the
     /// breakpoint has been hit. Return true if it has been hit, o false
     /// otherwise.
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool;
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool;
 }
 
 impl Breakpoint for () {
     fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {}
 
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
         false
     }
 }
@@ -36,21 +48,132 @@ impl Breakpoint for NextFrame {
         self.stat = soc.read(0xff41).unwrap();
     }
 
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
         let stat = soc.read(0xff41).unwrap();
         (self.stat & 0b11) == 1 && (stat & 0b11) == 2
     }
 }
 
-/// Breakpoint when the PC reached a particular value.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid address \"{0}\"")]
+pub struct AddressError(String);
+
+/// Address, optionally qualified with a bank (`bank:address`).
+///
+/// An address without a bank matches regardless of the mapped bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Address {
+    pub bank: Option<usize>,
+    pub address: u16,
+}
+
+crate::state::impl_state!(Address { bank, address });
+
+impl Address {
+    pub fn new(address: u16) -> Self {
+        Self {
+            bank: None,
+            address,
+        }
+    }
+
+    pub fn banked(bank: usize, address: u16) -> Self {
+        Self {
+            bank: Some(bank),
+            address,
+        }
+    }
+
+    /// Returns the given address qualified with the bank currently mapped at
+    /// it.
+    pub fn mapped(soc: &LR35902<impl Cartridge, impl LCD>, address: u16) -> Self {
+        Self {
+            bank: soc.bank(address),
+            address,
+        }
+    }
+
+    /// Returns true if the address is equal to `address` and, if the address
+    /// has a bank, the bank is the one currently mapped at it.
+    pub fn matches(&self, soc: &LR35902<impl Cartridge, impl LCD>, address: u16) -> bool {
+        self.address == address
+            && self
+                .bank
+                .map_or(true, |bank| soc.bank(address) == Some(bank))
+    }
+
+    /// Parses a label of the symbol table, or an address (see `FromStr`).
+    pub fn parse(s: &str, symbols: &Symbols) -> Result<Self, AddressError> {
+        match symbols.resolve(s.trim()) {
+            Some((bank, address)) => Ok(Self::banked(bank, address)),
+            None => s.parse(),
+        }
+    }
+
+    /// Returns the label at the address. Addresses without a bank are looked
+    /// up in bank 0.
+    pub fn label<'a>(&self, symbols: &'a Symbols) -> Option<&'a str> {
+        symbols.label(self.bank.unwrap_or(0), self.address)
+    }
+
+    /// Returns the address as `function+offset` (see `Symbols::function`), or
+    /// as a plain address if it isn't within a function.
+    pub fn symbolic(&self, symbols: &Symbols) -> String {
+        match symbols.function(self.bank.unwrap_or(0), self.address) {
+            Some((function, 0)) => function.to_string(),
+            Some((function, offset)) => format!("{function}+{offset}"),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl From<u16> for Address {
+    fn from(address: u16) -> Self {
+        Self::new(address)
+    }
+}
+
+/// Parses hexadecimal `address` or `bank:address` (with optional `0x` or `$`
+/// prefixes).
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |s: &str| {
+            let s = s.trim();
+            let s = s
+                .strip_prefix("0x")
+                .or_else(|| s.strip_prefix('$'))
+                .unwrap_or(s);
+            usize::from_str_radix(s, 16).map_err(|_| AddressError(s.to_string()))
+        };
+        let (bank, address) = match s.split_once(':') {
+            Some((bank, address)) => (Some(hex(bank)?), hex(address)?),
+            None => (None, hex(s)?),
+        };
+        let address = u16::try_from(address).map_err(|_| AddressError(s.to_string()))?;
+        Ok(Self { bank, address })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{bank:02X}:{:04X}", self.address),
+            None => write!(f, "{:04X}", self.address),
+        }
+    }
+}
+
+/// Breakpoint when the PC reached a particular (bank-aware) address.
 #[derive(Debug, Clone)]
-pub struct PC(pub u16);
+pub struct PC(pub Address);
 
 impl Breakpoint for PC {
     fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {}
 
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
-        soc.cpu().registers().pc == self.0
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+        self.0.matches(soc, soc.cpu().registers().pc)
     }
 }
 
@@ -72,51 +195,528 @@ impl Breakpoint for LY {
         self.ly_pre = soc.read(0xff44).unwrap();
     }
 
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
         let ly = soc.read(0xff44).unwrap();
         self.ly_pre != self.ly && ly == self.ly
     }
 }
 
-macro_rules! tuple {
-    ($($gen:ident,)*) => {
-        impl<$($gen:Breakpoint,)*> Breakpoint for ($($gen,)*) {
-            fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {
-                let ($($gen,)*) = self;
-                $($gen.init(soc);)*
-            }
+/// Breakpoint hit when either of the two breakpoints is hit.
+impl<A: Breakpoint, B: Breakpoint> Breakpoint for (A, B) {
+    fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {
+        self.0.init(soc);
+        self.1.init(soc);
+    }
 
-            fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
-                let ($($gen,)*) = self;
-                false  $( || $gen.breakpoint(soc))*
-            }
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+        // both are evaluated so that hit counters are updated
+        let a = self.0.breakpoint(soc);
+        let b = self.1.breakpoint(soc);
+        a || b
+    }
+}
+
+impl<B: Breakpoint> Breakpoint for &mut B {
+    fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {
+        (**self).init(soc)
+    }
+
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+        (**self).breakpoint(soc)
+    }
+}
+
+/// Type of memory access.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Both reads and writes (only used to define watchpoints).
+    ReadWrite,
+}
+
+/// Watchpoint on a range of addresses.
+///
+/// Watchpoints are checked on every memory access made by the CPU (except
+/// instruction fetches). Use the `Watch` breakpoint to stop the emulation when
+/// any of them is hit.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+    /// Only trigger when `value & mask == data & mask`, where `data` is the
+    /// value being read or written.
+    pub value: Option<(u8, u8)>,
+    /// Only trigger when this bank is mapped at the accessed address.
+    pub bank: Option<usize>,
+}
+
+impl Watchpoint {
+    /// Watchpoint on reads.
+    pub fn read(range: RangeInclusive<u16>) -> Self {
+        Self {
+            range,
+            access: Access::Read,
+            value: None,
+            bank: None,
         }
     }
+
+    /// Watchpoint on writes.
+    pub fn write(range: RangeInclusive<u16>) -> Self {
+        Self {
+            range,
+            access: Access::Write,
+            value: None,
+            bank: None,
+        }
+    }
+
+    /// Watchpoint on reads & writes.
+    pub fn read_write(range: RangeInclusive<u16>) -> Self {
+        Self {
+            range,
+            access: Access::ReadWrite,
+            value: None,
+            bank: None,
+        }
+    }
+
+    /// Only trigger when the value read or written is `value`.
+    pub fn value(self, value: u8) -> Self {
+        self.masked_value(value, 0xff)
+    }
+
+    /// Only trigger when the bits of the value read or written selected by
+    /// `mask` are equal to the ones in `value`.
+    pub fn masked_value(self, value: u8, mask: u8) -> Self {
+        Self {
+            value: Some((value & mask, mask)),
+            ..self
+        }
+    }
+
+    /// Only trigger when the given bank is mapped at the accessed address.
+    pub fn bank(self, bank: usize) -> Self {
+        Self {
+            bank: Some(bank),
+            ..self
+        }
+    }
+
+    fn matches(&self, access: Access, address: u16, bank: Option<usize>, data: u8) -> bool {
+        (self.access == access || self.access == Access::ReadWrite)
+            && self.range.contains(&address)
+            && self.bank.map_or(true, |b| Some(b) == bank)
+            && self
+                .value
+                .map_or(true, |(value, mask)| data & mask == value)
+    }
+}
+
+/// Watchpoint hit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WatchpointHit {
+    /// Index of the watchpoint (in insertion order).
+    pub index: usize,
+    /// Either `Access::Read` or `Access::Write`.
+    pub access: Access,
+    pub address: u16,
+    /// Bank mapped at the address at the time of the access.
+    pub bank: Option<usize>,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+    /// Value before the access.
+    pub old: u8,
+    /// Value after the access (same as `old` for reads).
+    pub new: u8,
+}
+
+/// Watchpoints of the SOC, and the hits of the last step.
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    // address of the instruction being executed
+    pc: u16,
+    // memory reads go through &self, hence the RefCell
+    hits: RefCell<Vec<WatchpointHit>>,
 }
 
-tuple!(A,);
-tuple!(A, B,);
-tuple!(A, B, C,);
-tuple!(A, B, C, D,);
+impl Watchpoints {
+    /// Add a watchpoint. Returns its index.
+    pub fn add(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    /// Remove the watchpoint with the given index (the indices of the
+    /// watchpoints after it are shifted).
+    pub fn remove(&mut self, index: usize) -> Watchpoint {
+        self.watchpoints.remove(index)
+    }
+
+    /// Remove all the watchpoints.
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+        self.hits.get_mut().clear();
+    }
 
+    /// Returns the watchpoints.
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    /// Returns the watchpoints hit during the last step.
+    pub fn hits(&self) -> Ref<'_, [WatchpointHit]> {
+        Ref::map(self.hits.borrow(), |hits| &hits[..])
+    }
+
+    pub(crate) fn start_step(&mut self, pc: u16) {
+        self.pc = pc;
+        self.hits.get_mut().clear();
+    }
+
+    pub(crate) fn access(
+        &self,
+        access: Access,
+        address: u16,
+        bank: Option<usize>,
+        old: u8,
+        new: u8,
+    ) {
+        let data = if access == Access::Write { new } else { old };
+        let hits = self
+            .watchpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.matches(access, address, bank, data))
+            .map(|(index, _)| WatchpointHit {
+                index,
+                access,
+                address,
+                bank,
+                pc: self.pc,
+                old,
+                new,
+            });
+        self.hits.borrow_mut().extend(hits);
+    }
+}
+
+/// Breakpoint when any watchpoint is hit.
+#[derive(Debug, Clone, Default)]
+pub struct Watch;
+
+impl Breakpoint for Watch {
+    fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {}
+
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+        !soc.watchpoints().hits().is_empty()
+    }
+}
+
+/// Event that triggers a breakpoint of a `BreakpointSet`.
 #[derive(Debug, Clone)]
-pub enum Either<A: Breakpoint, B: Breakpoint> {
-    A(A),
-    B(B),
+pub enum Trigger {
+    /// Triggered on every step (use with a condition).
+    Step,
+    PC(PC),
+    LY(LY),
+    NextFrame(NextFrame),
+    /// Triggered when any watchpoint is hit.
+    Watch(Watch),
 }
 
-impl<A: Breakpoint, B: Breakpoint> Breakpoint for Either<A, B> {
+impl Breakpoint for Trigger {
     fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {
         match self {
-            Either::A(breakpoint) => breakpoint.init(soc),
-            Either::B(breakpoint) => breakpoint.init(soc),
+            Trigger::Step => {}
+            Trigger::PC(breakpoint) => breakpoint.init(soc),
+            Trigger::LY(breakpoint) => breakpoint.init(soc),
+            Trigger::NextFrame(breakpoint) => breakpoint.init(soc),
+            Trigger::Watch(breakpoint) => breakpoint.init(soc),
         }
     }
 
-    fn breakpoint(&self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
         match self {
-            Either::A(breakpoint) => breakpoint.breakpoint(soc),
-            Either::B(breakpoint) => breakpoint.breakpoint(soc),
+            Trigger::Step => true,
+            Trigger::PC(breakpoint) => breakpoint.breakpoint(soc),
+            Trigger::LY(breakpoint) => breakpoint.breakpoint(soc),
+            Trigger::NextFrame(breakpoint) => breakpoint.breakpoint(soc),
+            Trigger::Watch(breakpoint) => breakpoint.breakpoint(soc),
+        }
+    }
+}
+
+/// Breakpoint of a `BreakpointSet`.
+#[derive(Debug, Clone)]
+pub struct BreakpointEntry {
+    pub trigger: Trigger,
+    /// Only hit when the condition evaluates to non-zero.
+    pub condition: Option<Expr>,
+    pub enabled: bool,
+    /// Number of times the trigger fired with the condition met (including
+    /// the ignored ones).
+    pub hits: u64,
+    /// Number of hits to ignore before stopping the emulation.
+    pub ignore: u64,
+}
+
+impl BreakpointEntry {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            condition: None,
+            enabled: true,
+            hits: 0,
+            ignore: 0,
+        }
+    }
+
+    /// Breakpoint when the PC reaches the given address.
+    pub fn pc(pc: impl Into<Address>) -> Self {
+        Self::new(Trigger::PC(PC(pc.into())))
+    }
+
+    /// Breakpoint when the LY register reaches the given value.
+    pub fn ly(ly: u8) -> Self {
+        Self::new(Trigger::LY(LY::new(ly)))
+    }
+
+    /// Breakpoint when the given condition is met, checked after every step.
+    pub fn when(condition: Expr) -> Self {
+        Self::new(Trigger::Step).condition(condition)
+    }
+
+    /// Set the condition of the breakpoint.
+    pub fn condition(self, condition: Expr) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    /// Set the number of hits to ignore.
+    pub fn ignore(self, ignore: u64) -> Self {
+        Self { ignore, ..self }
+    }
+}
+
+/// Dynamic set of breakpoints.
+///
+/// The set is hit when any of its enabled breakpoints is hit (the trigger
+/// fires, the condition holds, and the ignore count has been exhausted).
+#[derive(Debug, Clone, Default)]
+pub struct BreakpointSet {
+    entries: Vec<BreakpointEntry>,
+    hit: Option<usize>,
+}
+
+impl BreakpointSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a breakpoint. Returns its index.
+    pub fn add(&mut self, entry: BreakpointEntry) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    /// Remove the breakpoint with the given index (the indices of the
+    /// breakpoints after it are shifted).
+    pub fn remove(&mut self, index: usize) -> BreakpointEntry {
+        self.hit = None;
+        self.entries.remove(index)
+    }
+
+    /// Remove all the breakpoints.
+    pub fn clear(&mut self) {
+        self.hit = None;
+        self.entries.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&BreakpointEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut BreakpointEntry> {
+        self.entries.get_mut(index)
+    }
+
+    /// Returns the breakpoints.
+    pub fn iter(&self) -> impl Iterator<Item = &BreakpointEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the index of the first breakpoint hit during the last step.
+    pub fn hit(&self) -> Option<usize> {
+        self.hit
+    }
+}
+
+impl Breakpoint for BreakpointSet {
+    fn init(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) {
+        self.hit = None;
+        for entry in self.entries.iter_mut().filter(|e| e.enabled) {
+            entry.trigger.init(soc);
         }
     }
+
+    fn breakpoint(&mut self, soc: &LR35902<impl Cartridge, impl LCD>) -> bool {
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            if !entry.enabled
+                || !entry.trigger.breakpoint(soc)
+                || !entry.condition.as_ref().map_or(true, |c| c.test(soc))
+            {
+                continue;
+            }
+            entry.hits += 1;
+            if entry.hits > entry.ignore && self.hit.is_none() {
+                self.hit = Some(index);
+            }
+        }
+        self.hit.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Access, Address, BreakpointEntry, BreakpointSet, Watch, Watchpoint, WatchpointHit,
+    };
+    use crate::{
+        cartridge::{MBC1, ROM},
+        gb::GameBoy,
+    };
+    use utils::symbols::Symbols;
+
+    #[test]
+    fn watchpoints() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$42; LD ($C010),A; LD A,($C010); JR -2
+        let code = [0x3e, 0x42, 0xea, 0x10, 0xc0, 0xfa, 0x10, 0xc0, 0x18, 0xfe];
+        rom[0x100..0x10a].copy_from_slice(&code);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let soc = gb.soc_mut();
+        soc.watchpoints_mut()
+            .add(Watchpoint::write(0xc000..=0xc0ff).value(0x43));
+        soc.watchpoints_mut()
+            .add(Watchpoint::read_write(0xc010..=0xc010).masked_value(0x40, 0xf0));
+        soc.step_breakpoint(Watch).unwrap();
+        assert_eq!(
+            [WatchpointHit {
+                index: 1,
+                access: Access::Write,
+                address: 0xc010,
+                bank: Some(0),
+                pc: 0x102,
+                old: 0x00,
+                new: 0x42
+            }],
+            &soc.watchpoints().hits()[..]
+        );
+        soc.step_breakpoint(Watch).unwrap();
+        let hit = soc.watchpoints().hits()[0];
+        assert_eq!((Access::Read, 0x105, 0x42), (hit.access, hit.pc, hit.new));
+    }
+
+    #[test]
+    fn breakpoint_set() {
+        let mut rom = vec![0; 0x8000];
+        // INC A; JR -3
+        rom[0x100..0x103].copy_from_slice(&[0x3c, 0x18, 0xfd]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let mut set = BreakpointSet::new();
+        set.add(BreakpointEntry::pc(0x101).ignore(2));
+        set.add(BreakpointEntry::when("a == 0x10".parse().unwrap()));
+        set.add(BreakpointEntry::pc(0x100));
+        set.get_mut(2).unwrap().enabled = false;
+
+        let soc = gb.soc_mut();
+        let a = soc.cpu().registers().a;
+        soc.step_breakpoint(&mut set).unwrap();
+        assert_eq!(Some(0), set.hit());
+        assert_eq!(3, set.get(0).unwrap().hits);
+        assert_eq!(0, set.get(2).unwrap().hits);
+        assert_eq!(a.wrapping_add(3), soc.cpu().registers().a);
+
+        set.get_mut(0).unwrap().enabled = false;
+        soc.cpu_mut().registers_mut().a = 0x0e;
+        soc.step_breakpoint(&mut set).unwrap();
+        assert_eq!(Some(1), set.hit());
+        let r = soc.cpu().registers();
+        assert_eq!((0x10, 0x101), (r.a, r.pc));
+    }
+
+    #[test]
+    fn banked_breakpoint() {
+        assert_eq!(Ok(Address::banked(2, 0x4000)), "02:4000".parse());
+        assert_eq!(Ok(Address::new(0xff44)), "$ff44".parse());
+        assert!("1:10000".parse::<Address>().is_err());
+        assert_eq!("02:4000", Address::banked(2, 0x4000).to_string());
+
+        let mut rom = vec![0; 0x4000 * 4];
+        rom[0x147] = 0x01;
+        // LD A,1; LD ($2000),A; CALL $4000; INC A; JR -9
+        let code = [
+            0x3e, 0x01, 0xea, 0x00, 0x20, 0xcd, 0x00, 0x40, 0x3c, 0x18, 0xf7,
+        ];
+        rom[0x100..0x10b].copy_from_slice(&code);
+        // RET in every bank
+        for bank in 1..4 {
+            rom[bank * 0x4000] = 0xc9;
+        }
+        let mut gb = GameBoy::new(MBC1::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let mut set = BreakpointSet::new();
+        set.add(BreakpointEntry::pc(Address::banked(2, 0x4000)));
+        let soc = gb.soc_mut();
+        soc.watchpoints_mut()
+            .add(Watchpoint::write(0x2000..=0x2000).bank(1));
+        soc.watchpoints_mut()
+            .add(Watchpoint::write(0x2000..=0x2000).bank(0));
+        soc.step_breakpoint(Watch).unwrap();
+        let hits = soc.watchpoints().hits().to_vec();
+        assert_eq!(
+            vec![(1, Some(0))],
+            hits.iter().map(|h| (h.index, h.bank)).collect::<Vec<_>>()
+        );
+
+        soc.step_breakpoint(&mut set).unwrap();
+        assert_eq!(0x4000, soc.cpu().registers().pc);
+        assert_eq!(Some(2), soc.bank(0x4000));
+    }
+
+    #[test]
+    fn address_symbols() {
+        let symbols = Symbols::parse("00:0150 Main\n02:4000 Bank2\n").unwrap();
+        assert_eq!(
+            Ok(Address::banked(2, 0x4000)),
+            Address::parse("Bank2", &symbols)
+        );
+        assert_eq!(Ok(Address::new(0x150)), Address::parse("150", &symbols));
+        assert!(Address::parse("Missing", &symbols).is_err());
+        assert_eq!(Some("Main"), Address::new(0x150).label(&symbols));
+        assert_eq!("Main+3", Address::banked(0, 0x153).symbolic(&symbols));
+        assert_eq!("01:4000", Address::banked(1, 0x4000).symbolic(&symbols));
+    }
 }
@@ -0,0 +1,462 @@
+//! Breakpoint condition expressions.
+//!
+//! Expressions are evaluated against the state of the SOC, with a C-like
+//! syntax:
+//!
+//! - Numbers: `144`, `0x4a2f` or `$4a2f`.
+//! - Registers: `a`, `f`, `b`, `c`, `d`, `e`, `h`, `l`, `af`, `bc`, `de`,
+//!   `hl`, `sp` and `pc`.
+//! - Flags: `zf`, `nf`, `hf` and `cf` (either 0 or 1).
+//! - Memory bytes: `[ff44]`, `[hl]`, `[sp + 1]`. Numbers between brackets are
+//!   in hexadecimal (register names take precedence, so `[de]` reads from the
+//!   address in DE and `[0xde]` from 0x00de).
+//! - `ly`, and the mapped banks: `bank` (ROM), `rambank` (cartridge RAM),
+//!   `wbank` (CGB WRAM) and `vbank` (CGB VRAM).
+//! - Operators, by increasing precedence: `||`, `&&`, `|`, `^`, `&`,
+//!   `==` `!=`, `<` `<=` `>` `>=`, `+` `-`, and the unary `!` and `-`.
+//!
+//! Comparison and boolean operators evaluate to 1 (true) or 0 (false). For
+//! example, `pc == 0x4a2f && [ff44] >= 0x90 && bank == 3`.
+use crate::{cartridge::Cartridge, device::Device, ppu::LCD, LR35902};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("Unexpected character '{0}'")]
+    UnexpectedChar(char),
+
+    #[error("Invalid number \"{0}\"")]
+    InvalidNumber(String),
+
+    #[error("Unknown variable \"{0}\"")]
+    UnknownVar(String),
+
+    #[error("Unexpected \"{0}\"")]
+    UnexpectedToken(String),
+
+    #[error("Unexpected end of expression")]
+    UnexpectedEnd,
+}
+
+/// Value of the SOC state that can be used in an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    ZF,
+    NF,
+    HF,
+    CF,
+    LY,
+    Bank,
+    RamBank,
+    WBank,
+    VBank,
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "a" => Var::A,
+            "f" => Var::F,
+            "b" => Var::B,
+            "c" => Var::C,
+            "d" => Var::D,
+            "e" => Var::E,
+            "h" => Var::H,
+            "l" => Var::L,
+            "af" => Var::AF,
+            "bc" => Var::BC,
+            "de" => Var::DE,
+            "hl" => Var::HL,
+            "sp" => Var::SP,
+            "pc" => Var::PC,
+            "zf" => Var::ZF,
+            "nf" => Var::NF,
+            "hf" => Var::HF,
+            "cf" => Var::CF,
+            "ly" => Var::LY,
+            "bank" => Var::Bank,
+            "rambank" => Var::RamBank,
+            "wbank" => Var::WBank,
+            "vbank" => Var::VBank,
+            _ => return None,
+        })
+    }
+
+    fn eval<C: Cartridge, O: LCD>(self, soc: &LR35902<C, O>) -> i64 {
+        let r = soc.cpu().registers();
+        let read = |address| Device::read(soc, address).unwrap_or(0) as i64;
+        let flag = |bit: u8| ((r.f >> bit) & 1) as i64;
+        match self {
+            Var::A => r.a as _,
+            Var::F => r.f as _,
+            Var::B => r.b as _,
+            Var::C => r.c as _,
+            Var::D => r.d as _,
+            Var::E => r.e as _,
+            Var::H => r.h as _,
+            Var::L => r.l as _,
+            Var::AF => r.af() as _,
+            Var::BC => r.bc() as _,
+            Var::DE => r.de() as _,
+            Var::HL => r.hl() as _,
+            Var::SP => r.sp as _,
+            Var::PC => r.pc as _,
+            Var::ZF => flag(7),
+            Var::NF => flag(6),
+            Var::HF => flag(5),
+            Var::CF => flag(4),
+            Var::LY => read(0xff44),
+            Var::Bank => soc.cartridge().rom_bank() as _,
+            Var::RamBank => soc.cartridge().ram_bank() as _,
+            Var::WBank => soc.wram().bank() as _,
+            Var::VBank => soc.vram().bank() as _,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Not,
+}
+
+impl Op {
+    // binary operator precedence (higher binds tighter)
+    fn precedence(self) -> Option<u8> {
+        Some(match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::BitOr => 3,
+            Op::BitXor => 4,
+            Op::BitAnd => 5,
+            Op::Eq | Op::Ne => 6,
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => 7,
+            Op::Add | Op::Sub => 8,
+            Op::Not => return None,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Or => "||",
+            Op::And => "&&",
+            Op::BitOr => "|",
+            Op::BitXor => "^",
+            Op::BitAnd => "&",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Not => "!",
+        }
+    }
+
+    fn apply(self, l: i64, r: i64) -> i64 {
+        match self {
+            Op::Or => (l != 0 || r != 0) as _,
+            Op::And => (l != 0 && r != 0) as _,
+            Op::BitOr => l | r,
+            Op::BitXor => l ^ r,
+            Op::BitAnd => l & r,
+            Op::Eq => (l == r) as _,
+            Op::Ne => (l != r) as _,
+            Op::Lt => (l < r) as _,
+            Op::Le => (l <= r) as _,
+            Op::Gt => (l > r) as _,
+            Op::Ge => (l >= r) as _,
+            Op::Add => l.wrapping_add(r),
+            Op::Sub => l.wrapping_sub(r),
+            Op::Not => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Var(Var),
+    Op(Op),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{n}"),
+            Token::Var(var) => write!(f, "{}", format!("{var:?}").to_lowercase()),
+            Token::Op(op) => write!(f, "{}", op.symbol()),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    // numbers between brackets default to hexadecimal
+    let mut brackets = 0;
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' | '\t' => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => {
+                brackets += 1;
+                Token::LBracket
+            }
+            ']' => {
+                brackets -= 1;
+                Token::RBracket
+            }
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '^' => Token::Op(Op::BitXor),
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Op(Op::Or),
+            '|' => Token::Op(Op::BitOr),
+            '&' if chars.next_if_eq(&'&').is_some() => Token::Op(Op::And),
+            '&' => Token::Op(Op::BitAnd),
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Op(Op::Not),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '$' | '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                let word = word.to_ascii_lowercase();
+                if let Some(var) = Var::parse(&word) {
+                    Token::Var(var)
+                } else if let Some(hex) = word.strip_prefix("0x").or(word.strip_prefix('$')) {
+                    Token::Num(parse_num(hex, 16, &word)?)
+                } else if brackets > 0 {
+                    Token::Num(parse_num(&word, 16, &word)?)
+                } else if c.is_ascii_digit() {
+                    Token::Num(parse_num(&word, 10, &word)?)
+                } else {
+                    return Err(Error::UnknownVar(word));
+                }
+            }
+            c => return Err(Error::UnexpectedChar(c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_num(digits: &str, radix: u32, word: &str) -> Result<i64, Error> {
+    i64::from_str_radix(digits, radix).map_err(|_| Error::InvalidNumber(word.to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Num(i64),
+    Var(Var),
+    Mem(Box<Node>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval<C: Cartridge, O: LCD>(&self, soc: &LR35902<C, O>) -> i64 {
+        match self {
+            Node::Num(n) => *n,
+            Node::Var(var) => var.eval(soc),
+            Node::Mem(address) => {
+                let address = address.eval(soc) as u16;
+                Device::read(soc, address).unwrap_or(0) as _
+            }
+            Node::Not(node) => (node.eval(soc) == 0) as _,
+            Node::Neg(node) => node.eval(soc).wrapping_neg(),
+            Node::Binary(op, l, r) => op.apply(l.eval(soc), r.eval(soc)),
+        }
+    }
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peek: Option<Token>,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        self.peek.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peek.is_none() {
+            self.peek = self.tokens.next();
+        }
+        self.peek.as_ref()
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), Error> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(Error::UnexpectedToken(t.to_string())),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+
+    // precedence climbing
+    fn binary(&mut self, min: u8) -> Result<Node, Error> {
+        let mut node = self.unary()?;
+        while let Some(&Token::Op(op)) = self.peek() {
+            match op.precedence() {
+                Some(p) if p >= min => {
+                    self.next();
+                    let rhs = self.binary(p + 1)?;
+                    node = Node::Binary(op, Box::new(node), Box::new(rhs));
+                }
+                _ => break,
+            }
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        match self.next() {
+            Some(Token::Op(Op::Not)) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Op(Op::Sub)) => Ok(Node::Neg(Box::new(self.unary()?))),
+            Some(Token::Num(n)) => Ok(Node::Num(n)),
+            Some(Token::Var(var)) => Ok(Node::Var(var)),
+            Some(Token::LParen) => {
+                let node = self.binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Some(Token::LBracket) => {
+                let node = self.binary(0)?;
+                self.expect(Token::RBracket)?;
+                Ok(Node::Mem(Box::new(node)))
+            }
+            Some(token) => Err(Error::UnexpectedToken(token.to_string())),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+}
+
+/// Parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+impl Expr {
+    /// Evaluate the expression.
+    ///
+    /// Memory is read without side effects (watchpoints are not triggered).
+    pub fn eval<C: Cartridge, O: LCD>(&self, soc: &LR35902<C, O>) -> i64 {
+        self.node.eval(soc)
+    }
+
+    /// Evaluate the expression as a condition (true if non-zero).
+    pub fn test<C: Cartridge, O: LCD>(&self, soc: &LR35902<C, O>) -> bool {
+        self.eval(soc) != 0
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?.into_iter(),
+            peek: None,
+        };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.next() {
+            return Err(Error::UnexpectedToken(token.to_string()));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            node,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Expr};
+    use crate::{cartridge::ROM, gb::GameBoy};
+
+    #[test]
+    fn expr() {
+        let mut rom = vec![0; 0x8000];
+        // LD HL,$C000; LD (HL),$42; JR -2
+        rom[0x100..0x107].copy_from_slice(&[0x21, 0x00, 0xc0, 0x36, 0x42, 0x18, 0xfe]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb.soc_mut().step().unwrap();
+        gb.soc_mut().step().unwrap();
+
+        let soc = gb.soc();
+        let eval = |source: &str| source.parse::<Expr>().unwrap().eval(soc);
+        assert_eq!(0x105, eval("pc"));
+        assert_eq!(0x42, eval("[hl]"));
+        assert_eq!(0x42, eval("[c000]"));
+        assert_eq!(0x43, eval("[$c000 - 0x4000 + 0x4000] + 1"));
+        assert_eq!(1, eval("pc == 0x105 && [c000] >= 0x40 && bank == 1"));
+        assert_eq!(0, eval("pc == 0x105 && !(h == $c0)"));
+        assert_eq!(1, eval("1 + 2 == 3 || 0"));
+        assert_eq!(2, eval("0x06 & 0x03 | 0 ^ 0"));
+        assert_eq!(-1, eval("-1"));
+
+        assert_eq!(
+            Err(Error::UnknownVar("foo".into())),
+            "foo == 1".parse::<Expr>()
+        );
+        assert_eq!(Err(Error::UnexpectedEnd), "pc ==".parse::<Expr>());
+        assert_eq!(
+            Err(Error::UnexpectedToken(")".into())),
+            "pc)".parse::<Expr>()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Cycle profiler.
+//!
+//! A `Profiler` set with `CPU::set_profiler` attributes the T-cycles of every
+//! step to the executed instruction (bank & PC) and to the functions of the
+//! shadow call stack (see `stack`). Cycles spent halted are counted apart,
+//! along with the running and halted cycles of every frame.
+//!
+//! Interrupt dispatches are attributed to the interrupt vector. Frames that
+//! don't halt at all usually mean the game didn't finish its work in time
+//! (unless it waits for VBLANK by polling LY instead of halting).
+use super::{stack::Frame, Address};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+use utils::symbols::Symbols;
+
+/// Cycles of a frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameCycles {
+    pub running: u64,
+    pub halted: u64,
+}
+
+/// Cycle profiler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profiler {
+    // cycles per instruction
+    instructions: HashMap<Address, u64>,
+    // cycles per call stack (the called address of each frame, outermost
+    // first), running and halted
+    stacks: HashMap<Vec<Address>, FrameCycles>,
+    // call stack of the last step
+    stack: Vec<Address>,
+    frame: FrameCycles,
+    frames: Vec<FrameCycles>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cycles of every complete frame.
+    pub fn frames(&self) -> &[FrameCycles] {
+        &self.frames
+    }
+
+    /// Returns the total cycles running and halted.
+    pub fn total(&self) -> FrameCycles {
+        self.stacks
+            .values()
+            .fold(FrameCycles::default(), |total, cycles| FrameCycles {
+                running: total.running + cycles.running,
+                halted: total.halted + cycles.halted,
+            })
+    }
+
+    /// Returns the instructions sorted by cycles (most cycles first).
+    pub fn instructions(&self) -> Vec<(Address, u64)> {
+        let mut instructions: Vec<_> = self.instructions.iter().map(|(a, c)| (*a, *c)).collect();
+        instructions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.address.cmp(&b.0.address)));
+        instructions
+    }
+
+    /// Returns the running cycles of the functions (the called addresses of
+    /// the call stack), sorted by exclusive cycles. Returns the function,
+    /// and its exclusive & inclusive cycles (which include the functions it
+    /// calls). Code outside of any call is attributed to `None`.
+    pub fn functions(&self) -> Vec<(Option<Address>, u64, u64)> {
+        let mut functions: HashMap<Option<Address>, (u64, u64)> = HashMap::new();
+        for (stack, cycles) in &self.stacks {
+            functions.entry(stack.last().copied()).or_default().0 += cycles.running;
+            // recursive functions are only counted once
+            let mut seen = Vec::new();
+            for function in std::iter::once(None).chain(stack.iter().copied().map(Some)) {
+                if !seen.contains(&function) {
+                    functions.entry(function).or_default().1 += cycles.running;
+                    seen.push(function);
+                }
+            }
+        }
+        let mut functions: Vec<_> = functions
+            .into_iter()
+            .map(|(function, (exclusive, inclusive))| (function, exclusive, inclusive))
+            .collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+        functions
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Sets the call stack of the next step. It must be the one before the
+    /// step is executed, so the cycles of a call (or return) go to the caller
+    /// (or callee) that executes it.
+    pub(crate) fn set_stack(&mut self, frames: &[Frame]) {
+        if !self.stack.iter().eq(frames.iter().map(|frame| &frame.to)) {
+            self.stack.clear();
+            self.stack.extend(frames.iter().map(|frame| frame.to));
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: Address, cycles: u64, halted: bool) {
+        let stack = match self.stacks.get_mut(&self.stack) {
+            Some(stack) => stack,
+            None => self.stacks.entry(self.stack.clone()).or_default(),
+        };
+        if halted {
+            stack.halted += cycles;
+            self.frame.halted += cycles;
+        } else {
+            stack.running += cycles;
+            self.frame.running += cycles;
+            *self.instructions.entry(pc).or_default() += cycles;
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frames.push(std::mem::take(&mut self.frame));
+    }
+
+    /// Write the profile as text: cycles per frame, and the functions &
+    /// instructions with the most cycles (up to `top` of each).
+    pub fn write_report<W: Write>(
+        &self,
+        mut out: W,
+        symbols: &Symbols,
+        top: usize,
+    ) -> io::Result<()> {
+        let total = self.total();
+        let cycles = total.running + total.halted;
+        let percent = |part: u64| match cycles {
+            0 => 0.0,
+            cycles => 100.0 * part as f64 / cycles as f64,
+        };
+        writeln!(
+            out,
+            "cycles: {cycles} (running {:.1}%, halted {:.1}%)",
+            percent(total.running),
+            percent(total.halted)
+        )?;
+        if !self.frames.is_empty() {
+            let frames = self.frames.len() as u64;
+            let running: u64 = self.frames.iter().map(|f| f.running).sum();
+            let (worst, max) = self
+                .frames
+                .iter()
+                .enumerate()
+                .map(|(i, f)| (i, f.running))
+                .max_by_key(|(_, running)| *running)
+                .unwrap();
+            let lag = self.frames.iter().filter(|f| f.halted == 0).count();
+            writeln!(
+                out,
+                "frames: {frames}, running {} cycles/frame on average, {max} at most (frame {worst}), {lag} without halting",
+                running / frames,
+            )?;
+        }
+
+        writeln!(
+            out,
+            "\n{:>10} {:>6} {:>10} {:>6}  function",
+            "self", "%", "total", "%"
+        )?;
+        for (function, exclusive, inclusive) in self.functions().into_iter().take(top) {
+            let name = match function {
+                Some(address) => address.symbolic(symbols),
+                None => "(top level)".to_string(),
+            };
+            writeln!(
+                out,
+                "{exclusive:>10} {:>5.1}% {inclusive:>10} {:>5.1}%  {name}",
+                percent(exclusive),
+                percent(inclusive)
+            )?;
+        }
+
+        writeln!(out, "\n{:>10} {:>6}  instruction", "cycles", "%")?;
+        for (address, cycles) in self.instructions().into_iter().take(top) {
+            let name = address.symbolic(symbols);
+            writeln!(out, "{cycles:>10} {:>5.1}%  {name}", percent(cycles))?;
+        }
+        Ok(())
+    }
+
+    /// Write the call stacks in the folded format of `flamegraph.pl` (and
+    /// compatible tools), one line per stack: `root;function;function cycles`.
+    /// Cycles spent halted end with a `[halted]` frame.
+    pub fn write_folded<W: Write>(&self, mut out: W, symbols: &Symbols) -> io::Result<()> {
+        let mut lines: Vec<_> = self
+            .stacks
+            .iter()
+            .flat_map(|(stack, cycles)| {
+                let names: Vec<_> = std::iter::once("root".to_string())
+                    .chain(stack.iter().map(|address| address.symbolic(symbols)))
+                    .collect();
+                let stack = names.join(";");
+                [
+                    (stack.clone(), cycles.running),
+                    (format!("{stack};[halted]"), cycles.halted),
+                ]
+            })
+            .filter(|(_, cycles)| *cycles > 0)
+            .collect();
+        lines.sort();
+        for (stack, cycles) in lines {
+            writeln!(out, "{stack} {cycles}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Profiler;
+    use crate::debug::{
+        stack::{CallKind, Frame},
+        Address,
+    };
+    use utils::symbols::Symbols;
+
+    #[test]
+    fn profile() {
+        let frame = |to: u16| Frame {
+            kind: CallKind::Call,
+            from: Address::banked(0, 0x150),
+            to: Address::banked(0, to),
+            ret: 0x153,
+            sp: 0xfffc,
+        };
+        let main = Address::banked(0, 0x150);
+        let func = Address::banked(0, 0x200);
+        let mut profiler = Profiler::new();
+        profiler.set_stack(&[]);
+        profiler.record(main, 12, false);
+        profiler.set_stack(&[frame(0x200)]);
+        profiler.record(func, 8, false);
+        profiler.record(func, 8, false);
+        profiler.end_frame();
+        profiler.set_stack(&[]);
+        profiler.record(main, 4, true);
+        profiler.end_frame();
+
+        assert_eq!(vec![(func, 16), (main, 12)], profiler.instructions());
+        assert_eq!(
+            vec![(Some(func), 16, 16), (None, 12, 28)],
+            profiler.functions()
+        );
+        assert_eq!(2, profiler.frames().len());
+        assert_eq!(
+            (28, 0),
+            (profiler.frames()[0].running, profiler.frames()[0].halted)
+        );
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0, 0x200, "Update");
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded, &symbols).unwrap();
+        assert_eq!(
+            "root 12\nroot;Update 16\nroot;[halted] 4\n",
+            String::from_utf8(folded).unwrap()
+        );
+        let mut report = Vec::new();
+        profiler.write_report(&mut report, &symbols, 10).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("cycles: 32 (running 87.5%, halted 12.5%)\n"));
+        assert!(report.contains("  Update\n"));
+    }
+}
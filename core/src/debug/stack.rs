@@ -0,0 +1,267 @@
+//! Shadow call stack.
+//!
+//! The CPU records every CALL, RST and interrupt dispatch (which push a return
+//! address) and every RET and RETI (which pop one). Games don't always pair
+//! them up: return addresses get popped or overwritten, `PUSH nn; RET` is used
+//! as an indirect jump, and SP is reset. Frames whose slot in the stack has
+//! been discarded are dropped, and counted as mismatches.
+use super::Address;
+use crate::{error::StateError, state::State};
+
+// deeper calls drop the outermost frames (runaway recursion)
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CallKind {
+    #[default]
+    Call,
+    Rst,
+    Interrupt,
+}
+
+/// Call stack frame.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Frame {
+    pub kind: CallKind,
+    /// Address of the call instruction (or the interrupted instruction).
+    pub from: Address,
+    /// Called address.
+    pub to: Address,
+    /// Return address pushed to the stack.
+    pub ret: u16,
+    /// Address of the return address in the stack.
+    pub sp: u16,
+}
+
+/// Shadow call stack.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    mismatches: u64,
+}
+
+impl CallStack {
+    /// Returns the frames, from the outermost to the innermost.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the number of returns that didn't match the innermost frame.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.mismatches = 0;
+    }
+
+    /// Returns the addresses of the backtrace of the instruction at `pc`: the
+    /// instruction itself, followed by the call instruction of every frame
+    /// from the innermost to the outermost.
+    pub fn backtrace(&self, pc: Address) -> Vec<Address> {
+        std::iter::once(pc)
+            .chain(self.frames.iter().rev().map(|frame| frame.from))
+            .collect()
+    }
+
+    pub(crate) fn push(&mut self, frame: Frame) {
+        // the stack has been discarded past these frames (e.g. SP was reset)
+        while self.frames.last().map_or(false, |top| top.sp <= frame.sp) {
+            self.frames.pop();
+            self.mismatches += 1;
+        }
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // `sp` is the address the return address was popped from
+    pub(crate) fn ret(&mut self, sp: u16, address: u16) {
+        // the return addresses of these frames were popped without a RET
+        while self.frames.last().map_or(false, |top| top.sp < sp) {
+            self.frames.pop();
+            self.mismatches += 1;
+        }
+        // otherwise, RET is being used as an indirect jump
+        if self.frames.last().map_or(false, |top| top.sp == sp) {
+            let frame = self.frames.pop().unwrap();
+            if frame.ret != address {
+                self.mismatches += 1;
+            }
+        }
+    }
+}
+
+impl State for CallKind {
+    fn save(&self, buf: &mut Vec<u8>) {
+        (*self as u8).save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut kind = 0u8;
+        kind.load(buf)?;
+        *self = match kind {
+            1 => CallKind::Rst,
+            2 => CallKind::Interrupt,
+            _ => CallKind::Call,
+        };
+        Ok(())
+    }
+}
+
+crate::state::impl_state!(Frame {
+    kind,
+    from,
+    to,
+    ret,
+    sp
+});
+// size of the frames in the states (kind, banked from & to, ret & sp)
+const FRAME_LEN: usize = 27;
+
+// The frames are saved in fixed-size slots, so that states have the same
+// layout whatever the depth (and deltas between them stay small).
+impl State for CallStack {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.frames.len().save(buf);
+        self.mismatches.save(buf);
+        for i in 0..MAX_DEPTH {
+            let start = buf.len();
+            if let Some(frame) = self.frames.get(i) {
+                frame.save(buf);
+            }
+            // a longer frame would overwrite the next slot
+            assert!(buf.len() - start <= FRAME_LEN, "call stack frame too long");
+            buf.resize(start + FRAME_LEN, 0);
+        }
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut len = 0usize;
+        len.load(buf)?;
+        self.mismatches.load(buf)?;
+        if buf.len() < MAX_DEPTH * FRAME_LEN {
+            return Err(StateError::UnexpectedEnd);
+        }
+        self.frames.clear();
+        for slot in buf[..MAX_DEPTH * FRAME_LEN].chunks(FRAME_LEN).take(len) {
+            let mut frame = Frame::default();
+            frame.load(&mut &slot[..])?;
+            self.frames.push(frame);
+        }
+        *buf = &buf[MAX_DEPTH * FRAME_LEN..];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CallKind, CallStack, Frame};
+    use crate::state::State;
+    use crate::{cartridge::ROM, debug::Address, gb::GameBoy};
+
+    #[test]
+    fn call_stack() {
+        let mut rom = vec![0; 0x8000];
+        // 0x100: CALL $0200; JR -2
+        rom[0x100..0x105].copy_from_slice(&[0xcd, 0x00, 0x02, 0x18, 0xfe]);
+        // 0x200: RST $08; RST $10
+        rom[0x200..0x202].copy_from_slice(&[0xcf, 0xd7]);
+        // 0x08: RET
+        rom[0x08] = 0xc9;
+        // 0x10: POP HL; RET
+        rom[0x10..0x12].copy_from_slice(&[0xe1, 0xc9]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let soc = gb.soc_mut();
+        let sp = soc.cpu().registers().sp;
+        soc.step().unwrap();
+        soc.step().unwrap();
+        let stack = soc.cpu().call_stack();
+        assert_eq!(
+            &[
+                Frame {
+                    kind: CallKind::Call,
+                    from: Address::banked(0, 0x100),
+                    to: Address::banked(0, 0x200),
+                    ret: 0x103,
+                    sp: sp - 2,
+                },
+                Frame {
+                    kind: CallKind::Rst,
+                    from: Address::banked(0, 0x200),
+                    to: Address::banked(0, 0x08),
+                    ret: 0x201,
+                    sp: sp - 4,
+                }
+            ],
+            stack.frames()
+        );
+        assert_eq!(
+            vec![
+                Address::new(0x08),
+                Address::banked(0, 0x200),
+                Address::banked(0, 0x100)
+            ],
+            stack.backtrace(Address::new(0x08))
+        );
+
+        // RET (matches)
+        soc.step().unwrap();
+        let stack = soc.cpu().call_stack();
+        assert_eq!((1, 0), (stack.depth(), stack.mismatches()));
+        assert_eq!(0x201, soc.cpu().registers().pc);
+
+        // POP HL discards the return address, RET returns to the caller
+        for _ in 0..3 {
+            soc.step().unwrap();
+        }
+        let stack = soc.cpu().call_stack();
+        assert_eq!((0, 1), (stack.depth(), stack.mismatches()));
+        assert_eq!(0x103, soc.cpu().registers().pc);
+
+        let mut stack = CallStack::default();
+        stack.push(Frame {
+            sp: 0xdff0,
+            ..Default::default()
+        });
+        stack.ret(0xdff0, 0x1234);
+        assert_eq!((0, 1), (stack.depth(), stack.mismatches()));
+    }
+
+    #[test]
+    fn state() {
+        let empty = CallStack::default();
+        let mut stack = CallStack::default();
+        stack.push(Frame {
+            kind: CallKind::Interrupt,
+            from: Address::banked(0x1ff, 0x4567),
+            to: Address::banked(0, 0x40),
+            ret: 0x4568,
+            sp: 0xdff0,
+        });
+        stack.push(Frame {
+            sp: 0xdfee,
+            ..Default::default()
+        });
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        empty.save(&mut a);
+        stack.save(&mut b);
+        // same layout whatever the depth
+        assert_eq!(a.len(), b.len());
+
+        let mut loaded = CallStack::default();
+        loaded.load(&mut &b[..]).unwrap();
+        assert_eq!(stack, loaded);
+        loaded.load(&mut &a[..]).unwrap();
+        assert_eq!(empty, loaded);
+        assert!(loaded.load(&mut &b[..b.len() - 1]).is_err());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::error::{Component, ReadError, WriteError};
+use crate::{
+    cpu::Registers,
+    error::{Component, ReadError, WriteError},
+};
 use byteorder::{ByteOrder, LittleEndian};
 
 pub trait Device {
@@ -45,36 +48,65 @@ pub trait Device {
     }
 }
 
-pub trait MemoryBus: Device {
-    fn read(&self, address: u16) -> Result<u8, ReadError> {
-        match <Self as Device>::read(self, address) {
-            Ok(b) => Ok(b),
-            Err(err) => {
-                match err {
-                    ReadError::UnknownAddr(_) => log::warn!("{err}"),
-                    ReadError::AddrNotImpl(_, Some(Component::APU)) => {}
-                    ReadError::AddrNotImpl(_, Some(Component::Serial)) => {}
-                    _ => log::error!("{err}"),
-                }
-                Ok(0x00)
+// Device read where errors are logged and ignored.
+pub(crate) fn read_or_log<D: Device + ?Sized>(device: &D, address: u16) -> Result<u8, ReadError> {
+    match device.read(address) {
+        Ok(b) => Ok(b),
+        Err(err) => {
+            match err {
+                ReadError::UnknownAddr(_) => log::warn!("{err}"),
+                ReadError::AddrNotImpl(_, Some(Component::APU)) => {}
+                ReadError::AddrNotImpl(_, Some(Component::Serial)) => {}
+                _ => log::error!("{err}"),
             }
+            Ok(0x00)
         }
     }
+}
 
-    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
-        match <Self as Device>::write(self, address, data) {
-            Ok(_) => Ok(()),
-            Err(err) => {
-                match err {
-                    WriteError::UnknownAddr(_, _) => log::warn!("{err}"),
-                    WriteError::AddrNotImpl(_, _, Some(Component::APU)) => {}
-                    WriteError::AddrNotImpl(_, _, Some(Component::Serial)) => {}
-                    _ => log::error!("{err}"),
-                }
-                Ok(())
+// Device write where errors are logged and ignored.
+pub(crate) fn write_or_log<D: Device + ?Sized>(
+    device: &mut D,
+    address: u16,
+    data: u8,
+) -> Result<(), WriteError> {
+    match device.write(address, data) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            match err {
+                WriteError::UnknownAddr(_, _) => log::warn!("{err}"),
+                WriteError::AddrNotImpl(_, _, Some(Component::APU)) => {}
+                WriteError::AddrNotImpl(_, _, Some(Component::Serial)) => {}
+                _ => log::error!("{err}"),
             }
+            Ok(())
         }
     }
+}
+
+pub trait MemoryBus: Device {
+    fn read(&self, address: u16) -> Result<u8, ReadError> {
+        read_or_log(self, address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        write_or_log(self, address, data)
+    }
+
+    /// Read a byte of an instruction (opcode or immediate operand).
+    /// Unlike `read`, instruction fetches don't trigger watchpoints.
+    fn fetch(&self, address: u16) -> Result<u8, ReadError> {
+        <Self as MemoryBus>::read(self, address)
+    }
+
+    /// Returns the bank mapped at the given address (see `LR35902::bank`).
+    fn bank(&self, address: u16) -> Option<usize> {
+        None
+    }
+
+    /// Called right before the CPU executes the instruction at
+    /// `registers.pc` (used for instruction tracing).
+    fn instruction(&mut self, registers: &Registers) {}
 
     /// Read little-endian u16 word from given address.
     /// May return Err if `address` or `address + 1` are not mapped to the
@@ -7,13 +7,18 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct OAMDMA {}
+pub struct OAMDMA {
+    /// High byte of the source address of the last transfer (FF46).
+    pub source: u8,
+}
+
+crate::state::impl_state!(OAMDMA { source });
 
 impl Device for OAMDMA {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
         dev_read! {
             address {
-                0xff46 => Ok(0x00),
+                0xff46 => Ok(self.source),
             }
         }
     }
@@ -33,6 +38,14 @@ pub struct VRAMDMA {
     pub hdma4: u8,
 }
 
+#[cfg(feature = "cgb")]
+crate::state::impl_state!(VRAMDMA {
+    hdma1,
+    hdma2,
+    hdma3,
+    hdma4
+});
+
 #[cfg(feature = "cgb")]
 impl Device for VRAMDMA {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
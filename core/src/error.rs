@@ -61,3 +61,19 @@ pub enum WriteError {
     #[error("Invalid write address {0:04X} data {1:02X}")]
     InvalidData(u16, u8),
 }
+
+/// Errors restoring a save state.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum StateError {
+    /// The state ended before all the components were restored.
+    #[error("Unexpected end of save state")]
+    UnexpectedEnd,
+
+    /// The state contains data that doesn't belong to any component.
+    #[error("Trailing data in save state")]
+    TrailingData,
+
+    /// The state was saved from a different ROM.
+    #[error("Save state doesn't belong to the loaded ROM")]
+    InvalidRom,
+}
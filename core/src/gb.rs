@@ -1,11 +1,14 @@
 use crate::{
     cartridge::Cartridge,
+    cheats::{self, Cheats},
     debug::NextFrame,
     device::{Device, MemoryBus},
-    error::Error,
-    joypad::Button,
+    error::{Error, StateError},
+    joypad::{Button, JoypadInput},
     ppu::LCD,
-    LR35902,
+    rewind::Rewind,
+    state::State,
+    utils, LR35902,
 };
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -24,20 +27,104 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GameBoy<C: Cartridge, O: LCD> {
     soc: LR35902<C, O>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rewind: Option<Rewind>,
 }
 
 impl<C: Cartridge, O: LCD> GameBoy<C, O> {
     pub fn new(cartridge: C, output: O) -> Self {
         let soc = LR35902::new(cartridge, output);
-        Self { soc }
+        Self { soc, rewind: None }
     }
 
     /// Update until emulator reaches the next frame.
     pub fn next_frame(&mut self) -> Result<(), Error> {
+        self.start_frame();
         let _ = self.soc.step_breakpoint(NextFrame::new())?;
         Ok(())
     }
 
+    /// Signal the start of a new frame.
+    /// It is called by `next_frame`. Frontends that step the SOC directly must
+    /// call it before emulating each frame for the rewind buffer to work.
+    pub fn start_frame(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.frame(&self.soc);
+        }
+    }
+
+    /// Enable (or disable, if `None`) the rewind buffer.
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    /// Returns the rewind buffer, if enabled.
+    pub fn rewind_buffer(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    /// Step the emulation back by the given number of frames.
+    /// Returns the number of frames actually rewound, which is limited by the
+    /// contents of the rewind buffer (zero if it's disabled).
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, Error> {
+        match &mut self.rewind {
+            Some(rewind) => rewind.rewind(&mut self.soc, frames),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the cheats.
+    pub fn cheats(&self) -> &Cheats {
+        self.soc.cheats()
+    }
+
+    /// Returns the cheats as mutable (to add or toggle cheats).
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.soc.cheats_mut()
+    }
+
+    /// Replace the cheats (for example, with the ones read from a file).
+    /// Fails if the cheats are meant for a different ROM.
+    pub fn set_cheats(&mut self, cheats: Cheats) -> Result<(), cheats::Error> {
+        let found = self.cheats().rom_hash();
+        if cheats.rom_hash() != found {
+            return Err(cheats::Error::Rom {
+                expected: cheats.rom_hash(),
+                found,
+            });
+        }
+        *self.soc.cheats_mut() = cheats;
+        Ok(())
+    }
+
+    /// Save the state of the emulator.
+    /// The state can only be loaded back on the same ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        utils::crc32(self.soc.cartridge.rom()).save(&mut buf);
+        self.soc.save(&mut buf);
+        buf
+    }
+
+    /// Restore a state saved with `save_state`.
+    /// If the state is not valid, the emulator is left untouched.
+    pub fn load_state(&mut self, mut state: &[u8]) -> Result<(), StateError> {
+        let mut hash = 0u32;
+        hash.load(&mut state)?;
+        if hash != utils::crc32(self.soc.cartridge.rom()) {
+            return Err(StateError::InvalidRom);
+        }
+        let backup = self.save_state();
+        let result = match self.soc.load(&mut state) {
+            Ok(_) if !state.is_empty() => Err(StateError::TrailingData),
+            result => result,
+        };
+        if result.is_err() {
+            self.soc.load(&mut &backup[4..]).unwrap();
+        }
+        result
+    }
+
     /// Get the SOC device.
     pub fn soc(&self) -> &LR35902<C, O> {
         &self.soc
@@ -48,15 +135,37 @@ impl<C: Cartridge, O: LCD> GameBoy<C, O> {
         &mut self.soc
     }
 
-    /// Register a button press.
-    /// This operation triggers the JOYPAD interrupt.
+    /// Register a button press on the first joypad.
+    /// The JOYPAD interrupt is raised if the button is currently selected.
     pub fn press(&mut self, button: &Button) {
-        self.soc.press(button);
+        let input = self.input(0) | JoypadInput::from(*button);
+        self.set_input(0, input);
     }
 
-    /// Register a button release.
+    /// Register a button release on the first joypad.
     pub fn release(&mut self, button: &Button) {
-        self.soc.release(button)
+        let input = self.input(0) - JoypadInput::from(*button);
+        self.set_input(0, input);
+    }
+
+    /// Set the state of all the buttons of the given joypad (0 to 3, others
+    /// are ignored). Joypads other than the first are only read when the game
+    /// enables them through the Super Game Boy (MLT_REQ).
+    ///
+    /// The JOYPAD interrupt is raised only if a selected line goes low.
+    pub fn set_input(&mut self, player: usize, input: JoypadInput) {
+        self.soc.set_input(player, input);
+    }
+
+    /// Returns the state of the buttons of the given joypad.
+    pub fn input(&self, player: usize) -> JoypadInput {
+        self.soc.joypad.input(player)
+    }
+
+    /// Reset the emulator (power cycle).
+    /// The cartridge RAM and the state of the joypads are preserved.
+    pub fn reset(&mut self) {
+        self.soc.reset();
     }
 
     /// Skip boot sequence.
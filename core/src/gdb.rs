@@ -0,0 +1,542 @@
+//! GDB remote serial protocol (RSP) stub.
+//!
+//! Serves a single GDB client over TCP (localhost only). The registers are
+//! exposed as the 16 bit pairs AF, BC, DE, HL, SP and PC (register numbers 0
+//! to 5, in that order), in little-endian. A target description is provided
+//! to clients that support `qXfer:features:read`.
+//!
+//! Supported commands: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `s`, `c`, `Z0`-`Z4`
+//! and `z0`-`z4` (software & hardware breakpoints, and write, read & access
+//! watchpoints), `D` and `k`, plus the queries needed to attach.
+use crate::{
+    cartridge::Cartridge,
+    cpu::Registers,
+    debug::{
+        Access, Address, Breakpoint, BreakpointEntry, BreakpointSet, Trigger, Watch, Watchpoint, PC,
+    },
+    device::Device,
+    ppu::LCD,
+    LR35902,
+};
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+};
+
+// number of steps emulated between checks for a client interrupt
+const INTERRUPT_POLL: usize = 4096;
+
+// largest packet accepted from the client (as advertised by qSupported)
+const PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.lr35902.cpu">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Malformed packet")]
+    Packet,
+}
+
+/// GDB server listening on localhost.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    /// Listen on the given port of localhost (0 to pick any free port).
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Wait for a client and serve it until it detaches, kills the target or
+    /// closes the connection.
+    ///
+    /// Breakpoints and watchpoints set by the client are removed when the
+    /// session ends.
+    pub fn serve<C: Cartridge, O: LCD>(&self, soc: &mut LR35902<C, O>) -> Result<(), Error> {
+        let (stream, _) = self.listener.accept()?;
+        log::info!("GDB client connected");
+        let mut session = Session {
+            stream,
+            input: Vec::new(),
+            ack: true,
+            breakpoints: BreakpointSet::new(),
+            kinds: Vec::new(),
+            watchpoints: Vec::new(),
+        };
+        let result = session.run(soc);
+        for watchpoint in session.watchpoints.drain(..) {
+            remove_watchpoint(soc, &watchpoint);
+        }
+        log::info!("GDB client disconnected");
+        result
+    }
+}
+
+// software or hardware breakpoint (both are implemented the same way)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Software,
+    Hardware,
+}
+
+enum Reply {
+    Packet(String),
+    // stop the session after sending the reply
+    Close(String),
+}
+
+struct Session {
+    stream: TcpStream,
+    // bytes received but not processed yet
+    input: Vec<u8>,
+    ack: bool,
+    breakpoints: BreakpointSet,
+    // kind of each entry of the breakpoint set
+    kinds: Vec<Kind>,
+    // watchpoints added to the SOC by the client
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Session {
+    fn run<C: Cartridge, O: LCD>(&mut self, soc: &mut LR35902<C, O>) -> Result<(), Error> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match std::str::from_utf8(&packet) {
+                Ok(packet) => self.handle(soc, packet)?,
+                Err(_) => Reply::Packet("E01".to_string()),
+            };
+            match reply {
+                Reply::Packet(reply) => self.write_packet(&reply)?,
+                Reply::Close(reply) => {
+                    self.write_packet(&reply)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        if self.input.is_empty() {
+            let mut buf = [0; 1024];
+            let len = self.stream.read(&mut buf)?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.input.extend_from_slice(&buf[..len]);
+        }
+        Ok(Some(self.input.remove(0)))
+    }
+
+    // Returns None when the client closes the connection.
+    fn read_packet(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            // skip acks, interrupts (the target isn't running) & garbage
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut packet = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b'}') => match self.read_byte()? {
+                        Some(byte) => packet.push(byte ^ 0x20),
+                        None => return Ok(None),
+                    },
+                    Some(byte) => packet.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                *digit = self.read_byte()?.ok_or(Error::Packet)?;
+            }
+            let checksum = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+            if !self.ack {
+                return Ok(Some(packet));
+            }
+            if checksum == Some(checksum_of(&packet)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(packet));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> Result<(), Error> {
+        let packet = format!("${data}#{:02x}", checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if !self.ack {
+                return Ok(());
+            }
+            // resend until acknowledged
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                Some(_) | None => return Ok(()),
+            }
+        }
+    }
+
+    // Returns true if the client sent an interrupt (0x03).
+    fn interrupted(&mut self) -> Result<bool, Error> {
+        let mut buf = [0; 64];
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(len) => self.input.extend_from_slice(&buf[..len]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err.into()),
+        }
+        match self.input.iter().position(|b| *b == 0x03) {
+            Some(i) => {
+                self.input.remove(i);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn handle<C: Cartridge, O: LCD>(
+        &mut self,
+        soc: &mut LR35902<C, O>,
+        packet: &str,
+    ) -> Result<Reply, Error> {
+        let ok = || Reply::Packet("OK".to_string());
+        let error = || Reply::Packet("E01".to_string());
+        let empty = || Reply::Packet(String::new());
+
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => Reply::Packet("S05".to_string()),
+            "g" => {
+                let r = soc.cpu().registers();
+                let regs: String = registers(r).iter().map(|r| hex_u16(*r)).collect();
+                Reply::Packet(regs)
+            }
+            "G" => match parse_registers(args) {
+                Some(values) => {
+                    let r = soc.cpu_mut().registers_mut();
+                    for (n, value) in values.into_iter().enumerate() {
+                        set_register(r, n, value);
+                    }
+                    ok()
+                }
+                None => error(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < 6 => Reply::Packet(hex_u16(registers(soc.cpu().registers())[n])),
+                _ => error(),
+            },
+            "P" => {
+                let value = args.split_once('=').and_then(|(n, v)| {
+                    Some((usize::from_str_radix(n, 16).ok()?, parse_register(v)?))
+                });
+                match value {
+                    Some((n, value)) if n < 6 => {
+                        set_register(soc.cpu_mut().registers_mut(), n, value);
+                        ok()
+                    }
+                    _ => error(),
+                }
+            }
+            "m" => match parse_memory_range(args) {
+                Some((address, len)) => {
+                    let mut data = String::with_capacity(len * 2);
+                    for i in 0..len {
+                        let address = address.wrapping_add(i as u16);
+                        let byte = Device::read(soc, address).unwrap_or(0xff);
+                        data.push_str(&format!("{byte:02x}"));
+                    }
+                    Reply::Packet(data)
+                }
+                None => error(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (address, len) = parse_memory_range(range)?;
+                    let data = parse_hex(data)?;
+                    (data.len() == len).then_some((address, data))
+                });
+                match write {
+                    Some((address, data)) => {
+                        let mut result = Ok(());
+                        for (i, byte) in data.into_iter().enumerate() {
+                            let address = address.wrapping_add(i as u16);
+                            result = result.and(Device::write(soc, address, byte));
+                        }
+                        if result.is_ok() {
+                            ok()
+                        } else {
+                            error()
+                        }
+                    }
+                    None => error(),
+                }
+            }
+            "s" => {
+                if let Some(pc) = parse_u16(args) {
+                    soc.cpu_mut().registers_mut().pc = pc;
+                }
+                Reply::Packet(match soc.step() {
+                    Ok(_) => "S05".to_string(),
+                    Err(err) => emulation_error(err),
+                })
+            }
+            "c" => {
+                if let Some(pc) = parse_u16(args) {
+                    soc.cpu_mut().registers_mut().pc = pc;
+                }
+                Reply::Packet(self.resume(soc)?)
+            }
+            "Z" | "z" => match self.breakpoint(soc, command == "Z", args) {
+                Some(true) => ok(),
+                Some(false) => error(),
+                // unsupported type
+                None => empty(),
+            },
+            "D" => Reply::Close("OK".to_string()),
+            "k" => Reply::Close(String::new()),
+            "H" => ok(),
+            "q" | "Q" => self.query(packet),
+            _ => empty(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> Reply {
+        let reply = if packet.starts_with("qSupported") {
+            format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+            )
+        } else if packet == "QStartNoAckMode" {
+            self.ack = false;
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_range(args) {
+                Some((offset, len)) => {
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = offset.saturating_add(len).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                    format!("{prefix}{}", &TARGET_XML[offset..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        };
+        Reply::Packet(reply)
+    }
+
+    // Returns None for unsupported breakpoint types, or whether the packet
+    // was valid otherwise.
+    fn breakpoint<C: Cartridge, O: LCD>(
+        &mut self,
+        soc: &mut LR35902<C, O>,
+        insert: bool,
+        args: &str,
+    ) -> Option<bool> {
+        let mut args = args.split(',');
+        let ty = args.next()?;
+        let (address, len) = match (args.next().and_then(parse_u16), args.next()) {
+            (Some(address), Some(len)) => (address, usize::from_str_radix(len, 16).unwrap_or(1)),
+            _ => return Some(false),
+        };
+        let kind = match ty {
+            "0" => Kind::Software,
+            "1" => Kind::Hardware,
+            "2" | "3" | "4" => {
+                // the watched range can't go past the end of the address space
+                let end = u16::try_from(len.max(1) - 1)
+                    .ok()
+                    .and_then(|last| address.checked_add(last));
+                let Some(end) = end else {
+                    return Some(false);
+                };
+                let watchpoint = match ty {
+                    "2" => Watchpoint::write(address..=end),
+                    "3" => Watchpoint::read(address..=end),
+                    _ => Watchpoint::read_write(address..=end),
+                };
+                if insert {
+                    soc.watchpoints_mut().add(watchpoint.clone());
+                    self.watchpoints.push(watchpoint);
+                } else if let Some(i) = self.watchpoints.iter().position(|w| *w == watchpoint) {
+                    self.watchpoints.remove(i);
+                    remove_watchpoint(soc, &watchpoint);
+                }
+                return Some(true);
+            }
+            _ => return None,
+        };
+        if insert {
+            self.breakpoints.add(BreakpointEntry::pc(address));
+            self.kinds.push(kind);
+        } else {
+            let index = self.breakpoints.iter().zip(&self.kinds).position(|(e, k)| {
+                *k == kind && matches!(e.trigger, Trigger::PC(PC(a)) if a == Address::new(address))
+            });
+            if let Some(index) = index {
+                self.breakpoints.remove(index);
+                self.kinds.remove(index);
+            }
+        }
+        Some(true)
+    }
+
+    // Run until a breakpoint or watchpoint is hit, or the client interrupts.
+    // Returns the stop reply.
+    fn resume<C: Cartridge, O: LCD>(&mut self, soc: &mut LR35902<C, O>) -> Result<String, Error> {
+        let mut breakpoint = (&mut self.breakpoints, Watch);
+        loop {
+            for _ in 0..INTERRUPT_POLL {
+                breakpoint.init(soc);
+                if let Err(err) = soc.step() {
+                    return Ok(emulation_error(err));
+                }
+                if breakpoint.breakpoint(soc) {
+                    return Ok(stop_reply(soc, breakpoint.0, &self.kinds));
+                }
+            }
+            if self.interrupted()? {
+                return Ok("S02".to_string());
+            }
+            breakpoint = (&mut self.breakpoints, Watch);
+        }
+    }
+}
+
+fn stop_reply<C: Cartridge, O: LCD>(
+    soc: &LR35902<C, O>,
+    breakpoints: &BreakpointSet,
+    kinds: &[Kind],
+) -> String {
+    if let Some(hit) = soc.watchpoints().hits().first() {
+        let access = soc.watchpoints().iter().nth(hit.index).map(|w| w.access);
+        let name = match access {
+            Some(Access::ReadWrite) => "awatch",
+            Some(Access::Read) => "rwatch",
+            _ => "watch",
+        };
+        return format!("T05{name}:{:x};", hit.address);
+    }
+    match breakpoints.hit().map(|i| kinds[i]) {
+        Some(Kind::Hardware) => "T05hwbreak:;".to_string(),
+        _ => "T05swbreak:;".to_string(),
+    }
+}
+
+fn emulation_error(err: crate::error::Error) -> String {
+    log::error!("{err:?}");
+    // SIGILL
+    "S04".to_string()
+}
+
+fn remove_watchpoint<C: Cartridge, O: LCD>(soc: &mut LR35902<C, O>, watchpoint: &Watchpoint) {
+    let index = soc.watchpoints().iter().position(|w| w == watchpoint);
+    if let Some(index) = index {
+        soc.watchpoints_mut().remove(index);
+    }
+}
+
+fn registers(r: &Registers) -> [u16; 6] {
+    [r.af(), r.bc(), r.de(), r.hl(), r.sp, r.pc]
+}
+
+fn set_register(r: &mut Registers, n: usize, value: u16) {
+    match n {
+        0 => r.set_af(value & 0xfff0),
+        1 => r.set_bc(value),
+        2 => r.set_de(value),
+        3 => r.set_hl(value),
+        4 => r.sp = value,
+        5 => r.pc = value,
+        _ => {}
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+// register values are little-endian
+fn hex_u16(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xff, value >> 8)
+}
+
+fn parse_hex(data: &str) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_u16(data: &str) -> Option<u16> {
+    u16::from_str_radix(data, 16).ok()
+}
+
+fn parse_register(data: &str) -> Option<u16> {
+    match parse_hex(data)?[..] {
+        [lo, hi] => Some(u16::from_le_bytes([lo, hi])),
+        _ => None,
+    }
+}
+
+fn parse_registers(data: &str) -> Option<Vec<u16>> {
+    let bytes = parse_hex(data)?;
+    (bytes.len() == 12).then(|| {
+        bytes
+            .chunks(2)
+            .map(|r| u16::from_le_bytes([r[0], r[1]]))
+            .collect()
+    })
+}
+
+// "addr,len"
+fn parse_range(data: &str) -> Option<(u16, usize)> {
+    let (address, len) = data.split_once(',')?;
+    Some((parse_u16(address)?, usize::from_str_radix(len, 16).ok()?))
+}
+
+// Like `parse_range`, but the bytes must fit in a packet (as hex digits).
+fn parse_memory_range(data: &str) -> Option<(u16, usize)> {
+    let (address, len) = parse_range(data)?;
+    len.checked_mul(2).filter(|digits| *digits <= PACKET_SIZE)?;
+    Some((address, len))
+}
@@ -0,0 +1,213 @@
+use crate::{
+    device::Device,
+    error::{ReadError, WriteError},
+};
+use educe::Educe;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// Bit 0   - Write Data   (0=LED Off, 1=LED On)             (Read/Write)
+// Bit 1   - Read Data    (0=Receiving IR Signal, 1=Normal) (Read Only)
+// Bit 6-7 - Data Read Enable (0=Disable, 3=Enable)         (Read/Write)
+const LED: u8 = 0b0000_0001;
+const RECEIVE: u8 = 0b0000_0010;
+const READ_ENABLE: u8 = 0b1100_0000;
+
+/// The other end of the infrared link.
+///
+/// Implement this trait to connect the infrared port of the emulator to
+/// something that emits (or receives) light. [`Link`] connects two emulator
+/// instances together.
+pub trait InfraredPeer {
+    /// Called whenever the local LED is switched on or off.
+    fn led(&mut self, on: bool);
+
+    /// Returns true if the peer is currently emitting light towards the local
+    /// sensor, or false otherwise.
+    fn light(&self) -> bool;
+
+    /// Called after every emulation step with the number of elapsed CPU ticks.
+    /// Scripted light sources may use this to follow emulated time.
+    fn update(&mut self, _ticks: u64) {}
+}
+
+/// An empty tuple represents the absence of a peer (nothing emits light).
+impl InfraredPeer for () {
+    fn led(&mut self, _on: bool) {}
+
+    fn light(&self) -> bool {
+        false
+    }
+}
+
+/// In-process infrared link between two emulator instances.
+/// Light emitted by the LED of one end is received by the other end.
+#[derive(Debug, Clone)]
+pub struct Link {
+    local: Arc<AtomicBool>,
+    remote: Arc<AtomicBool>,
+}
+
+impl Link {
+    /// Create both ends of the link. Light emitted from one end is received by
+    /// the other one.
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(AtomicBool::new(false));
+        let b = Arc::new(AtomicBool::new(false));
+        let left = Self {
+            local: Arc::clone(&a),
+            remote: Arc::clone(&b),
+        };
+        let right = Self {
+            local: b,
+            remote: a,
+        };
+        (left, right)
+    }
+}
+
+impl InfraredPeer for Link {
+    fn led(&mut self, on: bool) {
+        self.local.store(on, Ordering::SeqCst);
+    }
+
+    fn light(&self) -> bool {
+        self.remote.load(Ordering::SeqCst)
+    }
+}
+
+fn default_peer() -> Box<dyn InfraredPeer + Send> {
+    Box::new(())
+}
+
+/// Infrared communications port (RP register).
+#[derive(Educe)]
+#[educe(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Infrared {
+    rp: u8,
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_peer"))]
+    peer: Box<dyn InfraredPeer + Send>,
+}
+
+crate::state::impl_state!(Infrared { rp });
+
+impl Default for Infrared {
+    fn default() -> Self {
+        Self {
+            rp: 0,
+            peer: default_peer(),
+        }
+    }
+}
+
+impl Infrared {
+    /// Replace the other end of the infrared link.
+    pub fn set_peer(&mut self, peer: Box<dyn InfraredPeer + Send>) {
+        self.peer = peer;
+        self.peer.led(self.led());
+    }
+
+    /// Returns true if the local LED is on.
+    pub fn led(&self) -> bool {
+        self.rp & LED != 0
+    }
+
+    // Reset the RP register (the peer stays connected).
+    pub(crate) fn reset(&mut self) {
+        self.rp = 0;
+        self.peer.led(false);
+    }
+
+    pub(crate) fn update(&mut self, ticks: u64) {
+        self.peer.update(ticks);
+    }
+}
+
+impl Device for Infrared {
+    fn read(&self, address: u16) -> Result<u8, ReadError> {
+        dev_read! {
+            address {
+                0xff56 => {
+                    // the sensor can only be read if both bits 6 and 7 are set
+                    let receiving = self.rp & READ_ENABLE == READ_ENABLE && self.peer.light();
+                    let data = if receiving { 0 } else { RECEIVE };
+                    // unused bits 2-5 always read back as 1
+                    Ok((self.rp & (LED | READ_ENABLE)) | data | 0b0011_1100)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        dev_write! {
+            address, data {
+                0xff56 => {
+                    let led = self.led();
+                    self.rp = data & (LED | READ_ENABLE);
+                    if led != self.led() {
+                        self.peer.led(self.led());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Infrared, InfraredPeer, Link};
+    use crate::device::Device;
+
+    // Light source that flashes on and off every 100 ticks.
+    struct Flash {
+        ticks: u64,
+    }
+
+    impl InfraredPeer for Flash {
+        fn led(&mut self, _on: bool) {}
+
+        fn light(&self) -> bool {
+            (self.ticks / 100) % 2 == 1
+        }
+
+        fn update(&mut self, ticks: u64) {
+            self.ticks += ticks;
+        }
+    }
+
+    #[test]
+    fn scripted_light_source() {
+        let mut ir = Infrared::default();
+        ir.set_peer(Box::new(Flash { ticks: 0 }));
+        ir.update(150);
+        // read disabled
+        assert_eq!(0b0011_1110, ir.read(0xff56).unwrap());
+        ir.write(0xff56, 0xc0).unwrap();
+        assert_eq!(0b1111_1100, ir.read(0xff56).unwrap());
+        ir.update(100);
+        assert_eq!(0b1111_1110, ir.read(0xff56).unwrap());
+    }
+
+    #[test]
+    fn link() {
+        let (left, right) = Link::pair();
+        let mut a = Infrared::default();
+        let mut b = Infrared::default();
+        a.set_peer(Box::new(left));
+        b.set_peer(Box::new(right));
+        b.write(0xff56, 0xc0).unwrap();
+        assert_eq!(0b10, b.read(0xff56).unwrap() & 0b10);
+        a.write(0xff56, 0x01).unwrap();
+        assert_eq!(0b00, b.read(0xff56).unwrap() & 0b10);
+        a.write(0xff56, 0x00).unwrap();
+        assert_eq!(0b10, b.read(0xff56).unwrap() & 0b10);
+    }
+}
@@ -25,6 +25,9 @@ pub struct IRQ {
     pub ie: Flags,
 }
 
+crate::state::impl_state_bitflags!(Flags);
+crate::state::impl_state!(IRQ { fi, ie });
+
 impl Device for IRQ {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
         dev_read! {
@@ -2,23 +2,17 @@ use crate::{
     device::Device,
     error::{ReadError, WriteError},
 };
+use bitflags::bitflags;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::mem;
 #[cfg(feature = "wasm-bindgen")]
 use wasm_bindgen::prelude::*;
 
 const BUTTON: u8 = 0b0010_0000;
 const DIRECTION: u8 = 0b0001_0000;
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[rustfmt::skip]
-enum Select {
-    Button    = BUTTON,
-    Direction = DIRECTION,
-}
+/// Maximum number of joypads (through the SGB multitap).
+pub const MAX_PLAYERS: usize = 4;
 
 #[repr(u8)]
 #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
@@ -35,31 +29,112 @@ pub enum Button {
     Down   = 0b1000_0000,
 }
 
+bitflags! {
+    /// State of the 8 buttons of a joypad (set bits are pressed buttons).
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    #[rustfmt::skip]
+    pub struct JoypadInput: u8 {
+        const A      = Button::A as u8;
+        const B      = Button::B as u8;
+        const SELECT = Button::Select as u8;
+        const START  = Button::Start as u8;
+        const RIGHT  = Button::Right as u8;
+        const LEFT   = Button::Left as u8;
+        const UP     = Button::Up as u8;
+        const DOWN   = Button::Down as u8;
+    }
+}
+
+crate::state::impl_state_bitflags!(JoypadInput);
+
+impl From<Button> for JoypadInput {
+    fn from(button: Button) -> Self {
+        Self::from_bits_truncate(button as u8)
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(super) struct Joypad {
-    select: Select,
-    matrix: u8,
+    // selected lines (P14 & P15), inverted so that set bits are selected lines
+    select: u8,
+    // one input per joypad (up to 4 joypads through the SGB)
+    input: [JoypadInput; MAX_PLAYERS],
+    player: usize,
+    multiplayer: bool,
 }
 
+crate::state::impl_state!(Joypad {
+    select,
+    input,
+    player,
+    multiplayer
+});
+
 impl Default for Joypad {
     fn default() -> Self {
         Self {
-            select: Select::Button,
-            matrix: 0,
+            select: BUTTON,
+            input: [JoypadInput::empty(); MAX_PLAYERS],
+            player: 0,
+            multiplayer: false,
         }
     }
 }
 
 impl Joypad {
-    pub fn press(&mut self, button: &Button) {
-        log::trace!("button press: {button:?}");
-        self.matrix |= unsafe { mem::transmute::<_, u8>(*button) };
+    // Reset the selected lines (the state of the buttons is kept).
+    pub fn reset(&mut self) {
+        *self = Self {
+            input: self.input,
+            ..Default::default()
+        };
     }
 
-    pub fn release(&mut self, button: &Button) {
-        log::trace!("button release: {button:?}");
-        self.matrix &= !unsafe { mem::transmute::<_, u8>(*button) };
+    /// Returns the state of the buttons of the given joypad (no buttons are
+    /// pressed on joypads past `MAX_PLAYERS`).
+    pub fn input(&self, player: usize) -> JoypadInput {
+        self.input.get(player).copied().unwrap_or_default()
+    }
+
+    /// Update the state of the buttons of the given joypad (joypads past
+    /// `MAX_PLAYERS` are ignored).
+    /// Returns true if any of the selected lines went from high to low (which
+    /// raises the JOYPAD interrupt).
+    pub fn set_input(&mut self, player: usize, input: JoypadInput) -> bool {
+        if player >= MAX_PLAYERS {
+            log::warn!("joypad input for unknown player {player}");
+            return false;
+        }
+        if self.input[player] != input {
+            log::trace!("joypad input (player {player}): {input:?}");
+        }
+        let lines = self.lines();
+        self.input[player] = input;
+        self.lines() & !lines != 0
+    }
+
+    /// Returns the P10-P13 lines that are low, as set bits.
+    /// If both P14 and P15 are selected, both button groups are wired together.
+    pub fn lines(&self) -> u8 {
+        let input = self.input[self.player].bits();
+        let mut lines = 0;
+        if self.select & BUTTON != 0 {
+            lines |= input & 0xf;
+        }
+        if self.select & DIRECTION != 0 {
+            lines |= input >> 4;
+        }
+        lines
+    }
+
+    /// Select the joypad being read.
+    /// In multiplayer mode, the joypad ID is read when no lines are selected.
+    #[cfg(feature = "sgb")]
+    pub fn set_player(&mut self, player: usize, multiplayer: bool) {
+        self.player = player;
+        self.multiplayer = multiplayer;
     }
 }
 
@@ -68,14 +143,16 @@ impl Device for Joypad {
         dev_read! {
             address {
                 0xff00 => {
-                    let data = match self.select {
-                        Select::Button => (self.matrix & 0xf) | BUTTON,
-                        Select::Direction => (self.matrix >> 4) | DIRECTION,
+                    let data = if self.select == 0 && self.multiplayer {
+                        // joypad ID (0xf = joypad 1, 0xe = joypad 2, ...)
+                        self.player as u8
+                    } else {
+                        self.lines()
                     };
 
                     // we're swapping the meaning of 0 and 1 internally
                     // so we need to invert the data bits
-                    Ok(!data & 0b0011_1111)
+                    Ok(!(data | self.select) & 0b0011_1111)
                 }
             }
         }
@@ -89,12 +166,7 @@ impl Device for Joypad {
                     // so we need to invert the data bits
                     data = !data;
                     data &= 0b0011_0000;
-
-                    match data {
-                        BUTTON => self.select = Select::Button,
-                        DIRECTION => self.select = Select::Direction,
-                        _ => {},
-                    }
+                    self.select = data;
                 }
             }
         }
@@ -104,4 +176,41 @@ impl Device for Joypad {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::{Joypad, JoypadInput, MAX_PLAYERS};
+    use crate::device::Device;
+
+    #[test]
+    fn select() {
+        let mut joypad = Joypad::default();
+        joypad.set_input(0, JoypadInput::A | JoypadInput::DOWN);
+        joypad.write(0xff00, 0x10).unwrap();
+        assert_eq!(0b0001_1110, joypad.read(0xff00).unwrap());
+        joypad.write(0xff00, 0x20).unwrap();
+        assert_eq!(0b0010_0111, joypad.read(0xff00).unwrap());
+        // both groups selected
+        joypad.write(0xff00, 0x00).unwrap();
+        assert_eq!(0b0000_0110, joypad.read(0xff00).unwrap());
+        // no group selected
+        joypad.write(0xff00, 0x30).unwrap();
+        assert_eq!(0b0011_1111, joypad.read(0xff00).unwrap());
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut joypad = Joypad::default();
+        joypad.write(0xff00, 0x20).unwrap();
+        // buttons not selected
+        assert!(!joypad.set_input(0, JoypadInput::A));
+        assert!(joypad.set_input(0, JoypadInput::A | JoypadInput::UP));
+        // line already low
+        assert!(!joypad.set_input(0, JoypadInput::UP));
+        assert!(joypad.set_input(0, JoypadInput::UP | JoypadInput::DOWN));
+        assert!(!joypad.set_input(0, JoypadInput::empty()));
+        // other joypads are not being read
+        assert!(!joypad.set_input(1, JoypadInput::UP));
+        // unknown joypads are ignored
+        assert!(!joypad.set_input(MAX_PLAYERS, JoypadInput::DOWN));
+        assert_eq!(JoypadInput::empty(), joypad.input(MAX_PLAYERS));
+    }
+}
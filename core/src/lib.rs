@@ -44,42 +44,72 @@ macro_rules! dev_write {
     }
 }
 
-#[cfg(feature = "cgb")]
-use crate::dma::VRAMDMA;
+#[cfg(all(feature = "sgb", feature = "cgb"))]
+compile_error!("features \"sgb\" and \"cgb\" are mutually exclusive");
+
+#[cfg(feature = "sgb")]
+use crate::sgb::Sgb;
 use crate::{
-    apu::APU,
+    apu::{AudioOutput, APU},
     boot::Boot,
     cartridge::Cartridge,
-    cpu::CPU,
-    debug::Breakpoint,
+    cheats::Cheats,
+    cpu::{Registers, CPU},
+    debug::{Access, Breakpoint, Watchpoints},
     device::{Device, MemoryBus},
     dma::OAMDMA,
-    error::{Error, ReadError, WriteError},
+    error::{Error, ReadError, StateError, WriteError},
+    infrared::InfraredPeer,
     irq::IRQ,
-    joypad::{Button, Joypad},
+    joypad::{Joypad, JoypadInput},
     ppu::{LCD, PPU},
     ram::{hram::HRAM, vram::VRAM, wram::WRAM},
     serial::Serial,
+    state::State,
     timer::Timer,
+    trace::{TraceEntry, Tracer},
+};
+#[cfg(feature = "cgb")]
+use crate::{dma::VRAMDMA, infrared::Infrared};
+use ::utils::{
+    cdl::{self, CodeDataLog},
+    dasm::Disassembler,
+    vgm::VgmLog,
 };
+use educe::Educe;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Ref, RefCell},
+    ops::RangeInclusive,
+};
 
-mod apu;
+pub mod apu;
 mod boot;
+#[cfg(feature = "capture")]
+pub mod capture;
 pub mod cartridge;
+pub mod cheats;
 pub mod cpu;
 pub mod debug;
 pub mod device;
 mod dma;
 pub mod error;
 pub mod gb;
+pub mod gdb;
+pub mod infrared;
 mod irq;
 pub mod joypad;
+pub mod movie;
 pub mod ppu;
 pub mod ram;
+pub mod rewind;
 mod serial;
+#[cfg(feature = "sgb")]
+pub mod sgb;
+pub mod state;
 mod timer;
+pub mod trace;
 mod utils;
 
 const CLOCK: u64 = 4_194_304;
@@ -88,13 +118,17 @@ trait Update {
     fn update(&mut self, ticks: u64, flags: &mut irq::Flags);
 }
 
-#[derive(Debug)]
+#[derive(Educe)]
+#[educe(Debug(bound))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LR35902<C: Cartridge, O: LCD> {
     // borrow checker workaround
     // cpu will be leaving the Option temporarily
     cpu: Option<CPU>,
     cartridge: C,
+    // saved next to the ROM, not in the states
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cheats: Cheats,
     boot: Boot,
     oam_dma: OAMDMA,
     oam_buf: Option<Box<[u8; 0xa0]>>,
@@ -109,13 +143,33 @@ pub struct LR35902<C: Cartridge, O: LCD> {
     apu: APU,
     serial: Serial,
     #[cfg(feature = "cgb")]
+    infrared: Infrared,
+    #[cfg(feature = "sgb")]
+    sgb: Sgb,
+    #[cfg(feature = "cgb")]
     double_speed: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watchpoints: Watchpoints,
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tracer: Option<Box<dyn Tracer + Send>>,
+    // memory reads go through &self, hence the RefCell
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    code_data_log: Option<RefCell<CodeDataLog>>,
+    #[educe(Debug(ignore))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    vgm_log: Option<VgmLog>,
+    // CPU cycles & frames since power-on
+    cycles: u64,
+    frames: u64,
 }
 
 impl<C: Cartridge, O: LCD> LR35902<C, O> {
     pub fn new(cartridge: C, output: O) -> Self {
         Self {
             cpu: Some(Default::default()),
+            cheats: Cheats::for_rom(cartridge.rom()),
             cartridge,
             boot: Default::default(),
             oam_dma: Default::default(),
@@ -131,10 +185,55 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
             apu: Default::default(),
             serial: Default::default(),
             #[cfg(feature = "cgb")]
+            infrared: Default::default(),
+            #[cfg(feature = "sgb")]
+            sgb: Default::default(),
+            #[cfg(feature = "cgb")]
             double_speed: false,
+            watchpoints: Default::default(),
+            tracer: None,
+            code_data_log: None,
+            vgm_log: None,
+            cycles: 0,
+            frames: 0,
         }
     }
 
+    /// Reset the system to its power-on state, as if the power was cycled.
+    /// The cartridge (including its RAM), the LCD & audio outputs and the state
+    /// of the joypads are preserved.
+    pub fn reset(&mut self) {
+        self.cpu = Some(Default::default());
+        self.cartridge.reset();
+        self.boot = Default::default();
+        self.oam_dma = Default::default();
+        #[cfg(feature = "cgb")]
+        {
+            self.vram_dma = Default::default();
+        }
+        self.joypad.reset();
+        self.ppu.reset();
+        self.timer = Default::default();
+        self.work_ram = Default::default();
+        self.high_ram = Default::default();
+        self.irq = Default::default();
+        self.apu.reset();
+        self.log_apu_state();
+        self.serial = Default::default();
+        #[cfg(feature = "cgb")]
+        self.infrared.reset();
+        #[cfg(feature = "sgb")]
+        {
+            self.sgb = Default::default();
+        }
+        #[cfg(feature = "cgb")]
+        {
+            self.double_speed = false;
+        }
+        self.cycles = 0;
+        self.frames = 0;
+    }
+
     /// Return the current frequency of the CPU.
     pub fn clock_freq(&self) -> u64 {
         #[cfg(feature = "cgb")]
@@ -163,11 +262,231 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
         self.cpu.as_mut().unwrap()
     }
 
+    /// Returns the cartridge.
+    pub fn cartridge(&self) -> &C {
+        &self.cartridge
+    }
+
+    /// Returns the work RAM.
+    pub fn wram(&self) -> &WRAM {
+        &self.work_ram
+    }
+
+    /// Returns the bank mapped at the given address, or `None` if the address
+    /// is outside of the banked regions (ROM, VRAM, cartridge RAM & WRAM).
+    /// The fixed regions report bank 0.
+    pub fn bank(&self, address: u16) -> Option<usize> {
+        match address {
+            0x0000..=0x3fff => Some(0),
+            0x4000..=0x7fff => Some(self.cartridge.rom_bank()),
+            0x8000..=0x9fff => Some(self.ppu.vram().bank()),
+            0xa000..=0xbfff => Some(self.cartridge.ram_bank()),
+            0xc000..=0xcfff => Some(0),
+            0xd000..=0xdfff => Some(self.work_ram.bank()),
+            0xe000..=0xfdff => self.bank(address - 0x2000),
+            _ => None,
+        }
+    }
+
+    /// Returns the banks that can be mapped at the given address (see
+    /// `bank`), or `None` if the address is outside of the banked regions.
+    pub fn banks(&self, address: u16) -> Option<RangeInclusive<usize>> {
+        let count = |len: usize, size: usize| len.div_ceil(size).max(1) - 1;
+        match address {
+            0x0000..=0x3fff | 0xc000..=0xcfff => Some(0..=0),
+            0x4000..=0x7fff => Some(1..=count(self.cartridge.rom().len(), 0x4000).max(1)),
+            0x8000..=0x9fff if cfg!(feature = "cgb") => Some(0..=1),
+            0x8000..=0x9fff => Some(0..=0),
+            0xa000..=0xbfff => Some(0..=count(self.cartridge.ram().len(), 0x2000)),
+            0xd000..=0xdfff if cfg!(feature = "cgb") => Some(1..=7),
+            0xd000..=0xdfff => Some(1..=1),
+            0xe000..=0xfdff => self.banks(address - 0x2000),
+            _ => None,
+        }
+    }
+
+    /// Read a byte from the given bank of a banked region (see `banks`),
+    /// regardless of the bank currently mapped at the address. Other addresses
+    /// are read from the memory map (without triggering watchpoints).
+    pub fn read_bank(&self, bank: usize, address: u16) -> Result<u8, ReadError> {
+        if !self
+            .banks(address)
+            .is_some_and(|banks| banks.contains(&bank))
+        {
+            return Device::read(self, address);
+        }
+        let offset = |size: u16| bank * (size as usize) + (address % size) as usize;
+        let data = match address {
+            0x4000..=0x7fff => self.cartridge.rom().get(offset(0x4000)).copied(),
+            0x8000..=0x9fff => Some(self.ppu.vram().data(bank, address)),
+            0xa000..=0xbfff => self.cartridge.ram().get(offset(0x2000)).copied(),
+            0xd000..=0xdfff => Some(self.work_ram.read_bank(bank, address)),
+            0xe000..=0xfdff => return self.read_bank(bank, address - 0x2000),
+            _ => return Device::read(self, address),
+        };
+        data.ok_or(ReadError::UnknownAddr(address))
+    }
+
+    /// Write a byte to the given bank of a banked region (see `read_bank`).
+    /// ROM can't be written.
+    pub fn write_bank(&mut self, bank: usize, address: u16, data: u8) -> Result<(), WriteError> {
+        if !self
+            .banks(address)
+            .is_some_and(|banks| banks.contains(&bank))
+        {
+            return Device::write(self, address, data);
+        }
+        match address {
+            0x0000..=0x7fff => return Err(WriteError::ROMAddress(address, data)),
+            0x8000..=0x9fff => self.ppu.vram_mut().write_bank(bank, address, data),
+            0xa000..=0xbfff => {
+                let offset = bank * 0x2000 + (address as usize) - 0xa000;
+                match self.cartridge.ram_mut().get_mut(offset) {
+                    Some(byte) => *byte = data,
+                    None => return Err(WriteError::UnknownAddr(address, data)),
+                }
+            }
+            0xd000..=0xdfff => self.work_ram.write_bank(bank, address, data),
+            0xe000..=0xfdff => return self.write_bank(bank, address - 0x2000, data),
+            _ => return Device::write(self, address, data),
+        }
+        Ok(())
+    }
+
     /// Return the VRAM device
     pub fn vram(&self) -> &VRAM {
         self.ppu.vram()
     }
 
+    /// Return the Super Game Boy state.
+    #[cfg(feature = "sgb")]
+    pub fn sgb(&self) -> &Sgb {
+        &self.sgb
+    }
+
+    /// Returns the cheats.
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    /// Returns the cheats as mutable.
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Returns the watchpoints.
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    /// Returns the watchpoints as mutable (to add or remove watchpoints).
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    /// Set the instruction tracer. Returns the previous one.
+    pub fn set_tracer(
+        &mut self,
+        tracer: Option<Box<dyn Tracer + Send>>,
+    ) -> Option<Box<dyn Tracer + Send>> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Start logging how the bytes of the ROM are used (see `utils::cdl`), or
+    /// stop it with `None`. Returns the previous log.
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) -> Option<CodeDataLog> {
+        std::mem::replace(&mut self.code_data_log, log.map(RefCell::new)).map(RefCell::into_inner)
+    }
+
+    /// Returns the code/data log.
+    pub fn code_data_log(&self) -> Option<Ref<'_, CodeDataLog>> {
+        self.code_data_log.as_ref().map(RefCell::borrow)
+    }
+
+    /// Start logging the writes to the APU registers (see `utils::vgm`), or
+    /// stop it with `None`. Returns the previous log.
+    ///
+    /// The log starts with the writes that bring the APU to its current state.
+    pub fn set_vgm_log(&mut self, log: Option<VgmLog>) -> Option<VgmLog> {
+        let log = std::mem::replace(&mut self.vgm_log, log);
+        self.log_apu_state();
+        log
+    }
+
+    /// Returns the VGM log as mutable (to set the loop point).
+    pub fn vgm_log_mut(&mut self) -> Option<&mut VgmLog> {
+        self.vgm_log.as_mut()
+    }
+
+    // log the state of the APU, after it changed without register writes
+    // (reset, state loaded, ...)
+    fn log_apu_state(&mut self) {
+        if let Some(log) = &mut self.vgm_log {
+            for (address, data) in self.apu.register_writes() {
+                log.write(address, data);
+            }
+        }
+    }
+
+    // ROM offset of the address, if the cartridge ROM is mapped at it
+    fn rom_offset(&self, address: u16) -> Option<usize> {
+        let boot = self.boot.is_enabled()
+            && (address <= 0xff || cfg!(feature = "cgb") && (0x150..=0x900).contains(&address));
+        match address {
+            _ if boot => None,
+            0x0000..=0x3fff => Some(address as usize),
+            0x4000..=0x7fff => {
+                Some(self.cartridge.rom_bank() * 0x4000 + (address as usize - 0x4000))
+            }
+            _ => None,
+        }
+    }
+
+    fn log_rom(&self, address: u16, flags: u8) {
+        if let Some(log) = &self.code_data_log {
+            if let Some(offset) = self.rom_offset(address) {
+                log.borrow_mut().mark(offset, flags);
+            }
+        }
+    }
+
+    /// Returns the number of CPU cycles emulated since power-on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the number of frames (VBLANK periods) emulated since power-on.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the bytes sent through the serial port since the output was
+    /// last taken (see `take_serial_output`).
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    /// Take the bytes sent through the serial port so far. Only the last
+    /// 64KiB are kept if the output is never taken.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+
+    /// Connect the audio output. Samples are produced at the rate of the
+    /// output.
+    pub fn set_audio_output<A: AudioOutput + Send + 'static>(&mut self, output: A) {
+        self.apu.set_output(Box::new(output));
+    }
+
+    /// Connect the infrared port to the given peer (CGB only).
+    /// On DMG builds the peer is dropped, as there is no infrared port.
+    pub fn set_infrared_peer<P: InfraredPeer + Send + 'static>(&mut self, peer: P) {
+        #[cfg(feature = "cgb")]
+        self.infrared.set_peer(Box::new(peer));
+        #[cfg(not(feature = "cgb"))]
+        drop(peer);
+    }
+
     fn update_cpu(&mut self) -> Result<u64, Error> {
         // borrow-checker workaround / hack
         // temporarily take ownership of CPU away
@@ -185,7 +504,10 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
     }
 
     pub fn step(&mut self) -> Result<(), Error> {
+        let pc = self.cpu().registers().pc;
+        self.watchpoints.start_step(pc);
         let mut ticks = self.update_cpu()?;
+        self.cycles += ticks;
 
         // run 2x clocks in the CPU if double speed is enabled
         #[cfg(feature = "cgb")]
@@ -198,6 +520,24 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
         // sync the rest of the components
         self.timer.update(ticks, &mut flags);
         self.ppu.update(ticks, &mut flags);
+        self.serial.update(ticks, &mut flags);
+        self.apu.update(ticks, &mut flags);
+        if let Some(log) = &mut self.vgm_log {
+            log.tick(ticks);
+        }
+        #[cfg(feature = "cgb")]
+        self.infrared.update(ticks);
+        #[cfg(feature = "sgb")]
+        if flags.contains(irq::Flags::VBLANK) {
+            self.sgb.vblank(&self.ppu);
+        }
+        if flags.contains(irq::Flags::VBLANK) {
+            self.apply_cheats();
+            if let Some(profiler) = self.cpu_mut().profiler_mut() {
+                profiler.end_frame();
+            }
+            self.frames += 1;
+        }
 
         self.irq.fi |= flags;
         Ok(())
@@ -215,22 +555,66 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
         Ok(breakpoit)
     }
 
-    fn press(&mut self, button: &Button) {
-        self.joypad.press(button);
-        self.irq.fi |= irq::Flags::JOYPAD;
+    fn set_input(&mut self, player: usize, input: JoypadInput) {
+        if self.joypad.set_input(player, input) {
+            self.irq.fi |= irq::Flags::JOYPAD;
+        }
     }
 
-    fn release(&mut self, button: &Button) {
-        self.joypad.release(button)
+    // selecting a group of buttons may also pull lines low
+    fn joypad_write(&mut self, data: u8) -> Result<(), WriteError> {
+        let lines = self.joypad.lines();
+        self.joypad.write(0xff00, data)?;
+        #[cfg(feature = "sgb")]
+        if sgb::supported(self.cartridge.rom()) {
+            self.sgb.write_p1(data);
+            self.joypad
+                .set_player(self.sgb.player(), self.sgb.players() > 1);
+        }
+        if self.joypad.lines() & !lines != 0 {
+            self.irq.fi |= irq::Flags::JOYPAD;
+        }
+        Ok(())
+    }
+
+    // GameShark codes are applied on every VBlank
+    fn apply_cheats(&mut self) {
+        let cheats = std::mem::take(&mut self.cheats);
+        for (bank, address, data) in cheats.writes() {
+            let result = match (bank, address) {
+                (0x01, _) => <Self as Device>::write(self, address, data),
+                (0x80..=0x9f, 0xd000..=0xdfff) => {
+                    self.work_ram
+                        .write_bank((bank & 0x7) as usize, address, data);
+                    Ok(())
+                }
+                (_, 0xa000..=0xbfff) => {
+                    let offset = 0x2000 * (bank as usize) + (address as usize) - 0xa000;
+                    if let Some(byte) = self.cartridge.ram_mut().get_mut(offset) {
+                        *byte = data;
+                    }
+                    Ok(())
+                }
+                _ => <Self as Device>::write(self, address, data),
+            };
+            if let Err(err) = result {
+                log::warn!("cheat write error: {err}");
+            }
+        }
+        self.cheats = cheats;
     }
 
     // TODO(german) emulate OAM timings
     fn do_oam_dma(&mut self, data: u8) {
+        self.oam_dma.source = data;
         let mut oam_buf = self.oam_buf.take().unwrap();
         let src = (data as u16) << 8;
         self.read_exact(src, &mut oam_buf[..]).unwrap();
         self.ppu.write_exact(0xfe00, &oam_buf[..]).unwrap();
         self.oam_buf = Some(oam_buf);
+        for address in src..src + 0xa0 {
+            self.log_rom(address, cdl::DMA);
+        }
     }
 
     // TODO(german) optimise using memcpy
@@ -249,10 +633,16 @@ impl<C: Cartridge, O: LCD> LR35902<C, O> {
 
         let src = source..source + len;
         let dst = destination..destination + len;
-        for (src, dst) in src.zip(dst) {
+        // source bytes are logged as DMA reads, rather than data reads
+        let log = self.code_data_log.take();
+        for (src, dst) in src.clone().zip(dst) {
             let data = <Self as MemoryBus>::read(self, src).unwrap();
             <Self as MemoryBus>::write(self, dst, data).unwrap();
         }
+        self.code_data_log = log;
+        for address in src {
+            self.log_rom(address, cdl::DMA);
+        }
     }
 
     #[cfg(feature = "cgb")]
@@ -282,7 +672,7 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
                 0x0000..=0x00ff if boot => self.boot.read(address),
                 #[cfg(feature = "cgb")]
                 0x0150..=0x0900 if boot => self.boot.read(address),
-                0x0000..=0x7fff => self.cartridge.read(address),
+                0x0000..=0x7fff => self.cartridge.read(address).map(|data| self.cheats.patch(address, data)),
                 0x8000..=0x9fff => self.ppu.read(address),
                 0xa000..=0xbfff => self.cartridge.read(address),
                 0xc000..=0xdfff => self.work_ram.read(address),
@@ -314,6 +704,8 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
                 0xff51..=0xff54 => self.vram_dma.read(address),
                 // TODO Emulate OAM timings
                 0xff55 => Ok(0xff),
+                #[cfg(feature = "cgb")]
+                0xff56 => self.infrared.read(address),
                 0xff70 => self.work_ram.read(address),
                 0xff71..=0xff7f => Err(ReadError::UnknownAddr(address)), // undocumented registers
                 //
@@ -338,7 +730,7 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
                 // TODO emulate behavior depending on device & hardware revision (https://gbdev.io/pandocs/#fea0-feff-range)
                 0xfea0..=0xfeff => Ok(()),
                 // IO registers
-                0xff00 => self.joypad.write(address, data),
+                0xff00 => self.joypad_write(data),
                 0xff01 | 0xff02 => self.serial.write(address, data),
                 0xff04..=0xff07 => self.timer.write(address, data),
                 0xff0f => self.irq.write(address, data),
@@ -347,7 +739,12 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
                 0xff1a..=0xff1e |
                 0xff1f..=0xff26 |
                 0xff27..=0xff2f |
-                0xff30..=0xff3f => self.apu.write(address, data),
+                0xff30..=0xff3f => {
+                    if let Some(log) = &mut self.vgm_log {
+                        log.write(address, data);
+                    }
+                    self.apu.write(address, data)
+                }
                 0xff40..=0xff45 |
                 0xff47..=0xff4b |
                 0xff4f          |
@@ -366,6 +763,8 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
                     self.do_vram_dma(data);
                     Ok(())
                 }
+                #[cfg(feature = "cgb")]
+                0xff56 => self.infrared.write(address, data),
                 0xff70 => self.work_ram.write(address, data),
                 0xff71..=0xff7f => Err(WriteError::UnknownAddr(address, data)), // undocumented registers
                 //
@@ -393,12 +792,275 @@ impl<C: Cartridge, O: LCD> Device for LR35902<C, O> {
     }
 }
 
-impl<C: Cartridge, O: LCD> MemoryBus for LR35902<C, O> {}
+// The LCD output, the audio output and the infrared peer are not part of the
+// state.
+impl<C: Cartridge, O: LCD> State for LR35902<C, O> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.cpu().save(buf);
+        self.cartridge.save(buf);
+        self.boot.save(buf);
+        self.oam_dma.save(buf);
+        #[cfg(feature = "cgb")]
+        self.vram_dma.save(buf);
+        self.joypad.save(buf);
+        self.ppu.save(buf);
+        self.timer.save(buf);
+        self.work_ram.save(buf);
+        self.high_ram.save(buf);
+        self.irq.save(buf);
+        self.apu.save(buf);
+        self.serial.save(buf);
+        #[cfg(feature = "cgb")]
+        self.infrared.save(buf);
+        #[cfg(feature = "sgb")]
+        self.sgb.save(buf);
+        #[cfg(feature = "cgb")]
+        self.double_speed.save(buf);
+        self.cycles.save(buf);
+        self.frames.save(buf);
+        // fixed size, last (see CallStack)
+        self.cpu().call_stack().save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.cpu_mut().load(buf)?;
+        self.cartridge.load(buf)?;
+        self.boot.load(buf)?;
+        self.oam_dma.load(buf)?;
+        #[cfg(feature = "cgb")]
+        self.vram_dma.load(buf)?;
+        self.joypad.load(buf)?;
+        self.ppu.load(buf)?;
+        self.timer.load(buf)?;
+        self.work_ram.load(buf)?;
+        self.high_ram.load(buf)?;
+        self.irq.load(buf)?;
+        self.apu.load(buf)?;
+        self.log_apu_state();
+        self.serial.load(buf)?;
+        #[cfg(feature = "cgb")]
+        self.infrared.load(buf)?;
+        #[cfg(feature = "sgb")]
+        self.sgb.load(buf)?;
+        #[cfg(feature = "cgb")]
+        self.double_speed.load(buf)?;
+        self.cycles.load(buf)?;
+        self.frames.load(buf)?;
+        self.cpu_mut().call_stack_mut().load(buf)?;
+        Ok(())
+    }
+}
+
+// CPU accesses are reported to the watchpoints
+impl<C: Cartridge, O: LCD> MemoryBus for LR35902<C, O> {
+    fn read(&self, address: u16) -> Result<u8, ReadError> {
+        let mut data = device::read_or_log(self, address)?;
+        if address == 0xff44 {
+            if let Some(ly) = self.tracer.as_ref().and_then(|tracer| tracer.ly()) {
+                data = ly;
+            }
+        }
+        self.log_rom(address, cdl::DATA);
+        if !self.watchpoints.is_empty() {
+            let bank = self.bank(address);
+            self.watchpoints
+                .access(Access::Read, address, bank, data, data);
+        }
+        Ok(data)
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
+        if !self.watchpoints.is_empty() {
+            let old = <Self as Device>::read(self, address).unwrap_or(0xff);
+            let bank = self.bank(address);
+            self.watchpoints
+                .access(Access::Write, address, bank, old, data);
+        }
+        device::write_or_log(self, address, data)
+    }
+
+    fn fetch(&self, address: u16) -> Result<u8, ReadError> {
+        device::read_or_log(self, address)
+    }
+
+    fn bank(&self, address: u16) -> Option<usize> {
+        LR35902::bank(self, address)
+    }
+
+    fn instruction(&mut self, registers: &Registers) {
+        if self.code_data_log.is_some() {
+            let pc = registers.pc;
+            let bytes = [0, 1, 2].map(|i| Device::read(self, pc.wrapping_add(i)).unwrap_or(0));
+            let len = match Disassembler::new(&bytes).next() {
+                Some(Ok((_, len))) => len,
+                _ => 1,
+            };
+            self.log_rom(pc, cdl::CODE);
+            for i in 1..len {
+                self.log_rom(pc.wrapping_add(i as u16), cdl::OPERAND);
+            }
+        }
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.trace(&TraceEntry::new(self, registers));
+            self.tracer = Some(tracer);
+        }
+    }
+}
 
 #[cfg(test)]
 mod test {
+    use crate::{
+        cartridge::ROM,
+        debug::{profile::Profiler, Address},
+        device::Device,
+        error::WriteError,
+        gb::GameBoy,
+        LR35902,
+    };
+    use ::utils::{
+        cdl::{self, CodeDataLog},
+        vgm::VgmLog,
+    };
+
     #[test]
     fn oam_dma() {
         todo!();
     }
+
+    #[test]
+    fn banks() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4000] = 0x42;
+        let mut soc = LR35902::new(ROM::new(rom.into_boxed_slice()), ());
+        assert_eq!(Some(1..=1), soc.banks(0x4000));
+        assert_eq!(Some(0..=0), soc.banks(0xa000));
+        assert_eq!(None, soc.banks(0xff80));
+        assert_eq!(Ok(0x42), soc.read_bank(1, 0x4000));
+        assert_eq!(
+            Err(WriteError::ROMAddress(0x4000, 0)),
+            soc.write_bank(1, 0x4000, 0)
+        );
+        soc.write_bank(1, 0xd000, 0x12).unwrap();
+        soc.write_bank(0, 0x8010, 0x34).unwrap();
+        soc.write_bank(0, 0xa000, 0x56).unwrap();
+        assert_eq!(Ok(0x12), Device::read(&soc, 0xf000));
+        assert_eq!(Ok(0x34), Device::read(&soc, 0x8010));
+        assert_eq!(Ok(0x56), soc.read_bank(0, 0xa000));
+        // addresses outside of the banked regions ignore the bank
+        soc.write_bank(3, 0xff80, 0x78).unwrap();
+        assert_eq!(Ok(0x78), soc.read_bank(5, 0xff80));
+    }
+
+    #[test]
+    fn state() {
+        let mut gb = GameBoy::new(ROM::new(vec![0; 0x8000].into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb.soc_mut().write(0xc100, 0x12).unwrap();
+        gb.soc_mut().write(0xff46, 0xc1).unwrap();
+        let state = gb.save_state();
+        gb.soc_mut().write(0xff46, 0xc2).unwrap();
+        assert_eq!(Ok(0xc2), gb.soc().read(0xff46));
+        gb.load_state(&state).unwrap();
+        assert_eq!(Ok(0xc1), gb.soc().read(0xff46));
+        assert_eq!(Ok(0x12), gb.soc().read(0xfe00));
+        assert_eq!(state, gb.save_state());
+    }
+
+    #[test]
+    fn code_data_log() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,($0150); JR -5
+        rom[0x100..0x105].copy_from_slice(&[0xfa, 0x50, 0x01, 0x18, 0xfb]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb.soc_mut()
+            .set_code_data_log(Some(CodeDataLog::new(0x8000)));
+        for _ in 0..3 {
+            gb.soc_mut().step().unwrap();
+        }
+        // OAM DMA from 0x4000
+        gb.soc_mut().write(0xff46, 0x40).unwrap();
+
+        let log = gb.soc_mut().set_code_data_log(None).unwrap();
+        let flags = [
+            0x100, 0x101, 0x102, 0x103, 0x104, 0x150, 0x4000, 0x409f, 0x40a0,
+        ]
+        .map(|o| log.get(o));
+        assert_eq!(
+            [
+                cdl::CODE,
+                cdl::OPERAND,
+                cdl::OPERAND,
+                cdl::CODE,
+                cdl::OPERAND,
+                cdl::DATA,
+                cdl::DMA,
+                cdl::DMA,
+                0
+            ],
+            flags
+        );
+    }
+
+    #[test]
+    fn profile() {
+        let mut rom = vec![0; 0x8000];
+        // CALL $0200; ... $0200: RET
+        rom[0x100..0x103].copy_from_slice(&[0xcd, 0x00, 0x02]);
+        rom[0x200] = 0xc9;
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb.soc_mut().cpu_mut().set_profiler(Some(Profiler::new()));
+        gb.soc_mut().step().unwrap();
+        gb.soc_mut().step().unwrap();
+
+        // the CALL runs in the caller, and the RET in the callee
+        let main = Address::banked(0, 0x100);
+        let func = Address::banked(0, 0x200);
+        let profiler = gb.soc().cpu().profiler().unwrap();
+        assert_eq!(vec![(main, 24), (func, 16)], profiler.instructions());
+        assert_eq!(
+            vec![(None, 24, 40), (Some(func), 16, 16)],
+            profiler.functions()
+        );
+    }
+
+    #[test]
+    fn vgm_log() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$80; LDH ($26),A; LD A,$F0; LDH ($12),A; JR -2
+        rom[0x100..0x10a]
+            .copy_from_slice(&[0x3e, 0x80, 0xe0, 0x26, 0x3e, 0xf0, 0xe0, 0x12, 0x18, 0xfe]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb.soc_mut().set_vgm_log(Some(VgmLog::new()));
+        for _ in 0..4 {
+            gb.soc_mut().step().unwrap();
+        }
+        gb.soc_mut().vgm_log_mut().unwrap().set_loop();
+        for _ in 0..10_000 {
+            gb.soc_mut().step().unwrap();
+        }
+
+        let log = gb.soc_mut().set_vgm_log(None).unwrap();
+        let file = log.to_bytes();
+        let data = &file[0x100..];
+        // the state of the APU, then the writes of the program
+        let mut writes = Vec::new();
+        let mut i = 0;
+        while data[i] != 0x66 {
+            i += match data[i] {
+                0xb3 => {
+                    writes.push((data[i + 1], data[i + 2]));
+                    3
+                }
+                0x61 => 3,
+                _ => 1,
+            };
+        }
+        assert_eq!((0x16, gb.soc().apu.read(0xff26).unwrap() & 0x80), writes[0]);
+        assert_eq!([(0x16, 0x80), (0x02, 0xf0)], writes[writes.len() - 2..]);
+        assert!(log.samples() > 0);
+        assert_eq!(Some(0), log.loop_point());
+    }
 }
@@ -0,0 +1,495 @@
+//! Deterministic input recording and playback.
+//!
+//! A movie stores the power-on state of the emulator (ROM hash, model, boot ROM
+//! and initial RTC) followed by the state of the joypads on every frame, as
+//! well as the frames where the emulator was reset.
+//!
+//! Recording must start right after the emulator is created (and optionally
+//! after the boot sequence is skipped with `GameBoy::boot`). Then, before each
+//! frame is emulated, call `Recorder::frame` (or `Player::frame` on playback).
+use crate::{
+    cartridge::Cartridge,
+    error,
+    gb::GameBoy,
+    joypad::{JoypadInput, MAX_PLAYERS},
+    ppu::LCD,
+    utils,
+};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const VERSION: u8 = 1;
+
+// frame record flags
+const RESET: u8 = 0b0000_0001;
+const PLAYERS_SHIFT: u8 = 1;
+
+// header flags
+const BOOT: u8 = 0b0000_0001;
+const RTC: u8 = 0b0000_0010;
+
+// longest movie that can be read (a day at 60 frames per second), so that
+// corrupted run lengths don't exhaust the memory
+const MAX_FRAMES: u64 = 60 * 60 * 60 * 24;
+
+/// Emulated hardware model.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Model {
+    DMG = 0,
+    CGB = 1,
+    SGB = 2,
+}
+
+impl Model {
+    /// Returns the model the emulator has been built for.
+    pub fn current() -> Self {
+        if cfg!(feature = "cgb") {
+            Self::CGB
+        } else if cfg!(feature = "sgb") {
+            Self::SGB
+        } else {
+            Self::DMG
+        }
+    }
+
+    fn from_u8(model: u8) -> Option<Self> {
+        match model {
+            0 => Some(Self::DMG),
+            1 => Some(Self::CGB),
+            2 => Some(Self::SGB),
+            _ => None,
+        }
+    }
+}
+
+/// Movie errors.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The file is not a movie or it is corrupted.
+    #[error("Invalid movie file")]
+    InvalidFile,
+
+    /// The movie was recorded with a different ROM.
+    #[error("Desync: ROM hash mismatch (expected {expected:08X}, found {found:08X})")]
+    Desync { expected: u32, found: u32 },
+
+    /// The movie was recorded on a different hardware model.
+    #[error("Model mismatch (expected {expected:?}, found {found:?})")]
+    Model { expected: Model, found: Model },
+
+    /// The movie was recorded with (or without) the boot ROM.
+    #[error("Boot ROM mismatch (boot ROM used: {0})")]
+    Boot(bool),
+
+    /// Emulation error during playback.
+    #[error("Emulation error: {0}")]
+    Emulation(#[from] error::Error),
+}
+
+/// Input of a single frame.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Frame {
+    /// The emulator was reset before this frame.
+    pub reset: bool,
+    /// State of the joypads during this frame.
+    pub input: [JoypadInput; MAX_PLAYERS],
+}
+
+/// Recorded movie.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Movie {
+    /// Hardware model.
+    pub model: Model,
+    /// The movie starts from the boot ROM (otherwise the boot sequence is skipped).
+    pub boot: bool,
+    /// Hash of the cartridge ROM (see `hash`).
+    pub rom_hash: u32,
+    /// RTC registers of the cartridge at power-on.
+    pub rtc: Option<[u8; 5]>,
+    /// Recorded frames.
+    pub frames: Vec<Frame>,
+}
+
+/// Hash used to identify ROMs (CRC-32).
+/// It can also be used to compare framebuffers in regression tests.
+pub fn hash(data: &[u8]) -> u32 {
+    utils::crc32(data)
+}
+
+impl Movie {
+    /// Create an empty movie from the current (power-on) state of the emulator.
+    pub fn new<C: Cartridge, O: LCD>(gb: &GameBoy<C, O>) -> Self {
+        Self {
+            model: Model::current(),
+            boot: gb.soc().boot.is_enabled(),
+            rom_hash: hash(gb.soc().cartridge.rom()),
+            rtc: gb.soc().cartridge.rtc(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Read a movie file.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || reader.read_u8()? != VERSION {
+            return Err(Error::InvalidFile);
+        }
+        let model = Model::from_u8(reader.read_u8()?).ok_or(Error::InvalidFile)?;
+        let flags = reader.read_u8()?;
+        let rom_hash = reader.read_u32::<LE>()?;
+        let rtc = if flags & RTC != 0 {
+            let mut rtc = [0; 5];
+            reader.read_exact(&mut rtc)?;
+            Some(rtc)
+        } else {
+            None
+        };
+
+        // frames are run-length encoded
+        let mut frames = Vec::new();
+        loop {
+            let flags = match reader.read_u8() {
+                Ok(flags) => flags,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            let mut frame = Frame {
+                reset: flags & RESET != 0,
+                ..Default::default()
+            };
+            for (player, input) in frame.input.iter_mut().enumerate() {
+                if (flags >> PLAYERS_SHIFT) & (1 << player) != 0 {
+                    *input = JoypadInput::from_bits_truncate(reader.read_u8()?);
+                }
+            }
+            let count = read_varint(&mut reader)?;
+            if count == 0 || frames.len() as u64 + count > MAX_FRAMES {
+                return Err(Error::InvalidFile);
+            }
+            // only the first frame of a run can be a reset
+            frames.push(frame);
+            frame.reset = false;
+            frames.extend(std::iter::repeat(frame).take(count as usize - 1));
+        }
+
+        Ok(Self {
+            model,
+            boot: flags & BOOT != 0,
+            rom_hash,
+            rtc,
+            frames,
+        })
+    }
+
+    /// Write the movie file.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(VERSION)?;
+        writer.write_u8(self.model as u8)?;
+        let mut flags = 0;
+        if self.boot {
+            flags |= BOOT;
+        }
+        if self.rtc.is_some() {
+            flags |= RTC;
+        }
+        writer.write_u8(flags)?;
+        writer.write_u32::<LE>(self.rom_hash)?;
+        if let Some(rtc) = &self.rtc {
+            writer.write_all(rtc)?;
+        }
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut count = 1u64;
+            while frames
+                .peek()
+                .map_or(false, |next| !next.reset && next.input == frame.input)
+            {
+                frames.next();
+                count += 1;
+            }
+            let mut flags = if frame.reset { RESET } else { 0 };
+            for (player, input) in frame.input.iter().enumerate() {
+                if !input.is_empty() {
+                    flags |= 1 << (player as u8 + PLAYERS_SHIFT);
+                }
+            }
+            writer.write_u8(flags)?;
+            for input in frame.input.iter().filter(|input| !input.is_empty()) {
+                writer.write_u8(input.bits())?;
+            }
+            write_varint(&mut writer, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Movie recorder.
+#[derive(Debug)]
+pub struct Recorder {
+    movie: Movie,
+    reset: bool,
+}
+
+impl Recorder {
+    /// Start recording from the current (power-on) state of the emulator.
+    pub fn new<C: Cartridge, O: LCD>(gb: &GameBoy<C, O>) -> Self {
+        Self {
+            movie: Movie::new(gb),
+            reset: false,
+        }
+    }
+
+    /// Record the state of the joypads.
+    /// Must be called once before every emulated frame.
+    pub fn frame<C: Cartridge, O: LCD>(&mut self, gb: &GameBoy<C, O>) {
+        let mut input = [JoypadInput::empty(); MAX_PLAYERS];
+        for (player, input) in input.iter_mut().enumerate() {
+            *input = gb.input(player);
+        }
+        self.movie.frames.push(Frame {
+            reset: std::mem::take(&mut self.reset),
+            input,
+        });
+    }
+
+    /// Reset the emulator and record the reset.
+    pub fn reset<C: Cartridge, O: LCD>(&mut self, gb: &mut GameBoy<C, O>) {
+        reset(gb, self.movie.boot);
+        self.reset = true;
+    }
+
+    /// Returns the movie recorded so far.
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Stop recording and return the movie.
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Movie player.
+#[derive(Debug)]
+pub struct Player {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Player {
+    /// Prepare the emulator for playback.
+    /// The emulator must be in its power-on state.
+    pub fn new<C: Cartridge, O: LCD>(movie: Movie, gb: &mut GameBoy<C, O>) -> Result<Self, Error> {
+        let found = hash(gb.soc().cartridge.rom());
+        if found != movie.rom_hash {
+            return Err(Error::Desync {
+                expected: movie.rom_hash,
+                found,
+            });
+        }
+        if movie.model != Model::current() {
+            return Err(Error::Model {
+                expected: movie.model,
+                found: Model::current(),
+            });
+        }
+        if movie.boot && !gb.soc().boot.is_enabled() {
+            return Err(Error::Boot(movie.boot));
+        }
+        if let Some(rtc) = movie.rtc {
+            gb.soc_mut().cartridge.set_rtc(rtc);
+        }
+        if !movie.boot && gb.soc().boot.is_enabled() {
+            gb.boot()?;
+        }
+        Ok(Self { movie, frame: 0 })
+    }
+
+    /// Apply the input of the next frame (resetting the emulator if needed).
+    /// Must be called once before every emulated frame.
+    /// Returns false if the movie has ended.
+    pub fn frame<C: Cartridge, O: LCD>(&mut self, gb: &mut GameBoy<C, O>) -> bool {
+        let Some(frame) = self.movie.frames.get(self.frame) else {
+            return false;
+        };
+        if frame.reset {
+            reset(gb, self.movie.boot);
+        }
+        for (player, input) in frame.input.iter().enumerate() {
+            gb.set_input(player, *input);
+        }
+        self.frame += 1;
+        true
+    }
+
+    /// Play the rest of the movie.
+    pub fn run<C: Cartridge, O: LCD>(&mut self, gb: &mut GameBoy<C, O>) -> Result<(), Error> {
+        while self.frame(gb) {
+            gb.next_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames played so far.
+    pub fn position(&self) -> usize {
+        self.frame
+    }
+
+    /// Returns true if all the frames have been played.
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Returns the movie being played.
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+// resets the emulator, skipping the boot sequence if the movie does, so that
+// the recording and the playback stay in sync
+fn reset<C: Cartridge, O: LCD>(gb: &mut GameBoy<C, O>, boot: bool) {
+    gb.reset();
+    if !boot {
+        // boot can't fail after a reset
+        gb.boot().unwrap();
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidFile)
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_u8(byte);
+        }
+        writer.write_u8(byte | 0x80)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hash, Error, Frame, Movie, Player, Recorder};
+    use crate::{cartridge::ROM, device::Device, gb::GameBoy, joypad::JoypadInput};
+
+    fn rom(byte: u8) -> ROM {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134] = byte;
+        ROM::new(rom.into_boxed_slice())
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(0xcbf43926, hash(b"123456789"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let gb = GameBoy::new(rom(0), ());
+        let mut movie = Movie::new(&gb);
+        let input = [
+            JoypadInput::A,
+            JoypadInput::empty(),
+            JoypadInput::UP,
+            JoypadInput::empty(),
+        ];
+        movie.frames.extend([Frame::default(); 300]);
+        movie.frames.push(Frame { reset: true, input });
+        movie.frames.push(Frame {
+            reset: false,
+            input,
+        });
+        movie.frames.push(Frame::default());
+
+        let mut file = Vec::new();
+        movie.write(&mut file).unwrap();
+        assert!(file.len() < 32);
+        assert_eq!(movie, Movie::read(&file[..]).unwrap());
+
+        // run longer than a day
+        let mut file = Vec::new();
+        Movie::new(&gb).write(&mut file).unwrap();
+        file.extend([0x00, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(matches!(Movie::read(&file[..]), Err(Error::InvalidFile)));
+    }
+
+    #[test]
+    fn record() {
+        let mut gb = GameBoy::new(rom(0), ());
+        let mut recorder = Recorder::new(&gb);
+        gb.press(&crate::joypad::Button::Start);
+        recorder.frame(&gb);
+        recorder.reset(&mut gb);
+        recorder.frame(&gb);
+        let movie = recorder.finish();
+        assert_eq!(2, movie.frames.len());
+        assert_eq!(JoypadInput::START, movie.frames[0].input[0]);
+        assert!(movie.frames[1].reset);
+    }
+
+    #[test]
+    fn replay_reset() {
+        let rom = || {
+            let mut rom = vec![0; 0x8000];
+            // LD HL,$C000; INC (HL); JR -3
+            rom[0x100..0x106].copy_from_slice(&[0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd]);
+            ROM::new(rom.into_boxed_slice())
+        };
+        let mut gb = GameBoy::new(rom(), ());
+        gb.boot().unwrap();
+        let mut recorder = Recorder::new(&gb);
+        for frame in 0..6 {
+            if frame == 3 {
+                recorder.reset(&mut gb);
+            }
+            recorder.frame(&gb);
+            gb.next_frame().unwrap();
+        }
+        let movie = recorder.finish();
+        assert!(!movie.boot);
+
+        let mut replay = GameBoy::new(rom(), ());
+        Player::new(movie, &mut replay)
+            .unwrap()
+            .run(&mut replay)
+            .unwrap();
+        let ram = |gb: &GameBoy<ROM, ()>| {
+            (0xc000..0xc010)
+                .map(|address| gb.soc().read(address).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(0, ram(&gb)[0]);
+        assert_eq!(ram(&gb), ram(&replay));
+        assert_eq!(gb.save_state(), replay.save_state());
+    }
+
+    #[test]
+    fn desync() {
+        let movie = Movie::new(&GameBoy::new(rom(0), ()));
+        let mut gb = GameBoy::new(rom(1), ());
+        assert!(matches!(
+            Player::new(movie, &mut gb),
+            Err(Error::Desync { .. })
+        ));
+    }
+}
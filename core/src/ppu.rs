@@ -4,7 +4,7 @@ use crate::ppu::io::ColorPalette as ColorPaletteIO;
 use crate::ram::vram::Attributes;
 use crate::{
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
     irq,
     ppu::{
         io::{Palette, Scroll, Window, LCDC, STAT},
@@ -12,6 +12,7 @@ use crate::{
         oam::{Entry, Flags, OAM},
     },
     ram::vram::VRAM,
+    state::State,
     Update,
 };
 #[cfg(feature = "serde")]
@@ -23,7 +24,7 @@ use wasm_bindgen::prelude::*;
 #[cfg(feature = "lcd_debug_overlay")]
 mod debug;
 mod io;
-mod lcd;
+pub(crate) mod lcd;
 mod oam;
 
 pub type Color = [u8; 4];
@@ -46,6 +47,12 @@ pub const PALETTE: [Color; 4] = [
 /// A trait for types to drive output of the PPU.
 pub trait LCD {
     fn output_line(&mut self, ly: u8, data: &[Color; LCD_WIDTH]);
+
+    /// Called with `true` before frames that were already output are emulated
+    /// again (when rewinding), and with `false` after them. Outputs that
+    /// record the frames should skip them.
+    #[allow(unused_variables)]
+    fn replay(&mut self, replaying: bool) {}
 }
 
 #[cfg(feature = "lcd_debug_overlay")]
@@ -82,6 +89,9 @@ pub struct PPU<O: LCD> {
     color_palette: ColorPaletteIO,
     #[cfg(feature = "lcd_debug_overlay")]
     pub lcd_debug_overlay: LCDDebugOverlay,
+    // shade (0-3) of every pixel of the last frame, palettized by the SGB
+    #[cfg(feature = "sgb")]
+    shades: Box<[u8]>,
 }
 
 impl<O: LCD> PPU<O> {
@@ -101,7 +111,28 @@ impl<O: LCD> PPU<O> {
             color_palette: Default::default(),
             #[cfg(feature = "lcd_debug_overlay")]
             lcd_debug_overlay: LCDDebugOverlay::empty(),
+            #[cfg(feature = "sgb")]
+            shades: vec![0; LCD_WIDTH * LCD_HEIGHT].into_boxed_slice(),
+        }
+    }
+
+    // Reset to the power-on state (the output is kept).
+    pub(crate) fn reset(&mut self) {
+        *self.line = Default::default();
+        *self.color_line = Default::default();
+        self.oam = Default::default();
+        self.video_ram = Default::default();
+        self.lcdc = Default::default();
+        self.stat = Default::default();
+        self.scroll = Default::default();
+        self.window = Default::default();
+        self.palette = Default::default();
+        #[cfg(feature = "cgb")]
+        {
+            self.color_palette = Default::default();
         }
+        #[cfg(feature = "sgb")]
+        self.shades.fill(0);
     }
 
     pub fn output(&self) -> &O {
@@ -141,6 +172,38 @@ impl<O: LCD> PPU<O> {
         &self.video_ram
     }
 
+    pub(crate) fn vram_mut(&mut self) -> &mut VRAM {
+        &mut self.video_ram
+    }
+
+    #[cfg(feature = "sgb")]
+    pub(crate) fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    // Copy the tile data of the tiles displayed on the screen, in BG map order.
+    // This is how the SGB receives data from VRAM (20 tiles per row).
+    #[cfg(feature = "sgb")]
+    pub(crate) fn screen_tile_data(&self, buf: &mut [u8]) {
+        let map = self.lcdc.bg_map_select();
+        let data_select = self.lcdc.bg_window_data_select();
+        for (i, tile) in buf.chunks_exact_mut(16).enumerate() {
+            let (row, col) = ((i / 20) as u16, (i % 20) as u16);
+            let index = self.video_ram.data(0, map + 32 * row + col);
+            let address = data_select + decode_tile_data_offset(index, data_select) * 16;
+            for (offset, byte) in tile.iter_mut().enumerate() {
+                *byte = self.video_ram.data(0, address + offset as u16);
+            }
+        }
+    }
+
+    // Record the shade of a pixel (the color mapped by a DMG palette register),
+    // as it's drawn.
+    #[cfg(feature = "sgb")]
+    fn record_shade(&mut self, ly: u8, dot: usize, palette: u8, color_id: ColorId) {
+        self.shades[ly as usize * LCD_WIDTH + dot] = (palette >> (2 * color_id)) & 0b11;
+    }
+
     fn clear_display(&mut self) {
         #[cfg(not(feature = "cgb"))]
         let color = PALETTE[0];
@@ -161,6 +224,9 @@ impl<O: LCD> PPU<O> {
     }
 
     fn draw_scanline(&mut self, ly: u8, dots: Range<usize>) {
+        // blank (white) unless the background is drawn
+        #[cfg(feature = "sgb")]
+        self.shades[ly as usize * LCD_WIDTH..][dots.clone()].fill(0);
         // When Bit 0 is cleared, both background and window become blank (white), and
         // the Window Display Bit is ignored in that case. Only Sprites may still be
         // displayed (if enabled in Bit 1).
@@ -204,6 +270,8 @@ impl<O: LCD> PPU<O> {
                 self.decode_bg_win(row, col, self.lcdc.bg_map_select())
             };
 
+            #[cfg(feature = "sgb")]
+            self.record_shade(ly, dot, self.palette.registers()[0], color_id);
             self.draw_pixel(dot, color, color_id, attributes);
         }
     }
@@ -261,18 +329,27 @@ impl<O: LCD> PPU<O> {
                     #[cfg(feature = "cgb")]
                     let bank = flags.bank();
 
+                    let decoded = self.decode_tile(
+                        row as u16,
+                        col as u16,
+                        index,
+                        bank,
+                        0x8000,
+                        color_palette,
+                        true,
+                    );
+                    #[cfg(feature = "sgb")]
+                    if let Some((_, color_id)) = decoded {
+                        let [_, obp0, obp1] = self.palette.registers();
+                        let palette = if flags.contains(Flags::PAL_NUMBER) {
+                            obp1
+                        } else {
+                            obp0
+                        };
+                        self.record_shade(ly as u8, dot as usize, palette, color_id);
+                    }
                     #[allow(unused_mut)]
-                    let (mut color, _) = self
-                        .decode_tile(
-                            row as u16,
-                            col as u16,
-                            index,
-                            bank,
-                            0x8000,
-                            color_palette,
-                            true,
-                        )
-                        .unwrap_or((color, 0));
+                    let (mut color, _) = decoded.unwrap_or((color, 0));
 
                     #[cfg(feature = "lcd_debug_overlay")]
                     if self.lcd_debug_overlay.contains(LCDDebugOverlay::SPRITES) {
@@ -452,6 +529,38 @@ impl<O: LCD> Update for PPU<O> {
     }
 }
 
+// The output and the line buffers (scratch space) are not part of the state.
+impl<O: LCD> State for PPU<O> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.oam.save(buf);
+        self.video_ram.save(buf);
+        self.lcdc.save(buf);
+        self.stat.save(buf);
+        self.scroll.save(buf);
+        self.window.save(buf);
+        self.palette.save(buf);
+        #[cfg(feature = "cgb")]
+        self.color_palette.save(buf);
+        #[cfg(feature = "sgb")]
+        self.shades.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.oam.load(buf)?;
+        self.video_ram.load(buf)?;
+        self.lcdc.load(buf)?;
+        self.stat.load(buf)?;
+        self.scroll.load(buf)?;
+        self.window.load(buf)?;
+        self.palette.load(buf)?;
+        #[cfg(feature = "cgb")]
+        self.color_palette.load(buf)?;
+        #[cfg(feature = "sgb")]
+        self.shades.load(buf)?;
+        Ok(())
+    }
+}
+
 impl<O: LCD> Device for PPU<O> {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
         dev_read! {
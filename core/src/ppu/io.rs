@@ -1,7 +1,8 @@
 use crate::{
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
     ppu::{Color, PALETTE},
+    state::State,
 };
 pub use lcdc::LCDC;
 #[cfg(feature = "serde")]
@@ -18,6 +19,8 @@ pub struct Scroll {
     pub scx: u8,
 }
 
+crate::state::impl_state!(Scroll { scy, scx });
+
 impl Device for Scroll {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
         dev_read! {
@@ -55,6 +58,8 @@ pub struct Window {
     pub wx: u8,
 }
 
+crate::state::impl_state!(Window { wy, wx });
+
 impl Device for Window {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
         dev_read! {
@@ -115,6 +120,24 @@ impl Default for Palette {
     }
 }
 
+// the cached colors are recomputed when the state is loaded
+impl State for Palette {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.bgp.save(buf);
+        self.obp0.save(buf);
+        self.obp1.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        for address in 0xff47..=0xff49 {
+            let mut data = 0u8;
+            data.load(buf)?;
+            self.write(address, data).unwrap();
+        }
+        Ok(())
+    }
+}
+
 impl Palette {
     #[allow(unused)]
     pub fn bgp(&self) -> &[Color; 4] {
@@ -131,6 +154,12 @@ impl Palette {
         &self.obp1_cache
     }
 
+    /// Returns the BGP, OBP0 and OBP1 registers.
+    #[cfg(feature = "sgb")]
+    pub(crate) fn registers(&self) -> [u8; 3] {
+        [self.bgp, self.obp0, self.obp1]
+    }
+
     #[allow(unused)]
     pub fn palette(pal: u8) -> [Color; 4] {
         [
@@ -201,6 +230,25 @@ impl Default for ColorPalette {
     }
 }
 
+#[cfg(feature = "cgb")]
+impl State for ColorPalette {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.bgpi.save(buf);
+        self.obpi.save(buf);
+        self.bgp.save(buf);
+        self.obp.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.bgpi.load(buf)?;
+        self.obpi.load(buf)?;
+        self.bgp.load(buf)?;
+        self.obp.load(buf)?;
+        self.compute_palettes();
+        Ok(())
+    }
+}
+
 #[cfg(feature = "cgb")]
 impl ColorPalette {
     fn compute_palettes(&mut self) {
@@ -27,6 +27,9 @@ pub struct LCDC {
     flags: Flags,
 }
 
+crate::state::impl_state_bitflags!(Flags);
+crate::state::impl_state!(LCDC { flags });
+
 impl LCDC {
     pub fn reset(&mut self) {}
 
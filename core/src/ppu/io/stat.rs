@@ -33,6 +33,14 @@ pub struct STAT {
     pub lyc_hist: Box<[bool; LCD_HEIGHT]>,
 }
 
+crate::state::impl_state!(STAT {
+    dots,
+    stat,
+    ly,
+    lyc,
+    lyc_hist
+});
+
 impl Default for STAT {
     fn default() -> Self {
         Self {
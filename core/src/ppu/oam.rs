@@ -36,6 +36,8 @@ impl Flags {
     }
 }
 
+crate::state::impl_state_bitflags!(Flags);
+
 #[repr(C)]
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -46,12 +48,16 @@ pub struct Entry {
     pub flags: Flags,
 }
 
+crate::state::impl_state!(Entry { y, x, index, flags });
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OAM {
     table: Box<[Entry]>,
 }
 
+crate::state::impl_state!(OAM { table });
+
 impl Default for OAM {
     fn default() -> Self {
         Self {
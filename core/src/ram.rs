@@ -1,3 +1,3 @@
 pub(crate) mod hram;
 pub mod vram;
-pub(crate) mod wram;
+pub mod wram;
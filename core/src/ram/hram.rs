@@ -12,6 +12,8 @@ pub struct HRAM {
     data: Box<[u8]>,
 }
 
+crate::state::impl_state!(HRAM { data });
+
 impl Default for HRAM {
     fn default() -> Self {
         Self {
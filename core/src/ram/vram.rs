@@ -1,6 +1,7 @@
 use crate::{
     device::Device,
-    error::{ReadError, WriteError},
+    error::{ReadError, StateError, WriteError},
+    state::State,
 };
 use bitflags::bitflags;
 #[cfg(feature = "serde")]
@@ -138,7 +139,33 @@ impl Default for VRAM {
     }
 }
 
+// the tile data cache is rebuilt from the restored data
+impl State for VRAM {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.data.save(buf);
+        self.bank.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.data.load(buf)?;
+        self.bank.load(buf)?;
+        let banks = if cfg!(feature = "cgb") { 2 } else { 1 };
+        for bank in 0..banks {
+            for address in (0x8000..0x9800).step_by(2) {
+                let data = [0, self.data(bank, address), self.data(bank, address + 1)];
+                self.tile_data_cache.update_cache(address, data, bank);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl VRAM {
+    /// Returns the VRAM bank mapped at 0x8000-0x9fff.
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
     pub fn tile_data_cache(&self) -> &TileDataCache {
         &self.tile_data_cache
     }
@@ -148,6 +175,25 @@ impl VRAM {
         self.data[offset + (address as usize) - 0x8000]
     }
 
+    // Write to the given bank (keeping the tile data cache up to date).
+    pub(crate) fn write_bank(&mut self, bank: usize, address: u16, data: u8) {
+        if let 0x8000..=0x97ff = address {
+            let prev = if address > 0x8000 {
+                self.data(bank, address - 1)
+            } else {
+                0x00
+            };
+            let next = if address < 0x97ff {
+                self.data(bank, address + 1)
+            } else {
+                0x00
+            };
+            self.tile_data_cache
+                .update_cache(address, [prev, data, next], bank);
+        }
+        self.data[0x2000 * bank + (address as usize) - 0x8000] = data;
+    }
+
     #[cfg(feature = "cgb")]
     pub(crate) fn attributes(&self, address: u16) -> Attributes {
         Attributes::from_bits(self.data(1, address)).unwrap()
@@ -176,14 +222,7 @@ impl Device for VRAM {
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
         dev_write! {
             address, data {
-                0x8000..=0x9fff => {
-                    if let 0x8000..=0x97ff = address {
-                        let prev = if address > 0x8000 { self.read(address-1).unwrap() } else { 0x00 };
-                        let next = if address < 0x97ff { self.read(address+1).unwrap() } else { 0x00 };
-                        self.tile_data_cache.update_cache(address, [prev, data, next], self.bank);
-                    }
-                    self.data[self.bank_address(address)] = data;
-                },
+                0x8000..=0x9fff => self.write_bank(self.bank, address, data),
                 0xff4f => {
                     self.bank = (data & 1) as _;
                 }
@@ -13,6 +13,8 @@ pub struct WRAM {
     svbk: u8,
 }
 
+crate::state::impl_state!(WRAM { data, svbk });
+
 impl Default for WRAM {
     fn default() -> Self {
         Self {
@@ -23,13 +25,23 @@ impl Default for WRAM {
 }
 
 impl WRAM {
-    fn bank(&self) -> usize {
-        (self.svbk & 0x7) as usize
+    /// Returns the WRAM bank (1-7) mapped at 0xd000-0xdfff.
+    pub fn bank(&self) -> usize {
+        (self.svbk & 0x7).max(1) as usize
+    }
+
+    // Read from the given bank (1-7) of the switchable region (0xd000-0xdfff).
+    pub(crate) fn read_bank(&self, bank: usize, address: u16) -> u8 {
+        self.data[(address as usize) - 0xd000 + (bank.max(1) * 0x1000)]
+    }
+
+    // Write to the given bank (1-7) of the switchable region (0xd000-0xdfff).
+    pub(crate) fn write_bank(&mut self, bank: usize, address: u16, data: u8) {
+        self.data[(address as usize) - 0xd000 + (bank.max(1) * 0x1000)] = data;
     }
 
     fn bank_addr(&self, address: u16) -> usize {
-        let bank = self.bank().max(1);
-        (address as usize) - 0xd000 + (bank * 0x1000)
+        (address as usize) - 0xd000 + (self.bank() * 0x1000)
     }
 }
 
@@ -0,0 +1,448 @@
+//! Rewind buffer.
+//!
+//! Save states are taken every few frames and kept in a ring buffer. To bound
+//! the memory use, only some of the states (keyframes) are stored in full. The
+//! rest are stored as the XOR against the previous keyframe, run-length encoded
+//! (most of the state doesn't change between frames so the XOR is mostly
+//! zeroes).
+//!
+//! The input of the joypads is also recorded on every frame, so the emulator
+//! can be rewound to any frame (not just the ones with a save state) by loading
+//! the closest state and emulating the frames in between. The outputs that
+//! record the emulation (audio, video capture, tracer, logs and profiler) don't
+//! receive the frames emulated again.
+use crate::{
+    apu::AudioOutput,
+    cartridge::Cartridge,
+    debug::{profile::Profiler, NextFrame},
+    error::Error,
+    joypad::{JoypadInput, MAX_PLAYERS},
+    ppu::LCD,
+    state::State,
+    trace::Tracer,
+    LR35902,
+};
+use std::collections::VecDeque;
+use utils::{cdl::CodeDataLog, vgm::VgmLog};
+
+/// Default memory budget (in bytes).
+pub const DEFAULT_BUDGET: usize = 32 * 1024 * 1024;
+
+/// Default number of frames between save states.
+pub const DEFAULT_INTERVAL: usize = 4;
+
+// max number of deltas stored against the same keyframe
+const KEYFRAME_DELTAS: usize = 30;
+
+struct Keyframe {
+    frame: u64,
+    state: Vec<u8>,
+    // (frame, encoded XOR against the keyframe state)
+    deltas: Vec<(u64, Vec<u8>)>,
+}
+
+impl Keyframe {
+    fn size(&self) -> usize {
+        self.state.len() + self.deltas.iter().map(|(_, d)| d.len()).sum::<usize>()
+    }
+}
+
+/// Ring buffer of compressed save states.
+pub struct Rewind {
+    budget: usize,
+    interval: u64,
+    // number of frames recorded (the frame about to be emulated)
+    frame: u64,
+    keyframes: VecDeque<Keyframe>,
+    // input of every frame, starting from the oldest keyframe
+    inputs: VecDeque<[JoypadInput; MAX_PLAYERS]>,
+    size: usize,
+    state: Vec<u8>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET, DEFAULT_INTERVAL)
+    }
+}
+
+impl Rewind {
+    /// Create a rewind buffer taking up to `budget` bytes, with a save state
+    /// taken every `interval` frames.
+    ///
+    /// The oldest keyframe is always kept, even if it doesn't fit the budget.
+    pub fn new(budget: usize, interval: usize) -> Self {
+        assert!(interval > 0);
+        Self {
+            budget,
+            interval: interval as u64,
+            frame: 0,
+            keyframes: VecDeque::new(),
+            inputs: VecDeque::new(),
+            size: 0,
+            state: Vec::new(),
+        }
+    }
+
+    /// Returns the memory budget (in bytes).
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Returns the memory used by the buffer (in bytes).
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of frames that can be rewound.
+    pub fn len(&self) -> usize {
+        // at least one frame is emulated after loading a state (see `rewind`)
+        self.inputs.len().saturating_sub(1)
+    }
+
+    /// Returns true if the buffer holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop all the save states.
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.inputs.clear();
+        self.size = 0;
+    }
+
+    // first frame in the buffer
+    fn base(&self) -> u64 {
+        self.keyframes
+            .front()
+            .map(|k| k.frame)
+            .unwrap_or(self.frame)
+    }
+
+    // must be called before emulating a new frame
+    pub(crate) fn frame<C: Cartridge, O: LCD>(&mut self, soc: &LR35902<C, O>) {
+        if self.frame % self.interval == 0 {
+            self.snapshot(soc);
+        }
+        if !self.keyframes.is_empty() {
+            let mut input = [JoypadInput::empty(); MAX_PLAYERS];
+            for (player, input) in input.iter_mut().enumerate() {
+                *input = soc.joypad.input(player);
+            }
+            self.inputs.push_back(input);
+            self.size += MAX_PLAYERS;
+        }
+        self.frame += 1;
+    }
+
+    fn snapshot<C: Cartridge, O: LCD>(&mut self, soc: &LR35902<C, O>) {
+        self.state.clear();
+        soc.save(&mut self.state);
+
+        match self.keyframes.back_mut() {
+            Some(keyframe) if keyframe.deltas.len() < KEYFRAME_DELTAS => {
+                let delta = encode(&keyframe.state, &self.state);
+                self.size += delta.len();
+                keyframe.deltas.push((self.frame, delta));
+            }
+            _ => {
+                self.size += self.state.len();
+                self.keyframes.push_back(Keyframe {
+                    frame: self.frame,
+                    state: self.state.clone(),
+                    deltas: Vec::new(),
+                });
+            }
+        }
+
+        // drop the oldest states
+        while self.size > self.budget && self.keyframes.len() > 1 {
+            let keyframe = self.keyframes.pop_front().unwrap();
+            self.size -= keyframe.size();
+            let frames = self.base() - keyframe.frame;
+            self.inputs.drain(..frames as usize);
+            self.size -= MAX_PLAYERS * frames as usize;
+        }
+    }
+
+    // Rewind the emulator by the given number of frames.
+    // Returns the number of frames rewound.
+    pub(crate) fn rewind<C: Cartridge, O: LCD>(
+        &mut self,
+        soc: &mut LR35902<C, O>,
+        frames: usize,
+    ) -> Result<usize, Error> {
+        // the closest state *before* the target frame is loaded so that at least
+        // one frame is emulated and the LCD output reflects the rewound frame
+        let target = self
+            .frame
+            .saturating_sub(frames as u64)
+            .max(self.base() + 1);
+        if self.keyframes.is_empty() || target >= self.frame {
+            return Ok(0);
+        }
+        let rewound = self.frame - target;
+        let base = self.base();
+
+        let mut k = self
+            .keyframes
+            .iter()
+            .rposition(|k| k.frame < target)
+            .unwrap();
+        let keyframe = &self.keyframes[k];
+        let d = keyframe.deltas.iter().rposition(|(f, _)| *f < target);
+        let frame = match d {
+            Some(d) => {
+                let (frame, delta) = &keyframe.deltas[d];
+                decode(&keyframe.state, delta, &mut self.state);
+                *frame
+            }
+            None => {
+                self.state.clear();
+                self.state.extend_from_slice(&keyframe.state);
+                keyframe.frame
+            }
+        };
+        let sinks = Sinks::detach(soc);
+        soc.load(&mut &self.state[..])
+            .expect("Error loading rewind state");
+
+        // drop the states after (and including) the loaded one
+        // the states are taken again when the inputs are replayed
+        if let Some(d) = d {
+            for (_, delta) in self.keyframes[k].deltas.drain(d..) {
+                self.size -= delta.len();
+            }
+            k += 1;
+        }
+        for keyframe in self.keyframes.drain(k..) {
+            self.size -= keyframe.size();
+        }
+
+        let start = (frame - base) as usize;
+        let end = (target - base) as usize;
+        let inputs: Vec<_> = self.inputs.drain(start..).take(end - start).collect();
+        self.size -= MAX_PLAYERS * (self.frame - frame) as usize;
+        self.frame = frame;
+
+        let mut result = Ok(rewound as usize);
+        for input in inputs {
+            for (player, input) in input.into_iter().enumerate() {
+                soc.set_input(player, input);
+            }
+            self.frame(soc);
+            if let Err(err) = soc.step_breakpoint(NextFrame::new()) {
+                result = Err(err);
+                break;
+            }
+        }
+        sinks.attach(soc);
+        result
+    }
+}
+
+// Outputs that record the emulation, detached while frames are emulated again
+// so they don't receive them twice.
+struct Sinks {
+    audio: Box<dyn AudioOutput + Send>,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    code_data_log: Option<CodeDataLog>,
+    vgm_log: Option<VgmLog>,
+    profiler: Option<Profiler>,
+}
+
+impl Sinks {
+    fn detach<C: Cartridge, O: LCD>(soc: &mut LR35902<C, O>) -> Self {
+        soc.ppu.output_mut().replay(true);
+        Self {
+            audio: soc.apu.set_output(Box::new(())),
+            tracer: soc.set_tracer(None),
+            code_data_log: soc.set_code_data_log(None),
+            vgm_log: soc.set_vgm_log(None),
+            profiler: soc.cpu_mut().set_profiler(None),
+        }
+    }
+
+    // the VGM log gets the writes that bring the APU to the rewound state
+    fn attach<C: Cartridge, O: LCD>(self, soc: &mut LR35902<C, O>) {
+        soc.ppu.output_mut().replay(false);
+        soc.apu.set_output(self.audio);
+        soc.set_tracer(self.tracer);
+        soc.set_code_data_log(self.code_data_log);
+        soc.set_vgm_log(self.vgm_log);
+        soc.cpu_mut().set_profiler(self.profiler);
+    }
+}
+
+// RLE encoding of the XOR of two states, as a sequence of:
+// [zero run length (varint)] [literal length (varint)] [literal bytes]
+// preceded by the length of the state (the states may differ in size).
+fn encode(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| state[i] ^ keyframe.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    write_varint(&mut out, state.len());
+    let mut i = 0;
+    while i < state.len() {
+        let zeroes = (i..state.len()).take_while(|i| xor(*i) == 0).count();
+        i += zeroes;
+        // short zero runs are cheaper to store as literals
+        let mut literal = 0;
+        while i + literal < state.len() {
+            let run = (i + literal..state.len())
+                .take(4)
+                .take_while(|i| xor(*i) == 0)
+                .count();
+            if run == 4 || i + literal + run == state.len() {
+                break;
+            }
+            literal += run.max(1);
+        }
+        write_varint(&mut out, zeroes);
+        write_varint(&mut out, literal);
+        out.extend((i..i + literal).map(xor));
+        i += literal;
+    }
+    out
+}
+
+fn decode(keyframe: &[u8], mut delta: &[u8], out: &mut Vec<u8>) {
+    let len = read_varint(&mut delta);
+    out.clear();
+    out.extend((0..len).map(|i| keyframe.get(i).copied().unwrap_or(0)));
+    let mut i = 0;
+    while !delta.is_empty() {
+        i += read_varint(&mut delta);
+        let literal = read_varint(&mut delta);
+        for (out, byte) in out[i..i + literal].iter_mut().zip(&delta[..literal]) {
+            *out ^= byte;
+        }
+        delta = &delta[literal..];
+        i += literal;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, Rewind, DEFAULT_BUDGET};
+    use crate::{apu::AudioOutput, cartridge::ROM, gb::GameBoy, joypad::JoypadInput};
+    use std::sync::{Arc, Mutex};
+
+    // INC A; LD (0xc000),A; JR -6
+    fn gb() -> GameBoy<ROM, ()> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0x3c, 0xea, 0x00, 0xc0, 0x18, 0xfa]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        gb
+    }
+
+    fn input(frame: usize) -> JoypadInput {
+        if frame % 3 == 0 {
+            JoypadInput::A
+        } else {
+            JoypadInput::empty()
+        }
+    }
+
+    #[test]
+    fn rewind() {
+        let mut gb = gb();
+        gb.set_rewind(Some(Rewind::new(DEFAULT_BUDGET, 4)));
+        let mut states = Vec::new();
+        for frame in 0..40 {
+            gb.set_input(0, input(frame));
+            states.push(gb.save_state());
+            gb.next_frame().unwrap();
+        }
+        assert_eq!(39, gb.rewind_buffer().unwrap().len());
+
+        // the first frame can't be rewound (no frames before it are emulated)
+        for (frames, rewound, target) in [(10, 10, 30), (1, 1, 29), (3, 3, 26), (1000, 25, 1)] {
+            assert_eq!(rewound, gb.rewind(frames).unwrap());
+            gb.set_input(0, input(target));
+            assert_eq!(states[target], gb.save_state());
+        }
+        assert_eq!(0, gb.rewind(1).unwrap());
+    }
+
+    #[test]
+    fn sinks() {
+        struct Samples(usize);
+
+        impl AudioOutput for Samples {
+            fn sample(&mut self, _left: i16, _right: i16) {
+                self.0 += 1;
+            }
+        }
+
+        let mut gb = gb();
+        gb.set_rewind(Some(Rewind::new(DEFAULT_BUDGET, 4)));
+        let samples = Arc::new(Mutex::new(Samples(0)));
+        gb.soc_mut().set_audio_output(Arc::clone(&samples));
+        for _ in 0..20 {
+            gb.next_frame().unwrap();
+        }
+        let frame = samples.lock().unwrap().0;
+        assert!(frame > 0);
+
+        assert_eq!(10, gb.rewind(10).unwrap());
+        assert_eq!(frame, samples.lock().unwrap().0);
+        // still connected
+        gb.next_frame().unwrap();
+        assert!(samples.lock().unwrap().0 > frame);
+    }
+
+    #[test]
+    fn budget() {
+        let mut gb = gb();
+        gb.set_rewind(Some(Rewind::new(0, 1)));
+        for _ in 0..60 {
+            gb.next_frame().unwrap();
+        }
+        let rewind = gb.rewind_buffer().unwrap();
+        assert!(rewind.len() < 59);
+        assert_eq!(rewind.len(), gb.rewind(60).unwrap());
+    }
+
+    #[test]
+    fn delta() {
+        let keyframe = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let states: &[&[u8]] = &[
+            &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            &[0, 1, 2, 0, 4, 5, 6, 7, 8, 9, 10, 11, 0],
+            &[1, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14],
+            &[0, 1],
+            &[],
+        ];
+        let mut out = Vec::new();
+        for state in states {
+            let delta = encode(&keyframe, state);
+            decode(&keyframe, &delta, &mut out);
+            assert_eq!(state, &out);
+        }
+        assert_eq!(3, encode(&keyframe, &keyframe).len());
+    }
+}
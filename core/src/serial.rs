@@ -1,24 +1,125 @@
 use crate::{
     device::Device,
-    error::{Component, ReadError, WriteError},
+    error::{ReadError, WriteError},
+    irq, Update,
 };
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+// Bit 7 - Transfer Start Flag (0=No transfer in progress or requested, 1=Transfer in progress, or requested)
+// Bit 0 - Shift Clock (0=External Clock, 1=Internal Clock)
+const START: u8 = 0b1000_0000;
+const INTERNAL: u8 = 0b0000_0001;
+
+// 8 bits at 8192Hz
+const TRANSFER_TICKS: u64 = 8 * 512;
+
+// bytes kept in the output buffer until they are taken
+const OUTPUT_LEN: usize = 0x10000;
+
+/// Serial port (SB & SC registers).
+///
+/// Nothing is connected to the port: transfers using the internal clock
+/// complete after 8 bits, shifting in `0xff`, and transfers using an external
+/// clock never complete. Transmitted bytes are kept in an output buffer (test
+/// ROMs print their results that way).
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct Serial;
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // remaining ticks of the transfer in progress
+    ticks: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    output: Vec<u8>,
+}
+
+crate::state::impl_state!(Serial { sb, sc, ticks });
+
+impl Serial {
+    /// Returns the bytes transmitted since the output was last taken.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Take the bytes transmitted so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Update for Serial {
+    fn update(&mut self, ticks: u64, flags: &mut irq::Flags) {
+        if self.ticks == 0 {
+            return;
+        }
+        self.ticks = self.ticks.saturating_sub(ticks);
+        if self.ticks == 0 {
+            if self.output.len() == OUTPUT_LEN {
+                self.output.remove(0);
+            }
+            self.output.push(self.sb);
+            self.sb = 0xff;
+            self.sc &= !START;
+            flags.set(irq::Flags::SERIAL, true);
+        }
+    }
+}
 
 impl Device for Serial {
     fn read(&self, address: u16) -> Result<u8, ReadError> {
-        Err(ReadError::AddrNotImpl(address, Some(Component::Serial)))
+        dev_read! {
+            address {
+                0xff01 => Ok(self.sb),
+                // unused bits 1-6 always read back as 1
+                0xff02 => Ok(self.sc | 0b0111_1110),
+            }
+        }
     }
 
     fn write(&mut self, address: u16, data: u8) -> Result<(), WriteError> {
-        Err(WriteError::AddrNotImpl(
-            address,
-            data,
-            Some(Component::Serial),
-        ))
+        dev_write! {
+            address, data {
+                0xff01 => self.sb = data,
+                0xff02 => {
+                    self.sc = data & (START | INTERNAL);
+                    self.ticks = if self.sc == START | INTERNAL {
+                        TRANSFER_TICKS
+                    } else {
+                        0
+                    };
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Serial;
+    use crate::{device::Device, irq, Update};
+
+    #[test]
+    fn transfer() {
+        let mut serial = Serial::default();
+        let mut flags = irq::Flags::empty();
+        serial.write(0xff01, b'A').unwrap();
+        serial.write(0xff02, 0x81).unwrap();
+        serial.update(4000, &mut flags);
+        assert_eq!(0xff, serial.read(0xff02).unwrap());
+        assert!(flags.is_empty());
+        serial.update(96, &mut flags);
+        assert_eq!(0x7f, serial.read(0xff02).unwrap());
+        assert_eq!(0xff, serial.read(0xff01).unwrap());
+        assert!(flags.contains(irq::Flags::SERIAL));
+        assert_eq!(b"A".to_vec(), serial.take_output());
+        assert!(serial.output().is_empty());
+
+        // external clock: nothing on the other end
+        serial.write(0xff02, 0x80).unwrap();
+        serial.update(1 << 20, &mut flags);
+        assert_eq!(0xfe, serial.read(0xff02).unwrap());
     }
 }
@@ -0,0 +1,695 @@
+//! Super Game Boy support.
+//!
+//! The SGB receives commands from the game through the joypad register (P1) as
+//! 16-byte packets. Commands can recolor the LCD using up to four palettes per
+//! frame (assigned to 8x8 regions of the screen), draw a border around the game
+//! and enable up to four joypads.
+use crate::{
+    error::StateError,
+    ppu::{lcd, Color, LCD_HEIGHT, LCD_WIDTH, PPU},
+    state::State,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Width of the SGB output (border included).
+pub const SGB_WIDTH: usize = 256;
+
+/// Height of the SGB output (border included).
+pub const SGB_HEIGHT: usize = 224;
+
+// position of the game screen within the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// the attribute map assigns a palette to each 8x8 cell of the game screen
+const CELLS_X: usize = LCD_WIDTH / 8;
+const CELLS_Y: usize = LCD_HEIGHT / 8;
+
+// size of the data sent through VRAM transfers (CHR_TRN, PCT_TRN, ...)
+const TRANSFER_LEN: usize = 0x1000;
+
+const ATTR_FILES: usize = 45;
+const ATTR_FILE_LEN: usize = CELLS_X * CELLS_Y / 4;
+
+// commands
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0a;
+const PAL_TRN: u8 = 0x0b;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// Default palette (shades of gray), in BGR555 format.
+const DEFAULT_PALETTE: [u16; 4] = [0x7fff, 0x56b5, 0x294a, 0x0000];
+
+// packet bit counter value after the 128 data bits (waiting for the stop bit)
+const STOP_BIT: usize = 128;
+// packet bit counter value after the stop bit (waiting for P14 & P15 to be released)
+const STOP_BIT_RELEASE: usize = 129;
+
+/// Game screen masking (MASK_EN).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Mask {
+    /// Game screen is displayed normally.
+    None,
+    /// Last frame before the mask is kept on screen.
+    Freeze,
+    /// Game screen is blacked out.
+    Black,
+    /// Game screen is filled with color 0.
+    Color0,
+}
+
+// pending VRAM transfer, performed on the next frame
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum Transfer {
+    Palettes,
+    Tiles(usize),
+    Picture,
+    Attributes,
+}
+
+/// Super Game Boy state.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sgb {
+    // last value of the P14 & P15 lines
+    p1: u8,
+    // number of packet bits received (None if not receiving)
+    bits: Option<usize>,
+    packet: [u8; 16],
+    // packets of the command being received
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    system_palettes: Box<[u16]>,
+    attributes: Box<[u8]>,
+    attribute_files: Box<[u8]>,
+    border_tiles: Box<[u8]>,
+    border_map: Box<[u16]>,
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    transfer: Option<Transfer>,
+    players: usize,
+    player: usize,
+    screen: Box<[Color]>,
+    frame: Box<[Color]>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self {
+            p1: 0x30,
+            bits: None,
+            packet: [0; 16],
+            command: Vec::with_capacity(16 * 7),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; 512 * 4].into_boxed_slice(),
+            attributes: vec![0; CELLS_X * CELLS_Y].into_boxed_slice(),
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_LEN].into_boxed_slice(),
+            border_tiles: vec![0; 256 * 32].into_boxed_slice(),
+            border_map: vec![0; 32 * 32].into_boxed_slice(),
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            transfer: None,
+            players: 1,
+            player: 0,
+            screen: vec![bgr555(DEFAULT_PALETTE[0]); LCD_WIDTH * LCD_HEIGHT].into_boxed_slice(),
+            frame: vec![bgr555(DEFAULT_PALETTE[0]); SGB_WIDTH * SGB_HEIGHT].into_boxed_slice(),
+        }
+    }
+}
+
+impl State for Sgb {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.p1.save(buf);
+        self.bits.save(buf);
+        self.packet.save(buf);
+        self.command.save(buf);
+        self.palettes.save(buf);
+        self.system_palettes.save(buf);
+        self.attributes.save(buf);
+        self.attribute_files.save(buf);
+        self.border_tiles.save(buf);
+        self.border_map.save(buf);
+        self.border_palettes.save(buf);
+        (self.mask as u8).save(buf);
+        let (transfer, first): (u8, usize) = match self.transfer {
+            None => (0, 0),
+            Some(Transfer::Palettes) => (1, 0),
+            Some(Transfer::Tiles(first)) => (2, first),
+            Some(Transfer::Picture) => (3, 0),
+            Some(Transfer::Attributes) => (4, 0),
+        };
+        transfer.save(buf);
+        first.save(buf);
+        self.players.save(buf);
+        self.player.save(buf);
+        self.screen.save(buf);
+        self.frame.save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.p1.load(buf)?;
+        self.bits.load(buf)?;
+        self.packet.load(buf)?;
+        self.command.load(buf)?;
+        self.palettes.load(buf)?;
+        self.system_palettes.load(buf)?;
+        self.attributes.load(buf)?;
+        self.attribute_files.load(buf)?;
+        self.border_tiles.load(buf)?;
+        self.border_map.load(buf)?;
+        self.border_palettes.load(buf)?;
+        let mut mask = 0u8;
+        mask.load(buf)?;
+        self.mask = match mask {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => Mask::None,
+        };
+        let (mut transfer, mut first) = (0u8, 0usize);
+        transfer.load(buf)?;
+        first.load(buf)?;
+        self.transfer = match transfer {
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(first)),
+            3 => Some(Transfer::Picture),
+            4 => Some(Transfer::Attributes),
+            _ => None,
+        };
+        self.players.load(buf)?;
+        self.player.load(buf)?;
+        self.screen.load(buf)?;
+        self.frame.load(buf)?;
+        Ok(())
+    }
+}
+
+/// Returns true if the cartridge header enables the SGB functions (SGB flag
+/// 0x146 set to 0x03, and old licensee code 0x14B set to 0x33). Packets sent
+/// by other games are ignored.
+pub fn supported(rom: &[u8]) -> bool {
+    rom.get(0x146) == Some(&0x03) && rom.get(0x14b) == Some(&0x33)
+}
+
+impl Sgb {
+    /// Returns the last rendered frame (game screen and border).
+    /// The frame is `SGB_WIDTH` x `SGB_HEIGHT` pixels in row-major order.
+    pub fn frame(&self) -> &[Color] {
+        &self.frame
+    }
+
+    /// Returns the current game screen mask.
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// Returns the palette assigned to each 8x8 cell of the game screen.
+    pub fn attributes(&self) -> &[u8] {
+        &self.attributes
+    }
+
+    /// Returns the number of joypads enabled by the game (1, 2 or 4).
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    /// Returns the joypad currently selected for reading.
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    // Handle a write to the P1 register.
+    pub(crate) fn write_p1(&mut self, data: u8) {
+        let lines = data & 0x30;
+        let prev = std::mem::replace(&mut self.p1, lines);
+
+        match (lines, self.bits) {
+            // reset pulse (P14 & P15 low) starts a new packet
+            (0x00, _) => {
+                self.bits = Some(0);
+                self.packet = [0; 16];
+            }
+            // P14 low = 0, P15 low = 1
+            (0x10 | 0x20, Some(bits)) if prev == 0x30 => {
+                let bit = lines == 0x10;
+                if bits < STOP_BIT {
+                    if bit {
+                        self.packet[bits / 8] |= 1 << (bits % 8);
+                    }
+                    self.bits = Some(bits + 1);
+                } else if bits == STOP_BIT && !bit {
+                    self.bits = Some(STOP_BIT_RELEASE);
+                    self.receive_packet();
+                } else {
+                    log::warn!("SGB packet without stop bit");
+                    self.bits = None;
+                }
+            }
+            (0x30, Some(STOP_BIT_RELEASE)) => self.bits = None,
+            // releasing P14 & P15 selects the next joypad
+            (0x30, None) if self.players > 1 && prev != 0x30 && prev != 0x00 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        // number of packets is stored in the lower 3 bits of the first byte
+        let len = ((self.command[0] & 0x7) as usize).max(1);
+        if self.command.len() >= len * 16 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+            self.command = command;
+            self.command.clear();
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        let command = data[0] >> 3;
+        log::trace!("SGB command: {command:02x}");
+        match command {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => self.pal_set(data),
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0x3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => self.transfer = Some(Transfer::Tiles((data[1] & 1) as usize * 128)),
+            PCT_TRN => self.transfer = Some(Transfer::Picture),
+            ATTR_TRN => self.transfer = Some(Transfer::Attributes),
+            ATTR_SET => {
+                self.attr_set((data[1] & 0x3f) as usize);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0x3 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None,
+                };
+            }
+            _ => log::warn!("Unsupported SGB command: {command:02x}"),
+        }
+    }
+
+    // PAL01, PAL23, PAL03 & PAL12
+    // Color 0 is shared by all palettes.
+    fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + 2 * i], data[2 + 2 * i]]);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[a][i] = color(i);
+            self.palettes[b][i] = color(3 + i);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let mut control = set[0] & 0x7;
+            let inside = set[1] & 0x3;
+            let mut border = (set[1] >> 2) & 0x3;
+            let outside = (set[1] >> 4) & 0x3;
+            // if only one of inside or outside is selected, the border takes its palette
+            match control {
+                0b001 => {
+                    control |= 0b010;
+                    border = inside;
+                }
+                0b100 => {
+                    control |= 0b010;
+                    border = outside;
+                }
+                _ => {}
+            }
+            let (x1, y1, x2, y2) = (
+                set[2] as usize,
+                set[3] as usize,
+                set[4] as usize,
+                set[5] as usize,
+            );
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let edge = x == x1 || x == x2 || y == y1 || y == y2;
+                    let palette = match (within, edge) {
+                        (true, false) if control & 0b001 != 0 => inside,
+                        (true, true) if control & 0b010 != 0 => border,
+                        (false, _) if control & 0b100 != 0 => outside,
+                        _ => continue,
+                    };
+                    self.attributes[y * CELLS_X + x] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let n = (line & 0x1f) as usize;
+            let palette = (line >> 5) & 0x3;
+            if line & 0x80 != 0 {
+                // horizontal line
+                if n < CELLS_Y {
+                    self.attributes[n * CELLS_X..(n + 1) * CELLS_X].fill(palette);
+                }
+            } else if n < CELLS_X {
+                // vertical line
+                for y in 0..CELLS_Y {
+                    self.attributes[y * CELLS_X + n] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x3;
+        let before = (data[1] >> 2) & 0x3;
+        let on = (data[1] >> 4) & 0x3;
+        let horizontal = data[1] & 0x40 != 0;
+        let coord = data[2] as usize;
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let n = if horizontal { y } else { x };
+                self.attributes[y * CELLS_X + x] = match n.cmp(&coord) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = data[1] as usize;
+        let mut y = data[2] as usize;
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 1 != 0;
+        for i in 0..count {
+            let Some(byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+            // 4 cells per byte, first cell in the upper bits
+            self.attributes[y * CELLS_X + x] = (byte >> (6 - 2 * (i % 4))) & 0x3;
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let index = u16::from_le_bytes([data[1 + 2 * palette], data[2 + 2 * palette]]);
+            let offset = (index as usize & 0x1ff) * 4;
+            self.palettes[palette].copy_from_slice(&self.system_palettes[offset..offset + 4]);
+        }
+        // color 0 of palette 0 is shared by all palettes
+        for palette in 1..4 {
+            self.palettes[palette][0] = self.palettes[0][0];
+        }
+        let flags = data[9];
+        if flags & 0x80 != 0 {
+            self.attr_set((flags & 0x3f) as usize);
+        }
+        if flags & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    fn attr_set(&mut self, file: usize) {
+        if file >= ATTR_FILES {
+            log::warn!("Invalid SGB attribute file: {file}");
+            return;
+        }
+        let file = &self.attribute_files[file * ATTR_FILE_LEN..(file + 1) * ATTR_FILE_LEN];
+        for (i, attr) in self.attributes.iter_mut().enumerate() {
+            *attr = (file[i / 4] >> (6 - 2 * (i % 4))) & 0x3;
+        }
+    }
+
+    // Perform pending VRAM transfers and render a new frame.
+    // Called when the PPU enters the VBLANK period.
+    pub(crate) fn vblank<O: crate::ppu::LCD>(&mut self, ppu: &PPU<O>) {
+        if let Some(transfer) = self.transfer.take() {
+            let mut data = vec![0; TRANSFER_LEN];
+            ppu.screen_tile_data(&mut data);
+            self.do_transfer(transfer, &data);
+        }
+        self.render(ppu.shades());
+    }
+
+    fn do_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        log::trace!("SGB transfer: {transfer:?}");
+        let words = data
+            .chunks_exact(2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]));
+        match transfer {
+            Transfer::Palettes => {
+                for (dst, src) in self.system_palettes.iter_mut().zip(words) {
+                    *dst = src;
+                }
+            }
+            Transfer::Tiles(first) => {
+                let offset = first * 32;
+                self.border_tiles[offset..offset + 128 * 32].copy_from_slice(&data[..128 * 32]);
+            }
+            Transfer::Picture => {
+                let mut words = words;
+                for (dst, src) in self.border_map.iter_mut().zip(&mut words) {
+                    *dst = src;
+                }
+                // palettes 4-7 follow the map
+                for palette in self.border_palettes.iter_mut() {
+                    for (dst, src) in palette.iter_mut().zip(&mut words) {
+                        *dst = src;
+                    }
+                }
+            }
+            Transfer::Attributes => {
+                let len = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..len]);
+            }
+        }
+    }
+
+    fn render(&mut self, shades: &[u8]) {
+        let backdrop = bgr555(self.palettes[0][0]);
+        match self.mask {
+            Mask::None => {
+                for (i, (dst, &shade)) in self.screen.iter_mut().zip(shades).enumerate() {
+                    let (x, y) = (i % LCD_WIDTH, i / LCD_WIDTH);
+                    let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                    let color = if shade == 0 {
+                        self.palettes[0][0]
+                    } else {
+                        self.palettes[palette][shade as usize]
+                    };
+                    *dst = bgr555(color);
+                }
+            }
+            Mask::Freeze => {}
+            Mask::Black => self.screen.fill(lcd::color(0, 0, 0)),
+            Mask::Color0 => self.screen.fill(backdrop),
+        }
+
+        self.frame.fill(backdrop);
+        for (y, row) in self.screen.chunks_exact(LCD_WIDTH).enumerate() {
+            let offset = (SCREEN_Y + y) * SGB_WIDTH + SCREEN_X;
+            self.frame[offset..offset + LCD_WIDTH].copy_from_slice(row);
+        }
+        self.render_border();
+    }
+
+    // The border is made of SNES 4bpp tiles and drawn over the game screen.
+    // Color 0 of each border palette is transparent.
+    fn render_border(&mut self) {
+        for (i, entry) in self.border_map.iter().take(32 * 28).enumerate() {
+            let (tx, ty) = (i % 32, i / 32);
+            let tile = &self.border_tiles[(*entry & 0xff) as usize * 32..][..32];
+            let palette = &self.border_palettes[((*entry >> 10) & 0x3) as usize];
+            let x_flip = *entry & 0x4000 != 0;
+            let y_flip = *entry & 0x8000 != 0;
+            for row in 0..8 {
+                let r = if y_flip { 7 - row } else { row };
+                let planes = [
+                    tile[2 * r],
+                    tile[2 * r + 1],
+                    tile[16 + 2 * r],
+                    tile[17 + 2 * r],
+                ];
+                for col in 0..8 {
+                    let bit = if x_flip { col } else { 7 - col };
+                    let index = planes
+                        .iter()
+                        .enumerate()
+                        .fold(0, |acc, (p, plane)| acc | (((plane >> bit) & 1) << p));
+                    if index != 0 {
+                        let offset = (ty * 8 + row) * SGB_WIDTH + tx * 8 + col;
+                        self.frame[offset] = bgr555(palette[index as usize]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn bgr555(color: u16) -> Color {
+    let r = (0xff * (color & 0x1f) / 0x1f) as u8;
+    let g = (0xff * ((color >> 5) & 0x1f) / 0x1f) as u8;
+    let b = (0xff * ((color >> 10) & 0x1f) / 0x1f) as u8;
+    lcd::color(r, g, b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mask, Sgb};
+    use crate::{cartridge::ROM, device::Device, gb::GameBoy, ppu::LCD_WIDTH, LR35902};
+
+    fn send(sgb: &mut Sgb, packet: &[u8; 16]) {
+        send_with(|p1| sgb.write_p1(p1), packet);
+    }
+
+    fn send_with(mut write_p1: impl FnMut(u8), packet: &[u8; 16]) {
+        write_p1(0x00);
+        write_p1(0x30);
+        for byte in packet {
+            for bit in 0..8 {
+                write_p1(if (byte >> bit) & 1 != 0 { 0x10 } else { 0x20 });
+                write_p1(0x30);
+            }
+        }
+        // stop bit
+        write_p1(0x20);
+        write_p1(0x30);
+    }
+
+    fn packet(data: &[u8]) -> [u8; 16] {
+        let mut packet = [0; 16];
+        packet[..data.len()].copy_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn pal01() {
+        let mut sgb = Sgb::default();
+        send(
+            &mut sgb,
+            &packet(&[0x01, 0x11, 0x11, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0]),
+        );
+        assert_eq!([0x1111, 1, 2, 3], sgb.palettes[0]);
+        assert_eq!([0x1111, 4, 5, 6], sgb.palettes[1]);
+        assert_eq!(0x1111, sgb.palettes[3][0]);
+    }
+
+    #[test]
+    fn attr_blk() {
+        let mut sgb = Sgb::default();
+        // inside only (border takes the inside palette)
+        send(
+            &mut sgb,
+            &packet(&[(0x04 << 3) | 1, 1, 0b001, 0b00_00_10, 1, 1, 3, 3]),
+        );
+        assert_eq!(0, sgb.attributes[0]);
+        assert_eq!(2, sgb.attributes[20 + 1]);
+        assert_eq!(2, sgb.attributes[2 * 20 + 2]);
+        assert_eq!(0, sgb.attributes[4 * 20 + 4]);
+    }
+
+    #[test]
+    fn mask_and_multiplayer() {
+        let mut sgb = Sgb::default();
+        send(&mut sgb, &packet(&[(0x17 << 3) | 1, 2]));
+        assert_eq!(Mask::Black, sgb.mask());
+        send(&mut sgb, &packet(&[(0x11 << 3) | 1, 1]));
+        assert_eq!(2, sgb.players());
+        assert_eq!(0, sgb.player());
+        sgb.write_p1(0x20);
+        sgb.write_p1(0x30);
+        assert_eq!(1, sgb.player());
+        sgb.write_p1(0x10);
+        sgb.write_p1(0x30);
+        assert_eq!(0, sgb.player());
+    }
+
+    #[test]
+    fn shades() {
+        let mut rom = vec![0; 0x8000];
+        // JR -2
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        // first row of tile 0 in color 1, mapped to shade 2 by BGP
+        gb.soc_mut().write(0x8000, 0xff).unwrap();
+        gb.soc_mut().write(0xff47, 0b00_00_10_00).unwrap();
+        gb.next_frame().unwrap();
+        gb.next_frame().unwrap();
+        let shades = gb.soc().ppu().shades();
+        assert_eq!(
+            [2, 0, 2],
+            [shades[0], shades[LCD_WIDTH], shades[8 * LCD_WIDTH]]
+        );
+    }
+
+    #[test]
+    fn header() {
+        for (sgb_flag, licensee, players) in [(0x03, 0x33, 2), (0x00, 0x33, 1), (0x03, 0x01, 1)] {
+            let mut rom = vec![0; 0x8000];
+            rom[0x146] = sgb_flag;
+            rom[0x14b] = licensee;
+            let mut soc = LR35902::new(ROM::new(rom.into_boxed_slice()), ());
+            send_with(
+                |p1| soc.write(0xff00, p1).unwrap(),
+                &packet(&[(0x11 << 3) | 1, 1]),
+            );
+            assert_eq!(players, soc.sgb().players());
+        }
+    }
+}
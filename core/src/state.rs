@@ -0,0 +1,225 @@
+//! Save states.
+//!
+//! Components serialize themselves into a compact binary format. The format
+//! is only meant to be loaded back by the same build of the emulator.
+use crate::error::StateError;
+
+/// A trait for components which state can be saved and restored.
+pub trait State {
+    /// Append the state to the buffer.
+    fn save(&self, buf: &mut Vec<u8>);
+
+    /// Restore the state from the front of the buffer, advancing it.
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError>;
+}
+
+/// Implement `State` for a struct by saving & loading the given fields in order.
+macro_rules! impl_state {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::state::State for $ty {
+            #[allow(unused_variables)]
+            fn save(&self, buf: &mut Vec<u8>) {
+                $($crate::state::State::save(&self.$field, buf);)*
+            }
+
+            #[allow(unused_variables)]
+            fn load(&mut self, buf: &mut &[u8]) -> Result<(), $crate::error::StateError> {
+                $($crate::state::State::load(&mut self.$field, buf)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+/// Implement `State` for bitflags types.
+macro_rules! impl_state_bitflags {
+    ($ty:ty) => {
+        impl $crate::state::State for $ty {
+            fn save(&self, buf: &mut Vec<u8>) {
+                $crate::state::State::save(&self.bits(), buf);
+            }
+
+            fn load(&mut self, buf: &mut &[u8]) -> Result<(), $crate::error::StateError> {
+                let mut bits = self.bits();
+                $crate::state::State::load(&mut bits, buf)?;
+                *self = <$ty>::from_bits_truncate(bits);
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use impl_state;
+pub(crate) use impl_state_bitflags;
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], StateError> {
+    if buf.len() < len {
+        return Err(StateError::UnexpectedEnd);
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+macro_rules! impl_state_int {
+    ($($ty:ty),*) => {
+        $(
+            impl State for $ty {
+                fn save(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+                    let bytes = take(buf, std::mem::size_of::<$ty>())?;
+                    *self = <$ty>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_state_int!(u8, u16, u32, u64);
+
+impl State for usize {
+    fn save(&self, buf: &mut Vec<u8>) {
+        (*self as u64).save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut value = 0u64;
+        value.load(buf)?;
+        *self = value as usize;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        *self = take(buf, 1)?[0] != 0;
+        Ok(())
+    }
+}
+
+impl<T: State> State for [T] {
+    fn save(&self, buf: &mut Vec<u8>) {
+        for item in self {
+            item.save(buf);
+        }
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        for item in self {
+            item.load(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self[..].save(buf)
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self[..].load(buf)
+    }
+}
+
+impl<T: State + ?Sized> State for Box<T> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.as_ref().save(buf)
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        self.as_mut().load(buf)
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.is_some().save(buf);
+        if let Some(value) = self {
+            value.save(buf);
+        }
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut some = false;
+        some.load(buf)?;
+        *self = if some {
+            let mut value = T::default();
+            value.load(buf)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+// variable length vectors (length prefixed)
+impl<T: State + Default> State for Vec<T> {
+    fn save(&self, buf: &mut Vec<u8>) {
+        self.len().save(buf);
+        self[..].save(buf);
+    }
+
+    fn load(&mut self, buf: &mut &[u8]) -> Result<(), StateError> {
+        let mut len = 0usize;
+        len.load(buf)?;
+        if len > buf.len() {
+            return Err(StateError::UnexpectedEnd);
+        }
+        self.clear();
+        self.resize_with(len, T::default);
+        self[..].load(buf)
+    }
+}
+
+/// An empty tuple has no state.
+impl State for () {
+    fn save(&self, _buf: &mut Vec<u8>) {}
+
+    fn load(&mut self, _buf: &mut &[u8]) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::State;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Foo {
+        a: u8,
+        b: u16,
+        c: Box<[u8]>,
+        d: Option<bool>,
+    }
+
+    impl_state!(Foo { a, b, c, d });
+
+    #[test]
+    fn round_trip() {
+        let foo = Foo {
+            a: 1,
+            b: 0x1234,
+            c: vec![1, 2, 3].into_boxed_slice(),
+            d: Some(true),
+        };
+        let mut buf = Vec::new();
+        foo.save(&mut buf);
+        let mut bar = Foo {
+            c: vec![0; 3].into_boxed_slice(),
+            ..Default::default()
+        };
+        bar.load(&mut &buf[..]).unwrap();
+        assert_eq!(foo, bar);
+        assert!(bar.load(&mut &buf[..4]).is_err());
+    }
+}
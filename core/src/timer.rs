@@ -23,6 +23,15 @@ pub struct Timer {
     tac: u8,
 }
 
+crate::state::impl_state!(Timer {
+    div,
+    div_clock,
+    tima,
+    tima_clock,
+    tma,
+    tac
+});
+
 impl Default for Timer {
     fn default() -> Self {
         Self {
@@ -0,0 +1,285 @@
+//! Instruction tracing.
+//!
+//! A `Tracer` set with `LR35902::set_tracer` receives every instruction right
+//! before it is executed (interrupt dispatches and halted steps are not
+//! traced). `TraceWriter` writes the instructions to a file (or any writer),
+//! optionally filtered by a PC and a frame range.
+//!
+//! The `Format::Doctor` format follows the logs of
+//! [Gameboy Doctor](https://github.com/robert/gameboy-doctor), so traces can
+//! be diffed against the ones of other emulators. As Gameboy Doctor expects,
+//! LY (0xff44) always reads 0x90 while a `TraceWriter` in this format is set.
+use crate::{
+    cartridge::Cartridge, cpu::Registers, debug::Address, device::Device, ppu::LCD, LR35902,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+use utils::dasm::Disassembler;
+
+/// Instruction about to be executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    /// ROM (or RAM) bank mapped at the PC.
+    pub bank: Option<usize>,
+    /// Memory at the PC (instructions are up to 3 bytes long).
+    pub bytes: [u8; 4],
+    /// Registers before executing the instruction.
+    pub registers: Registers,
+    /// CPU cycles since power-on.
+    pub cycles: u64,
+    /// Frames since power-on.
+    pub frame: u64,
+}
+
+impl TraceEntry {
+    pub(crate) fn new<C: Cartridge, O: LCD>(soc: &LR35902<C, O>, registers: &Registers) -> Self {
+        let pc = registers.pc;
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = Device::read(soc, pc.wrapping_add(i as u16)).unwrap_or(0xff);
+        }
+        Self {
+            pc,
+            bank: soc.bank(pc),
+            bytes,
+            registers: registers.clone(),
+            cycles: soc.cycles(),
+            frame: soc.frames(),
+        }
+    }
+
+    /// Returns the bytes of the instruction.
+    pub fn instruction(&self) -> &[u8] {
+        match Disassembler::new(&self.bytes).next() {
+            Some(Ok((_, len))) => &self.bytes[..len],
+            _ => &self.bytes[..1],
+        }
+    }
+
+    /// Returns the disassembled instruction.
+    pub fn disassembly(&self) -> String {
+        match Disassembler::new(&self.bytes).next() {
+            Some(Ok((opcode, _))) => opcode.to_string(),
+            _ => format!("db ${:02x}", self.bytes[0]),
+        }
+    }
+}
+
+/// A trait for instruction tracers.
+pub trait Tracer {
+    fn trace(&mut self, entry: &TraceEntry);
+
+    /// Returns the value the CPU reads from LY (0xff44) while tracing, if it
+    /// must be pinned to one.
+    fn ly(&self) -> Option<u8> {
+        None
+    }
+}
+
+impl<F: FnMut(&TraceEntry)> Tracer for F {
+    fn trace(&mut self, entry: &TraceEntry) {
+        self(entry)
+    }
+}
+
+/// Format of the trace lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+    ///
+    /// LY reads 0x90 while tracing in this format.
+    Doctor,
+    /// Bank, address, instruction bytes, disassembly, registers and cycles.
+    Verbose,
+}
+
+impl Format {
+    /// Write the trace line of the entry (including the line break).
+    pub fn write<W: Write>(self, out: &mut W, entry: &TraceEntry) -> io::Result<()> {
+        let r = &entry.registers;
+        match self {
+            Format::Doctor => {
+                let [m0, m1, m2, m3] = entry.bytes;
+                writeln!(
+                    out,
+                    "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{m0:02X},{m1:02X},{m2:02X},{m3:02X}",
+                    r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc
+                )
+            }
+            Format::Verbose => {
+                let address = Address {
+                    bank: entry.bank,
+                    address: entry.pc,
+                };
+                let bytes: String = entry
+                    .instruction()
+                    .iter()
+                    .map(|b| format!("{b:02X} "))
+                    .collect();
+                writeln!(
+                    out,
+                    "{address:>7}  {bytes:<9} {:<16} AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} CY:{}",
+                    entry.disassembly(),
+                    r.af(),
+                    r.bc(),
+                    r.de(),
+                    r.hl(),
+                    r.sp,
+                    entry.cycles
+                )
+            }
+        }
+    }
+}
+
+/// Tracer that writes the instructions to a writer.
+///
+/// Write errors are logged, and the tracer stops writing after the first one.
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: Format,
+    pc: Option<RangeInclusive<u16>>,
+    frames: Option<RangeInclusive<u64>>,
+    error: bool,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    /// Create (or truncate) the trace file.
+    pub fn create<P: AsRef<Path>>(path: P, format: Format) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            pc: None,
+            frames: None,
+            error: false,
+        }
+    }
+
+    /// Only trace the instructions in the given range of addresses.
+    pub fn pc_range(self, range: RangeInclusive<u16>) -> Self {
+        Self {
+            pc: Some(range),
+            ..self
+        }
+    }
+
+    /// Only trace the instructions executed during the given range of frames.
+    pub fn frame_range(self, range: RangeInclusive<u64>) -> Self {
+        Self {
+            frames: Some(range),
+            ..self
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, entry: &TraceEntry) {
+        if self.error
+            || !self.pc.as_ref().map_or(true, |r| r.contains(&entry.pc))
+            || !self
+                .frames
+                .as_ref()
+                .map_or(true, |r| r.contains(&entry.frame))
+        {
+            return;
+        }
+        if let Err(err) = self.format.write(&mut self.out, entry) {
+            log::error!("Error writing trace: {err}");
+            self.error = true;
+        }
+    }
+
+    fn ly(&self) -> Option<u8> {
+        (self.format == Format::Doctor).then_some(0x90)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Format, TraceEntry, TraceWriter, Tracer};
+    use crate::{cartridge::ROM, gb::GameBoy};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn trace() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$42; LD ($C010),A; JR -2
+        let code = [0x3e, 0x42, 0xea, 0x10, 0xc0, 0x18, 0xfe];
+        rom[0x100..0x107].copy_from_slice(&code);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let tracer = Arc::clone(&entries);
+        gb.soc_mut()
+            .set_tracer(Some(Box::new(move |entry: &TraceEntry| {
+                tracer.lock().unwrap().push(entry.clone())
+            })));
+        for _ in 0..4 {
+            gb.soc_mut().step().unwrap();
+        }
+        let entries = entries.lock().unwrap();
+        assert_eq!(
+            vec![0x100, 0x102, 0x105, 0x105],
+            entries.iter().map(|e| e.pc).collect::<Vec<_>>()
+        );
+        assert_eq!(&[0xea, 0x10, 0xc0], entries[1].instruction());
+        assert!(entries[1].cycles > entries[0].cycles);
+
+        let mut writer = TraceWriter::new(Vec::new(), Format::Doctor).pc_range(0x102..=0x104);
+        for entry in entries.iter() {
+            writer.trace(entry);
+        }
+        let out = String::from_utf8(writer.into_inner()).unwrap();
+        let r = &entries[1].registers;
+        assert_eq!(
+            format!(
+                "A:42 F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:0102 PCMEM:EA,10,C0,18\n",
+                r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp
+            ),
+            out
+        );
+
+        let mut writer = TraceWriter::new(Vec::new(), Format::Verbose).frame_range(1..=1);
+        writer.trace(&entries[0]);
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn doctor() {
+        let mut rom = vec![0; 0x8000];
+        // LDH A,($44); JR -4
+        rom[0x100..0x104].copy_from_slice(&[0xf0, 0x44, 0x18, 0xfc]);
+        let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+        gb.boot().unwrap();
+        let ly = |gb: &mut GameBoy<ROM, ()>, format| {
+            let tracer = TraceWriter::new(io::sink(), format);
+            gb.soc_mut().set_tracer(Some(Box::new(tracer)));
+            for _ in 0..2 {
+                gb.soc_mut().step().unwrap();
+            }
+            gb.soc().cpu().registers().a
+        };
+        assert_eq!(0x90, ly(&mut gb, Format::Doctor));
+        assert_ne!(0x90, ly(&mut gb, Format::Verbose));
+    }
+}
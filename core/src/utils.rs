@@ -11,6 +11,14 @@ pub struct ClockDecimate {
     carry: u64,
 }
 
+crate::state::impl_state!(ClockDecimate {
+    base,
+    target,
+    clocks_per_tick,
+    base_ticks,
+    carry
+});
+
 impl ClockDecimate {
     pub fn new(base: u64, target: u64) -> Self {
         assert!(base >= target);
@@ -41,5 +49,18 @@ impl ClockDecimate {
     }
 }
 
+/// CRC-32 (IEEE) checksum.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod test {}
@@ -0,0 +1,131 @@
+use core::{cartridge::ROM, gb::GameBoy, gdb::Server};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+};
+
+// Scripted RSP client.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${data}#{checksum:02x}");
+        self.stream.write_all(packet.as_bytes()).unwrap();
+        assert_eq!(b'+', self.byte());
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn recv(&mut self) -> String {
+        assert_eq!(b'$', self.byte());
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(
+            checksum,
+            data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.recv()
+    }
+}
+
+#[test]
+fn gdb() {
+    let mut rom = vec![0; 0x8000];
+    // LD A,$42; LD ($C010),A; INC A; JR -3
+    let code = [0x3e, 0x42, 0xea, 0x10, 0xc0, 0x3c, 0x18, 0xfd];
+    rom[0x100..0x108].copy_from_slice(&code);
+    let mut gb = GameBoy::new(ROM::new(rom.into_boxed_slice()), ());
+    gb.boot().unwrap();
+
+    let server = Server::bind(0).unwrap();
+    let addr = server.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        server.serve(gb.soc_mut()).unwrap();
+        gb
+    });
+
+    let mut client = Client {
+        stream: TcpStream::connect(addr).unwrap(),
+    };
+    let supported = client.request("qSupported:swbreak+;hwbreak+");
+    assert!(supported.contains("swbreak+"));
+    assert!(client
+        .request("qXfer:features:read:target.xml:0,1000")
+        .starts_with("l<?xml"));
+    assert_eq!("S05", client.request("?"));
+    assert_eq!("0001", &client.request("g")[20..24]);
+    assert_eq!("3e42ea", client.request("m100,3"));
+
+    // single step
+    assert_eq!("S05", client.request("s"));
+    assert_eq!("0201", client.request("p5"));
+    assert_eq!("42", &client.request("g")[2..4]);
+
+    // watchpoint is hit before the breakpoint
+    assert_eq!("OK", client.request("Z2,c010,1"));
+    assert_eq!("OK", client.request("Z0,105,1"));
+    assert_eq!("T05watch:c010;", client.request("c"));
+    assert_eq!("OK", client.request("z2,c010,1"));
+    assert_eq!("42", client.request("mc010,1"));
+    assert_eq!("OK", client.request("Mc010,2:9988"));
+    assert_eq!("9988", client.request("mc010,2"));
+    assert_eq!("T05swbreak:;", client.request("c"));
+    assert_eq!("0501", client.request("p5"));
+    assert_eq!("OK", client.request("z0,105,1"));
+
+    // hardware breakpoint & register writes
+    assert_eq!("OK", client.request("Z1,106,1"));
+    assert_eq!("OK", client.request("P0=00ff"));
+    assert_eq!("00ff", &client.request("g")[0..4]);
+    assert_eq!("T05hwbreak:;", client.request("c"));
+    assert_eq!("a000", &client.request("g")[0..4]);
+    assert_eq!("OK", client.request("z1,106,1"));
+
+    // interrupt
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!("S02", client.recv());
+
+    // memory accesses of the debugger don't hit watchpoints
+    assert_eq!("OK", client.request("Z4,c010,1"));
+    assert_eq!("9988", client.request("mc010,2"));
+    assert_eq!("OK", client.request("Mc010,1:99"));
+
+    // lengths that don't fit in a packet or in the address space
+    assert_eq!("E01", client.request("m0,ffffffffffffffff"));
+    assert_eq!("E01", client.request("m0,801"));
+    assert_eq!(2 * 0x800, client.request("m0,800").len());
+    assert_eq!("E01", client.request("Z2,c000,10000"));
+    assert_eq!("E01", client.request("Z3,ff00,101"));
+    assert_eq!("OK", client.request("Z3,ff00,100"));
+    assert_eq!("OK", client.request("z3,ff00,100"));
+
+    assert_eq!("", client.request("vMustReplyEmpty"));
+    assert_eq!("OK", client.request("D"));
+
+    let gb = server.join().unwrap();
+    assert!(gb.soc().watchpoints().is_empty());
+    assert!(gb.soc().watchpoints().hits().is_empty());
+    assert_eq!(0x99, core::device::Device::read(gb.soc(), 0xc010).unwrap());
+}
@@ -0,0 +1,317 @@
+//! Headless runner (no window system needed).
+//!
+//! ```text
+//! gbrun <rom> [--frames <n>] [--input <script>] [--screenshot <png>]
+//!       [--video <gif|y4m|raw>] [--scale <n>]
+//!       [--wav <file>] [--wav-stems] [--sample-rate <n>]
+//!       [--vgm <file>] [--vgm-loop <frame>] [--song <n>]
+//!       [--dump-ram <file>] [--serial-out <file>]
+//!       [--until-pc <address>] [--until-mem <address>=<value>]
+//!       [--until <condition>] [--until-serial <text>] [--fail-serial <text>]
+//! ```
+//!
+//! Runs the ROM for the given number of frames (3600 by default), or until one
+//! of the conditions is met. Conditions use the expression syntax of
+//! `core::debug::expr` (addresses and values are hexadecimal). The exit status
+//! is:
+//!
+//! - 0 if an `--until` condition was met (or all the frames ran, if there are
+//!   none).
+//! - 1 if the serial output contains the `--fail-serial` text, or the frames
+//!   ran out before any `--until` condition was met.
+//! - 2 if the emulation failed.
+//!
+//! The screenshot, video, RAM dump and serial output are written in any case.
+//! The audio of the video goes next to it, with the `.wav` extension. With
+//! `--wav-stems`, each APU channel is also recorded to its own file
+//! (`out.ch1.wav`, ..., `out.ch4.wav`). `--vgm` logs the APU register writes
+//! as a VGM file, looping back to the start of the given frame.
+//!
+//! GBS files are played like ROMs, starting with the given song (the first
+//! one of the file by default), so `--wav` renders the song.
+use core::{
+    apu::wav::WavRecorder,
+    capture::Capture,
+    cartridge::{Cartridge, GbsHeader},
+    debug::expr::Expr,
+};
+use script::Script;
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+use utils::vgm::VgmLog;
+
+mod script;
+
+const USAGE: &str = "usage: gbrun <rom> [--frames <n>] [--input <script>] [--screenshot <png>] \
+                     [--video <gif|y4m|raw>] [--scale <n>] \
+                     [--wav <file>] [--wav-stems] [--sample-rate <n>] \
+                     [--vgm <file>] [--vgm-loop <frame>] [--song <n>] [--dump-ram <file>] [--serial-out <file>] [--until-pc <address>] \
+                     [--until-mem <address>=<value>] [--until <condition>] \
+                     [--until-serial <text>] [--fail-serial <text>]";
+
+const FRAMES: u64 = 3600;
+
+type GameBoy = core::gb::GameBoy<Box<dyn Cartridge>, Capture<()>>;
+
+/// How the run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Timeout,
+    Error(String),
+}
+
+impl Outcome {
+    fn status(&self) -> i32 {
+        match self {
+            Outcome::Pass(_) => 0,
+            Outcome::Fail(_) | Outcome::Timeout => 1,
+            Outcome::Error(_) => 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    frames: Option<u64>,
+    until: Vec<Expr>,
+    until_serial: Vec<String>,
+    fail_serial: Vec<String>,
+    vgm_loop: Option<u64>,
+}
+
+fn main() {
+    let mut rom = None;
+    let mut options = Options::default();
+    let mut input = None;
+    let mut screenshot = None;
+    let mut video = None;
+    let mut scale = 1;
+    let mut wav = None;
+    let mut wav_stems = false;
+    let mut sample_rate = 44_100;
+    let mut vgm = None;
+    let mut song = None;
+    let mut dump_ram = None;
+    let mut serial_out = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect(USAGE);
+        match arg.as_str() {
+            "--frames" => options.frames = Some(value().parse().expect(USAGE)),
+            "--input" => input = Some(value()),
+            "--screenshot" => screenshot = Some(value()),
+            "--video" => video = Some(value()),
+            "--scale" => scale = value().parse().expect(USAGE),
+            "--wav" => wav = Some(value()),
+            "--wav-stems" => wav_stems = true,
+            "--sample-rate" => sample_rate = value().parse().expect(USAGE),
+            "--vgm" => vgm = Some(value()),
+            "--song" => song = Some(value().parse::<u8>().expect(USAGE)),
+            "--vgm-loop" => options.vgm_loop = Some(value().parse().expect(USAGE)),
+            "--dump-ram" => dump_ram = Some(value()),
+            "--serial-out" => serial_out = Some(value()),
+            "--until-pc" => options
+                .until
+                .push(condition(&format!("pc == 0x{}", value()))),
+            "--until-mem" => {
+                let value = value();
+                let (address, data) = value.split_once('=').expect(USAGE);
+                options
+                    .until
+                    .push(condition(&format!("[{address}] == 0x{data}")));
+            }
+            "--until" => options.until.push(condition(&value())),
+            "--until-serial" => options.until_serial.push(value()),
+            "--fail-serial" => options.fail_serial.push(value()),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = fs::read(rom.expect(USAGE)).expect("Error reading ROM");
+    // GBS song to play (starting at 0)
+    let song = GbsHeader::parse(&rom).ok().map(|header| {
+        let song = song.unwrap_or(header.first_song);
+        if !(1..=header.songs).contains(&song) {
+            eprintln!("song {song} out of range (1-{})", header.songs);
+            std::process::exit(2);
+        }
+        eprintln!(
+            "{} - {} ({}): song {song}/{}",
+            header.title, header.author, header.copyright, header.songs
+        );
+        song - 1
+    });
+    let mut script = match input {
+        Some(path) => {
+            let source = fs::read_to_string(path).expect("Error reading input script");
+            Script::parse(&source).unwrap_or_else(|err| panic!("Error parsing input script: {err}"))
+        }
+        None => Script::default(),
+    };
+
+    // no camera sensor: the captured images are blank
+    let cartridge = camera::load_cartridge(rom.into_boxed_slice(), ()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+    let mut gb = GameBoy::new(cartridge, Capture::new(()));
+    let recorder = wav.as_ref().map(|path| {
+        let recorder = if wav_stems {
+            WavRecorder::with_stems(path, sample_rate)
+        } else {
+            WavRecorder::create(path, sample_rate)
+        };
+        let recorder = Arc::new(Mutex::new(recorder.unwrap_or_else(|err| {
+            eprintln!("{path}: {err}");
+            std::process::exit(2);
+        })));
+        gb.soc_mut().set_audio_output(Arc::clone(&recorder));
+        recorder
+    });
+    if let Some(path) = &video {
+        if let Err(err) = gb.soc_mut().start_recording(path, scale) {
+            eprintln!("{path}: {err}");
+            std::process::exit(2);
+        }
+    }
+    if vgm.is_some() {
+        gb.soc_mut().set_vgm_log(Some(VgmLog::new()));
+    }
+    let mut serial = Vec::new();
+    let start = match song {
+        Some(song) => gb.play_song(song),
+        None => gb.boot(),
+    };
+    let outcome = match start {
+        Ok(()) => run(&mut gb, &options, &mut script, &mut serial),
+        Err(err) => Outcome::Error(err.to_string()),
+    };
+
+    match &outcome {
+        Outcome::Pass(reason) => eprintln!("pass: {reason} (frame {})", gb.soc().frames()),
+        Outcome::Fail(reason) => eprintln!("fail: {reason} (frame {})", gb.soc().frames()),
+        Outcome::Timeout if !options.until.is_empty() || !options.until_serial.is_empty() => {
+            eprintln!("fail: timeout after {} frames", gb.soc().frames())
+        }
+        Outcome::Timeout => {}
+        Outcome::Error(err) => eprintln!("error: {err} (frame {})", gb.soc().frames()),
+    }
+
+    if let Some(path) = screenshot {
+        if let Err(err) = gb.soc().ppu().output().screenshot(&path, scale) {
+            eprintln!("{path}: {err}");
+        }
+    }
+    if let (Some(path), Some(Err(err))) = (video, gb.soc_mut().stop_recording()) {
+        eprintln!("{path}: {err}");
+    }
+    if let (Some(path), Some(log)) = (vgm, gb.soc_mut().set_vgm_log(None)) {
+        if let Err(err) = log.save(&path) {
+            eprintln!("{path}: {err}");
+        }
+    }
+    if let (Some(path), Some(recorder)) = (wav, recorder) {
+        // drops the other reference to the recorder
+        gb.soc_mut().set_audio_output(());
+        let recorder = Arc::try_unwrap(recorder)
+            .ok()
+            .unwrap()
+            .into_inner()
+            .unwrap();
+        if let Err(err) = recorder.finish() {
+            eprintln!("{path}: {err}");
+        }
+    }
+    if let Some(path) = dump_ram {
+        if let Err(err) = fs::write(&path, work_ram(&gb)) {
+            eprintln!("{path}: {err}");
+        }
+    }
+    if let Some(path) = serial_out {
+        if let Err(err) = fs::write(&path, &serial) {
+            eprintln!("{path}: {err}");
+        }
+    }
+
+    let status = match outcome {
+        // without conditions, running every frame is a success
+        Outcome::Timeout if options.until.is_empty() && options.until_serial.is_empty() => 0,
+        outcome => outcome.status(),
+    };
+    std::process::exit(status);
+}
+
+fn condition(source: &str) -> Expr {
+    source
+        .parse()
+        .unwrap_or_else(|err| panic!("Invalid condition \"{source}\": {err}"))
+}
+
+fn run(gb: &mut GameBoy, options: &Options, script: &mut Script, serial: &mut Vec<u8>) -> Outcome {
+    let frames = options.frames.unwrap_or(FRAMES);
+    // serial text is looked for in the bytes received since the last check
+    // (plus enough of the previous ones for text split between steps)
+    let longest = options
+        .until_serial
+        .iter()
+        .chain(&options.fail_serial)
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    loop {
+        let frame = gb.soc().frames();
+        if frame >= frames {
+            return Outcome::Timeout;
+        }
+        if let Some(input) = script.update(frame) {
+            gb.set_input(0, input);
+        }
+        if let Some(log) = gb.soc_mut().vgm_log_mut() {
+            if options.vgm_loop == Some(frame) && log.loop_point().is_none() {
+                log.set_loop();
+            }
+        }
+        if let Err(err) = gb.soc_mut().step() {
+            return Outcome::Error(err.to_string());
+        }
+
+        let output = gb.soc_mut().take_serial_output();
+        if !output.is_empty() {
+            let start = serial.len().saturating_sub(longest);
+            serial.extend(output);
+            let text = String::from_utf8_lossy(&serial[start..]);
+            if let Some(text) = options
+                .fail_serial
+                .iter()
+                .find(|t| text.contains(t.as_str()))
+            {
+                return Outcome::Fail(format!("serial output \"{text}\""));
+            }
+            if let Some(text) = options
+                .until_serial
+                .iter()
+                .find(|t| text.contains(t.as_str()))
+            {
+                return Outcome::Pass(format!("serial output \"{text}\""));
+            }
+        }
+        if let Some(until) = options.until.iter().find(|until| until.test(gb.soc())) {
+            return Outcome::Pass(until.to_string());
+        }
+    }
+}
+
+// WRAM bank 0, followed by the switchable banks (a single one on DMG)
+fn work_ram(gb: &GameBoy) -> Vec<u8> {
+    let soc = gb.soc();
+    let banks = soc.banks(0xd000).unwrap_or(1..=1);
+    std::iter::once((0, 0xc000..=0xcfff))
+        .chain(banks.map(|bank| (bank, 0xd000..=0xdfff)))
+        .flat_map(|(bank, addresses)| {
+            addresses.map(move |address| soc.read_bank(bank, address).unwrap_or(0xff))
+        })
+        .collect()
+}
@@ -0,0 +1,122 @@
+//! Input scripts.
+//!
+//! One line per change of the joypad: the frame, followed by the buttons held
+//! from that frame on (none to release every button). Lines must be sorted by
+//! frame, and `#` starts a comment:
+//!
+//! ```text
+//! # skip the title screen
+//! 120 start
+//! 125
+//! 300 right a
+//! 340 right
+//! 400
+//! ```
+use core::joypad::JoypadInput;
+use std::fmt;
+
+// thiserror can't be used here: its generated code refers to the standard
+// `core` crate, which is shadowed by the emulator crate
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidFrame(usize, String),
+    UnknownButton(usize, String),
+    Unsorted(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidFrame(line, frame) => write!(f, "line {line}: invalid frame \"{frame}\""),
+            Error::UnknownButton(line, name) => write!(f, "line {line}: unknown button \"{name}\""),
+            Error::Unsorted(line) => write!(f, "line {line}: frames must be sorted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Joypad input over time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    // frame & buttons held from that frame on
+    changes: Vec<(u64, JoypadInput)>,
+    next: usize,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut changes: Vec<(u64, JoypadInput)> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let mut words = line.split_whitespace();
+            let Some(frame) = words.next() else {
+                continue;
+            };
+            let frame = frame
+                .parse()
+                .map_err(|_| Error::InvalidFrame(i + 1, frame.to_string()))?;
+            if changes.last().is_some_and(|(last, _)| *last > frame) {
+                return Err(Error::Unsorted(i + 1));
+            }
+            let mut input = JoypadInput::empty();
+            for name in words {
+                input |=
+                    button(name).ok_or_else(|| Error::UnknownButton(i + 1, name.to_string()))?;
+            }
+            changes.push((frame, input));
+        }
+        Ok(Self { changes, next: 0 })
+    }
+
+    /// Returns the new input of the joypad if it changes at the given frame
+    /// (or at any frame before it that was skipped).
+    pub fn update(&mut self, frame: u64) -> Option<JoypadInput> {
+        let mut input = None;
+        while let Some((_, change)) = self.changes[self.next..]
+            .first()
+            .filter(|(at, _)| *at <= frame)
+        {
+            input = Some(*change);
+            self.next += 1;
+        }
+        input
+    }
+}
+
+fn button(name: &str) -> Option<JoypadInput> {
+    let button = match name.to_ascii_lowercase().as_str() {
+        "a" => JoypadInput::A,
+        "b" => JoypadInput::B,
+        "select" => JoypadInput::SELECT,
+        "start" => JoypadInput::START,
+        "right" => JoypadInput::RIGHT,
+        "left" => JoypadInput::LEFT,
+        "up" => JoypadInput::UP,
+        "down" => JoypadInput::DOWN,
+        _ => return None,
+    };
+    Some(button)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Script};
+    use core::joypad::JoypadInput;
+
+    #[test]
+    fn parse() {
+        let mut script = Script::parse("# title\n10 start\n12\n\n20 Right A # jump\n").unwrap();
+        assert_eq!(None, script.update(0));
+        assert_eq!(Some(JoypadInput::START), script.update(10));
+        assert_eq!(None, script.update(11));
+        assert_eq!(Some(JoypadInput::RIGHT | JoypadInput::A), script.update(30));
+        assert_eq!(None, script.update(31));
+
+        assert_eq!(
+            Err(Error::UnknownButton(1, "x".to_string())),
+            Script::parse("1 x")
+        );
+        assert_eq!(Err(Error::Unsorted(2)), Script::parse("2\n1"));
+    }
+}
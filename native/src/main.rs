@@ -1,11 +1,23 @@
+#[cfg(not(feature = "sgb"))]
+use core::ppu::LCD_HEIGHT;
 use core::{
-    cartridge::{Cartridge, MBC1, MBC2, MBC3, MBC5, ROM},
+    apu::wav::WavRecorder,
+    capture::Capture,
+    cartridge::{Cartridge, GbsHeader},
+    cheats::Cheats,
     cpu::Registers,
-    debug::Breakpoint,
+    debug::{
+        expr::Expr, profile::Profiler, Address, BreakpointEntry, BreakpointSet, NextFrame, Trigger,
+        Watch, Watchpoint,
+    },
     device::Device,
-    joypad::Button,
-    ppu::{Color, ColorPalette, LCDDebugOverlay, LCD, LCD_HEIGHT, LCD_WIDTH},
+    gdb,
+    joypad::JoypadInput,
+    movie::{Movie, Player, Recorder},
+    ppu::{Color, ColorPalette, LCDDebugOverlay, LCD, LCD_WIDTH},
     ram::vram::TileDataCache,
+    rewind::{self, Rewind},
+    trace::{self, TraceWriter},
 };
 use dialog::{DialogBox, FileSelectionMode};
 use embedded_graphics::{
@@ -16,15 +28,40 @@ use embedded_graphics::{
     text::Text,
 };
 use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
-use std::{cell::RefCell, convert::Infallible, rc::Rc};
-use utils::dasm::Disassembler;
+use std::{
+    cell::RefCell,
+    convert::Infallible,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+use utils::{
+    capture::Format,
+    cdl::CodeDataLog,
+    dasm::Disassembler,
+    symbols::Symbols,
+    vgm::{self, VgmLog},
+};
 
-type GameBoy = core::gb::GameBoy<Box<dyn Cartridge>, GameBoyLCD>;
+#[cfg(feature = "mem")]
+mod mem;
+#[cfg(feature = "search")]
+mod search;
+
+type GameBoy = core::gb::GameBoy<Box<dyn Cartridge>, Capture<GameBoyLCD>>;
 
 // LCD window
 const WINDOW_LCD_TITLE: &str = "LCD";
-const WINDOW_LCD_W: usize = LCD_WIDTH + 16;
-const WINDOW_LCD_H: usize = LCD_HEIGHT + 7;
+#[cfg(not(feature = "sgb"))]
+const SCREEN_W: usize = LCD_WIDTH;
+#[cfg(not(feature = "sgb"))]
+const SCREEN_H: usize = LCD_HEIGHT;
+#[cfg(feature = "sgb")]
+const SCREEN_W: usize = core::sgb::SGB_WIDTH;
+#[cfg(feature = "sgb")]
+const SCREEN_H: usize = core::sgb::SGB_HEIGHT;
+const WINDOW_LCD_W: usize = SCREEN_W + 16;
+const WINDOW_LCD_H: usize = SCREEN_H + 7;
 
 // VRAM window
 const WINDOW_VRAM_TITLE: &str = "VRAM";
@@ -33,14 +70,26 @@ const WINDOW_VRAM_H: usize = TileDataCache::DOTS_HEIGHT;
 
 // CPU window
 const WINDOW_CPU_TITLE: &str = "CPU";
-const WINDOW_CPU_W: usize = 5 * (30 + 20);
+const WINDOW_CPU_W: usize = 5 * (33 + 20);
 const WINDOW_CPU_ROWS: usize = 16;
-const WINDOW_CPU_H: usize = 7 * WINDOW_CPU_ROWS;
+const WINDOW_CPU_STACK_ROWS: usize = 6;
+const WINDOW_CPU_H: usize = 7 * (WINDOW_CPU_ROWS + WINDOW_CPU_STACK_ROWS);
 
 // MEM window
+#[cfg(feature = "mem")]
 const WINDOW_MEM_TITLE: &str = "MEM";
-const WINDOW_MEM_W: usize = 5 * 52;
-const WINDOW_MEM_H: usize = 7 * 16;
+#[cfg(feature = "mem")]
+const WINDOW_MEM_W: usize = mem::WIDTH;
+#[cfg(feature = "mem")]
+const WINDOW_MEM_H: usize = mem::HEIGHT;
+
+// SEARCH window
+#[cfg(feature = "search")]
+const WINDOW_SEARCH_TITLE: &str = "SEARCH";
+#[cfg(feature = "search")]
+const WINDOW_SEARCH_W: usize = search::WIDTH;
+#[cfg(feature = "search")]
+const WINDOW_SEARCH_H: usize = search::HEIGHT;
 
 struct CameraSensor {
     fosdem: image::GrayImage,
@@ -103,6 +152,11 @@ impl camera::Sensor for CameraSensor {
 
 struct GameBoyLCD(Rc<RefCell<[Color; WINDOW_LCD_W * WINDOW_LCD_H]>>);
 impl LCD for GameBoyLCD {
+    // in SGB mode the display shows the frame composed by the SGB instead
+    #[cfg(feature = "sgb")]
+    fn output_line(&mut self, _ly: u8, _data: &[Color; LCD_WIDTH]) {}
+
+    #[cfg(not(feature = "sgb"))]
     fn output_line(&mut self, ly: u8, data: &[Color; LCD_WIDTH]) {
         let display = self.0.borrow_mut();
         let off = WINDOW_LCD_W * (ly as usize);
@@ -204,19 +258,19 @@ fn draw_color_palette(
     display: &mut LCDDrawTarget,
 ) {
     let square = Size::new(size as _, size as _);
-    Rectangle::new(Point::new(offx + 160, offy + size * 0), square)
+    Rectangle::new(Point::new(offx + SCREEN_W as i32, offy + size * 0), square)
         .into_styled(color_style(palette[0]))
         .draw(display)
         .unwrap();
-    Rectangle::new(Point::new(offx + 160, offy + size * 1), square)
+    Rectangle::new(Point::new(offx + SCREEN_W as i32, offy + size * 1), square)
         .into_styled(color_style(palette[1]))
         .draw(display)
         .unwrap();
-    Rectangle::new(Point::new(offx + 160, offy + size * 2), square)
+    Rectangle::new(Point::new(offx + SCREEN_W as i32, offy + size * 2), square)
         .into_styled(color_style(palette[2]))
         .draw(display)
         .unwrap();
-    Rectangle::new(Point::new(offx + 160, offy + size * 3), square)
+    Rectangle::new(Point::new(offx + SCREEN_W as i32, offy + size * 3), square)
         .into_styled(color_style(palette[3]))
         .draw(display)
         .unwrap();
@@ -255,6 +309,8 @@ struct Windows {
     pub window_cpu: Window,
     #[cfg(feature = "mem")]
     pub window_mem: Window,
+    #[cfg(feature = "search")]
+    pub window_search: Window,
     #[cfg(not(feature = "cgb"))]
     pub window_vram: Window,
     #[cfg(feature = "cgb")]
@@ -292,19 +348,37 @@ impl Windows {
         let mut opts_mem = WindowOptions::default();
         #[cfg(feature = "mem")]
         {
-            opts_mem.scale = Scale::X1;
+            opts_mem.scale = Scale::X2;
         }
         #[cfg(feature = "mem")]
         let mut window_mem =
             Window::new(WINDOW_MEM_TITLE, WINDOW_MEM_W, WINDOW_MEM_H, opts_mem).unwrap();
         #[cfg(feature = "mem")]
         window_mem.limit_update_rate(Some(std::time::Duration::from_micros(16750)));
+        #[cfg(feature = "search")]
+        let mut opts_search = WindowOptions::default();
+        #[cfg(feature = "search")]
+        {
+            opts_search.scale = Scale::X2;
+        }
+        #[cfg(feature = "search")]
+        let mut window_search = Window::new(
+            WINDOW_SEARCH_TITLE,
+            WINDOW_SEARCH_W,
+            WINDOW_SEARCH_H,
+            opts_search,
+        )
+        .unwrap();
+        #[cfg(feature = "search")]
+        window_search.limit_update_rate(Some(std::time::Duration::from_micros(16750)));
         Self {
             window_lcd,
             #[cfg(feature = "cpu")]
             window_cpu,
             #[cfg(feature = "mem")]
             window_mem,
+            #[cfg(feature = "search")]
+            window_search,
             #[cfg(not(feature = "cgb"))]
             window_vram,
             #[cfg(feature = "cgb")]
@@ -314,21 +388,18 @@ impl Windows {
         }
     }
 
+    // the MEM & SEARCH windows are left out (they have keys of their own)
     fn is_key_pressed(&self, key: Key, repeat: KeyRepeat) -> bool {
         #[cfg(not(feature = "cgb"))]
         let vram = self.window_vram.is_key_pressed(key, repeat);
         #[cfg(feature = "cgb")]
         let vram = self.window_vram0.is_key_pressed(key, repeat)
             || self.window_vram1.is_key_pressed(key, repeat);
-        #[cfg(not(feature = "mem"))]
-        let mem = false;
-        #[cfg(feature = "mem")]
-        let mem = self.window_mem.is_key_pressed(key, repeat);
         #[cfg(not(feature = "cpu"))]
         let cpu = false;
         #[cfg(feature = "cpu")]
         let cpu = self.window_cpu.is_key_pressed(key, repeat);
-        self.window_lcd.is_key_pressed(key, repeat) || cpu || vram || mem
+        self.window_lcd.is_key_pressed(key, repeat) || cpu || vram
     }
 
     fn is_open(&self) -> bool {
@@ -336,16 +407,190 @@ impl Windows {
     }
 }
 
+// input recording & playback
+enum MovieMode {
+    None,
+    Record(String, Recorder),
+    Play(Player),
+}
+
+impl MovieMode {
+    fn new(record: Option<String>, play: Option<String>, gb: &mut GameBoy) -> Self {
+        if let Some(path) = play {
+            let movie = std::fs::File::open(&path)
+                .map_err(Into::into)
+                .and_then(|file| Movie::read(std::io::BufReader::new(file)))
+                .and_then(|movie| Player::new(movie, gb));
+            match movie {
+                Ok(player) => Self::Play(player),
+                Err(err) => {
+                    log::error!("{path}: {err}");
+                    std::process::exit(1);
+                }
+            }
+        } else if let Some(path) = record {
+            Self::Record(path, Recorder::new(gb))
+        } else {
+            Self::None
+        }
+    }
+
+    // must be called before emulating a new frame
+    fn frame(&mut self, gb: &mut GameBoy) {
+        match self {
+            Self::None => {}
+            Self::Record(_, recorder) => recorder.frame(gb),
+            Self::Play(player) => {
+                if !player.frame(gb) {
+                    log::info!("movie finished ({} frames)", player.position());
+                    *self = Self::None;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self, gb: &mut GameBoy) {
+        match self {
+            Self::Record(_, recorder) => recorder.reset(gb),
+            _ => gb.reset(),
+        }
+    }
+
+    // stop playback or recording (saving the movie file)
+    fn stop(&mut self) {
+        if let Self::Record(path, recorder) = std::mem::replace(self, Self::None) {
+            let file = std::fs::File::create(&path).unwrap();
+            let mut file = std::io::BufWriter::new(file);
+            if let Err(err) = recorder.movie().write(&mut file) {
+                log::error!("{path}: {err}");
+            }
+        }
+    }
+}
+
 fn main() {
     pretty_env_logger::init();
     let (mut gb, display) = make_emulator();
 
+    // parse std args: [rom] [--record <file>] [--play <file>] [--rewind <MiB>] [--gdb <port>]
+    //                 [--trace <file>] [--trace-format doctor|verbose]
+    //                 [--trace-pc <start>-<end>] [--trace-frames <start>-<end>]
+    //                 [--cdl <file>] [--profile <file>]
+    //                 [--capture-format gif|y4m|raw] [--capture-scale <n>]
+    //                 [--wav <file>] [--wav-stems] [--vgm <file>]
+    let mut rom = None;
+    let mut sym = None;
+    let mut gdb = None;
+    let mut trace = None;
+    let mut trace_format = trace::Format::Doctor;
+    let mut trace_pc = None;
+    let mut trace_frames = None;
+    let mut cdl = None;
+    let mut profile = None;
+    let mut record = None;
+    let mut play = None;
+    let mut rewind_budget = rewind::DEFAULT_BUDGET;
+    let mut capture_format = Format::Gif;
+    let mut capture_scale = 1;
+    let mut wav = None;
+    let mut wav_stems = false;
+    let mut vgm = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = args.next(),
+            "--play" => play = args.next(),
+            "--sym" => sym = args.next(),
+            "--trace" => trace = args.next(),
+            "--cdl" => cdl = args.next(),
+            "--profile" => profile = args.next(),
+            "--wav" => wav = args.next(),
+            "--wav-stems" => wav_stems = true,
+            "--vgm" => vgm = args.next(),
+            "--trace-format" => {
+                trace_format = match args.next().as_deref() {
+                    Some("doctor") => trace::Format::Doctor,
+                    Some("verbose") => trace::Format::Verbose,
+                    _ => panic!("--trace-format expects doctor or verbose"),
+                }
+            }
+            "--trace-pc" => {
+                let range = args.next().and_then(|r| parse_range(&r, 16));
+                let (start, end) = range.expect("--trace-pc expects <start>-<end> (hex)");
+                trace_pc = Some(start as u16..=end as u16);
+            }
+            "--trace-frames" => {
+                let range = args.next().and_then(|r| parse_range(&r, 10));
+                let (start, end) = range.expect("--trace-frames expects <start>-<end>");
+                trace_frames = Some(start..=end);
+            }
+            "--gdb" => {
+                let port = args.next().and_then(|port| port.parse::<u16>().ok());
+                gdb = Some(port.expect("--gdb expects a port"));
+            }
+            "--capture-format" => {
+                capture_format = match args.next().as_deref() {
+                    Some("gif") => Format::Gif,
+                    Some("y4m") => Format::Y4m,
+                    Some("raw") => Format::Raw,
+                    _ => panic!("--capture-format expects gif, y4m or raw"),
+                }
+            }
+            "--capture-scale" => {
+                let scale = args.next().and_then(|scale| scale.parse::<usize>().ok());
+                capture_scale = scale.expect("--capture-scale expects a factor");
+            }
+            "--rewind" => {
+                let mib = args.next().and_then(|mib| mib.parse::<usize>().ok());
+                rewind_budget = mib.expect("--rewind expects a size in MiB") * 1024 * 1024;
+            }
+            _ => rom = Some(arg),
+        }
+    }
+
     // load rom from std args
-    if let Some(path) = std::env::args().skip(1).next() {
-        gb = load_rom(Some(&path), Rc::clone(&display), gb);
-    } else {
-        gb = load_rom(None, Rc::clone(&display), gb);
+    gb = load_rom(rom.as_deref(), Rc::clone(&display), gb);
+    let mut gbs = GbsPlayer::load(rom.as_deref());
+    if let Some(gbs) = &gbs {
+        gbs.play(&mut gb);
+    }
+    load_cheats(rom.as_deref(), &mut gb);
+    let symbols = load_symbols(rom.as_deref(), sym.as_deref());
+    gb.set_rewind(Some(Rewind::new(rewind_budget, rewind::DEFAULT_INTERVAL)));
+    if let Some(path) = trace {
+        let mut tracer =
+            TraceWriter::create(path, trace_format).expect("Error creating trace file");
+        if let Some(range) = trace_pc {
+            tracer = tracer.pc_range(range);
+        }
+        if let Some(range) = trace_frames {
+            tracer = tracer.frame_range(range);
+        }
+        gb.soc_mut().set_tracer(Some(Box::new(tracer)));
+    }
+    if let Some(path) = &cdl {
+        load_code_data_log(path, &mut gb);
+    }
+    if profile.is_some() {
+        gb.soc_mut().cpu_mut().set_profiler(Some(Profiler::new()));
+    }
+    if vgm.is_some() {
+        gb.soc_mut().set_vgm_log(Some(VgmLog::new()));
     }
+    let mut wav = wav.map(|path| start_wav(path, wav_stems, &mut gb));
+    let mut movie = MovieMode::new(record, play, &mut gb);
+
+    // debug with a GDB client before opening the windows
+    // emulation resumes normally once the client detaches
+    if let Some(port) = gdb {
+        let server = gdb::Server::bind(port).expect("Error starting GDB server");
+        log::info!("waiting for GDB client on {}", server.local_addr().unwrap());
+        if let Err(err) = server.serve(gb.soc_mut()) {
+            log::error!("{err}");
+        }
+    }
+    // movie frames are only recorded/played from frame boundaries
+    let mut frame_start = true;
 
     let mut windows = Windows::new();
 
@@ -353,6 +598,12 @@ fn main() {
     let mut cpu_eg = EGDrawTarget::new(WINDOW_CPU_W, WINDOW_CPU_H);
     #[cfg(feature = "mem")]
     let mut mem_eg = EGDrawTarget::new(WINDOW_MEM_W, WINDOW_MEM_H);
+    #[cfg(feature = "mem")]
+    let mut mem_editor = mem::MemoryEditor::new(&mut windows.window_mem);
+    #[cfg(feature = "search")]
+    let mut search_eg = EGDrawTarget::new(WINDOW_SEARCH_W, WINDOW_SEARCH_H);
+    #[cfg(feature = "search")]
+    let mut ram_search = search::RamSearch::default();
 
     let mut buf = Box::new([0u8; 0x10000]);
     #[cfg(nope)]
@@ -364,8 +615,7 @@ fn main() {
     }
 
     // breakpoints
-    let mut pc_break = None;
-    let mut ly_break = None;
+    let mut breakpoints = BreakpointSet::new();
 
     // state
     let mut pause = false;
@@ -375,7 +625,9 @@ fn main() {
 
     while windows.is_open() {
         frame += 1;
-        handle_joypad_input(&windows.window_lcd, &mut gb);
+        if !matches!(movie, MovieMode::Play(_)) {
+            handle_joypad_input(&windows.window_lcd, &mut gb);
+        }
         handle_lcd_debug_overlay(&windows.window_lcd, &mut lcd_debug_overlay);
         gb.soc_mut().ppu_mut().lcd_debug_overlay = lcd_debug_overlay;
 
@@ -385,17 +637,108 @@ fn main() {
                 .mode(FileSelectionMode::Open)
                 .show()
             {
+                movie.stop();
+                stop_recording(&mut gb);
+                if let Some(wav) = wav.take() {
+                    finish_wav(wav, &mut gb);
+                }
+                if let Some(path) = cdl.take() {
+                    save_code_data_log(&path, &mut gb);
+                }
+                if let Some(path) = vgm.take() {
+                    save_vgm_log(&path, &mut gb);
+                }
+                if let Some(path) = profile.take() {
+                    save_profile(&path, &mut gb, &symbols);
+                }
+                save_cheats(rom.as_deref(), &gb);
                 gb = load_rom(Some(&path), Rc::clone(&display), gb);
+                gbs = GbsPlayer::load(Some(&path));
+                if let Some(gbs) = &gbs {
+                    gbs.play(&mut gb);
+                }
+                load_cheats(Some(&path), &mut gb);
+                rom = Some(path);
+                gb.set_rewind(Some(Rewind::new(rewind_budget, rewind::DEFAULT_INTERVAL)));
+                frame_start = true;
                 pause = false;
             }
         }
 
         // reset
         if windows.is_key_pressed(Key::R, KeyRepeat::No) {
-            gb = load_rom(None, Rc::clone(&display), gb);
+            movie.reset(&mut gb);
+            if let Some(gbs) = &gbs {
+                gbs.play(&mut gb);
+            }
+            frame_start = true;
             pause = false;
         }
 
+        // GBS next/previous song
+        if let Some(gbs) = &mut gbs {
+            let next = windows.is_key_pressed(Key::RightBracket, KeyRepeat::No);
+            let previous = windows.is_key_pressed(Key::LeftBracket, KeyRepeat::No);
+            if next || previous {
+                gbs.select(if next { 1 } else { -1 });
+                gbs.play(&mut gb);
+                frame_start = true;
+                pause = false;
+            }
+        }
+
+        // add cheat
+        if windows.is_key_pressed(Key::G, KeyRepeat::No) {
+            if let Ok(Some(name)) = dialog::Input::new("name").title("cheat").show() {
+                if let Ok(Some(codes)) = dialog::Input::new("codes").title(&name).show() {
+                    if let Err(err) = gb.cheats_mut().add(&name, &codes) {
+                        log::error!("{err}");
+                    }
+                }
+            }
+        }
+
+        // toggle cheat
+        if windows.is_key_pressed(Key::H, KeyRepeat::No) {
+            if let Ok(Some(name)) = dialog::Input::new("name").title("toggle cheat").show() {
+                match gb.cheats_mut().toggle(&name) {
+                    Some(enabled) => log::info!("cheat {name:?} enabled: {enabled}"),
+                    None => log::error!("unknown cheat {name:?}"),
+                }
+            }
+        }
+
+        // screenshot
+        if windows.is_key_pressed(Key::F12, KeyRepeat::No) {
+            let path = capture_path(rom.as_deref(), Format::Png);
+            match gb.soc().ppu().output().screenshot(&path, capture_scale) {
+                Ok(()) => log::info!("screenshot saved to {}", path.display()),
+                Err(err) => log::error!("{}: {err}", path.display()),
+            }
+        }
+
+        // start/stop recording
+        if windows.is_key_pressed(Key::F11, KeyRepeat::No) {
+            if gb.soc().ppu().output().is_recording() {
+                stop_recording(&mut gb);
+            } else {
+                let path = capture_path(rom.as_deref(), capture_format);
+                match gb.soc_mut().start_recording(&path, capture_scale) {
+                    Ok(()) => log::info!("recording to {}", path.display()),
+                    Err(err) => log::error!("{}: {err}", path.display()),
+                }
+            }
+        }
+
+        // VGM loop point
+        if windows.is_key_pressed(Key::F10, KeyRepeat::No) {
+            if let Some(log) = gb.soc_mut().vgm_log_mut() {
+                log.set_loop();
+                let seconds = log.samples() as f64 / vgm::SAMPLE_RATE as f64;
+                log::info!("VGM loop point set at {seconds:.2}s");
+            }
+        }
+
         // emulation speed
         if windows.is_key_pressed(Key::K, KeyRepeat::Yes) {
             speed += 1;
@@ -406,28 +749,13 @@ fn main() {
 
         let mut disable_pause = false;
 
-        // poke memory
+        // hex editor
         #[cfg(feature = "mem")]
-        if windows.window_mem.is_key_down(Key::RightShift)
-            && windows.window_mem.is_key_pressed(Key::P, KeyRepeat::No)
-        {
-            disable_pause = true;
-            let address = if let Ok(address) = dialog::Input::new("address").title("poke").show() {
-                address.and_then(|addr| u16::from_str_radix(&addr, 16).ok())
-            } else {
-                None
-            };
-            if let Some(address) = address {
-                let data = if let Ok(data) = dialog::Input::new("data").title("poke").show() {
-                    data.and_then(|addr| u8::from_str_radix(&addr, 16).ok())
-                } else {
-                    None
-                };
-                if let Some(data) = data {
-                    gb.soc_mut().write(address, data).unwrap();
-                }
-            }
-        }
+        mem_editor.update(&windows.window_mem, &mut gb, &symbols);
+
+        // RAM search
+        #[cfg(feature = "search")]
+        ram_search.update(&windows.window_search, &mut gb);
 
         // change PC
         #[cfg(feature = "cpu")]
@@ -450,47 +778,104 @@ fn main() {
         // set breakpoint
         #[cfg(feature = "cpu")]
         if windows.window_cpu.is_key_pressed(Key::B, KeyRepeat::No) {
-            if let Ok(Some(address)) = dialog::Input::new("PC").title("breakpoint").show() {
-                pc_break = u16::from_str_radix(&address, 16).ok();
+            if let Ok(Some(address)) = dialog::Input::new("[bank:]PC or label (empty for any)")
+                .title("breakpoint")
+                .show()
+            {
+                let condition = dialog::Input::new("condition (optional)")
+                    .title("breakpoint")
+                    .show();
+                let condition = condition.ok().flatten();
+                match add_breakpoint(&mut breakpoints, &symbols, &address, condition) {
+                    Ok(index) => log::info!("breakpoint #{index} added"),
+                    Err(err) => log::error!("{err}"),
+                }
             }
         }
 
+        // remove all breakpoints
         #[cfg(feature = "cpu")]
-        if windows.window_cpu.is_key_pressed(Key::L, KeyRepeat::No) {
-            if let Ok(Some(val)) = dialog::Input::new("ly").title("breakpoint").show() {
-                ly_break = val.parse::<u8>().ok();
+        if windows.window_cpu.is_key_pressed(Key::X, KeyRepeat::No) {
+            breakpoints.clear();
+        }
+
+        // set write watchpoint
+        #[cfg(feature = "cpu")]
+        if windows.window_cpu.is_key_pressed(Key::W, KeyRepeat::No) {
+            if let Ok(Some(address)) = dialog::Input::new("[bank:]address or label")
+                .title("watchpoint")
+                .show()
+            {
+                match Address::parse(&address, &symbols) {
+                    Ok(Address { bank, address }) => {
+                        let mut watchpoint = Watchpoint::write(address..=address);
+                        watchpoint.bank = bank;
+                        gb.soc_mut().watchpoints_mut().add(watchpoint);
+                    }
+                    Err(err) => log::error!("{err}"),
+                }
             }
         }
 
-        // read memory
-        #[cfg(feature = "mem")]
-        if windows.window_mem.is_key_pressed(Key::R, KeyRepeat::No) {
-            if let Ok(Some(address)) = dialog::Input::new("address").show() {
-                if let Ok(address) = u16::from_str_radix(&address, 16) {
-                    dialog::Message::new(format!("{address:04X}"))
-                        .show()
-                        .unwrap();
+        #[cfg(feature = "cpu")]
+        if windows.window_cpu.is_key_pressed(Key::L, KeyRepeat::No) {
+            if let Ok(Some(val)) = dialog::Input::new("ly").title("breakpoint").show() {
+                if let Ok(ly) = val.parse::<u8>() {
+                    breakpoints.add(BreakpointEntry::ly(ly));
                 }
-            };
+            }
         }
 
+        // rewind (one frame per update while the key is held)
+        // disabled while a movie is being recorded or played back
+        let rewind = matches!(movie, MovieMode::None)
+            && frame_start
+            && windows.window_lcd.is_key_down(Key::Backspace);
+
         // step emulation
-        if !pause {
+        if rewind {
+            if let Err(err) = gb.rewind(1) {
+                pause = true;
+                log::error!("{err:?}");
+            }
+        } else if !pause {
             // breakpoints
-            let mut all = (
-                core::debug::NextFrame::new(),
-                core::debug::Either::A(()),
-                core::debug::Either::A(()),
-            );
-            if let Some(pc) = pc_break {
-                all.1 = core::debug::Either::B(core::debug::PC(pc));
-            }
-            if let Some(ly) = ly_break {
-                all.2 = core::debug::Either::B(core::debug::LY::new(ly));
-            }
-            match gb.soc_mut().step_breakpoint(all.clone()) {
-                Ok(breakpoint) => {
-                    pause = breakpoint.1.breakpoint(gb.soc()) || breakpoint.2.breakpoint(gb.soc());
+            let all = (NextFrame::new(), (&mut breakpoints, Watch));
+            if frame_start {
+                movie.frame(&mut gb);
+                gb.start_frame();
+            }
+            match gb.soc_mut().step_breakpoint(all) {
+                Ok(_) => {
+                    pause =
+                        breakpoints.hit().is_some() || !gb.soc().watchpoints().hits().is_empty();
+                    if let Some(index) = breakpoints.hit() {
+                        let entry = breakpoints.get(index).unwrap();
+                        log::info!("breakpoint #{index} hit ({} hits)", entry.hits);
+                        let pc = gb.soc().cpu().registers().pc;
+                        let backtrace = gb
+                            .soc()
+                            .cpu()
+                            .call_stack()
+                            .backtrace(Address::mapped(gb.soc(), pc));
+                        for (i, address) in backtrace.iter().enumerate() {
+                            log::info!("  #{i} {address} {}", address.symbolic(&symbols));
+                        }
+                    }
+                    for hit in gb.soc().watchpoints().hits().iter() {
+                        log::info!(
+                            "watchpoint: PC={:04X} {:?} {} {:02X} -> {:02X}",
+                            hit.pc,
+                            hit.access,
+                            Address {
+                                bank: hit.bank,
+                                address: hit.address
+                            },
+                            hit.old,
+                            hit.new
+                        );
+                    }
+                    frame_start = !pause;
                 }
                 Err(err) => {
                     pause = true;
@@ -504,9 +889,14 @@ fn main() {
                     pause = true;
                     log::error!("{err:?}");
                 }
+                frame_start = false;
             }
 
             if windows.window_lcd.is_key_pressed(Key::S, KeyRepeat::Yes) {
+                if frame_start {
+                    movie.frame(&mut gb);
+                }
+                frame_start = true;
                 if let Err(err) = gb.next_frame() {
                     pause = true;
                     log::error!("{err:?}");
@@ -542,8 +932,13 @@ fn main() {
         }
         // draw LCD
         {
+            #[cfg(feature = "sgb")]
+            for (y, row) in gb.soc().sgb().frame().chunks_exact(SCREEN_W).enumerate() {
+                let off = WINDOW_LCD_W * y;
+                display.borrow_mut()[off..off + SCREEN_W].copy_from_slice(row);
+            }
             draw_color_palettes(&gb, &mut lcd_eg);
-            Rectangle::new(Point::new(0, LCD_HEIGHT as _), Size::new(LCD_WIDTH as _, 8))
+            Rectangle::new(Point::new(0, SCREEN_H as _), Size::new(SCREEN_W as _, 8))
                 .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
                 .draw(&mut lcd_eg)
                 .unwrap();
@@ -553,7 +948,7 @@ fn main() {
                 } else {
                     Rgb888::BLACK
                 };
-                Rectangle::new(Point::new(0, 0), Size::new(LCD_WIDTH as _, LCD_HEIGHT as _))
+                Rectangle::new(Point::new(0, 0), Size::new(SCREEN_W as _, SCREEN_H as _))
                     .into_styled(
                         PrimitiveStyleBuilder::new()
                             .stroke_color(color)
@@ -567,7 +962,7 @@ fn main() {
             #[rustfmt::skip]
             Text::new(
                 &format!("x{speed}"),
-                Point::new(0, (LCD_HEIGHT + 6) as _),
+                Point::new(0, (SCREEN_H + 6) as _),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
             )
                 .draw(&mut lcd_eg)
@@ -593,16 +988,20 @@ fn main() {
             let mut buf = Vec::with_capacity(3);
             let mut lines = 0;
             let mut pc = gb.soc().cpu().registers().pc;
-            while lines < 24 {
+            while lines < WINDOW_CPU_ROWS {
                 buf.push(gb.soc().read(pc).unwrap());
                 pc += 1;
                 let mut dasm = Disassembler::new(&buf);
                 if let Some(Ok((inst, len))) = dasm.next() {
-                    addr_buf.push_str(&format!("{:04X}\n", pc - (len as u16)));
+                    let address = Address::mapped(gb.soc(), pc - (len as u16));
+                    addr_buf.push_str(&format!("{address:>7}\n"));
                     for op in buf.iter().take(len) {
                         inst_buf.push_str(&format!("{op:02X} "));
                     }
                     inst_buf.push('\n');
+                    let inst = inst.display_with(address.address, |address| {
+                        Address::mapped(gb.soc(), address).label(&symbols)
+                    });
                     dasm_buf.push_str(&format!("{inst}\n"));
                     lines += 1;
                     buf.clear();
@@ -622,7 +1021,7 @@ fn main() {
             if marker {
                 Text::new(
                     ">",
-                    Point::new(20, 6),
+                    Point::new(35, 6),
                     MonoTextStyle::new(&FONT_5X7, Rgb888::RED),
                 )
                 .draw(&mut cpu_eg)
@@ -630,14 +1029,14 @@ fn main() {
             }
             Text::new(
                 &inst_buf,
-                Point::new(25, 6),
+                Point::new(40, 6),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
             )
             .draw(&mut cpu_eg)
             .unwrap();
             Text::new(
                 &dasm_buf,
-                Point::new((5 * 14), 6),
+                Point::new((5 * 17), 6),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
             )
             .draw(&mut cpu_eg)
@@ -653,7 +1052,7 @@ fn main() {
                 let inst = gb.soc().read(addr as u16).unwrap();
                 inst_buf.push_str(&format!("{inst:02X}\n"));
             }
-            let offset = 20;
+            let offset = 23;
             Text::new(
                 &addr_buf,
                 Point::new(5 * 8 + (5 * offset) + 0, 6),
@@ -678,14 +1077,14 @@ fn main() {
             let freq = gb.soc().clock_freq();
             Text::new(
                 &format!("{freq}"),
-                Point::new(WINDOW_CPU_W as i32 - 5 * 10, WINDOW_CPU_H as i32 - 1),
+                Point::new(WINDOW_CPU_W as i32 - 5 * 10, 7 * WINDOW_CPU_ROWS as i32 - 1),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
             )
             .draw(&mut cpu_eg)
             .unwrap();
             Text::new(
                 "Hz",
-                Point::new(WINDOW_CPU_W as i32 - 5 * 2, WINDOW_CPU_H as i32 - 1),
+                Point::new(WINDOW_CPU_W as i32 - 5 * 2, 7 * WINDOW_CPU_ROWS as i32 - 1),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
             )
             .draw(&mut cpu_eg)
@@ -731,25 +1130,51 @@ fn main() {
             .unwrap();
             let col_offset = 6 + 7 * (WINDOW_CPU_ROWS - 4);
             Text::new(
-                "Breakpoint\n\nPC\nLY",
+                "Breakpoints",
                 Point::new(5 * 8 + (5 * offset), col_offset as _),
                 MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
             )
             .draw(&mut cpu_eg)
             .unwrap();
-            if let Some(breakpoint) = pc_break {
+            let mut text = "\n".to_string();
+            for entry in breakpoints.iter().take(2) {
+                text.push('\n');
+                text.push_str(&breakpoint_label(entry));
+            }
+            Text::new(
+                &text,
+                Point::new(5 * 8 + (5 * offset), col_offset as _),
+                MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
+            )
+            .draw(&mut cpu_eg)
+            .unwrap();
+            // call stack (innermost frame first)
+            // only the current function while running
+            {
+                let row_offset = 6 + 7 * WINDOW_CPU_ROWS;
+                let stack = gb.soc().cpu().call_stack();
                 Text::new(
-                    &format!("\n\n   {breakpoint:04X}"),
-                    Point::new(5 * 8 + (5 * offset), col_offset as _),
-                    MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
+                    &format!("Call stack ({} mismatched returns)", stack.mismatches()),
+                    Point::new(0, row_offset as _),
+                    MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
                 )
                 .draw(&mut cpu_eg)
                 .unwrap();
-            }
-            if let Some(ly) = ly_break {
+                let pc = Address::mapped(gb.soc(), gb.soc().cpu().registers().pc);
+                let mut text = format!("\n#0  {pc:>7} {}", pc.symbolic(&symbols));
+                let frames = if pause { WINDOW_CPU_STACK_ROWS - 2 } else { 0 };
+                for (i, frame) in stack.frames().iter().rev().take(frames).enumerate() {
+                    text.push_str(&format!(
+                        "\n#{:<2} {:>7} {} (from {})",
+                        i + 1,
+                        frame.to,
+                        frame.to.symbolic(&symbols),
+                        frame.from.symbolic(&symbols),
+                    ));
+                }
                 Text::new(
-                    &format!("\n\n\n   {ly:02X}"),
-                    Point::new(5 * 8 + (5 * offset), col_offset as _),
+                    &text,
+                    Point::new(0, row_offset as _),
                     MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
                 )
                 .draw(&mut cpu_eg)
@@ -763,60 +1188,82 @@ fn main() {
         // draw MEM
         #[cfg(feature = "mem")]
         {
-            static BLACK: [u32; WINDOW_MEM_W * WINDOW_MEM_H] = [0; WINDOW_MEM_W * WINDOW_MEM_H];
-            mem_eg.buffer[..].copy_from_slice(&BLACK[..]);
-            let mut addr_buf = String::new();
-            let mut data_buf = String::new();
-            let mut zero_buf = String::new();
-            let mut data = [0u8; 0x10];
-            for addr in (0x0000..0x0100).step_by(0x10) {
-                addr_buf.push_str(&format!("{addr:04X}\n"));
-                gb.soc().read_exact(addr, &mut data[..]).unwrap();
-                for (i, data) in data.iter().enumerate() {
-                    if i % 8 == 0 {
-                        zero_buf.push('|');
-                        data_buf.push(' ');
-                    } else {
-                        zero_buf.push(' ');
-                        data_buf.push(' ');
-                    }
-                    if *data == 0x00 {
-                        zero_buf.push_str(&format!("{data:02X}"));
-                        data_buf.push_str(&format!("  "));
-                    } else {
-                        zero_buf.push_str(&format!("  "));
-                        data_buf.push_str(&format!("{data:02X}"));
-                    }
-                }
-                zero_buf.push('\n');
-                data_buf.push('\n');
-            }
-            Text::new(
-                &addr_buf,
-                Point::new(0, 6),
-                MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
-            )
-            .draw(&mut mem_eg)
-            .unwrap();
-            Text::new(
-                &zero_buf,
-                Point::new(5 * 4, 6),
-                MonoTextStyle::new(&FONT_5X7, Rgb888::CSS_DIM_GRAY),
-            )
-            .draw(&mut mem_eg)
-            .unwrap();
-            Text::new(
-                &data_buf,
-                Point::new(5 * 4, 6),
-                MonoTextStyle::new(&FONT_5X7, Rgb888::WHITE),
-            )
-            .draw(&mut mem_eg)
-            .unwrap();
+            mem_editor.draw(&gb, &symbols, &mut mem_eg);
             windows
                 .window_mem
                 .update_with_buffer(&mem_eg.buffer[..], WINDOW_MEM_W, WINDOW_MEM_H)
                 .unwrap();
         }
+        // draw SEARCH
+        #[cfg(feature = "search")]
+        {
+            ram_search.draw(&gb, &symbols, &mut search_eg);
+            windows
+                .window_search
+                .update_with_buffer(&search_eg.buffer[..], WINDOW_SEARCH_W, WINDOW_SEARCH_H)
+                .unwrap();
+        }
+    }
+    movie.stop();
+    stop_recording(&mut gb);
+    if let Some(wav) = wav {
+        finish_wav(wav, &mut gb);
+    }
+    if let Some(path) = cdl {
+        save_code_data_log(&path, &mut gb);
+    }
+    if let Some(path) = vgm {
+        save_vgm_log(&path, &mut gb);
+    }
+    if let Some(path) = profile {
+        save_profile(&path, &mut gb, &symbols);
+    }
+}
+
+// next unused capture path in the working directory, named after the ROM
+// (game-000.png, game-001.png, ...)
+fn capture_path(rom: Option<&str>, format: Format) -> PathBuf {
+    let stem = rom
+        .and_then(|rom| Path::new(rom).file_stem())
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("capture");
+    (0..)
+        .map(|i| PathBuf::from(format!("{stem}-{i:03}.{}", format.extension())))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn stop_recording(gb: &mut GameBoy) {
+    match gb.soc_mut().stop_recording() {
+        Some(Ok(frames)) => log::info!("recording stopped ({frames} frames)"),
+        Some(Err(err)) => log::error!("recording failed: {err}"),
+        None => {}
+    }
+}
+
+fn start_wav(path: String, stems: bool, gb: &mut GameBoy) -> (String, Arc<Mutex<WavRecorder>>) {
+    let recorder = if stems {
+        WavRecorder::with_stems(&path, 44_100)
+    } else {
+        WavRecorder::create(&path, 44_100)
+    };
+    let recorder = Arc::new(Mutex::new(recorder.expect("Error creating WAV file")));
+    gb.soc_mut().set_audio_output(Arc::clone(&recorder));
+    (path, recorder)
+}
+
+fn finish_wav((path, recorder): (String, Arc<Mutex<WavRecorder>>), gb: &mut GameBoy) {
+    // drops the other reference to the recorder
+    gb.soc_mut().set_audio_output(());
+    let recorder = Arc::try_unwrap(recorder)
+        .ok()
+        .unwrap()
+        .into_inner()
+        .unwrap();
+    let samples = recorder.samples();
+    match recorder.finish() {
+        Ok(()) => log::info!("{path}: {samples} samples recorded"),
+        Err(err) => log::error!("{path}: {err}"),
     }
 }
 
@@ -843,34 +1290,232 @@ fn handle_lcd_debug_overlay(window: &Window, flags: &mut LCDDebugOverlay) {
 }
 
 fn handle_joypad_input(window: &Window, gb: &mut GameBoy) {
-    fn handle_key(window: &Window, gb: &mut GameBoy, key: Key, button: Button) {
-        if window.is_key_pressed(key, KeyRepeat::No) {
-            gb.press(&button);
+    const KEYS: [(Key, JoypadInput); 8] = [
+        (Key::Z, JoypadInput::A),
+        (Key::X, JoypadInput::B),
+        (Key::Enter, JoypadInput::START),
+        (Key::RightShift, JoypadInput::SELECT),
+        (Key::Left, JoypadInput::LEFT),
+        (Key::Right, JoypadInput::RIGHT),
+        (Key::Up, JoypadInput::UP),
+        (Key::Down, JoypadInput::DOWN),
+    ];
+
+    let input = KEYS
+        .iter()
+        .filter(|(key, _)| window.is_key_down(*key))
+        .fold(JoypadInput::empty(), |input, (_, button)| input | *button);
+    gb.set_input(0, input);
+}
+
+// cheats are stored next to the ROM (with the .cht extension)
+// Add a breakpoint on the given PC (hexadecimal) and/or condition.
+#[cfg(feature = "cpu")]
+fn add_breakpoint(
+    breakpoints: &mut BreakpointSet,
+    symbols: &Symbols,
+    address: &str,
+    condition: Option<String>,
+) -> Result<usize, String> {
+    let condition = match condition.as_deref().map(str::trim) {
+        Some(c) if !c.is_empty() => Some(c.parse::<Expr>().map_err(|e| e.to_string())?),
+        _ => None,
+    };
+    let entry = match (address.trim(), condition) {
+        ("", None) => return Err("empty breakpoint".to_string()),
+        ("", Some(condition)) => BreakpointEntry::when(condition),
+        (address, condition) => {
+            let pc = Address::parse(address, symbols).map_err(|e| e.to_string())?;
+            let entry = BreakpointEntry::pc(pc);
+            match condition {
+                Some(condition) => entry.condition(condition),
+                None => entry,
+            }
+        }
+    };
+    Ok(breakpoints.add(entry))
+}
+
+#[cfg(feature = "cpu")]
+fn breakpoint_label(entry: &BreakpointEntry) -> String {
+    let mut label = match &entry.trigger {
+        Trigger::PC(pc) => format!("PC {}", pc.0),
+        Trigger::LY(_) => "LY".to_string(),
+        Trigger::NextFrame(_) => "frame".to_string(),
+        Trigger::Watch(_) => "watch".to_string(),
+        Trigger::Step => String::new(),
+    };
+    if let Some(condition) = &entry.condition {
+        label.push_str(&format!(" if {condition}"));
+    }
+    if !entry.enabled {
+        label.push_str(" (off)");
+    }
+    label
+}
+
+// "<start>-<end>"
+fn parse_range(range: &str, radix: u32) -> Option<(u64, u64)> {
+    let (start, end) = range.split_once('-')?;
+    let start = u64::from_str_radix(start, radix).ok()?;
+    let end = u64::from_str_radix(end, radix).ok()?;
+    Some((start, end))
+}
+
+// symbol file given with --sym, or next to the ROM (<rom>.sym)
+fn load_symbols(rom: Option<&str>, sym: Option<&str>) -> Symbols {
+    let path = match (sym, rom) {
+        (Some(sym), _) => std::path::PathBuf::from(sym),
+        (None, Some(rom)) => std::path::Path::new(rom).with_extension("sym"),
+        (None, None) => return Symbols::new(),
+    };
+    if sym.is_none() && !path.exists() {
+        return Symbols::new();
+    }
+    match Symbols::load(&path) {
+        Ok(symbols) => {
+            log::info!("{}: {} symbols", path.display(), symbols.len());
+            symbols
+        }
+        Err(err) => {
+            log::error!("{}: {err}", path.display());
+            Symbols::new()
+        }
+    }
+}
+
+fn cheats_path(rom: &str) -> std::path::PathBuf {
+    std::path::Path::new(rom).with_extension("cht")
+}
+
+fn load_cheats(rom: Option<&str>, gb: &mut GameBoy) {
+    let path = match rom {
+        Some(rom) => cheats_path(rom),
+        None => return,
+    };
+    if let Ok(file) = std::fs::File::open(&path) {
+        let cheats =
+            Cheats::read(std::io::BufReader::new(file)).and_then(|cheats| gb.set_cheats(cheats));
+        if let Err(err) = cheats {
+            log::error!("{}: {err}", path.display());
         }
-        if window.is_key_released(key) {
-            gb.release(&button);
+    }
+}
+
+fn save_cheats(rom: Option<&str>, gb: &GameBoy) {
+    let path = match rom {
+        Some(rom) => cheats_path(rom),
+        None => return,
+    };
+    if gb.cheats().iter().next().is_none() && !path.exists() {
+        return;
+    }
+    let result = std::fs::File::create(&path)
+        .and_then(|file| gb.cheats().write(std::io::BufWriter::new(file)));
+    if let Err(err) = result {
+        log::error!("{}: {err}", path.display());
+    }
+}
+
+// log the ROM usage, adding to the log of previous sessions
+fn load_code_data_log(path: &str, gb: &mut GameBoy) {
+    let len = gb.soc().cartridge().rom().len();
+    let mut log = CodeDataLog::new(len);
+    match CodeDataLog::load(path) {
+        Ok(previous) if previous.len() == len => log.merge(&previous),
+        Ok(_) => log::warn!("{path}: the log is for a different ROM (size mismatch)"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => log::error!("{path}: {err}"),
+    }
+    gb.soc_mut().set_code_data_log(Some(log));
+}
+
+// write the log, and the coverage report next to it (.html & .png)
+fn save_vgm_log(path: &str, gb: &mut GameBoy) {
+    if let Some(log) = gb.soc_mut().set_vgm_log(None) {
+        if let Err(err) = log.save(path) {
+            log::error!("{path}: {err}");
         }
     }
+}
+
+fn save_code_data_log(path: &str, gb: &mut GameBoy) {
+    let log = match gb.soc_mut().set_code_data_log(None) {
+        Some(log) => log,
+        None => return,
+    };
+    let path = std::path::Path::new(path);
+    let create = |path: &std::path::Path| std::fs::File::create(path).map(std::io::BufWriter::new);
+    let html = path.with_extension("html");
+    let result = create(path)
+        .and_then(|file| log.write(file))
+        .and_then(|_| create(&html))
+        .and_then(|file| log.write_html(file, &path.display().to_string()));
+    if let Err(err) = result {
+        log::error!("{}: {err}", path.display());
+    }
+    let (width, height, pixels) = log.map();
+    let map = image::RgbImage::from_raw(width as u32, height as u32, pixels).unwrap();
+    if let Err(err) = map.save(path.with_extension("png")) {
+        log::error!("{}: {err}", path.display());
+    }
+    let total = log.total();
+    log::info!(
+        "ROM coverage: {:.1}% (code {:.1}%, data {:.1}%)",
+        total.percent(total.used()),
+        total.percent(total.code),
+        total.percent(total.data)
+    );
+}
+
+// write the profile report, and the folded stacks next to it (.folded)
+fn save_profile(path: &str, gb: &mut GameBoy, symbols: &Symbols) {
+    let profiler = match gb.soc_mut().cpu_mut().set_profiler(None) {
+        Some(profiler) => profiler,
+        None => return,
+    };
+    let path = std::path::Path::new(path);
+    let create = |path: &std::path::Path| std::fs::File::create(path).map(std::io::BufWriter::new);
+    let result = create(path)
+        .and_then(|file| profiler.write_report(file, symbols, 50))
+        .and_then(|_| create(&path.with_extension("folded")))
+        .and_then(|file| profiler.write_folded(file, symbols));
+    if let Err(err) = result {
+        log::error!("{}: {err}", path.display());
+    }
+}
 
-    handle_key(&window, gb, Key::Z, Button::A);
-    handle_key(&window, gb, Key::X, Button::B);
-    handle_key(&window, gb, Key::Enter, Button::Start);
-    handle_key(&window, gb, Key::RightShift, Button::Select);
-    handle_key(&window, gb, Key::Left, Button::Left);
-    handle_key(&window, gb, Key::Right, Button::Right);
-    handle_key(&window, gb, Key::Up, Button::Up);
-    handle_key(&window, gb, Key::Down, Button::Down);
+// GBS file being played, and the song (starting at 0)
+struct GbsPlayer {
+    header: GbsHeader,
+    song: u8,
 }
 
-fn load_cartridge(file: Box<[u8]>) -> Box<dyn Cartridge> {
-    match file.get(0x147) {
-        Some(0x00 | 0x08 | 0x09) => Box::new(ROM::new(file)) as _,
-        Some(0x01 | 0x02 | 0x03) => Box::new(MBC1::new(file)) as _,
-        Some(0x05 | 0x06) => Box::new(MBC2::new(file)) as _,
-        Some(0x0f | 0x10 | 0x11 | 0x12 | 0x13) => Box::new(MBC3::new(file)) as _,
-        Some(0x19 | 0x1a | 0x1b | 0x1c | 0x1d | 0x1e) => Box::new(MBC5::new(file)) as _,
-        Some(0xfc) => Box::new(camera::PocketCamera::new(file, CameraSensor::new())) as _,
-        _ => Box::new(()) as _,
+impl GbsPlayer {
+    fn load(path: Option<&str>) -> Option<Self> {
+        let file = std::fs::read(path?).ok()?;
+        let header = GbsHeader::parse(&file).ok()?;
+        log::info!(
+            "GBS: {} - {} ({})",
+            header.title,
+            header.author,
+            header.copyright
+        );
+        let song = header.first_song - 1;
+        Some(Self { header, song })
+    }
+
+    // select the next (or previous) song, wrapping around
+    fn select(&mut self, offset: i32) {
+        let songs = self.header.songs as i32;
+        self.song = (self.song as i32 + offset).rem_euclid(songs) as u8;
+    }
+
+    fn play(&self, gb: &mut GameBoy) {
+        match gb.play_song(self.song) {
+            Ok(()) => log::info!("GBS song {}/{}", self.song + 1, self.header.songs),
+            Err(err) => log::error!("{err}"),
+        }
     }
 }
 
@@ -881,16 +1526,19 @@ fn load_rom(
 ) -> GameBoy {
     let cartridge = if let Some(path) = path {
         let file = std::fs::read(path).unwrap().into_boxed_slice();
-        load_cartridge(file)
+        camera::load_cartridge(file, CameraSensor::new()).unwrap_or_else(|err| {
+            log::error!("{path}: {err}");
+            Box::new(())
+        })
     } else {
         Box::new(()) as _
     };
-    GameBoy::new(cartridge, GameBoyLCD(display))
+    GameBoy::new(cartridge, Capture::new(GameBoyLCD(display)))
 }
 
 fn make_emulator() -> (GameBoy, Rc<RefCell<[Color; WINDOW_LCD_W * WINDOW_LCD_H]>>) {
     let disp = Rc::new(RefCell::new([[0, 0, 0, 0xff]; WINDOW_LCD_W * WINDOW_LCD_H]));
-    let lcd = GameBoyLCD(Rc::clone(&disp));
+    let lcd = Capture::new(GameBoyLCD(Rc::clone(&disp)));
     let gameboy = GameBoy::new(Box::new(()) as _, lcd);
     (gameboy, disp)
 }
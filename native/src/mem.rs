@@ -0,0 +1,399 @@
+//! MEM window: hex editor over the 64 KiB address space.
+//!
+//! The switchable regions (ROM, VRAM, SRAM & WRAM) show the bank mapped by
+//! the game, unless a bank is pinned with `Ctrl+B` (or by going to a
+//! `bank:address`). Bytes changed during the last frame are highlighted.
+use crate::GameBoy;
+use core::debug::Address;
+use dialog::DialogBox;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_5X7, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use minifb::{InputCallback, Key, KeyRepeat, Window};
+use std::{cell::RefCell, rc::Rc};
+use utils::symbols::Symbols;
+
+pub const ROWS: usize = 24;
+pub const WIDTH: usize = 5 * 74;
+pub const HEIGHT: usize = 7 * (ROWS + 2);
+
+// first column of the hex bytes & ASCII characters
+const HEX_COL: i32 = 6;
+const ASCII_COL: i32 = 57;
+
+// switchable regions
+const REGIONS: [&str; 4] = ["ROM", "VRAM", "SRAM", "WRAM"];
+
+fn region(address: u16) -> Option<usize> {
+    match address {
+        0x4000..=0x7fff => Some(0),
+        0x8000..=0x9fff => Some(1),
+        0xa000..=0xbfff => Some(2),
+        0xd000..=0xdfff | 0xf000..=0xfdff => Some(3),
+        _ => None,
+    }
+}
+
+// characters typed in the window
+struct Chars(Rc<RefCell<Vec<char>>>);
+
+impl InputCallback for Chars {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char).filter(|c| c.is_ascii_graphic() || *c == ' ') {
+            self.0.borrow_mut().push(c);
+        }
+    }
+}
+
+pub struct MemoryEditor {
+    cursor: u16,
+    // address of the first row
+    top: u16,
+    // the high nibble of the byte under the cursor has been typed
+    low_nibble: bool,
+    // typing into the ASCII column
+    ascii: bool,
+    // pinned bank of each region (None follows the mapped bank)
+    banks: [Option<usize>; 4],
+    search: Option<Vec<u8>>,
+    message: String,
+    // memory at the end of the last two frames (None if unreadable)
+    view: Box<[Option<u8>]>,
+    previous: Box<[Option<u8>]>,
+    frame: u64,
+    chars: Rc<RefCell<Vec<char>>>,
+}
+
+impl MemoryEditor {
+    pub fn new(window: &mut Window) -> Self {
+        let chars = Rc::new(RefCell::new(Vec::new()));
+        window.set_input_callback(Box::new(Chars(Rc::clone(&chars))));
+        Self {
+            cursor: 0xc000,
+            top: 0xc000,
+            low_nibble: false,
+            ascii: false,
+            banks: [None; 4],
+            search: None,
+            message: String::new(),
+            view: vec![None; 0x10000].into_boxed_slice(),
+            previous: vec![None; 0x10000].into_boxed_slice(),
+            frame: u64::MAX,
+            chars,
+        }
+    }
+
+    /// Handle the input of the window.
+    pub fn update(&mut self, window: &Window, gb: &mut GameBoy, symbols: &Symbols) {
+        let chars: Vec<_> = self.chars.borrow_mut().drain(..).collect();
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        let pressed = |key| window.is_key_pressed(key, KeyRepeat::Yes);
+        if ctrl {
+            if pressed(Key::G) {
+                self.go_to(gb, symbols);
+            }
+            if pressed(Key::F) {
+                self.find(gb);
+            }
+            if pressed(Key::N) {
+                self.find_next(gb);
+            }
+            if pressed(Key::B) {
+                self.next_bank(gb);
+            }
+            return;
+        }
+        if pressed(Key::F3) {
+            self.find_next(gb);
+        }
+        if pressed(Key::Tab) {
+            self.ascii = !self.ascii;
+            self.low_nibble = false;
+        }
+        if pressed(Key::Escape) {
+            self.low_nibble = false;
+        }
+        let rows = (ROWS * 16) as i32;
+        let moves = [
+            (Key::Left, -1),
+            (Key::Right, 1),
+            (Key::Up, -16),
+            (Key::Down, 16),
+            (Key::PageUp, -rows),
+            (Key::PageDown, rows),
+            (Key::Home, -(self.cursor as i32 & 0xf)),
+            (Key::End, 0xf - (self.cursor as i32 & 0xf)),
+        ];
+        for (key, offset) in moves {
+            if pressed(key) {
+                self.message.clear();
+                self.move_to((self.cursor as i32 + offset).clamp(0, 0xffff) as u16);
+            }
+        }
+        if let Some((_, y)) = window.get_scroll_wheel() {
+            let top = self.top as i32 - (y.signum() as i32) * 3 * 16;
+            self.top = top.clamp(0, 0x10000 - rows) as u16;
+        }
+        for c in chars {
+            if self.ascii {
+                self.write(gb, c as u8);
+                self.move_to(self.cursor.saturating_add(1));
+            } else if let Some(digit) = c.to_digit(16) {
+                let byte = self.read(gb, self.cursor).unwrap_or(0);
+                if self.low_nibble {
+                    self.write(gb, (byte & 0xf0) | digit as u8);
+                    self.move_to(self.cursor.saturating_add(1));
+                } else {
+                    self.write(gb, (byte & 0x0f) | (digit as u8) << 4);
+                    self.low_nibble = true;
+                }
+            }
+        }
+    }
+
+    // bank shown at the given address
+    fn bank(&self, gb: &GameBoy, address: u16) -> usize {
+        region(address)
+            .and_then(|region| self.banks[region])
+            .or_else(|| gb.soc().bank(address))
+            .unwrap_or(0)
+    }
+
+    fn read(&self, gb: &GameBoy, address: u16) -> Option<u8> {
+        gb.soc().read_bank(self.bank(gb, address), address).ok()
+    }
+
+    // write the byte under the cursor
+    fn write(&mut self, gb: &mut GameBoy, data: u8) {
+        let bank = self.bank(gb, self.cursor);
+        if let Err(err) = gb.soc_mut().write_bank(bank, self.cursor, data) {
+            self.message = err.to_string();
+        }
+        self.view[self.cursor as usize] = self.read(gb, self.cursor);
+    }
+
+    // move the cursor, scrolling the view to keep it visible
+    fn move_to(&mut self, address: u16) {
+        let rows = (ROWS * 16) as u32;
+        let row = (address & 0xfff0) as u32;
+        if row < self.top as u32 {
+            self.top = row as u16;
+        } else if row >= self.top as u32 + rows {
+            self.top = (row + 16 - rows) as u16;
+        }
+        self.cursor = address;
+        self.low_nibble = false;
+    }
+
+    fn go_to(&mut self, gb: &GameBoy, symbols: &Symbols) {
+        let input = dialog::Input::new("[bank:]address or label")
+            .title("go to")
+            .show();
+        let address = match input {
+            Ok(Some(address)) => Address::parse(&address, symbols),
+            _ => return,
+        };
+        match address {
+            Ok(Address { bank, address }) => {
+                self.move_to(address);
+                if let (Some(bank), Some(region)) = (bank, region(address)) {
+                    match gb.soc().banks(address) {
+                        Some(banks) if banks.contains(&bank) => self.pin(gb, region, Some(bank)),
+                        _ => self.message = format!("no {} bank {bank:02X}", REGIONS[region]),
+                    }
+                }
+            }
+            Err(err) => self.message = err.to_string(),
+        }
+    }
+
+    // cycle the pinned bank of the region under the cursor
+    fn next_bank(&mut self, gb: &GameBoy) {
+        let (Some(region), Some(banks)) = (region(self.cursor), gb.soc().banks(self.cursor)) else {
+            return;
+        };
+        let bank = match self.banks[region] {
+            None => Some(*banks.start()),
+            Some(bank) if bank < *banks.end() => Some(bank + 1),
+            Some(_) => None,
+        };
+        self.pin(gb, region, bank);
+        self.message = match bank {
+            Some(bank) => format!("{} bank {bank:02X}", REGIONS[region]),
+            None => format!("{} follows the mapped bank", REGIONS[region]),
+        };
+    }
+
+    // switching banks isn't highlighted as a change
+    fn pin(&mut self, gb: &GameBoy, region: usize, bank: Option<usize>) {
+        self.banks[region] = bank;
+        self.refresh(gb);
+        self.previous.copy_from_slice(&self.view);
+    }
+
+    fn find(&mut self, gb: &GameBoy) {
+        let input = dialog::Input::new("hex bytes (DE AD) or \"text\"")
+            .title("find")
+            .show();
+        if let Ok(Some(pattern)) = input {
+            match parse_pattern(&pattern) {
+                Some(pattern) => {
+                    self.search = Some(pattern);
+                    self.find_next(gb);
+                }
+                None => self.message = format!("invalid pattern {pattern:?}"),
+            }
+        }
+    }
+
+    // search after the cursor, wrapping around the address space
+    fn find_next(&mut self, gb: &GameBoy) {
+        let Some(pattern) = self.search.clone() else {
+            return;
+        };
+        self.refresh(gb);
+        let found = (1..=0x10000)
+            .map(|i| self.cursor.wrapping_add(i as u16))
+            .find(|&address| {
+                pattern.iter().enumerate().all(|(i, byte)| {
+                    let address = address as usize + i;
+                    address <= 0xffff && self.view[address] == Some(*byte)
+                })
+            });
+        match found {
+            Some(address) => {
+                self.move_to(address);
+                self.message = format!("found at {address:04X}");
+            }
+            None => self.message = "not found".to_string(),
+        }
+    }
+
+    // read the whole address space
+    fn refresh(&mut self, gb: &GameBoy) {
+        for address in 0..=0xffff {
+            self.view[address as usize] = self.read(gb, address);
+        }
+    }
+
+    pub fn draw<D>(&mut self, gb: &GameBoy, symbols: &Symbols, target: &mut D)
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        // changes are tracked from frame to frame
+        let frame = gb.soc().frames();
+        if frame != self.frame {
+            std::mem::swap(&mut self.view, &mut self.previous);
+            self.refresh(gb);
+            if self.frame == u64::MAX {
+                self.previous.copy_from_slice(&self.view);
+            }
+            self.frame = frame;
+        }
+        for address in self.top..=self.top.saturating_add((ROWS * 16 - 1) as u16) {
+            self.view[address as usize] = self.read(gb, address);
+        }
+        let _ = target.clear(Rgb888::BLACK);
+        let text = |target: &mut D, text: &str, col: i32, row: i32, color| {
+            let style = MonoTextStyle::new(&FONT_5X7, color);
+            let _ = Text::new(text, Point::new(5 * col, 6 + 7 * row), style).draw(target);
+        };
+        let cell = |target: &mut D, col: i32, row: i32, len: u32, color| {
+            let _ = Rectangle::new(Point::new(5 * col, 7 * row), Size::new(5 * len, 7))
+                .into_styled(PrimitiveStyle::with_fill(color))
+                .draw(target);
+        };
+
+        // banks of the switchable regions (pinned ones highlighted)
+        let mut col = 0;
+        for (region, name) in REGIONS.iter().enumerate() {
+            let address = [0x4000, 0x8000, 0xa000, 0xd000][region];
+            let label = format!("{name} {:02X}", self.bank(gb, address));
+            let color = match self.banks[region] {
+                Some(_) => Rgb888::CSS_ORANGE,
+                None => Rgb888::CSS_DIM_GRAY,
+            };
+            text(target, &label, col, 0, color);
+            col += label.len() as i32 + 2;
+        }
+        let cursor = Address::banked(self.bank(gb, self.cursor), self.cursor);
+        let label = cursor.symbolic(symbols);
+        text(
+            target,
+            &label,
+            ASCII_COL + 16 - label.len() as i32,
+            0,
+            Rgb888::WHITE,
+        );
+
+        for row in 0..ROWS {
+            let address = self.top as usize + row * 16;
+            let y = row as i32 + 1;
+            if address > 0xffff {
+                break;
+            }
+            text(
+                target,
+                &format!("{address:04X}"),
+                0,
+                y,
+                Rgb888::CSS_DIM_GRAY,
+            );
+            for i in 0..16 {
+                let address = address + i;
+                let x = HEX_COL + 3 * i as i32 + (i as i32 / 8);
+                let data = self.view[address];
+                if address == self.cursor as usize {
+                    let (hex, ascii) = match self.ascii {
+                        false => (Rgb888::CSS_STEEL_BLUE, Rgb888::CSS_DARK_SLATE_GRAY),
+                        true => (Rgb888::CSS_DARK_SLATE_GRAY, Rgb888::CSS_STEEL_BLUE),
+                    };
+                    cell(target, x, y, 2, hex);
+                    cell(target, ASCII_COL + i as i32, y, 1, ascii);
+                }
+                let color = if data != self.previous[address] {
+                    Rgb888::CSS_ORANGE
+                } else if data == Some(0) {
+                    Rgb888::CSS_DIM_GRAY
+                } else {
+                    Rgb888::WHITE
+                };
+                let (hex, ascii) = match data {
+                    Some(data) if data.is_ascii_graphic() => (format!("{data:02X}"), data as char),
+                    Some(data) => (format!("{data:02X}"), '.'),
+                    None => ("--".to_string(), ' '),
+                };
+                text(target, &hex, x, y, color);
+                text(target, &ascii.to_string(), ASCII_COL + i as i32, y, color);
+            }
+        }
+
+        let footer = if self.message.is_empty() {
+            "^G go to  ^F find  F3 next  ^B bank  Tab hex/ASCII"
+        } else {
+            &self.message
+        };
+        text(target, footer, 0, ROWS as i32 + 1, Rgb888::CSS_DIM_GRAY);
+    }
+}
+
+// "DE AD BE EF", "deadbeef" or "\"text\""
+fn parse_pattern(pattern: &str) -> Option<Vec<u8>> {
+    let pattern = pattern.trim();
+    if let Some(text) = pattern.strip_prefix('"') {
+        let text = text.strip_suffix('"').unwrap_or(text);
+        return Some(text.as_bytes().to_vec()).filter(|text| !text.is_empty());
+    }
+    let digits: String = pattern.split_whitespace().collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
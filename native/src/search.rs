@@ -0,0 +1,228 @@
+//! SEARCH window: RAM search (cheat finder).
+//!
+//! Start a search with `N` (8-bit) or `W` (16-bit), then narrow the candidates
+//! from frame to frame. The selected candidate can be frozen with a GameShark
+//! cheat (`Enter`).
+use crate::GameBoy;
+use core::debug::Address;
+use dialog::DialogBox;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_5X7, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use minifb::{Key, KeyRepeat, Window};
+use std::ops::RangeInclusive;
+use utils::{
+    search::{Filter, Memory, Search, Width},
+    symbols::Symbols,
+};
+
+pub const ROWS: usize = 20;
+pub const WIDTH: usize = 5 * 48;
+pub const HEIGHT: usize = 7 * (ROWS + 4);
+
+const HELP: [&str; 2] = [
+    "N/W new 8/16-bit  E same  C changed  V value",
+    "I/D inc/dec (Shift: by N)  Enter freeze",
+];
+
+struct Ram<'a>(&'a GameBoy);
+
+impl Memory for Ram<'_> {
+    fn banks(&self, address: u16) -> RangeInclusive<usize> {
+        self.0.soc().banks(address).unwrap_or(0..=0)
+    }
+
+    fn read(&self, bank: usize, address: u16) -> Option<u8> {
+        self.0.soc().read_bank(bank, address).ok()
+    }
+}
+
+#[derive(Default)]
+pub struct RamSearch {
+    search: Option<Search>,
+    // selected candidate & first visible one
+    cursor: usize,
+    top: usize,
+    message: String,
+}
+
+impl RamSearch {
+    /// Handle the input of the window.
+    pub fn update(&mut self, window: &Window, gb: &mut GameBoy) {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let pressed = |key| window.is_key_pressed(key, KeyRepeat::No);
+        if pressed(Key::N) {
+            self.start(gb, Width::Byte);
+        }
+        if pressed(Key::W) {
+            self.start(gb, Width::Word);
+        }
+        let filter = if pressed(Key::E) {
+            Some(Filter::Unchanged)
+        } else if pressed(Key::C) {
+            Some(Filter::Changed)
+        } else if pressed(Key::I) && shift {
+            prompt("increased by").map(Filter::IncreasedBy)
+        } else if pressed(Key::I) {
+            Some(Filter::Increased)
+        } else if pressed(Key::D) && shift {
+            prompt("decreased by").map(Filter::DecreasedBy)
+        } else if pressed(Key::D) {
+            Some(Filter::Decreased)
+        } else if pressed(Key::V) {
+            prompt("value").map(Filter::Value)
+        } else {
+            None
+        };
+        if let (Some(filter), Some(search)) = (filter, &mut self.search) {
+            let len = search.filter(&Ram(gb), filter);
+            self.message = format!("{filter}: {len} candidates");
+            self.cursor = 0;
+            self.top = 0;
+        }
+
+        let len = self.search.as_ref().map_or(0, |s| s.candidates().len());
+        let moves = [
+            (Key::Up, -1),
+            (Key::Down, 1),
+            (Key::PageUp, -(ROWS as isize)),
+            (Key::PageDown, ROWS as isize),
+        ];
+        for (key, offset) in moves {
+            if window.is_key_pressed(key, KeyRepeat::Yes) && len > 0 {
+                let cursor = (self.cursor as isize + offset).clamp(0, len as isize - 1);
+                self.cursor = cursor as usize;
+            }
+        }
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + ROWS {
+            self.top = self.cursor + 1 - ROWS;
+        }
+
+        if pressed(Key::Enter) {
+            self.freeze(gb);
+        }
+    }
+
+    fn start(&mut self, gb: &GameBoy, width: Width) {
+        let search = Search::new(&Ram(gb), width);
+        self.message = format!("new search: {} candidates", search.candidates().len());
+        self.search = Some(search);
+        self.cursor = 0;
+        self.top = 0;
+    }
+
+    // add a cheat that freezes the selected candidate
+    fn freeze(&mut self, gb: &mut GameBoy) {
+        let Some(candidate) = self
+            .search
+            .as_ref()
+            .and_then(|s| s.candidates().get(self.cursor))
+            .copied()
+        else {
+            return;
+        };
+        let value = candidate.read(&Ram(gb)).unwrap_or(candidate.value);
+        let Some(value) = prompt_with("freeze value", Some(value)) else {
+            return;
+        };
+        let Some(codes) = candidate.gameshark(value) else {
+            self.message = "bank 1 of the cartridge RAM can't be frozen".to_string();
+            return;
+        };
+        let name = format!("{:02X}:{:04X}", candidate.bank, candidate.address);
+        let name = dialog::Input::new("cheat name")
+            .title("freeze")
+            .default(name)
+            .show();
+        if let Ok(Some(name)) = name {
+            self.message = match gb.cheats_mut().add(&name, &codes) {
+                Ok(()) => format!("{name} = {codes}"),
+                Err(err) => err.to_string(),
+            };
+        }
+    }
+
+    pub fn draw<D>(&self, gb: &GameBoy, symbols: &Symbols, target: &mut D)
+    where
+        D: DrawTarget<Color = Rgb888>,
+    {
+        let _ = target.clear(Rgb888::BLACK);
+        let text = |target: &mut D, text: &str, col: i32, row: i32, color| {
+            let style = MonoTextStyle::new(&FONT_5X7, color);
+            let _ = Text::new(text, Point::new(5 * col, 6 + 7 * row), style).draw(target);
+        };
+
+        for (row, help) in HELP.iter().enumerate() {
+            text(
+                target,
+                help,
+                0,
+                (ROWS + 2 + row) as i32,
+                Rgb888::CSS_DIM_GRAY,
+            );
+        }
+        text(target, &self.message, 0, ROWS as i32 + 1, Rgb888::WHITE);
+        let Some(search) = &self.search else {
+            return;
+        };
+
+        let (width, digits) = match search.width() {
+            Width::Byte => ("8-bit", 2),
+            Width::Word => ("16-bit", 4),
+        };
+        let candidates = search.candidates();
+        let header = format!("{width}  {} candidates", candidates.len());
+        text(target, &header, 0, 0, Rgb888::WHITE);
+
+        let ram = Ram(gb);
+        for (row, candidate) in candidates.iter().enumerate().skip(self.top).take(ROWS) {
+            let y = (row - self.top) as i32 + 1;
+            if row == self.cursor {
+                let _ = Rectangle::new(Point::new(0, 7 * y), Size::new(WIDTH as u32, 7))
+                    .into_styled(PrimitiveStyle::with_fill(Rgb888::CSS_DARK_SLATE_GRAY))
+                    .draw(target);
+            }
+            let address = Address::banked(candidate.bank, candidate.address);
+            let mut label = address.symbolic(symbols);
+            label.truncate(20);
+            let value = candidate.read(&ram);
+            let color = match value {
+                Some(value) if value != candidate.value => Rgb888::CSS_ORANGE,
+                _ => Rgb888::WHITE,
+            };
+            let value = value.map_or("--".to_string(), |value| {
+                format!("{value:0digits$X} {value:>5}")
+            });
+            let last = format!("{:0digits$X}", candidate.value);
+            text(target, &label, 0, y, Rgb888::WHITE);
+            text(target, &last, 21, y, Rgb888::CSS_DIM_GRAY);
+            text(target, &value, 27, y, color);
+        }
+    }
+}
+
+// ask for a decimal (or 0x/$ hexadecimal) number
+fn prompt(title: &str) -> Option<u16> {
+    prompt_with(title, None)
+}
+
+fn prompt_with(title: &str, default: Option<u16>) -> Option<u16> {
+    let mut input = dialog::Input::new("decimal, 0x or $ hexadecimal");
+    input.title(title);
+    if let Some(default) = default {
+        input.default(default.to_string());
+    }
+    let input = input.show().ok().flatten()?;
+    let input = input.trim();
+    let hex = input.strip_prefix("0x").or_else(|| input.strip_prefix('$'));
+    match hex {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => input.parse().ok(),
+    }
+}
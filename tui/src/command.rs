@@ -0,0 +1,318 @@
+//! Debugger commands.
+//!
+//! ```text
+//! break <address|label> [if <condition>]   b
+//! break if <condition>
+//! delete [<index>]                         d
+//! watch [r|w|rw] <address>[-<end>]         w
+//! step [<count>]                           s
+//! next                                     n
+//! finish                                   fin
+//! continue                                 c
+//! x[/<count>] <address|label>
+//! set <register>=<value>
+//! set [<address|label>]=<value>
+//! lcd
+//! reset
+//! help
+//! quit                                     q
+//! ```
+//!
+//! Addresses are hexadecimal, optionally with a bank (`01:4a2f`). Values and
+//! conditions use the expression syntax of `core::debug::expr`.
+use core::debug::{
+    expr::{self, Expr},
+    Access,
+};
+use std::{fmt, str::FromStr};
+
+pub const HELP: &[&str] = &[
+    "break <address|label> [if <condition>], break if <condition>, delete [<index>]",
+    "watch [r|w|rw] <address>[-<end>]",
+    "step [<count>], next, finish, continue (Esc or Ctrl+C to interrupt)",
+    "x[/<count>] <address|label>, set <register>=<value>, set [<address>]=<value>",
+    "lcd, reset, quit (an empty command repeats the last one)",
+];
+
+// thiserror can't be used here: its generated code refers to the standard
+// `core` crate, which is shadowed by the emulator crate
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Unknown(String),
+    Usage(&'static str),
+    Expr(expr::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unknown(name) => write!(f, "Unknown command \"{name}\""),
+            Error::Usage(usage) => write!(f, "Usage: {usage}"),
+            Error::Expr(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<expr::Error> for Error {
+    fn from(err: expr::Error) -> Self {
+        Error::Expr(err)
+    }
+}
+
+/// Register that can be written with `set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    ZF,
+    NF,
+    HF,
+    CF,
+}
+
+impl FromStr for Register {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "a" => Register::A,
+            "f" => Register::F,
+            "b" => Register::B,
+            "c" => Register::C,
+            "d" => Register::D,
+            "e" => Register::E,
+            "h" => Register::H,
+            "l" => Register::L,
+            "af" => Register::AF,
+            "bc" => Register::BC,
+            "de" => Register::DE,
+            "hl" => Register::HL,
+            "sp" => Register::SP,
+            "pc" => Register::PC,
+            "zf" => Register::ZF,
+            "nf" => Register::NF,
+            "hf" => Register::HF,
+            "cf" => Register::CF,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Destination of `set`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Register(Register),
+    /// Address or label, resolved by the debugger.
+    Memory(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break {
+        address: Option<String>,
+        condition: Option<Expr>,
+    },
+    Delete(Option<usize>),
+    Watch {
+        access: Access,
+        start: String,
+        end: Option<String>,
+    },
+    Step(u64),
+    Next,
+    Finish,
+    Continue,
+    Examine {
+        count: usize,
+        address: String,
+    },
+    Set {
+        target: Target,
+        value: Expr,
+    },
+    Lcd,
+    Reset,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let args = args.trim();
+        let command = match name {
+            "break" | "b" => {
+                const USAGE: &str = "break <address|label> [if <condition>]";
+                let (address, condition) = match args.strip_prefix("if ") {
+                    Some(condition) => ("", Some(condition)),
+                    None => match args.split_once(" if ") {
+                        Some((address, condition)) => (address.trim(), Some(condition)),
+                        None => (args, None),
+                    },
+                };
+                if address.is_empty() && condition.is_none() {
+                    return Err(Error::Usage(USAGE));
+                }
+                Command::Break {
+                    address: Some(address.to_string()).filter(|a| !a.is_empty()),
+                    condition: condition.map(str::parse).transpose()?,
+                }
+            }
+            "delete" | "d" => match args {
+                "" => Command::Delete(None),
+                index => Command::Delete(Some(
+                    index
+                        .parse()
+                        .map_err(|_| Error::Usage("delete [<index>]"))?,
+                )),
+            },
+            "watch" | "w" => {
+                const USAGE: &str = "watch [r|w|rw] <address>[-<end>]";
+                let (access, range) = match args.split_once(char::is_whitespace) {
+                    Some(("r", range)) => (Access::Read, range.trim()),
+                    Some(("w", range)) => (Access::Write, range.trim()),
+                    Some(("rw", range)) => (Access::ReadWrite, range.trim()),
+                    Some(_) => return Err(Error::Usage(USAGE)),
+                    None => (Access::Write, args),
+                };
+                if range.is_empty() {
+                    return Err(Error::Usage(USAGE));
+                }
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (start, Some(end.to_string())),
+                    None => (range, None),
+                };
+                Command::Watch {
+                    access,
+                    start: start.to_string(),
+                    end,
+                }
+            }
+            "step" | "s" => match args {
+                "" => Command::Step(1),
+                count => Command::Step(
+                    count
+                        .parse()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or(Error::Usage("step [<count>]"))?,
+                ),
+            },
+            "next" | "n" => Command::Next,
+            "finish" | "fin" => Command::Finish,
+            "continue" | "c" => Command::Continue,
+            "lcd" => Command::Lcd,
+            "reset" => Command::Reset,
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            "set" => {
+                const USAGE: &str = "set <register>=<value>, set [<address>]=<value>";
+                let (target, value) = args.split_once('=').ok_or(Error::Usage(USAGE))?;
+                let target = target.trim();
+                let target = match target.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+                    Some(address) => Target::Memory(address.trim().to_string()),
+                    None => Target::Register(
+                        target
+                            .to_ascii_lowercase()
+                            .parse()
+                            .map_err(|_| Error::Usage(USAGE))?,
+                    ),
+                };
+                Command::Set {
+                    target,
+                    value: value.parse()?,
+                }
+            }
+            x if x == "x" || x.starts_with("x/") => {
+                const USAGE: &str = "x[/<count>] <address|label>";
+                let count = match x.strip_prefix("x/") {
+                    Some(count) => count.parse().map_err(|_| Error::Usage(USAGE))?,
+                    None => 16,
+                };
+                if args.is_empty() {
+                    return Err(Error::Usage(USAGE));
+                }
+                Command::Examine {
+                    count,
+                    address: args.to_string(),
+                }
+            }
+            _ => return Err(Error::Unknown(name.to_string())),
+        };
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, Error, Register, Target};
+    use core::debug::Access;
+
+    #[test]
+    fn parse() {
+        let parse = |s: &str| s.parse::<Command>();
+        assert_eq!(
+            Ok(Command::Break {
+                address: Some("Main".to_string()),
+                condition: Some("a == 3".parse().unwrap()),
+            }),
+            parse("break Main if a == 3")
+        );
+        assert_eq!(
+            Ok(Command::Break {
+                address: None,
+                condition: Some("[ff44] >= 0x90".parse().unwrap()),
+            }),
+            parse("b if [ff44] >= 0x90")
+        );
+        assert_eq!(
+            Ok(Command::Watch {
+                access: Access::ReadWrite,
+                start: "c000".to_string(),
+                end: Some("c0ff".to_string()),
+            }),
+            parse("watch rw c000-c0ff")
+        );
+        assert_eq!(Ok(Command::Step(10)), parse("s 10"));
+        assert_eq!(
+            Ok(Command::Examine {
+                count: 16,
+                address: "c000".to_string(),
+            }),
+            parse("x/16 c000")
+        );
+        assert_eq!(
+            Ok(Command::Set {
+                target: Target::Register(Register::A),
+                value: "3".parse().unwrap(),
+            }),
+            parse("set a=3")
+        );
+        assert_eq!(
+            Ok(Command::Set {
+                target: Target::Memory("c000".to_string()),
+                value: "0x42".parse().unwrap(),
+            }),
+            parse("set [c000] = 0x42")
+        );
+        assert_eq!(Err(Error::Unknown("jump".to_string())), parse("jump 100"));
+        assert!(matches!(parse("break"), Err(Error::Usage(_))));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::command::{Command, Register, Target, HELP};
+use core::{
+    cartridge::{self, Cartridge},
+    debug::{
+        expr::Expr, Access, Address, Breakpoint, BreakpointEntry, BreakpointSet, Trigger, Watch,
+        Watchpoint,
+    },
+    device::Device,
+    ppu::{Color, LCD, LCD_HEIGHT, LCD_WIDTH},
+};
+use std::{cell::RefCell, rc::Rc};
+use utils::{dasm::Disassembler, symbols::Symbols};
+
+pub type GameBoy = core::gb::GameBoy<Box<dyn Cartridge>, FrameLCD>;
+pub type Frame = [Color; LCD_WIDTH * LCD_HEIGHT];
+
+// lines kept in the console
+const LOG_LINES: usize = 256;
+
+/// LCD output to a shared frame buffer.
+pub struct FrameLCD(Rc<RefCell<Frame>>);
+
+impl LCD for FrameLCD {
+    fn output_line(&mut self, ly: u8, data: &[Color; LCD_WIDTH]) {
+        let offset = LCD_WIDTH * ly as usize;
+        self.0.borrow_mut()[offset..offset + LCD_WIDTH].copy_from_slice(data);
+    }
+}
+
+/// Emulation in progress, until a breakpoint is hit or it is interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Run {
+    Continue,
+    /// Remaining instructions.
+    Step(u64),
+    /// Step over calls (and interrupts) until the call depth is back to the
+    /// given one.
+    Next(usize),
+    /// Run until the call depth is below the given one.
+    Finish(usize),
+}
+
+/// Disassembled instruction.
+pub struct Instruction {
+    pub address: Address,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+pub struct Debugger {
+    pub gb: GameBoy,
+    pub symbols: Symbols,
+    pub breakpoints: BreakpointSet,
+    pub run: Option<Run>,
+    /// First address of the memory pane.
+    pub memory: u16,
+    /// Show the LCD instead of the disassembly and memory panes.
+    pub lcd: bool,
+    pub log: Vec<String>,
+    pub quit: bool,
+    frame: Rc<RefCell<Frame>>,
+}
+
+impl Debugger {
+    pub fn new(rom: Box<[u8]>, symbols: Symbols) -> Self {
+        let frame = Rc::new(RefCell::new([[0, 0, 0, 0xff]; LCD_WIDTH * LCD_HEIGHT]));
+        let mut log = Vec::new();
+        let cartridge = cartridge::load(rom).unwrap_or_else(|err| {
+            log.push(format!("error: {err}"));
+            Box::new(())
+        });
+        let mut gb = GameBoy::new(cartridge, FrameLCD(Rc::clone(&frame)));
+        if let Err(err) = gb.boot() {
+            log.push(format!("error: {err}"));
+        }
+        Self {
+            gb,
+            symbols,
+            breakpoints: BreakpointSet::new(),
+            run: None,
+            memory: 0xc000,
+            lcd: false,
+            log,
+            quit: false,
+            frame,
+        }
+    }
+
+    pub fn frame(&self) -> std::cell::Ref<'_, Frame> {
+        self.frame.borrow()
+    }
+
+    pub fn log(&mut self, line: impl Into<String>) {
+        self.log.push(line.into());
+        if self.log.len() > LOG_LINES {
+            self.log.drain(..self.log.len() - LOG_LINES);
+        }
+    }
+
+    /// Returns the current PC qualified with its bank.
+    pub fn pc(&self) -> Address {
+        Address::mapped(self.gb.soc(), self.gb.soc().cpu().registers().pc)
+    }
+
+    /// Stop the emulation.
+    pub fn interrupt(&mut self) {
+        if self.run.take().is_some() {
+            self.log(format!("interrupted at {}", self.location()));
+        }
+    }
+
+    pub fn execute(&mut self, command: Command) {
+        if let Err(err) = self.try_execute(command) {
+            self.log(format!("error: {err}"));
+        }
+    }
+
+    fn try_execute(&mut self, command: Command) -> Result<(), String> {
+        let depth = self.gb.soc().cpu().call_stack().depth();
+        match command {
+            Command::Break { address, condition } => {
+                let entry = match address {
+                    Some(address) => {
+                        let entry = BreakpointEntry::pc(self.address(&address)?);
+                        match condition {
+                            Some(condition) => entry.condition(condition),
+                            None => entry,
+                        }
+                    }
+                    None => BreakpointEntry::when(condition.unwrap()),
+                };
+                let label = breakpoint_label(&entry, &self.symbols);
+                let index = self.breakpoints.add(entry);
+                self.log(format!("breakpoint #{index}: {label}"));
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                self.gb.soc_mut().watchpoints_mut().clear();
+                self.log("breakpoints and watchpoints deleted");
+            }
+            Command::Delete(Some(index)) => {
+                if index >= self.breakpoints.len() {
+                    return Err(format!("no breakpoint #{index}"));
+                }
+                self.breakpoints.remove(index);
+                self.log(format!("breakpoint #{index} deleted"));
+            }
+            Command::Watch { access, start, end } => {
+                let start = self.address(&start)?;
+                let end = match end {
+                    Some(end) => self.address(&end)?.address,
+                    None => start.address,
+                };
+                let range = start.address..=end;
+                let mut watchpoint = match access {
+                    Access::Read => Watchpoint::read(range),
+                    Access::Write => Watchpoint::write(range),
+                    Access::ReadWrite => Watchpoint::read_write(range),
+                };
+                if let Some(bank) = start.bank {
+                    watchpoint = watchpoint.bank(bank);
+                }
+                let index = self.gb.soc_mut().watchpoints_mut().add(watchpoint);
+                self.log(format!("watchpoint #{index}: {start}-{end:04X}"));
+            }
+            Command::Step(count) => self.run = Some(Run::Step(count)),
+            Command::Next => self.run = Some(Run::Next(depth)),
+            Command::Finish if depth == 0 => return Err("outermost frame".to_string()),
+            Command::Finish => self.run = Some(Run::Finish(depth)),
+            Command::Continue => self.run = Some(Run::Continue),
+            Command::Examine { count, address } => {
+                let address = self.address(&address)?.address;
+                self.memory = address & 0xfff0;
+                for row in (0..count).step_by(16) {
+                    let row = address.wrapping_add(row as u16);
+                    let len = (count - (row.wrapping_sub(address) as usize)).min(16);
+                    let bytes: Vec<_> = (0..len as u16)
+                        .map(|i| self.read(row.wrapping_add(i)))
+                        .map(|byte| format!("{byte:02X}"))
+                        .collect();
+                    self.log(format!("{row:04X}: {}", bytes.join(" ")));
+                }
+            }
+            Command::Set { target, value } => self.set(target, &value)?,
+            Command::Lcd => self.lcd = !self.lcd,
+            Command::Reset => {
+                self.run = None;
+                self.gb.reset();
+                self.gb.boot().map_err(|err| err.to_string())?;
+                self.log("reset");
+            }
+            Command::Help => HELP.iter().for_each(|line| self.log(*line)),
+            Command::Quit => self.quit = true,
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, target: Target, value: &Expr) -> Result<(), String> {
+        let value = value.eval(self.gb.soc());
+        match target {
+            Target::Memory(address) => {
+                let address = self.address(&address)?.address;
+                Device::write(self.gb.soc_mut(), address, value as u8)
+                    .map_err(|err| err.to_string())?;
+                self.log(format!("[{address:04X}] = {:02X}", value as u8));
+            }
+            Target::Register(register) => {
+                let registers = self.gb.soc_mut().cpu_mut().registers_mut();
+                let flag = |f: u8, mask: u8| if value != 0 { f | mask } else { f & !mask };
+                match register {
+                    Register::A => registers.a = value as u8,
+                    Register::F => registers.f = value as u8 & 0xf0,
+                    Register::B => registers.b = value as u8,
+                    Register::C => registers.c = value as u8,
+                    Register::D => registers.d = value as u8,
+                    Register::E => registers.e = value as u8,
+                    Register::H => registers.h = value as u8,
+                    Register::L => registers.l = value as u8,
+                    Register::AF => registers.set_af(value as u16 & 0xfff0),
+                    Register::BC => registers.set_bc(value as u16),
+                    Register::DE => registers.set_de(value as u16),
+                    Register::HL => registers.set_hl(value as u16),
+                    Register::SP => registers.sp = value as u16,
+                    Register::PC => registers.pc = value as u16,
+                    Register::ZF => registers.f = flag(registers.f, 0x80),
+                    Register::NF => registers.f = flag(registers.f, 0x40),
+                    Register::HF => registers.f = flag(registers.f, 0x20),
+                    Register::CF => registers.f = flag(registers.f, 0x10),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Emulate (at most) one frame worth of cycles of the current run.
+    pub fn update(&mut self) {
+        let Some(mut run) = self.run.take() else {
+            return;
+        };
+        let start = self.gb.soc().cycles();
+        let budget = self.gb.soc().clock_freq() / 60;
+        while self.gb.soc().cycles() - start < budget {
+            match self.step() {
+                Ok(false) => {}
+                Ok(true) => return,
+                Err(err) => {
+                    self.log(format!("error: {err}"));
+                    return;
+                }
+            }
+            let depth = self.gb.soc().cpu().call_stack().depth();
+            let done = match &mut run {
+                Run::Continue => false,
+                Run::Step(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                Run::Next(next) => depth <= *next,
+                Run::Finish(finish) => depth < *finish,
+            };
+            if done {
+                if !matches!(run, Run::Step(_)) {
+                    let location = self.location();
+                    self.log(location);
+                }
+                return;
+            }
+        }
+        self.run = Some(run);
+    }
+
+    // step a single instruction, returns true if a breakpoint or watchpoint
+    // has been hit
+    fn step(&mut self) -> Result<bool, core::error::Error> {
+        let mut breakpoint = (&mut self.breakpoints, Watch);
+        breakpoint.init(self.gb.soc());
+        self.gb.soc_mut().step()?;
+        if !breakpoint.breakpoint(self.gb.soc()) {
+            return Ok(false);
+        }
+        if let Some(index) = self.breakpoints.hit() {
+            let entry = self.breakpoints.get(index).unwrap();
+            let line = format!("breakpoint #{index} hit ({} hits)", entry.hits);
+            self.log(line);
+        }
+        let hits: Vec<_> = self.gb.soc().watchpoints().hits().to_vec();
+        for hit in hits {
+            let access = match hit.access {
+                Access::Read => "read",
+                _ => "write",
+            };
+            self.log(format!(
+                "watchpoint #{} {access} {:04X} at {:04X}: {:02X} -> {:02X}",
+                hit.index, hit.address, hit.pc, hit.old, hit.new
+            ));
+        }
+        let location = self.location();
+        self.log(location);
+        Ok(true)
+    }
+
+    // "at 00:0150 Main+3"
+    fn location(&self) -> String {
+        let pc = self.pc();
+        format!("at {pc} {}", pc.symbolic(&self.symbols))
+    }
+
+    fn address(&self, address: &str) -> Result<Address, String> {
+        Address::parse(address, &self.symbols).map_err(|err| err.to_string())
+    }
+
+    /// Read memory without side effects.
+    pub fn read(&self, address: u16) -> u8 {
+        Device::read(self.gb.soc(), address).unwrap_or(0xff)
+    }
+
+    /// Disassemble `count` instructions starting at `address`.
+    pub fn disassemble(&self, mut address: u16, count: usize) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(count);
+        while instructions.len() < count {
+            let bytes: Vec<_> = (0..3).map(|i| self.read(address.wrapping_add(i))).collect();
+            let (text, len) = match Disassembler::new(&bytes).next() {
+                Some(Ok((opcode, len))) => {
+                    let text = opcode.display_with(address, |address| {
+                        Address::mapped(self.gb.soc(), address).label(&self.symbols)
+                    });
+                    (text, len)
+                }
+                _ => (format!("db {:02X}h", bytes[0]), 1),
+            };
+            instructions.push(Instruction {
+                address: Address::mapped(self.gb.soc(), address),
+                bytes: bytes[..len].to_vec(),
+                text,
+            });
+            address = address.wrapping_add(len as u16);
+        }
+        instructions
+    }
+
+    /// Disassemble (at most) `count` instructions leading to `address`.
+    ///
+    /// Instructions have variable length, so the disassembly starts at the
+    /// farthest address that decodes into an instruction ending right at
+    /// `address`.
+    pub fn disassemble_before(&self, address: u16, count: usize) -> Vec<Instruction> {
+        for start in (1..=3 * count as u16).rev() {
+            let Some(start) = address.checked_sub(start) else {
+                continue;
+            };
+            let mut instructions = Vec::new();
+            // wider than an address, so the last instruction can end past 0xffff
+            let mut pc = u32::from(start);
+            while pc < u32::from(address) {
+                let instruction = self.disassemble(pc as u16, 1).pop().unwrap();
+                pc += instruction.bytes.len() as u32;
+                instructions.push(instruction);
+            }
+            if pc == u32::from(address) {
+                let skip = instructions.len().saturating_sub(count);
+                return instructions.split_off(skip);
+            }
+        }
+        Vec::new()
+    }
+}
+
+pub fn breakpoint_label(entry: &BreakpointEntry, symbols: &Symbols) -> String {
+    let mut label = match &entry.trigger {
+        Trigger::PC(pc) => format!("PC {} {}", pc.0, pc.0.symbolic(symbols)),
+        Trigger::LY(_) => "LY".to_string(),
+        Trigger::NextFrame(_) => "frame".to_string(),
+        Trigger::Watch(_) => "watch".to_string(),
+        Trigger::Step => String::new(),
+    };
+    if let Some(condition) = &entry.condition {
+        label.push_str(&format!(" if {condition}"));
+    }
+    label.trim().to_string()
+}
@@ -0,0 +1,150 @@
+//! Terminal debugger.
+//!
+//! ```text
+//! tui <rom> [--sym <file>]
+//! ```
+//!
+//! Type `help` in the command line for the list of commands. F5, F10 and F11
+//! are shortcuts for `continue`, `next` and `step`.
+use command::Command;
+use debugger::Debugger;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use ui::Prompt;
+use utils::symbols::Symbols;
+
+mod command;
+mod debugger;
+mod ui;
+
+const USAGE: &str = "usage: tui <rom> [--sym <file>]";
+
+// time between updates while the emulation is running
+const FRAME: Duration = Duration::from_micros(16_742);
+
+fn main() {
+    let mut rom = None;
+    let mut sym = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sym" => sym = Some(args.next().expect(USAGE)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.expect(USAGE);
+    let file = std::fs::read(&rom).expect("Error reading ROM");
+
+    let mut debugger = Debugger::new(file.into_boxed_slice(), Symbols::new());
+    debugger.symbols = load_symbols(&mut debugger, &rom, sym.as_deref());
+    debugger.log("type \"help\" for the list of commands");
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut debugger);
+    ratatui::restore();
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(terminal: &mut ratatui::DefaultTerminal, debugger: &mut Debugger) -> std::io::Result<()> {
+    let mut prompt = Prompt::default();
+    let mut deadline = Instant::now();
+    while !debugger.quit {
+        terminal.draw(|frame| ui::draw(frame, debugger, &prompt))?;
+
+        // wait for input until the next frame is due (or indefinitely, if the
+        // emulation isn't running)
+        let timeout = match debugger.run {
+            Some(_) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::from_secs(1),
+        };
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    handle_key(key, debugger, &mut prompt);
+                }
+            }
+        }
+        if debugger.run.is_some() && Instant::now() >= deadline {
+            debugger.update();
+            deadline = Instant::now() + FRAME;
+        }
+    }
+    Ok(())
+}
+
+fn handle_key(key: KeyEvent, debugger: &mut Debugger, prompt: &mut Prompt) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Char('c') if ctrl => debugger.interrupt(),
+        KeyCode::Char('d') if ctrl => debugger.quit = true,
+        KeyCode::Esc => debugger.interrupt(),
+        KeyCode::F(5) => debugger.execute(Command::Continue),
+        KeyCode::F(10) => debugger.execute(Command::Next),
+        KeyCode::F(11) => debugger.execute(Command::Step(1)),
+        KeyCode::PageUp => debugger.memory = debugger.memory.wrapping_sub(0x100),
+        KeyCode::PageDown => debugger.memory = debugger.memory.wrapping_add(0x100),
+        KeyCode::Char(c) => prompt.input.push(c),
+        KeyCode::Backspace => {
+            prompt.input.pop();
+        }
+        KeyCode::Up | KeyCode::Down if !prompt.history.is_empty() => {
+            let len = prompt.history.len();
+            let browse = match (key.code, prompt.browse) {
+                (KeyCode::Up, None) => len - 1,
+                (KeyCode::Up, Some(i)) => i.saturating_sub(1),
+                (_, None) => return,
+                (_, Some(i)) => (i + 1).min(len - 1),
+            };
+            prompt.browse = Some(browse);
+            prompt.input = prompt.history[browse].clone();
+        }
+        KeyCode::Enter => {
+            let input = std::mem::take(&mut prompt.input);
+            prompt.browse = None;
+            // an empty line repeats the last command
+            let line = match input.trim() {
+                "" => match prompt.history.last() {
+                    Some(last) => last.clone(),
+                    None => return,
+                },
+                line => {
+                    prompt.history.push(line.to_string());
+                    line.to_string()
+                }
+            };
+            debugger.log(format!("> {line}"));
+            match line.parse() {
+                Ok(command) => debugger.execute(command),
+                Err(err) => debugger.log(format!("error: {err}")),
+            }
+        }
+        _ => {}
+    }
+}
+
+// symbol file given with --sym, or next to the ROM (<rom>.sym)
+fn load_symbols(debugger: &mut Debugger, rom: &str, sym: Option<&str>) -> Symbols {
+    let path = match sym {
+        Some(sym) => PathBuf::from(sym),
+        None => Path::new(rom).with_extension("sym"),
+    };
+    if sym.is_none() && !path.exists() {
+        return Symbols::new();
+    }
+    match Symbols::load(&path) {
+        Ok(symbols) => {
+            debugger.log(format!("{}: {} symbols", path.display(), symbols.len()));
+            symbols
+        }
+        Err(err) => {
+            debugger.log(format!("error: {}: {err}", path.display()));
+            Symbols::new()
+        }
+    }
+}
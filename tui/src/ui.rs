@@ -0,0 +1,324 @@
+use crate::debugger::{Debugger, Instruction};
+use core::{
+    debug::{Address, Trigger},
+    ppu::{LCD_HEIGHT, LCD_WIDTH},
+};
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph, Widget},
+    Frame,
+};
+
+const IO_REGISTERS: &[(&str, u16)] = &[
+    ("P1", 0xff00),
+    ("SB", 0xff01),
+    ("SC", 0xff02),
+    ("DIV", 0xff04),
+    ("TIMA", 0xff05),
+    ("TMA", 0xff06),
+    ("TAC", 0xff07),
+    ("IF", 0xff0f),
+    ("LCDC", 0xff40),
+    ("STAT", 0xff41),
+    ("SCY", 0xff42),
+    ("SCX", 0xff43),
+    ("LY", 0xff44),
+    ("LYC", 0xff45),
+    ("DMA", 0xff46),
+    ("BGP", 0xff47),
+    ("OBP0", 0xff48),
+    ("OBP1", 0xff49),
+    ("WY", 0xff4a),
+    ("WX", 0xff4b),
+    ("IE", 0xffff),
+];
+
+// disassembled instructions shown before the PC
+const DASM_BEFORE: usize = 4;
+
+/// Editable command line.
+#[derive(Debug, Default)]
+pub struct Prompt {
+    pub input: String,
+    pub history: Vec<String>,
+    /// Position in the history while browsing it.
+    pub browse: Option<usize>,
+}
+
+pub fn draw(frame: &mut Frame, debugger: &Debugger, prompt: &Prompt) {
+    let [main, console, input] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Min(0), Constraint::Length(30)]).areas(main);
+    let [registers, stack, io] = Layout::vertical([
+        Constraint::Length(9),
+        Constraint::Min(0),
+        Constraint::Length(13),
+    ])
+    .areas(right);
+    if debugger.lcd {
+        draw_lcd(frame, debugger, left);
+    } else {
+        let [dasm, memory] =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+        draw_disassembly(frame, debugger, dasm);
+        draw_memory(frame, debugger, memory);
+    }
+    draw_registers(frame, debugger, registers);
+    draw_stack(frame, debugger, stack);
+    draw_io(frame, debugger, io);
+    draw_console(frame, debugger, console);
+
+    let status = if debugger.run.is_some() {
+        "running "
+    } else {
+        "> "
+    };
+    let prompt = Line::from(vec![status.dark_gray(), Span::raw(&prompt.input)]);
+    let cursor = input.x + prompt.width() as u16;
+    frame.render_widget(prompt, input);
+    frame.set_cursor_position((cursor.min(input.right().saturating_sub(1)), input.y));
+}
+
+fn draw_disassembly(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let pc = debugger.gb.soc().cpu().registers().pc;
+    let rows = area.height.saturating_sub(2) as usize;
+    let mut instructions = debugger.disassemble_before(pc, DASM_BEFORE.min(rows / 2));
+    let current = instructions.len();
+    instructions.extend(debugger.disassemble(pc, rows.saturating_sub(current)));
+    let breakpoints: Vec<_> = debugger
+        .breakpoints
+        .iter()
+        .filter(|entry| entry.enabled)
+        .filter_map(|entry| match &entry.trigger {
+            Trigger::PC(pc) => Some(pc.0),
+            _ => None,
+        })
+        .collect();
+
+    let mut lines = Vec::with_capacity(rows);
+    for (
+        i,
+        Instruction {
+            address,
+            bytes,
+            text,
+        },
+    ) in instructions.iter().enumerate()
+    {
+        if lines.len() >= rows {
+            break;
+        }
+        if let Some(label) = address.label(&debugger.symbols) {
+            lines.push(Line::from(format!("{label}:").cyan()));
+        }
+        let breakpoint = breakpoints
+            .iter()
+            .any(|bp| bp.matches(debugger.gb.soc(), address.address));
+        let marker = match (i == current, breakpoint) {
+            (true, _) => ">".yellow(),
+            (false, true) => "*".red(),
+            (false, false) => " ".into(),
+        };
+        let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+        let style = if i == current {
+            Style::new().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::new()
+        };
+        lines.push(Line::from(vec![
+            marker,
+            Span::raw(format!(" {address:>7}  ")).dark_gray(),
+            Span::raw(format!("{:<9}", hex.join(" "))).dark_gray(),
+            Span::styled(text.clone(), style),
+        ]));
+    }
+    lines.truncate(rows);
+    let pc = debugger.pc();
+    let title = format!(" {} ", pc.symbolic(&debugger.symbols));
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_memory(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let rows = area.height.saturating_sub(2);
+    let lines: Vec<_> = (0..rows)
+        .map(|row| {
+            let address = debugger.memory.wrapping_add(row * 16);
+            let bytes: Vec<_> = (0..16)
+                .map(|i| debugger.read(address.wrapping_add(i)))
+                .collect();
+            let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            Line::from(vec![
+                Span::raw(format!("{address:04X}  ")).dark_gray(),
+                Span::raw(format!("{}  ", hex.join(" "))),
+                Span::raw(ascii).dark_gray(),
+            ])
+        })
+        .collect();
+    let title = " Memory (PgUp/PgDn) ";
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_registers(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let soc = debugger.gb.soc();
+    let registers = soc.cpu().registers();
+    let flag = |mask: u8, name: &'static str| {
+        if registers.f & mask != 0 {
+            Span::raw(name)
+        } else {
+            Span::raw("-").dark_gray()
+        }
+    };
+    let bank = |address| match soc.bank(address) {
+        Some(bank) => format!("{bank:02X}"),
+        None => "--".to_string(),
+    };
+    let lines = vec![
+        Line::from(vec![
+            Span::raw(format!("AF {:04X}  ", registers.af())),
+            flag(0x80, "Z"),
+            flag(0x40, "N"),
+            flag(0x20, "H"),
+            flag(0x10, "C"),
+        ]),
+        Line::from(format!(
+            "BC {:04X}  LY   {:02X}",
+            registers.bc(),
+            debugger.read(0xff44)
+        )),
+        Line::from(format!("DE {:04X}  ROM  {}", registers.de(), bank(0x4000))),
+        Line::from(format!(
+            "HL {:04X}  (HL) {:02X}",
+            registers.hl(),
+            debugger.read(registers.hl())
+        )),
+        Line::from(format!("SP {:04X}  WRAM {}", registers.sp, bank(0xd000))),
+        Line::from(format!("PC {:04X}  SRAM {}", registers.pc, bank(0xa000))),
+        Line::from(format!("frame {}", soc.frames())).dark_gray(),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Registers ")),
+        area,
+    );
+}
+
+// stack words from SP, with the return addresses of the call stack
+fn draw_stack(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let soc = debugger.gb.soc();
+    let sp = soc.cpu().registers().sp;
+    let frames = soc.cpu().call_stack().frames();
+    let lines: Vec<_> = (0..area.height.saturating_sub(2))
+        .map(|row| {
+            let address = sp.wrapping_add(row * 2);
+            let word = u16::from_le_bytes([
+                debugger.read(address),
+                debugger.read(address.wrapping_add(1)),
+            ]);
+            let mut line = Line::from(vec![
+                Span::raw(format!("{address:04X} ")).dark_gray(),
+                Span::raw(format!("{word:04X}")),
+            ]);
+            if let Some(frame) = frames.iter().rev().find(|f| f.sp == address) {
+                let ret = Address::mapped(soc, frame.ret);
+                line.push_span(format!(" ret {}", ret.symbolic(&debugger.symbols)).cyan());
+            }
+            line
+        })
+        .collect();
+    let title = format!(" Stack (depth {}) ", frames.len());
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_io(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let column = |(name, address): &(&str, u16)| {
+        vec![
+            Span::raw(format!("{name:<5}")).dark_gray(),
+            Span::raw(format!("{:02X}     ", debugger.read(*address))),
+        ]
+    };
+    let lines: Vec<_> = IO_REGISTERS
+        .chunks(2)
+        .map(|pair| Line::from(pair.iter().flat_map(column).collect::<Vec<_>>()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" IO ")),
+        area,
+    );
+}
+
+fn draw_console(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let skip = debugger.log.len().saturating_sub(rows);
+    let lines: Vec<_> = debugger.log[skip..]
+        .iter()
+        .map(|line| match line.starts_with("error") {
+            true => Line::from(line.as_str()).red(),
+            false => Line::from(line.as_str()),
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered()), area);
+}
+
+fn draw_lcd(frame: &mut Frame, debugger: &Debugger, area: Rect) {
+    let block = Block::bordered().title(" LCD ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    frame.render_widget(Screen(&debugger.frame()[..]), inner);
+}
+
+/// LCD rendered with half-block characters (two pixels per cell), scaled down
+/// to fit the area.
+struct Screen<'a>(&'a [core::ppu::Color]);
+
+impl Widget for Screen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        // pixels per cell (fixed point, 8 fractional bits)
+        let scale = (LCD_WIDTH << 8)
+            .div_ceil(area.width.max(1) as usize)
+            .max((LCD_HEIGHT << 8).div_ceil(2 * area.height.max(1) as usize))
+            .max(1 << 8);
+        let width = ((LCD_WIDTH << 8) / scale) as u16;
+        let height = ((LCD_HEIGHT << 8) / scale / 2) as u16;
+        let x0 = area.x + (area.width - width) / 2;
+        let pixel = |x: u16, y: usize| {
+            let x = (x as usize * scale) >> 8;
+            let y = ((y * scale) >> 8).min(LCD_HEIGHT - 1);
+            rgb(&self.0[LCD_WIDTH * y + x])
+        };
+        for row in 0..height {
+            for col in 0..width {
+                let top = pixel(col, 2 * row as usize);
+                let bottom = pixel(col, 2 * row as usize + 1);
+                buf[(x0 + col, area.y + row)]
+                    .set_char('▀')
+                    .set_fg(top)
+                    .set_bg(bottom);
+            }
+        }
+    }
+}
+
+// argb pixel format ([b, g, r, a] in memory)
+fn rgb(color: &core::ppu::Color) -> Color {
+    Color::Rgb(color[2], color[1], color[0])
+}
@@ -0,0 +1,848 @@
+//! LR35902 assembler.
+//!
+//! Parses the syntax of `dasm::Opcode`'s `Display` (`ld A,(FF40h)`,
+//! `cb bit 7,(HL)`, `rst 38H`), case-insensitive, plus:
+//!
+//! - Labels (`main:`), and local labels scoped to the previous label
+//!   (`.loop:`, referenced as `.loop` or `main.loop`).
+//! - Expressions with labels, numbers (`42`, `2Ah`, `$2a`, `0x2a`, `%101010`),
+//!   the address of the current instruction (`$` or `@`), parentheses, and the
+//!   `+ - * / % & | ^ << >> ~` operators.
+//! - The `org`, `db` (numbers and strings), `dw` and `ds count[,fill]`
+//!   directives.
+//! - Comments starting with `;`.
+//!
+//! Like the displayed opcodes, the numeric operand of `jr` is the (two's
+//! complement) offset. Operands with labels or `$` are targets, and are
+//! converted to offsets. Memory operands use the short encoding when they are
+//! spelled like the displayed opcodes (`ld A,(FF00+44h)`), or with the `ldh`
+//! mnemonic (`ldh A,(FF44h)`). Other addresses use the 16-bit encoding.
+use crate::dasm::{Disassembler, Opcode};
+use std::{collections::HashMap, sync::OnceLock};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("Line {line}: {kind}")]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ErrorKind {
+    #[error("Syntax error \"{0}\"")]
+    Syntax(String),
+    #[error("Unknown instruction \"{0}\"")]
+    Instruction(String),
+    #[error("Undefined label \"{0}\"")]
+    UndefinedLabel(String),
+    #[error("Duplicate label \"{0}\"")]
+    DuplicateLabel(String),
+    #[error("Value {0} out of range")]
+    Range(i64),
+}
+
+/// Assembled bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    origin: u16,
+    bytes: Vec<u8>,
+    labels: HashMap<String, u16>,
+}
+
+impl Program {
+    /// Returns the address of the first byte.
+    pub fn origin(&self) -> u16 {
+        self.origin
+    }
+
+    /// Returns the assembled bytes, from the origin. Gaps between `org`s are
+    /// filled with zeroes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the address of a label.
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).copied()
+    }
+
+    /// Copy the bytes to `memory`, at the origin.
+    pub fn write(&self, memory: &mut [u8]) {
+        let origin = self.origin as usize;
+        memory[origin..origin + self.bytes.len()].copy_from_slice(&self.bytes);
+    }
+}
+
+/// Assemble the source, starting at the given address.
+pub fn assemble(source: &str, origin: u16) -> Result<Program, Error> {
+    let mut assembler = Assembler {
+        pc: origin,
+        scope: String::new(),
+        labels: HashMap::new(),
+    };
+    // first pass: addresses of the labels and size of the statements
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let error = |kind| Error { line: i + 1, kind };
+        let pc = assembler.pc;
+        if let Some(statement) = assembler.statement(line).map_err(error)? {
+            assembler.pc = match statement {
+                Statement::Org(address) => address,
+                _ => assembler.pc.wrapping_add(statement.len()),
+            };
+            statements.push((i + 1, pc, statement));
+        }
+    }
+    // second pass: encode
+    let mut chunks: Vec<(u16, Vec<u8>)> = vec![(origin, Vec::new())];
+    for (line, pc, statement) in statements {
+        let error = |kind| Error { line, kind };
+        match statement {
+            Statement::Org(address) => chunks.push((address, Vec::new())),
+            statement => {
+                let bytes = &mut chunks.last_mut().unwrap().1;
+                statement
+                    .encode(pc, &assembler.labels, bytes)
+                    .map_err(error)?;
+            }
+        }
+    }
+    chunks.retain(|(_, bytes)| !bytes.is_empty());
+    let start = chunks.iter().map(|(a, _)| *a).min().unwrap_or(origin);
+    let end = chunks
+        .iter()
+        .map(|(a, b)| *a as usize + b.len())
+        .max()
+        .unwrap_or(origin as usize);
+    let mut bytes = vec![0; end - start as usize];
+    for (address, chunk) in chunks {
+        let offset = (address - start) as usize;
+        bytes[offset..offset + chunk.len()].copy_from_slice(&chunk);
+    }
+    Ok(Program {
+        origin: start,
+        bytes,
+        labels: assembler.labels,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    None,
+    /// 8-bit immediate.
+    N8,
+    /// 16-bit immediate (little-endian).
+    N16,
+    /// Address in 0xff00-0xffff (low byte).
+    A8,
+    /// `jr` offset.
+    Rel,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Encoding {
+    prefix: bool,
+    opcode: u8,
+    operand: Operand,
+}
+
+// Encodings keyed by the displayed opcode, with the immediate operand
+// replaced by "{n}" ("ld A,({n})").
+fn encodings() -> &'static HashMap<String, Vec<Encoding>> {
+    static ENCODINGS: OnceLock<HashMap<String, Vec<Encoding>>> = OnceLock::new();
+    ENCODINGS.get_or_init(|| {
+        let mut encodings: HashMap<String, Vec<Encoding>> = HashMap::new();
+        let opcodes = (0..=0xff)
+            .map(|op| (false, op))
+            .chain((0..=0xff).map(|op| (true, op)));
+        for (prefix, opcode) in opcodes {
+            let bytes = if prefix {
+                [0xcb, opcode, 0]
+            } else {
+                [opcode, 0x34, 0x12]
+            };
+            let (display, len) = match Disassembler::new(&bytes).next() {
+                Some(Ok((Opcode::Unknown, _))) | None | Some(Err(_)) => continue,
+                Some(Ok((Opcode::PrefixOpcode(_), _))) if !prefix => continue,
+                Some(Ok((op, len))) => (op.to_string(), len),
+            };
+            let display = display.strip_prefix("cb ").unwrap_or(&display);
+            let (key, operand) = match len {
+                3 => (display.replace("1234h", "{n}"), Operand::N16),
+                2 if !prefix && display.contains("FF00+34h") => {
+                    (display.replace("FF00+34h", "{n}"), Operand::A8)
+                }
+                2 if !prefix && display.starts_with("jr ") => {
+                    (display.replace("34h", "{n}"), Operand::Rel)
+                }
+                2 if !prefix => (display.replace("34h", "{n}"), Operand::N8),
+                _ => (display.to_string(), Operand::None),
+            };
+            let encoding = Encoding {
+                prefix,
+                opcode,
+                operand,
+            };
+            // jumps and calls are displayed as "jp (1234h)", also accept "jp 1234h"
+            if (key.starts_with("jp ") || key.starts_with("call ")) && key.contains("({n})") {
+                let key = key.replace("({n})", "{n}");
+                encodings.entry(key).or_default().push(encoding);
+            }
+            encodings.entry(key).or_default().push(encoding);
+        }
+        encodings
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Org(u16),
+    Instruction(Encoding, Option<Expr>),
+    Bytes(Vec<Data>),
+    Words(Vec<Expr>),
+    Space(u16, Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Data {
+    Expr(Expr),
+    String(Vec<u8>),
+}
+
+impl Statement {
+    fn len(&self) -> u16 {
+        match self {
+            Statement::Org(_) => 0,
+            Statement::Instruction(encoding, _) => {
+                let operand = match encoding.operand {
+                    Operand::None => 0,
+                    Operand::N8 | Operand::A8 | Operand::Rel => 1,
+                    Operand::N16 => 2,
+                };
+                1 + encoding.prefix as u16 + operand
+            }
+            Statement::Bytes(data) => data
+                .iter()
+                .map(|d| match d {
+                    Data::Expr(_) => 1,
+                    Data::String(s) => s.len() as u16,
+                })
+                .sum(),
+            Statement::Words(words) => 2 * words.len() as u16,
+            Statement::Space(count, _) => *count,
+        }
+    }
+
+    fn encode(
+        &self,
+        pc: u16,
+        labels: &HashMap<String, u16>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), ErrorKind> {
+        let byte = |expr: &Expr| {
+            let value = expr.eval(pc, labels)?;
+            match value {
+                -0x80..=0xff => Ok(value as u8),
+                _ => Err(ErrorKind::Range(value)),
+            }
+        };
+        let word = |expr: &Expr| {
+            let value = expr.eval(pc, labels)?;
+            match value {
+                -0x8000..=0xffff => Ok((value as u16).to_le_bytes()),
+                _ => Err(ErrorKind::Range(value)),
+            }
+        };
+        match self {
+            Statement::Org(_) => {}
+            Statement::Instruction(encoding, expr) => {
+                if encoding.prefix {
+                    out.push(0xcb);
+                }
+                out.push(encoding.opcode);
+                match (encoding.operand, expr) {
+                    (Operand::None, _) | (_, None) => {}
+                    (Operand::N8, Some(expr)) => out.push(byte(expr)?),
+                    (Operand::N16, Some(expr)) => out.extend_from_slice(&word(expr)?),
+                    (Operand::A8, Some(expr)) => match expr.eval(pc, labels)? {
+                        value @ 0xff00..=0xffff => out.push(value as u8),
+                        value => return Err(ErrorKind::Range(value)),
+                    },
+                    (Operand::Rel, Some(expr)) if expr.is_address() => {
+                        let offset = expr.eval(pc, labels)? - (pc as i64 + 2);
+                        match offset {
+                            -0x80..=0x7f => out.push(offset as u8),
+                            _ => return Err(ErrorKind::Range(offset)),
+                        }
+                    }
+                    (Operand::Rel, Some(expr)) => out.push(byte(expr)?),
+                }
+            }
+            Statement::Bytes(data) => {
+                for data in data {
+                    match data {
+                        Data::Expr(expr) => out.push(byte(expr)?),
+                        Data::String(s) => out.extend_from_slice(s),
+                    }
+                }
+            }
+            Statement::Words(words) => {
+                for expr in words {
+                    out.extend_from_slice(&word(expr)?);
+                }
+            }
+            Statement::Space(count, fill) => {
+                let fill = byte(fill)?;
+                out.resize(out.len() + *count as usize, fill);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Assembler {
+    pc: u16,
+    // last global label (scope of the local labels)
+    scope: String,
+    labels: HashMap<String, u16>,
+}
+
+impl Assembler {
+    // parses a line (during the first pass, so it also defines its label)
+    fn statement(&mut self, line: &str) -> Result<Option<Statement>, ErrorKind> {
+        let mut line = strip_comment(line).trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if is_identifier(label) {
+                let name = self.qualify(label);
+                if !label.starts_with('.') {
+                    self.scope = name.clone();
+                }
+                if self.labels.insert(name.clone(), self.pc).is_some() {
+                    return Err(ErrorKind::DuplicateLabel(name));
+                }
+                line = rest.trim();
+            }
+        }
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let (mnemonic, operands) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, split_operands(operands.trim())),
+            None => (line, Vec::new()),
+        };
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let statement = match mnemonic.as_str() {
+            "org" => match &operands[..] {
+                [address] => Statement::Org(self.constant(address)? as u16),
+                _ => return Err(ErrorKind::Syntax(line.to_string())),
+            },
+            "db" => Statement::Bytes(
+                operands
+                    .iter()
+                    .map(|operand| match string(operand) {
+                        Some(s) => Ok(Data::String(s)),
+                        None => self.expr(operand).map(Data::Expr),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            "dw" => Statement::Words(
+                operands
+                    .iter()
+                    .map(|operand| self.expr(operand))
+                    .collect::<Result<_, _>>()?,
+            ),
+            "ds" => match &operands[..] {
+                [count] => Statement::Space(self.constant(count)? as u16, Expr::Number(0)),
+                [count, fill] => Statement::Space(self.constant(count)? as u16, self.expr(fill)?),
+                _ => return Err(ErrorKind::Syntax(line.to_string())),
+            },
+            "cb" => return self.statement(&line[2..]),
+            _ => self.instruction(&mnemonic, &operands, line)?,
+        };
+        Ok(Some(statement))
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[&str],
+        line: &str,
+    ) -> Result<Statement, ErrorKind> {
+        let mut short = mnemonic == "ldh";
+        let mut key = if short { "ld" } else { mnemonic }.to_string();
+        let mut expr = None;
+        for (i, operand) in operands.iter().enumerate() {
+            key.push(if i == 0 { ' ' } else { ',' });
+            let upper = operand
+                .to_ascii_uppercase()
+                .replace('[', "(")
+                .replace(']', ")");
+            let unwrapped = unwrap_parens(operand);
+            if REGISTERS.contains(&upper.as_str()) {
+                key.push_str(&upper);
+            } else if i == 0 && matches!(mnemonic, "bit" | "res" | "set") {
+                key.push_str(&self.constant(operand)?.to_string());
+            } else if mnemonic == "rst" {
+                key.push_str(&format!("{:02X}H", self.constant(operand)?));
+            } else if mnemonic == "stop" {
+                key.push_str(operand);
+            } else if let Some(offset) = upper.strip_prefix("SP+") {
+                key.push_str("SP+{n}");
+                expr = Some(self.expr(&operand[operand.len() - offset.len()..])?);
+            } else if let Some(inner) = unwrapped {
+                key.push_str("({n})");
+                expr = Some(match inner.split_once('+') {
+                    // short encoding
+                    Some((high, low)) if high.trim().eq_ignore_ascii_case("FF00") => {
+                        short = true;
+                        let low = Box::new(self.expr(low)?);
+                        Expr::Binary(Op::Add, Box::new(Expr::Number(0xff00)), low)
+                    }
+                    _ => self.expr(inner)?,
+                });
+            } else {
+                key.push_str("{n}");
+                expr = Some(self.expr(operand)?);
+            }
+        }
+        if key == "stop" {
+            key.push_str(" 0");
+        }
+        let candidates = encodings()
+            .get(&key)
+            .ok_or_else(|| ErrorKind::Instruction(line.to_string()))?;
+        let encoding = candidates
+            .iter()
+            .find(|e| (e.operand == Operand::A8) == short)
+            // "ldh A,(C)"
+            .or_else(|| candidates.first().filter(|_| key.contains("(C)")))
+            .copied()
+            .ok_or_else(|| ErrorKind::Instruction(line.to_string()))?;
+        Ok(Statement::Instruction(encoding, expr))
+    }
+
+    fn expr(&self, source: &str) -> Result<Expr, ErrorKind> {
+        let tokens = tokenize(source, &self.scope)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            source,
+        };
+        let expr = parser.expr(0)?;
+        match parser.tokens {
+            [] => Ok(expr),
+            _ => Err(ErrorKind::Syntax(source.to_string())),
+        }
+    }
+
+    // expression that must be known during the first pass
+    fn constant(&self, source: &str) -> Result<i64, ErrorKind> {
+        self.expr(source)?.eval(self.pc, &self.labels)
+    }
+
+    fn qualify(&self, label: &str) -> String {
+        if label.starts_with('.') {
+            format!("{}{label}", self.scope)
+        } else {
+            label.to_string()
+        }
+    }
+}
+
+const REGISTERS: &[&str] = &[
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "NZ", "Z", "NC", "(C)",
+    "(BC)", "(DE)", "(HL)", "(HL+)", "(HL-)",
+];
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// splits at the commas outside of parentheses and strings
+fn split_operands(operands: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut depth, mut quoted, mut start) = (0, false, 0);
+    for (i, c) in operands.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                split.push(operands[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(operands[start..].trim());
+    split
+}
+
+// "(expr)" or "[expr]" -> "expr"
+fn unwrap_parens(operand: &str) -> Option<&str> {
+    let inner = operand
+        .strip_prefix('(')
+        .and_then(|o| o.strip_suffix(')'))
+        .or_else(|| operand.strip_prefix('[').and_then(|o| o.strip_suffix(']')))?;
+    // "(a)+(b)" isn't wrapped
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth == 0 => return None,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(inner)
+}
+
+fn string(operand: &str) -> Option<Vec<u8>> {
+    let s = operand.strip_prefix('"')?.strip_suffix('"')?;
+    Some(s.as_bytes().to_vec())
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Label(String),
+    /// Address of the current instruction.
+    Pc,
+    Unary(char, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 0,
+            Op::Xor => 1,
+            Op::And => 2,
+            Op::Shl | Op::Shr => 3,
+            Op::Add | Op::Sub => 4,
+            Op::Mul | Op::Div | Op::Rem => 5,
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, pc: u16, labels: &HashMap<String, u16>) -> Result<i64, ErrorKind> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Label(name) => *labels
+                .get(name)
+                .ok_or_else(|| ErrorKind::UndefinedLabel(name.clone()))?
+                as i64,
+            Expr::Pc => pc as i64,
+            Expr::Unary('-', e) => -e.eval(pc, labels)?,
+            Expr::Unary(_, e) => !e.eval(pc, labels)?,
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(pc, labels)?, r.eval(pc, labels)?);
+                match op {
+                    Op::Or => l | r,
+                    Op::Xor => l ^ r,
+                    Op::And => l & r,
+                    Op::Shl => l.wrapping_shl(r as u32),
+                    Op::Shr => l.wrapping_shr(r as u32),
+                    Op::Add => l.wrapping_add(r),
+                    Op::Sub => l.wrapping_sub(r),
+                    Op::Mul => l.wrapping_mul(r),
+                    Op::Div | Op::Rem if r == 0 => return Err(ErrorKind::Range(r)),
+                    Op::Div => l / r,
+                    Op::Rem => l % r,
+                }
+            }
+        })
+    }
+
+    // true if the expression refers to an address (labels or $)
+    fn is_address(&self) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Label(_) | Expr::Pc => true,
+            Expr::Unary(_, e) => e.is_address(),
+            Expr::Binary(_, l, r) => l.is_address() || r.is_address(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Label(String),
+    Pc,
+    Op(Op),
+    Neg,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str, scope: &str) -> Result<Vec<Token>, ErrorKind> {
+    let error = || ErrorKind::Syntax(source.to_string());
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let word_len = chars[i..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+            .count();
+        let word: String = chars[i..i + word_len].iter().collect();
+        let (token, len) = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '$' if chars.get(i + 1).is_some_and(|c| c.is_ascii_hexdigit()) => {
+                radix(&chars[i + 1..], 16).ok_or_else(error)?
+            }
+            '%' if matches!(chars.get(i + 1), Some('0' | '1')) => {
+                radix(&chars[i + 1..], 2).ok_or_else(error)?
+            }
+            '$' | '@' => (Token::Pc, 1),
+            '0'..='9' => (Token::Number(number(&word).ok_or_else(error)?), word_len),
+            _ if word_len > 0 => match number(&word) {
+                // "FF40h"
+                Some(n) if word.bytes().all(|b| !b.is_ascii_lowercase() || b == b'h') => {
+                    (Token::Number(n), word_len)
+                }
+                _ if word.starts_with('.') => (Token::Label(format!("{scope}{word}")), word_len),
+                _ => (Token::Label(word), word_len),
+            },
+            '(' | '[' => (Token::Open, 1),
+            ')' | ']' => (Token::Close, 1),
+            '<' | '>' if chars.get(i + 1) == Some(&c) => {
+                let op = if c == '<' { Op::Shl } else { Op::Shr };
+                (Token::Op(op), 2)
+            }
+            '-' if matches!(
+                tokens.last(),
+                None | Some(Token::Op(_) | Token::Open | Token::Neg | Token::Not)
+            ) =>
+            {
+                (Token::Neg, 1)
+            }
+            '~' => (Token::Not, 1),
+            '|' => (Token::Op(Op::Or), 1),
+            '^' => (Token::Op(Op::Xor), 1),
+            '&' => (Token::Op(Op::And), 1),
+            '+' => (Token::Op(Op::Add), 1),
+            '-' => (Token::Op(Op::Sub), 1),
+            '*' => (Token::Op(Op::Mul), 1),
+            '/' => (Token::Op(Op::Div), 1),
+            '%' => (Token::Op(Op::Rem), 1),
+            _ => return Err(error()),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+// digits after a "$" or "%" prefix
+fn radix(chars: &[char], radix: u32) -> Option<(Token, usize)> {
+    let len = chars
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .count();
+    let digits: String = chars[..len].iter().collect();
+    let n = i64::from_str_radix(&digits, radix).ok()?;
+    Some((Token::Number(n), 1 + len))
+}
+
+// "42", "0x2a", "2Ah"
+fn number(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = word.strip_suffix(['h', 'H']) {
+        i64::from_str_radix(hex, 16).ok()
+    } else {
+        word.parse().ok()
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    source: &'a str,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let (token, rest) = self.tokens.split_first()?;
+        self.tokens = rest;
+        Some(token)
+    }
+
+    // precedence climbing
+    fn expr(&mut self, precedence: u8) -> Result<Expr, ErrorKind> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.tokens.first() {
+            let op = *op;
+            if op.precedence() < precedence {
+                break;
+            }
+            self.next();
+            let right = self.expr(op.precedence() + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ErrorKind> {
+        let error = ErrorKind::Syntax(self.source.to_string());
+        match self.next().cloned() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Label(name)) => Ok(Expr::Label(name)),
+            Some(Token::Pc) => Ok(Expr::Pc),
+            Some(Token::Neg) => Ok(Expr::Unary('-', Box::new(self.unary()?))),
+            Some(Token::Not) => Ok(Expr::Unary('~', Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.expr(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(error),
+                }
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assemble, Error, ErrorKind};
+    use crate::dasm::{Disassembler, Opcode};
+
+    #[test]
+    fn round_trip() {
+        let opcodes = (0..=0xff)
+            .filter(|op| *op != 0xcb)
+            .map(|op| [op, 0x34, 0x12])
+            .chain((0..=0xff).map(|op| [0xcb, op, 0]))
+            // short & long encodings of addresses in 0xff00-0xffff
+            .chain([0xfa, 0xea, 0xe0, 0xf0].map(|op| [op, 0x44, 0xff]))
+            .chain([0xfa, 0xea].map(|op| [op, 0x00, 0xff]));
+        for bytes in opcodes {
+            let (opcode, len) = Disassembler::new(&bytes).next().unwrap().unwrap();
+            if opcode == Opcode::Unknown {
+                continue;
+            }
+            let source = opcode.to_string();
+            let program = assemble(&source, 0x100).unwrap();
+            assert_eq!(&bytes[..len], program.bytes(), "{source}");
+        }
+    }
+
+    #[test]
+    fn program() {
+        let source = r#"
+            org 150h
+        main:
+            ldh A,(FF44h)
+            cp 90h
+            jr NZ,main
+            ld HL,data
+        .loop:
+            ld A,(HL+)
+            and A
+            jr Z,.done
+            jr .loop
+        .done:
+            jp $
+            ld A,(data + 1)
+            ld (rLCDC),A
+        data:
+            db 1, $02, %11, "ok", -1
+            dw main, (data >> 8) + 1
+            ds 2, 0xff
+        rLCDC:
+        "#;
+        let program = assemble(source, 0).unwrap();
+        assert_eq!(0x150, program.origin());
+        assert_eq!(Some(0x150), program.label("main"));
+        #[rustfmt::skip]
+        assert_eq!(
+            &[
+                0xf0, 0x44,
+                0xfe, 0x90,
+                0x20, 0xfa,
+                0x21, 0x68, 0x01,
+                0x2a,
+                0xa7,
+                0x28, 0x02,
+                0x18, 0xfa,
+                0xc3, 0x5f, 0x01,
+                0xfa, 0x69, 0x01,
+                0xea, 0x74, 0x01,
+                0x01, 0x02, 0x03, b'o', b'k', 0xff,
+                0x50, 0x01, 0x02, 0x00,
+                0xff, 0xff,
+            ],
+            program.bytes()
+        );
+
+        let program = assemble("cb bit 7,(HL)\nrst 38h\nstop\nld HL,SP+FEh", 0).unwrap();
+        assert_eq!(&[0xcb, 0x7e, 0xff, 0x10, 0xf8, 0xfe], program.bytes());
+
+        let program = assemble("ld (FF00 + 40h),A\nld A,(FF40h)\nldh (C),A", 0).unwrap();
+        assert_eq!(&[0xe0, 0x40, 0xfa, 0x40, 0xff, 0xe2], program.bytes());
+
+        let mut rom = vec![0; 0x200];
+        assemble("org 100h\nnop\njp 150h", 0)
+            .unwrap()
+            .write(&mut rom);
+        assert_eq!(&[0x00, 0xc3, 0x50, 0x01], &rom[0x100..0x104]);
+
+        assert_eq!(
+            Err(Error {
+                line: 2,
+                kind: ErrorKind::UndefinedLabel("missing".to_string())
+            }),
+            assemble("nop\njp missing", 0)
+        );
+        assert_eq!(
+            ErrorKind::Instruction("ld (BC),B".to_string()),
+            assemble("ld (BC),B", 0).unwrap_err().kind
+        );
+        assert_eq!(
+            ErrorKind::Range(0x100),
+            assemble("ld A,100h", 0).unwrap_err().kind
+        );
+        assert_eq!(
+            ErrorKind::Range(0x44),
+            assemble("ldh A,(44h)", 0).unwrap_err().kind
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Disassemble a whole ROM into RGBDS source.
+//!
+//! ```text
+//! gbdasm <rom> [--sym <file>] [--cdl <file>] [-o <file>]
+//! ```
+//!
+//! A code/data log (`--cdl`) recorded while playing the game improves the
+//! split between code and data.
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+};
+use utils::{cdl::CodeDataLog, dasm::rom::Analysis, symbols::Symbols};
+
+const USAGE: &str = "usage: gbdasm <rom> [--sym <file>] [--cdl <file>] [-o <file>]";
+
+fn main() {
+    let mut rom = None;
+    let mut sym = None;
+    let mut cdl = None;
+    let mut output = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sym" => sym = Some(args.next().expect(USAGE)),
+            "--cdl" => cdl = Some(args.next().expect(USAGE)),
+            "-o" => output = Some(args.next().expect(USAGE)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = fs::read(rom.expect(USAGE)).expect("Error reading ROM");
+    let mut analysis = match cdl {
+        Some(cdl) => Analysis::with_log(
+            &rom,
+            &CodeDataLog::load(cdl).expect("Error reading code/data log"),
+        ),
+        None => Analysis::new(&rom),
+    };
+    if let Some(sym) = sym {
+        analysis = analysis.symbols(&Symbols::load(sym).expect("Error reading symbol file"));
+    }
+    let result = match output {
+        Some(path) => analysis.write(BufWriter::new(
+            File::create(path).expect("Error creating output file"),
+        )),
+        None => analysis.write(BufWriter::new(io::stdout().lock())),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
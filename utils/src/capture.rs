@@ -0,0 +1,348 @@
+//! Screenshots and video capture.
+//!
+//! Frames are RGB (3 bytes per pixel), optionally scaled up by an integer
+//! factor. Videos are written as:
+//!
+//! - Animated GIF (`.gif`). Repeated frames are merged into one, and frames
+//!   are kept at most at 50 fps (most viewers don't honor shorter delays).
+//! - YUV4MPEG2 (`.y4m`), 4:4:4, readable by `ffmpeg`, `x264`, ...
+//! - Raw RGB frames (`.raw` or `.rgb`):
+//!   `ffmpeg -f rawvideo -pix_fmt rgb24 -s 160x144 -r 4194304/70224 -i video.raw`
+//!
+//! The audio is written next to the video (`.wav`). Video frames are timed
+//! after the audio samples, so frames missed while the LCD is off are filled
+//! with the last one.
+use crate::wav::WavWriter;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, RgbaImage,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Frame rate (frames per second), as a fraction: CPU clock / clocks per frame.
+pub const FPS: (u64, u64) = (4_194_304, 70_224);
+
+// shortest GIF frame (1/100 s)
+const GIF_MIN_DELAY: u64 = 2;
+
+/// Capture formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Gif,
+    Y4m,
+    Raw,
+}
+
+impl Format {
+    /// Returns the format matching the extension of the path.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png),
+            "gif" => Some(Format::Gif),
+            "y4m" => Some(Format::Y4m),
+            "raw" | "rgb" => Some(Format::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Gif => "gif",
+            Format::Y4m => "y4m",
+            Format::Raw => "raw",
+        }
+    }
+}
+
+/// Scale an RGB frame up by an integer factor.
+pub fn scale(rgb: &[u8], width: usize, factor: usize) -> Vec<u8> {
+    if factor <= 1 {
+        return rgb.to_vec();
+    }
+    let mut scaled = Vec::with_capacity(rgb.len() * factor * factor);
+    for row in rgb.chunks(width * 3) {
+        let start = scaled.len();
+        for pixel in row.chunks(3) {
+            for _ in 0..factor {
+                scaled.extend_from_slice(pixel);
+            }
+        }
+        let line = scaled.len() - start;
+        for _ in 1..factor {
+            scaled.extend_from_within(start..start + line);
+        }
+    }
+    scaled
+}
+
+/// Save an RGB frame as PNG.
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    image::save_buffer(
+        path,
+        rgb,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgb8,
+    )
+    .map_err(io::Error::other)
+}
+
+enum Encoder {
+    Gif {
+        encoder: GifEncoder<BufWriter<File>>,
+        // frame waiting for its delay (until a different one comes), and the
+        // time it was shown at (1/100 s)
+        pending: Option<(Vec<u8>, u64)>,
+    },
+    Y4m(BufWriter<File>),
+    Raw(BufWriter<File>),
+}
+
+/// Video recording (and the audio along with it).
+pub struct Recorder {
+    encoder: Encoder,
+    width: usize,
+    height: usize,
+    scale: usize,
+    // frames written, and the last one (scaled)
+    frames: u64,
+    last: Vec<u8>,
+    audio: Option<WavWriter<BufWriter<File>>>,
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Start recording frames of the given size to the path (the format is
+    /// taken from the extension). The audio, if any, is written to the same
+    /// path with the `.wav` extension.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        scale: usize,
+        sample_rate: Option<u32>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let scale = scale.max(1);
+        let (w, h) = (width * scale, height * scale);
+        let file = || File::create(path).map(BufWriter::new);
+        let encoder = match Format::from_path(path) {
+            Some(Format::Gif) => {
+                let mut encoder = GifEncoder::new(file()?);
+                encoder
+                    .set_repeat(Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                Encoder::Gif {
+                    encoder,
+                    pending: None,
+                }
+            }
+            Some(Format::Y4m) => {
+                let mut out = file()?;
+                writeln!(out, "YUV4MPEG2 W{w} H{h} F{}:{} Ip A1:1 C444", FPS.0, FPS.1)?;
+                Encoder::Y4m(out)
+            }
+            Some(Format::Raw) => Encoder::Raw(file()?),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unsupported video format (gif, y4m or raw)",
+                ))
+            }
+        };
+        let audio = match sample_rate {
+            Some(rate) => Some(WavWriter::new(
+                BufWriter::new(File::create(path.with_extension("wav"))?),
+                2,
+                rate,
+            )?),
+            None => None,
+        };
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            scale,
+            frames: 0,
+            last: Vec::new(),
+            audio,
+            error: None,
+        })
+    }
+
+    /// Returns the number of frames written.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Add a frame. With audio, the frame is timed after the samples written
+    /// so far.
+    pub fn frame(&mut self, rgb: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let frame = scale(rgb, self.width, self.scale);
+        let due = match &self.audio {
+            Some(audio) => audio.samples() as u64 * FPS.0 / (audio.sample_rate() as u64 * FPS.1),
+            None => self.frames,
+        };
+        while self.frames < due && !self.last.is_empty() {
+            let last = std::mem::take(&mut self.last);
+            let result = self.write(&last);
+            self.last = last;
+            if let Err(err) = result {
+                self.error = Some(err);
+                return;
+            }
+        }
+        if let Err(err) = self.write(&frame) {
+            self.error = Some(err);
+        }
+        self.last = frame;
+    }
+
+    /// Add a stereo audio sample.
+    pub fn sample(&mut self, left: i16, right: i16) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(audio) = &mut self.audio {
+            if let Err(err) = audio.write(&[left, right]) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    /// Stop recording. Returns the first error of the recording, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let (width, height) = (self.width * self.scale, self.height * self.scale);
+        let end = centis(self.frames);
+        match self.encoder {
+            Encoder::Gif {
+                mut encoder,
+                pending,
+            } => {
+                if let Some((frame, start)) = pending {
+                    gif_frame(
+                        &mut encoder,
+                        width,
+                        height,
+                        frame,
+                        end.saturating_sub(start),
+                    )?;
+                }
+            }
+            Encoder::Y4m(mut out) | Encoder::Raw(mut out) => out.flush()?,
+        }
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        let (width, height) = (self.width * self.scale, self.height * self.scale);
+        let time = centis(self.frames);
+        self.frames += 1;
+        match &mut self.encoder {
+            Encoder::Gif { encoder, pending } => match pending {
+                Some((last, _)) if last == frame => Ok(()),
+                // too soon for a new GIF frame, so the pending one is dropped
+                Some((last, start)) if time - *start < GIF_MIN_DELAY => {
+                    *last = frame.to_vec();
+                    Ok(())
+                }
+                _ => {
+                    if let Some((last, start)) = pending.replace((frame.to_vec(), time)) {
+                        gif_frame(encoder, width, height, last, time - start)?;
+                    }
+                    Ok(())
+                }
+            },
+            Encoder::Y4m(out) => {
+                out.write_all(b"FRAME\n")?;
+                let (y, u, v) = yuv(frame);
+                out.write_all(&y)?;
+                out.write_all(&u)?;
+                out.write_all(&v)
+            }
+            Encoder::Raw(out) => out.write_all(frame),
+        }
+    }
+}
+
+// time of the given frame, in 1/100 s
+fn centis(frame: u64) -> u64 {
+    frame * 100 * FPS.1 / FPS.0
+}
+
+fn gif_frame(
+    encoder: &mut GifEncoder<BufWriter<File>>,
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+    delay: u64,
+) -> io::Result<()> {
+    let rgba = rgb
+        .chunks(3)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xff])
+        .collect();
+    let image = RgbaImage::from_raw(width as u32, height as u32, rgba).unwrap();
+    let delay = Delay::from_numer_denom_ms(delay as u32 * 10, 1);
+    encoder
+        .encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+        .map_err(io::Error::other)
+}
+
+// RGB to Y'CbCr (BT.601, limited range) planes
+fn yuv(rgb: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let len = rgb.len() / 3;
+    let (mut y, mut u, mut v) = (
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+    );
+    for pixel in rgb.chunks(3) {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        y.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+        u.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+        v.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+    }
+    (y, u, v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{centis, scale, yuv, Format};
+
+    #[test]
+    fn frames() {
+        assert_eq!(Some(Format::Y4m), Format::from_path("out/video.Y4M"));
+        assert_eq!(None, Format::from_path("video.mp4"));
+
+        let rgb = [1, 1, 1, 2, 2, 2];
+        assert_eq!(
+            vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2],
+            scale(&rgb, 2, 2)
+        );
+        assert_eq!((vec![16], vec![128], vec![128]), yuv(&[0, 0, 0]));
+        assert_eq!((vec![235], vec![128], vec![128]), yuv(&[255, 255, 255]));
+        // ~59.73 fps
+        assert_eq!(100, centis(60));
+    }
+}
@@ -0,0 +1,331 @@
+//! Code/Data Logs (`.cdl`).
+//!
+//! A CDL file has one byte per ROM byte, with flags telling how the byte was
+//! used while the game ran:
+//!
+//! - `CODE` (0x01) executed as the first byte of an instruction.
+//! - `OPERAND` (0x02) read as an operand of an instruction.
+//! - `DATA` (0x04) read by an instruction (`ld a, [hl]`, ...).
+//! - `DMA` (0x08) read by an OAM or VRAM DMA transfer.
+//!
+//! Logs of several sessions can be merged to measure the coverage of all of
+//! them.
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+pub const CODE: u8 = 0x01;
+pub const OPERAND: u8 = 0x02;
+pub const DATA: u8 = 0x04;
+pub const DMA: u8 = 0x08;
+
+pub const BANK_SIZE: usize = 0x4000;
+
+// banks per row of the coverage map (each bank is 128x128 pixels)
+const MAP_BANKS: usize = 8;
+const MAP_BANK_SIZE: usize = 128;
+
+/// How the bytes of a ROM were used.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+}
+
+impl std::fmt::Debug for CodeDataLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeDataLog")
+            .field("len", &self.flags.len())
+            .finish()
+    }
+}
+
+impl CodeDataLog {
+    /// Create an empty log for a ROM of the given size.
+    pub fn new(rom_len: usize) -> Self {
+        Self {
+            flags: vec![0; rom_len],
+        }
+    }
+
+    /// Create a log from the contents of a CDL file.
+    pub fn from_bytes(flags: Vec<u8>) -> Self {
+        Self { flags }
+    }
+
+    /// Read a CDL file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read(path).map(Self::from_bytes)
+    }
+
+    /// Write the log in the CDL format.
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(&self.flags)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.flags
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// Returns the flags of the byte at the ROM offset (0 if out of bounds).
+    pub fn get(&self, offset: usize) -> u8 {
+        self.flags.get(offset).copied().unwrap_or(0)
+    }
+
+    /// Add flags to the byte at the ROM offset. Offsets out of bounds are
+    /// ignored.
+    pub fn mark(&mut self, offset: usize, flags: u8) {
+        if let Some(byte) = self.flags.get_mut(offset) {
+            *byte |= flags;
+        }
+    }
+
+    /// Add the flags of another log (of the same ROM).
+    pub fn merge(&mut self, other: &CodeDataLog) {
+        for (byte, flags) in self.flags.iter_mut().zip(&other.flags) {
+            *byte |= flags;
+        }
+    }
+
+    /// Returns the coverage of each bank.
+    pub fn coverage(&self) -> Vec<Coverage> {
+        self.flags
+            .chunks(BANK_SIZE)
+            .enumerate()
+            .map(|(bank, flags)| Coverage::new(Some(bank), flags))
+            .collect()
+    }
+
+    /// Returns the coverage of the whole ROM.
+    pub fn total(&self) -> Coverage {
+        Coverage::new(None, &self.flags)
+    }
+
+    /// Write the coverage of every bank, and of the whole ROM, as text.
+    pub fn write_report<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "bank      code    data    used")?;
+        for coverage in self.coverage().iter().chain([&self.total()]) {
+            writeln!(out, "{coverage}")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the coverage map as an RGB image (one pixel per byte, banks of
+    /// 128x128 pixels side by side). Returns the width, height and pixels.
+    pub fn map(&self) -> (usize, usize, Vec<u8>) {
+        let banks = self.flags.len().div_ceil(BANK_SIZE);
+        let width = MAP_BANKS.min(banks.max(1)) * MAP_BANK_SIZE;
+        let height = banks.div_ceil(MAP_BANKS).max(1) * MAP_BANK_SIZE;
+        let mut pixels = vec![0; width * height * 3];
+        for (offset, flags) in self.flags.iter().enumerate() {
+            let bank = offset / BANK_SIZE;
+            let x = (bank % MAP_BANKS) * MAP_BANK_SIZE + offset % MAP_BANK_SIZE;
+            let y = (bank / MAP_BANKS) * MAP_BANK_SIZE + (offset % BANK_SIZE) / MAP_BANK_SIZE;
+            let i = (y * width + x) * 3;
+            pixels[i..i + 3].copy_from_slice(&color(*flags));
+        }
+        (width, height, pixels)
+    }
+
+    /// Write the coverage report and map as a standalone HTML page.
+    pub fn write_html<W: Write>(&self, mut out: W, title: &str) -> io::Result<()> {
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+        )?;
+        writeln!(out, "<title>{}</title>", escape(title))?;
+        writeln!(
+            out,
+            "<style>body{{font-family:monospace}} td{{padding:0 1em;text-align:right}} \
+             canvas{{image-rendering:pixelated;width:256px;margin:2px}}</style>"
+        )?;
+        writeln!(out, "</head>\n<body>\n<h1>{}</h1>", escape(title))?;
+        let legend = [
+            (CODE, "code"),
+            (OPERAND, "operand"),
+            (DATA, "data"),
+            (DMA, "DMA"),
+        ];
+        for (flags, name) in legend {
+            let [r, g, b] = color(flags);
+            writeln!(
+                out,
+                "<span style=\"background:rgb({r},{g},{b})\">&nbsp;&nbsp;</span> {name}"
+            )?;
+        }
+        writeln!(
+            out,
+            "<table>\n<tr><th>bank</th><th>code</th><th>data</th><th>used</th></tr>"
+        )?;
+        for coverage in self.coverage().iter().chain([&self.total()]) {
+            let bank = match coverage.bank {
+                Some(bank) => format!("{bank:03X}"),
+                None => "total".to_string(),
+            };
+            writeln!(
+                out,
+                "<tr><td>{bank}</td><td>{:.1}%</td><td>{:.1}%</td><td>{:.1}%</td></tr>",
+                coverage.percent(coverage.code),
+                coverage.percent(coverage.data),
+                coverage.percent(coverage.used()),
+            )?;
+        }
+        writeln!(out, "</table>\n<div id=\"map\"></div>\n<script>")?;
+        // the flags of each bank, as one hex digit per byte
+        writeln!(out, "const banks = [")?;
+        for flags in self.flags.chunks(BANK_SIZE) {
+            let digits: String = flags
+                .iter()
+                .map(|f| char::from_digit((*f & 0xf) as u32, 16).unwrap())
+                .collect();
+            writeln!(out, "\"{digits}\",")?;
+        }
+        writeln!(out, "];")?;
+        let colors: Vec<_> = (0..16u8)
+            .map(|flags| {
+                let [r, g, b] = color(flags);
+                format!("[{r},{g},{b}]")
+            })
+            .collect();
+        writeln!(out, "const colors = [{}];", colors.join(","))?;
+        writeln!(
+            out,
+            r#"banks.forEach((bank, i) => {{
+  const canvas = document.createElement("canvas");
+  canvas.width = {MAP_BANK_SIZE};
+  canvas.height = {MAP_BANK_SIZE};
+  canvas.title = "bank " + i.toString(16).padStart(3, "0");
+  const ctx = canvas.getContext("2d");
+  const image = ctx.createImageData({MAP_BANK_SIZE}, {MAP_BANK_SIZE});
+  for (let j = 0; j < bank.length; j++) {{
+    const [r, g, b] = colors[parseInt(bank[j], 16)];
+    image.data.set([r, g, b, 255], 4 * j);
+  }}
+  ctx.putImageData(image, 0, 0);
+  document.getElementById("map").appendChild(canvas);
+}});"#
+        )?;
+        writeln!(out, "</script>\n</body>\n</html>")
+    }
+}
+
+/// Coverage of a bank (or of the whole ROM).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage {
+    /// `None` for the whole ROM.
+    pub bank: Option<usize>,
+    pub size: usize,
+    /// Bytes executed (opcodes and operands).
+    pub code: usize,
+    /// Bytes read as data (or by DMA), but not executed.
+    pub data: usize,
+}
+
+impl Coverage {
+    fn new(bank: Option<usize>, flags: &[u8]) -> Self {
+        let code = flags.iter().filter(|f| *f & (CODE | OPERAND) != 0).count();
+        let used = flags.iter().filter(|f| **f != 0).count();
+        Self {
+            bank,
+            size: flags.len(),
+            code,
+            data: used - code,
+        }
+    }
+
+    /// Bytes used in any way.
+    pub fn used(&self) -> usize {
+        self.code + self.data
+    }
+
+    /// Returns the given number of bytes as a percentage of the size.
+    pub fn percent(&self, bytes: usize) -> f64 {
+        match self.size {
+            0 => 0.0,
+            size => 100.0 * bytes as f64 / size as f64,
+        }
+    }
+}
+
+impl std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bank = match self.bank {
+            Some(bank) => format!("{bank:03X}"),
+            None => "total".to_string(),
+        };
+        write!(
+            f,
+            "{bank:<5} {:>7.1}% {:>6.1}% {:>6.1}%",
+            self.percent(self.code),
+            self.percent(self.data),
+            self.percent(self.used())
+        )
+    }
+}
+
+/// Color of the bytes with the given flags in the coverage map.
+pub fn color(flags: u8) -> [u8; 3] {
+    if flags & CODE != 0 {
+        [0x40, 0xe0, 0x40]
+    } else if flags & OPERAND != 0 {
+        [0x20, 0x90, 0x20]
+    } else if flags & DMA != 0 {
+        [0xc0, 0x60, 0xe0]
+    } else if flags & DATA != 0 {
+        [0x40, 0x80, 0xf0]
+    } else {
+        [0x10, 0x10, 0x10]
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CodeDataLog, CODE, DATA, DMA, OPERAND};
+
+    #[test]
+    fn coverage() {
+        let mut cdl = CodeDataLog::new(0x8000);
+        cdl.mark(0x100, CODE);
+        cdl.mark(0x101, OPERAND);
+        cdl.mark(0x4000, DATA);
+        cdl.mark(0x4001, DATA | DMA);
+        cdl.mark(0x8000, CODE); // out of bounds
+
+        let coverage = cdl.coverage();
+        assert_eq!(2, coverage.len());
+        assert_eq!((2, 0), (coverage[0].code, coverage[0].data));
+        assert_eq!((0, 2), (coverage[1].code, coverage[1].data));
+        assert_eq!(4, cdl.total().used());
+
+        let mut other = CodeDataLog::new(0x8000);
+        other.mark(0x100, DATA);
+        cdl.merge(&other);
+        assert_eq!(CODE | DATA, cdl.get(0x100));
+        assert_eq!((2, 2), (cdl.total().code, cdl.total().data));
+
+        let mut report = Vec::new();
+        cdl.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(
+            Some("000       0.0%    0.0%    0.0%"),
+            report.lines().nth(1)
+        );
+        let (width, height, pixels) = cdl.map();
+        assert_eq!((256, 128, 256 * 128 * 3), (width, height, pixels.len()));
+    }
+}
@@ -2,6 +2,9 @@ use crate::dasm::PrefixRegister::HLPtr;
 use parse_display::Display;
 use std::io::{Bytes, Read};
 
+pub mod rgbds;
+pub mod rom;
+
 #[rustfmt::skip]
 const OPCODE_LEN: &[usize; 256] = &[
     1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
@@ -28,7 +31,7 @@ pub enum Address {
     #[display("({0:04X}h)")]
     U16(u16),
     /// 0xff00 + u8
-    #[display("(FF{0:02X}h)")]
+    #[display("(FF00+{0:02X}h)")]
     U8(u8),
     #[display("(HL)")]
     HLPtr,
@@ -302,6 +305,43 @@ pub enum Opcode {
     RST(RSTAddress),
 }
 
+impl Opcode {
+    /// Display the opcode with the target of jumps and calls, and 16-bit
+    /// immediate addresses, replaced by labels (when `label` returns one).
+    /// `pc` is the address of the opcode (to compute relative jumps).
+    pub fn display_with<'a, F>(&self, pc: u16, label: F) -> String
+    where
+        F: Fn(u16) -> Option<&'a str>,
+    {
+        use Opcode::*;
+        let jr = |offset: &u8| pc.wrapping_add(2).wrapping_add(*offset as i8 as u16);
+        let symbol = match self {
+            JR(offset) | JRFlags(_, offset) => Some(jr(offset)),
+            JP(Address::U16(address))
+            | JPFlags(_, Address::U16(address))
+            | CALL(Address::U16(address))
+            | CALLFlags(_, Address::U16(address))
+            | LD(_, LDSrc::Data(Data::U16(address)))
+            | LD(_, LDSrc::Address(Address::U16(address)))
+            | LD(LDDst::Address(Address::U16(address)), _) => Some(*address),
+            _ => None,
+        }
+        .and_then(|address| Some((address, label(address)?)));
+        match (self, symbol) {
+            (JR(_), Some((_, label))) => format!("jr {label}"),
+            (JRFlags(flags, _), Some((_, label))) => format!("jr {flags},{label}"),
+            (JP(_), Some((_, label))) => format!("jp {label}"),
+            (JPFlags(flags, _), Some((_, label))) => format!("jp {flags},{label}"),
+            (CALL(_), Some((_, label))) => format!("call {label}"),
+            (CALLFlags(flags, _), Some((_, label))) => format!("call {flags},{label}"),
+            (_, Some((address, label))) => {
+                self.to_string().replace(&format!("{address:04X}h"), label)
+            }
+            (_, None) => self.to_string(),
+        }
+    }
+}
+
 /// Registers used in 0xCB prefixed Opcodes.
 #[derive(Display, Debug, Eq, PartialEq, Copy, Clone)]
 pub enum PrefixRegister {
@@ -796,6 +836,26 @@ mod tests {
         assert_eq!(Some(Ok((Opcode::JP(Address::HLPtr), 1))), dis.next());
     }
 
+    #[test]
+    fn display_with() {
+        let label = |address| match address {
+            0x4a2f => Some("Player_Update"),
+            0xc010 => Some("wPlayerX"),
+            0x0150 => Some("Main"),
+            _ => None,
+        };
+        let display = |bytes: &[u8], pc| {
+            let (opcode, _) = Disassembler::new(bytes).next().unwrap().unwrap();
+            opcode.display_with(pc, label)
+        };
+        assert_eq!("call Player_Update", display(&[0xcd, 0x2f, 0x4a], 0x100));
+        assert_eq!("call NZ,Player_Update", display(&[0xc4, 0x2f, 0x4a], 0x100));
+        assert_eq!("ld A,(wPlayerX)", display(&[0xfa, 0x10, 0xc0], 0x100));
+        assert_eq!("ld (wPlayerX),A", display(&[0xea, 0x10, 0xc0], 0x100));
+        assert_eq!("jr Main", display(&[0x18, 0xfe], 0x150));
+        assert_eq!("jp (1234h)", display(&[0xc3, 0x34, 0x12], 0x100));
+    }
+
     #[test]
     fn rst() {
         let mut dis = Disassembler::new(&[0xc7, 0xd7, 0xe7, 0xf7, 0xcf, 0xdf, 0xef, 0xff]);
@@ -0,0 +1,124 @@
+//! RGBDS syntax for the disassembled opcodes.
+use crate::dasm::{ALUDst, ALUSrc, Address, Data, LDDst, LDSrc, Opcode, RSTAddress};
+
+/// Format the opcode at `pc` as RGBDS source. `label` replaces the target of
+/// jumps and calls, and the 16-bit address of loads.
+///
+/// Returns `None` for the unknown opcodes.
+pub fn format(opcode: &Opcode, pc: u16, label: Option<&str>) -> Option<String> {
+    use Opcode::*;
+    let address = |address: u16| match label {
+        Some(label) => label.to_string(),
+        None => format!("${address:04x}"),
+    };
+    let jr = |offset: &u8| address(pc.wrapping_add(2).wrapping_add(*offset as i8 as u16));
+    let text = match opcode {
+        Unknown => return None,
+        STOP => "stop".to_string(),
+        PrefixOpcode(opcode) => operand(opcode),
+        LD(LDDst::HL, LDSrc::SPOffset(offset)) => match *offset as i8 {
+            offset if offset < 0 => format!("ld hl, sp - {}", -(offset as i16)),
+            offset => format!("ld hl, sp + {offset}"),
+        },
+        LD(dst, LDSrc::Data(Data::U16(n))) => format!("ld {}, {}", operand(dst), address(*n)),
+        LD(LDDst::Address(Address::U16(n)), src) => {
+            format!("ld [{}], {}", address(*n), operand(src))
+        }
+        LD(dst, LDSrc::Address(Address::U16(n))) => {
+            format!("ld {}, [{}]", operand(dst), address(*n))
+        }
+        LD(LDDst::Address(Address::U8(n)), src) => format!("ldh [$ff{n:02x}], {}", operand(src)),
+        LD(dst, LDSrc::Address(Address::U8(n))) => format!("ldh {}, [$ff{n:02x}]", operand(dst)),
+        LD(dst @ (LDDst::Address(_) | LDDst::CPtr), src)
+        | LD(dst, src @ (LDSrc::Address(_) | LDSrc::CPtr)) => {
+            format!("ldh {}, {}", operand(dst), operand(src))
+        }
+        ADD(ALUDst::SP, ALUSrc::Data(Data::I8(offset))) => format!("add sp, {}", *offset as i8),
+        JR(offset) => format!("jr {}", jr(offset)),
+        JRFlags(flags, offset) => format!("jr {}, {}", operand(flags), jr(offset)),
+        JP(Address::HLPtr) => "jp hl".to_string(),
+        JP(Address::U16(n)) => format!("jp {}", address(*n)),
+        JPFlags(flags, Address::U16(n)) => format!("jp {}, {}", operand(flags), address(*n)),
+        CALL(Address::U16(n)) => format!("call {}", address(*n)),
+        CALLFlags(flags, Address::U16(n)) => {
+            format!("call {}, {}", operand(flags), address(*n))
+        }
+        RST(rst) => format!("rst ${:02x}", rst_address(rst)),
+        opcode => operand(opcode),
+    };
+    Some(text)
+}
+
+/// Returns the address called by the RST opcode.
+pub fn rst_address(rst: &RSTAddress) -> u16 {
+    match rst {
+        RSTAddress::H00 => 0x00,
+        RSTAddress::H08 => 0x08,
+        RSTAddress::H10 => 0x10,
+        RSTAddress::H18 => 0x18,
+        RSTAddress::H20 => 0x20,
+        RSTAddress::H28 => 0x28,
+        RSTAddress::H30 => 0x30,
+        RSTAddress::H38 => 0x38,
+    }
+}
+
+// Converts the default display of opcodes and operands to RGBDS syntax:
+// "ld A,(FF40h)" -> "ld a, [$ff40]"
+fn operand(display: &impl ToString) -> String {
+    let display = display.to_string();
+    let mut out = String::with_capacity(display.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        let digits = word.strip_suffix('h').unwrap_or_default();
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            out.push('$');
+            out.push_str(&digits.to_ascii_lowercase());
+        } else {
+            out.push_str(&word.to_ascii_lowercase());
+        }
+        word.clear();
+    };
+    for c in display.chars() {
+        if c.is_ascii_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut out);
+        match c {
+            '(' => out.push('['),
+            ')' => out.push(']'),
+            ',' => out.push_str(", "),
+            c => out.push(c),
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::format;
+    use crate::dasm::Disassembler;
+
+    #[test]
+    fn rgbds() {
+        let rgbds = |bytes: &[u8], label| {
+            let (opcode, _) = Disassembler::new(bytes).next().unwrap().unwrap();
+            format(&opcode, 0x150, label).unwrap()
+        };
+        assert_eq!("ld a, [$ff40]", rgbds(&[0xfa, 0x40, 0xff], None));
+        assert_eq!("ldh [$ff40], a", rgbds(&[0xe0, 0x40], None));
+        assert_eq!("ldh a, [c]", rgbds(&[0xf2], None));
+        assert_eq!("ld [hl+], a", rgbds(&[0x22], None));
+        assert_eq!("ld hl, sp - 2", rgbds(&[0xf8, 0xfe], None));
+        assert_eq!("add sp, 16", rgbds(&[0xe8, 0x10], None));
+        assert_eq!("add a, $05", rgbds(&[0xc6, 0x05], None));
+        assert_eq!("bit 7, [hl]", rgbds(&[0xcb, 0x7e], None));
+        assert_eq!("jr nz, $0150", rgbds(&[0x20, 0xfe], None));
+        assert_eq!("call Main", rgbds(&[0xcd, 0x00, 0x02], Some("Main")));
+        assert_eq!("rst $38", rgbds(&[0xff], None));
+        assert_eq!("jp hl", rgbds(&[0xe9], None));
+        assert_eq!(None, format(&crate::dasm::Opcode::Unknown, 0, None));
+    }
+}
@@ -0,0 +1,439 @@
+//! Whole-ROM disassembly.
+//!
+//! Code is told apart from data by following the control flow from the entry
+//! points (0x100, the RST and the interrupt vectors): jumps, calls and RSTs
+//! are followed, and returns and indirect jumps (`jp hl`) end the flow.
+//! Everything that isn't reached is data.
+//!
+//! Jumps and calls into 0x4000-0x7fff go to the bank of the calling code. From
+//! bank 0, the bank is the last one selected by a `ld a, n` followed by a write
+//! of A to the MBC bank register (0x2000-0x3fff). Targets that can't be
+//! resolved aren't followed.
+//!
+//! A code/data log recorded while running the game (see `cdl`) makes the
+//! split more accurate: the logged instructions are followed too (such as the
+//! targets of `jp hl`), and the bytes only read as data are never
+//! disassembled.
+use crate::{
+    cdl::{self, CodeDataLog},
+    dasm::{rgbds, Address, Data, Disassembler, LDDst, LDSrc, Opcode},
+    symbols::Symbols,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
+pub const BANK_SIZE: usize = 0x4000;
+
+// runs of repeated data bytes at least this long are written with `ds`
+const FILL_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Data,
+    /// Byte read as data (according to the code/data log).
+    Logged,
+    /// First byte of an instruction (and its length).
+    Code(usize),
+    /// Operand byte of an instruction.
+    Operand,
+}
+
+// what is known about the registers while following the control flow
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    a: Option<u8>,
+    bank: Option<usize>,
+}
+
+impl State {
+    fn update(self, opcode: &Opcode) -> Self {
+        use Opcode::*;
+        match opcode {
+            LD(LDDst::A, LDSrc::Data(Data::U8(n))) => Self {
+                a: Some(*n),
+                ..self
+            },
+            XOR(crate::dasm::ALUSrc::A) => Self { a: Some(0), ..self },
+            LD(LDDst::Address(Address::U16(0x2000..=0x3fff)), LDSrc::A) => Self {
+                bank: self.a.map(|a| (a as usize).max(1)),
+                ..self
+            },
+            opcode if writes_a(opcode) => Self { a: None, ..self },
+            _ => self,
+        }
+    }
+}
+
+fn writes_a(opcode: &Opcode) -> bool {
+    use crate::dasm::{ALUDst, PrefixOpcode as Prefix, PrefixRegister, StackRegister};
+    use Opcode::*;
+    match opcode {
+        LD(LDDst::A, _)
+        | ADD(ALUDst::A, _)
+        | ADC(_)
+        | SUB(_)
+        | SBC(_)
+        | AND(_)
+        | XOR(_)
+        | OR(_)
+        | INC(ALUDst::A)
+        | DEC(ALUDst::A)
+        | RLCA
+        | RRCA
+        | RLA
+        | RRA
+        | DAA
+        | CPL
+        | POP(StackRegister::AF)
+        | CALL(_)
+        | CALLFlags(..)
+        | RST(_) => true,
+        PrefixOpcode(opcode) => match opcode {
+            Prefix::BIT(..) => false,
+            Prefix::RLC(r)
+            | Prefix::RRC(r)
+            | Prefix::RL(r)
+            | Prefix::RR(r)
+            | Prefix::SLA(r)
+            | Prefix::SRA(r)
+            | Prefix::SRL(r)
+            | Prefix::SWAP(r)
+            | Prefix::RES(_, r)
+            | Prefix::SET(_, r) => *r == PrefixRegister::A,
+        },
+        _ => false,
+    }
+}
+
+// jump or call target, and whether the flow continues with the next
+// instruction
+fn flow(opcode: &Opcode, pc: u16) -> (Option<u16>, bool) {
+    use Opcode::*;
+    let jr = |offset: &u8| pc.wrapping_add(2).wrapping_add(*offset as i8 as u16);
+    match opcode {
+        JP(Address::U16(n)) => (Some(*n), false),
+        JP(_) | RET | RETI => (None, false),
+        JR(offset) => (Some(jr(offset)), false),
+        JRFlags(_, offset) => (Some(jr(offset)), true),
+        JPFlags(_, Address::U16(n)) | CALL(Address::U16(n)) | CALLFlags(_, Address::U16(n)) => {
+            (Some(*n), true)
+        }
+        RST(rst) => (Some(rgbds::rst_address(rst)), true),
+        _ => (None, true),
+    }
+}
+
+// decodes the instruction at the start of `bytes`
+fn decode(bytes: &[u8]) -> Option<(Opcode, usize)> {
+    match Disassembler::new(bytes).next()? {
+        Ok((Opcode::Unknown, _)) | Err(_) => None,
+        // STOP is followed by a padding byte
+        Ok((Opcode::STOP, _)) => (bytes.get(1) == Some(&0)).then_some((Opcode::STOP, 2)),
+        Ok(opcode) => Some(opcode),
+    }
+}
+
+/// Code and data of a ROM, with labels.
+#[derive(Debug)]
+pub struct Analysis<'a> {
+    rom: &'a [u8],
+    kinds: Vec<Kind>,
+    // instruction -> ROM offset of its jump or call target
+    targets: HashMap<usize, usize>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl<'a> Analysis<'a> {
+    /// Follow the control flow of the ROM from the entry points.
+    pub fn new(rom: &'a [u8]) -> Self {
+        Self::analyse(rom, None)
+    }
+
+    /// Follow the control flow of the ROM from the entry points and from the
+    /// instructions of the code/data log.
+    pub fn with_log(rom: &'a [u8], log: &CodeDataLog) -> Self {
+        Self::analyse(rom, Some(log))
+    }
+
+    fn analyse(rom: &'a [u8], log: Option<&CodeDataLog>) -> Self {
+        let mut analysis = Self {
+            rom,
+            kinds: vec![Kind::Data; rom.len()],
+            targets: HashMap::new(),
+            labels: BTreeMap::new(),
+        };
+        if let Some(log) = log {
+            for (offset, kind) in analysis.kinds.iter_mut().enumerate() {
+                let flags = log.get(offset);
+                if flags & (cdl::CODE | cdl::OPERAND) == 0 && flags & (cdl::DATA | cdl::DMA) != 0 {
+                    *kind = Kind::Logged;
+                }
+            }
+        }
+        let vectors = [
+            (0x100, "Entry"),
+            (0x40, "VBlankInterrupt"),
+            (0x48, "LCDStatInterrupt"),
+            (0x50, "TimerInterrupt"),
+            (0x58, "SerialInterrupt"),
+            (0x60, "JoypadInterrupt"),
+        ];
+        let rst = (0..8).map(|i| (i * 8, format!("Rst_{:02X}", i * 8)));
+        let entries = vectors
+            .into_iter()
+            .map(|(address, name)| (address, name.to_string()))
+            .chain(rst);
+        for (offset, name) in entries.filter(|(offset, _)| *offset < rom.len()) {
+            analysis.labels.insert(offset, name);
+            analysis.trace(offset);
+        }
+        if let Some(log) = log {
+            for offset in 0..rom.len() {
+                if log.get(offset) & cdl::CODE != 0 {
+                    analysis.trace(offset);
+                }
+            }
+        }
+        analysis
+    }
+
+    /// Name the labels after the symbols (see `symbols::Symbols`), and add
+    /// the symbols that aren't labels yet.
+    pub fn symbols(mut self, symbols: &Symbols) -> Self {
+        for (bank, address, name) in symbols.iter() {
+            let offset = match address {
+                0x0000..=0x3fff => address as usize,
+                0x4000..=0x7fff => bank.max(1) * BANK_SIZE + (address as usize - 0x4000),
+                _ => continue,
+            };
+            if offset < self.rom.len() {
+                self.labels.insert(offset, name.to_string());
+            }
+        }
+        self
+    }
+
+    /// Returns the number of banks of the ROM.
+    pub fn banks(&self) -> usize {
+        self.rom.len().div_ceil(BANK_SIZE)
+    }
+
+    /// Returns true if the byte at the ROM offset belongs to an instruction.
+    pub fn is_code(&self, offset: usize) -> bool {
+        self.kinds
+            .get(offset)
+            .is_some_and(|k| matches!(k, Kind::Code(_) | Kind::Operand))
+    }
+
+    /// Returns the label at the ROM offset.
+    pub fn label(&self, offset: usize) -> Option<&str> {
+        self.labels.get(&offset).map(String::as_str)
+    }
+
+    // bank and CPU address of a ROM offset
+    fn address(offset: usize) -> (usize, u16) {
+        let bank = offset / BANK_SIZE;
+        let address = offset % BANK_SIZE + if bank == 0 { 0 } else { 0x4000 };
+        (bank, address as u16)
+    }
+
+    // ROM offset of the target of a jump or call from the given bank
+    fn resolve(&self, bank: usize, address: u16, state: &State) -> Option<usize> {
+        let offset = match address {
+            0x0000..=0x3fff => address as usize,
+            0x4000..=0x7fff => {
+                let bank = match (state.bank, bank) {
+                    (Some(bank), _) => bank,
+                    (None, 0) if self.rom.len() <= 2 * BANK_SIZE => 1,
+                    (None, 0) => return None,
+                    (None, bank) => bank,
+                };
+                bank * BANK_SIZE + (address as usize - 0x4000)
+            }
+            _ => return None,
+        };
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    fn trace(&mut self, entry: usize) {
+        let mut queue = vec![(entry, State::default())];
+        while let Some((mut offset, mut state)) = queue.pop() {
+            // already visited, or in the middle of an instruction
+            while self.kinds[offset] == Kind::Data {
+                let (bank, pc) = Self::address(offset);
+                let end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+                let bytes = &self.rom[offset..end.min(offset + 3)];
+                let (opcode, len) = match decode(bytes) {
+                    Some(decoded) => decoded,
+                    None => break,
+                };
+                if offset + len > end
+                    || self.kinds[offset..offset + len]
+                        .iter()
+                        .any(|k| *k != Kind::Data)
+                {
+                    break;
+                }
+                self.kinds[offset] = Kind::Code(len);
+                self.kinds[offset + 1..offset + len].fill(Kind::Operand);
+
+                let (target, next) = flow(&opcode, pc);
+                if let Some(target) = target.and_then(|t| self.resolve(bank, t, &state)) {
+                    let (target_bank, target_pc) = Self::address(target);
+                    let call = matches!(opcode, Opcode::CALL(_) | Opcode::CALLFlags(..));
+                    let label = self.labels.entry(target).or_default();
+                    if label.is_empty() || (call && label.starts_with("Jump_")) {
+                        let kind = if call { "Call" } else { "Jump" };
+                        *label = format!("{kind}_{target_bank:03X}_{target_pc:04X}");
+                    }
+                    self.targets.insert(offset, target);
+                    queue.push((target, state));
+                }
+                if !next {
+                    break;
+                }
+                state = state.update(&opcode);
+                offset += len;
+                if offset >= end {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Write the disassembly as RGBDS source (one section per bank).
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for bank in 0..self.banks() {
+            if bank == 0 {
+                writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+            } else {
+                writeln!(
+                    out,
+                    "\nSECTION \"ROM Bank ${bank:03X}\", ROMX[$4000], BANK[${bank:X}]"
+                )?;
+            }
+            let end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+            let mut offset = bank * BANK_SIZE;
+            while offset < end {
+                if let Some(label) = self.labels.get(&offset) {
+                    writeln!(out, "\n{label}:")?;
+                }
+                offset += self.write_line(&mut out, offset, end)?;
+            }
+        }
+        Ok(())
+    }
+
+    // writes an instruction, or a line of data, and returns its length
+    fn write_line<W: Write>(&self, out: &mut W, offset: usize, end: usize) -> io::Result<usize> {
+        let (_, pc) = Self::address(offset);
+        if let Kind::Code(len) = self.kinds[offset] {
+            // instructions with labels in the middle are written as data
+            if self.labels.range(offset + 1..offset + len).next().is_none() {
+                let (opcode, _) = decode(&self.rom[offset..offset + len]).unwrap();
+                let label = self.targets.get(&offset).and_then(|t| self.label(*t));
+                let text = rgbds::format(&opcode, pc, label).unwrap();
+                writeln!(out, "    {text:<32} ; ${pc:04x}")?;
+                return Ok(len);
+            }
+        }
+        // data up to the next label or instruction
+        let len = (offset + 1..end)
+            .find(|o| matches!(self.kinds[*o], Kind::Code(_)) || self.labels.contains_key(o))
+            .unwrap_or(end)
+            - offset;
+        let byte = self.rom[offset];
+        let fill = self.rom[offset..offset + len]
+            .iter()
+            .take_while(|b| **b == byte)
+            .count();
+        if fill >= FILL_LEN {
+            let text = format!("ds {fill}, ${byte:02x}");
+            writeln!(out, "    {text:<32} ; ${pc:04x}")?;
+            return Ok(fill);
+        }
+        // stop before the next fill, so it can be written with `ds`
+        let mut len = len.min(8);
+        if let Some(fill) = (1..len).find(|i| {
+            let rest = &self.rom[offset + i..offset + i + FILL_LEN.min(end - offset - i)];
+            rest.len() == FILL_LEN && rest.iter().all(|b| *b == rest[0])
+        }) {
+            len = fill;
+        }
+        let bytes: Vec<_> = self.rom[offset..offset + len]
+            .iter()
+            .map(|b| format!("${b:02x}"))
+            .collect();
+        let text = format!("db {}", bytes.join(", "));
+        writeln!(out, "    {text:<32} ; ${pc:04x}")?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Analysis;
+    use crate::cdl::{self, CodeDataLog};
+
+    #[test]
+    fn analysis() {
+        let mut rom = vec![0; 0xc000];
+        // 0x100: nop; jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        // 0x150: call $0158; ld a,$02; ld ($2000),a; call $4000; jr $0150
+        #[rustfmt::skip]
+        let code = [
+            0xcd, 0x58, 0x01,
+            0x3e, 0x02,
+            0xea, 0x00, 0x20,
+            0xcd, 0x00, 0x40,
+            0x18, 0xf3,
+        ];
+        rom[0x150..0x15d].copy_from_slice(&code);
+        // 0x8000 (bank 2): ret
+        rom[0x8000] = 0xc9;
+        rom[0x8001..0x8004].copy_from_slice(&[0x11, 0x22, 0x33]);
+
+        let analysis = Analysis::new(&rom);
+        assert_eq!(3, analysis.banks());
+        assert!(analysis.is_code(0x150));
+        assert!(!analysis.is_code(0x104));
+        assert!(analysis.is_code(0x8000));
+        assert!(!analysis.is_code(0x8001));
+        assert!(!analysis.is_code(0x4000));
+        assert_eq!(Some("Jump_000_0150"), analysis.label(0x150));
+        assert_eq!(Some("Call_002_4000"), analysis.label(0x8000));
+
+        let mut out = Vec::new();
+        analysis.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\nEntry:\n    nop"));
+        assert!(out.contains("    jp Jump_000_0150 "));
+        assert!(out.contains("    call Call_002_4000 "));
+        assert!(out.contains("    jr Jump_000_0150 "));
+        assert!(out.contains("SECTION \"ROM Bank $002\", ROMX[$4000], BANK[$2]"));
+        assert!(out.contains("\nCall_002_4000:\n    ret"));
+        assert!(out.contains("    db $11, $22, $33"));
+        assert!(out.contains("    ds 16"));
+    }
+
+    #[test]
+    fn log() {
+        let mut rom = vec![0; 0x8000];
+        // 0x100: ld hl,$0200; jp hl
+        rom[0x100..0x104].copy_from_slice(&[0x21, 0x00, 0x02, 0xe9]);
+        // 0x200: ld a,$01; jr -2 (only reached through `jp hl`)
+        rom[0x200..0x204].copy_from_slice(&[0x3e, 0x01, 0x18, 0xfe]);
+
+        let mut log = CodeDataLog::new(rom.len());
+        log.mark(0x200, cdl::CODE);
+        log.mark(0x40, cdl::DATA);
+        let analysis = Analysis::with_log(&rom, &log);
+        assert!(analysis.is_code(0x200));
+        assert!(analysis.is_code(0x203));
+        assert!(!analysis.is_code(0x40));
+        assert!(!Analysis::new(&rom).is_code(0x200));
+    }
+}
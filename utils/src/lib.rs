@@ -1 +1,9 @@
+pub mod asm;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod cdl;
 pub mod dasm;
+pub mod search;
+pub mod symbols;
+pub mod vgm;
+pub mod wav;
@@ -0,0 +1,315 @@
+//! Memory search (cheat finder).
+//!
+//! A search starts from a snapshot of the cartridge RAM, work RAM and high RAM
+//! (every bank of them). Each filter compares the current value of the
+//! candidates with the value they had at the previous step, and only keeps the
+//! ones that pass:
+//!
+//! ```text
+//! new search
+//! value 3             the game starts with 3 lives
+//! decreased by 1      after losing a life
+//! unchanged           after playing for a while without dying
+//! ```
+//!
+//! The remaining candidates can be frozen with GameShark codes (see
+//! `Candidate::gameshark`).
+use std::{fmt, ops::RangeInclusive};
+
+/// Regions covered by a search.
+pub const REGIONS: [RangeInclusive<u16>; 4] = [
+    0xa000..=0xbfff, // cartridge RAM
+    0xc000..=0xcfff, // WRAM bank 0
+    0xd000..=0xdfff, // WRAM bank 1-7
+    0xff80..=0xfffe, // HRAM
+];
+
+/// Memory being searched.
+pub trait Memory {
+    /// Returns the banks of the region at the given address.
+    fn banks(&self, address: u16) -> RangeInclusive<usize>;
+
+    /// Read a byte from the given bank. Returns `None` if the memory doesn't
+    /// exist (such as the RAM of a cartridge without it).
+    fn read(&self, bank: usize, address: u16) -> Option<u8>;
+}
+
+/// Size of the searched values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    /// 16-bit, little endian.
+    Word,
+}
+
+impl Width {
+    /// Size of the value in bytes.
+    pub fn bytes(self) -> u16 {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+        }
+    }
+
+    fn wrap(self, value: u16) -> u16 {
+        match self {
+            Width::Byte => value & 0xff,
+            Width::Word => value,
+        }
+    }
+}
+
+/// Search filter.
+///
+/// Comparisons are made against the value of the previous step (or the
+/// snapshot, for the first filter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    /// The value increased by exactly N (wrapping).
+    IncreasedBy(u16),
+    /// The value decreased by exactly N (wrapping).
+    DecreasedBy(u16),
+    /// The current value is N.
+    Value(u16),
+}
+
+impl Filter {
+    fn matches(self, width: Width, previous: u16, value: u16) -> bool {
+        match self {
+            Filter::Unchanged => value == previous,
+            Filter::Changed => value != previous,
+            Filter::Increased => value > previous,
+            Filter::Decreased => value < previous,
+            Filter::IncreasedBy(n) => value == width.wrap(previous.wrapping_add(n)),
+            Filter::DecreasedBy(n) => value == width.wrap(previous.wrapping_sub(n)),
+            Filter::Value(n) => value == width.wrap(n),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Unchanged => write!(f, "unchanged"),
+            Filter::Changed => write!(f, "changed"),
+            Filter::Increased => write!(f, "increased"),
+            Filter::Decreased => write!(f, "decreased"),
+            Filter::IncreasedBy(n) => write!(f, "increased by {n}"),
+            Filter::DecreasedBy(n) => write!(f, "decreased by {n}"),
+            Filter::Value(n) => write!(f, "value {n}"),
+        }
+    }
+}
+
+/// Address that passed every filter so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub bank: usize,
+    pub address: u16,
+    pub width: Width,
+    /// Value at the previous step.
+    pub previous: u16,
+    /// Value at the last step.
+    pub value: u16,
+}
+
+impl Candidate {
+    /// Read the current value of the candidate.
+    pub fn read<M: Memory>(&self, memory: &M) -> Option<u16> {
+        read(memory, self.width, self.bank, self.address)
+    }
+
+    /// Returns the GameShark codes (separated by spaces) that freeze the
+    /// candidate to the given value, or `None` if there are no such codes.
+    ///
+    /// Switchable WRAM is written to the candidate bank (`8X`), and cartridge
+    /// RAM to the bank in the first byte of the code. A first byte of `01`
+    /// writes to whatever bank is mapped, so bank 1 of the cartridge RAM
+    /// can't be frozen.
+    pub fn gameshark(&self, value: u16) -> Option<String> {
+        let bank = match self.address {
+            0xd000..=0xdfff => 0x80 | (self.bank as u8 & 0x7),
+            0xa000..=0xbfff if self.bank == 0x01 => return None,
+            0xa000..=0xbfff => self.bank as u8,
+            _ => 0x01,
+        };
+        let codes = (0..self.width.bytes())
+            .map(|i| {
+                let [lo, hi] = self.address.wrapping_add(i).to_le_bytes();
+                let data = value.to_le_bytes()[i as usize];
+                format!("{bank:02X}{data:02X}{lo:02X}{hi:02X}")
+            })
+            .collect::<Vec<_>>();
+        Some(codes.join(" "))
+    }
+}
+
+/// Memory search.
+#[derive(Debug, Clone)]
+pub struct Search {
+    width: Width,
+    candidates: Vec<Candidate>,
+    filters: Vec<Filter>,
+}
+
+impl Search {
+    /// Start a search from a snapshot of the memory.
+    pub fn new<M: Memory>(memory: &M, width: Width) -> Self {
+        let mut candidates = Vec::new();
+        for region in REGIONS {
+            let (start, end) = (*region.start(), *region.end());
+            for bank in memory.banks(start) {
+                // values don't straddle the end of the region
+                for address in start..=end + 1 - width.bytes() {
+                    if let Some(value) = read(memory, width, bank, address) {
+                        candidates.push(Candidate {
+                            bank,
+                            address,
+                            width,
+                            previous: value,
+                            value,
+                        });
+                    }
+                }
+            }
+        }
+        Self {
+            width,
+            candidates,
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// Returns the remaining candidates.
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /// Returns the filters applied so far.
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// Keep the candidates whose current value passes the filter. Returns the
+    /// number of remaining candidates.
+    pub fn filter<M: Memory>(&mut self, memory: &M, filter: Filter) -> usize {
+        let width = self.width;
+        self.candidates
+            .retain_mut(|candidate| match candidate.read(memory) {
+                Some(value) if filter.matches(width, candidate.value, value) => {
+                    candidate.previous = candidate.value;
+                    candidate.value = value;
+                    true
+                }
+                _ => false,
+            });
+        self.filters.push(filter);
+        self.candidates.len()
+    }
+}
+
+fn read<M: Memory>(memory: &M, width: Width, bank: usize, address: u16) -> Option<u16> {
+    let lo = memory.read(bank, address)?;
+    match width {
+        Width::Byte => Some(lo as u16),
+        Width::Word => Some(u16::from_le_bytes([lo, memory.read(bank, address + 1)?])),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Candidate, Filter, Memory, Search, Width};
+    use std::ops::RangeInclusive;
+
+    // two banks of cartridge RAM, and a single bank of WRAM
+    struct Ram(Vec<u8>);
+
+    impl Ram {
+        fn offset(bank: usize, address: u16) -> usize {
+            match address {
+                0xa000..=0xbfff => bank * 0x2000 + (address as usize - 0xa000),
+                _ => 0x4000 + address as usize - 0xc000,
+            }
+        }
+
+        fn write(&mut self, bank: usize, address: u16, data: u8) {
+            self.0[Self::offset(bank, address)] = data;
+        }
+    }
+
+    impl Memory for Ram {
+        fn banks(&self, address: u16) -> RangeInclusive<usize> {
+            match address {
+                0xa000..=0xbfff => 0..=1,
+                0xd000..=0xdfff => 1..=1,
+                _ => 0..=0,
+            }
+        }
+
+        fn read(&self, bank: usize, address: u16) -> Option<u8> {
+            self.0.get(Self::offset(bank, address)).copied()
+        }
+    }
+
+    #[test]
+    fn search() {
+        let mut ram = Ram(vec![0; 0x4000 + 0x4000]);
+        ram.write(1, 0xa010, 3);
+        ram.write(0, 0xc100, 3);
+
+        let mut search = Search::new(&ram, Width::Byte);
+        assert_eq!(2, search.filter(&ram, Filter::Value(3)));
+        ram.write(1, 0xa010, 2);
+        assert_eq!(1, search.filter(&ram, Filter::DecreasedBy(1)));
+        assert_eq!(1, search.filter(&ram, Filter::Unchanged));
+
+        let candidate = search.candidates()[0];
+        assert_eq!(
+            (1, 0xa010, 2, 2),
+            (
+                candidate.bank,
+                candidate.address,
+                candidate.previous,
+                candidate.value
+            )
+        );
+        // 01 writes to the mapped bank of the cartridge RAM
+        assert_eq!(None, candidate.gameshark(0x63));
+        let candidate = Candidate {
+            bank: 0,
+            ..candidate
+        };
+        assert_eq!(Some("006310A0".to_string()), candidate.gameshark(0x63));
+        let candidate = Candidate {
+            address: 0xc100,
+            ..candidate
+        };
+        assert_eq!(Some("016300C1".to_string()), candidate.gameshark(0x63));
+    }
+
+    #[test]
+    fn word() {
+        let mut ram = Ram(vec![0; 0x4000 + 0x4000]);
+        ram.write(0, 0xd200, 0xff);
+        let mut search = Search::new(&ram, Width::Word);
+        assert_eq!(1, search.filter(&ram, Filter::Value(0x00ff)));
+        ram.write(0, 0xd200, 0x00);
+        ram.write(0, 0xd201, 0x01);
+        assert_eq!(1, search.filter(&ram, Filter::IncreasedBy(1)));
+
+        let candidate = search.candidates()[0];
+        assert_eq!(0xd200, candidate.address);
+        assert_eq!(
+            Some("81E700D2 810301D2".to_string()),
+            candidate.gameshark(999)
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Symbol files (`.sym`) generated by RGBDS and wla-dx (no$gmb format).
+//!
+//! ```text
+//! ; File generated by rgblink
+//! 00:0150 Main
+//! 01:4a2f Player_Update
+//! 01:4a40 Player_Update.loop
+//! ```
+//!
+//! Symbol banks follow the memory region of the address: the ROM bank for
+//! 0x0000-0x7fff, the VRAM bank, the cartridge RAM bank, the WRAM bank for
+//! 0xd000-0xdfff, and 0 everywhere else.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Line {line}: invalid symbol")]
+    Syntax { line: usize },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Symbol table.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<(usize, u16), String>,
+    names: HashMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a symbol file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the contents of a symbol file.
+    ///
+    /// Only the labels are read. Other wla-dx sections (such as
+    /// `[definitions]`) are skipped.
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut symbols = Self::new();
+        let mut labels = true;
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[') {
+                labels = section.trim_end_matches(']') == "labels";
+                continue;
+            }
+            if !labels {
+                continue;
+            }
+            let error = || Error::Syntax { line: i + 1 };
+            let mut split = line.split_whitespace();
+            let (address, name) = match (split.next(), split.next()) {
+                (Some(address), Some(name)) => (address, name),
+                _ => return Err(error()),
+            };
+            let (bank, address) = address.split_once(':').ok_or_else(error)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| error())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| error())?;
+            symbols.insert(bank, address, name);
+        }
+        Ok(symbols)
+    }
+
+    /// Add a label. Addresses with several labels keep the first one.
+    pub fn insert(&mut self, bank: usize, address: u16, name: &str) {
+        self.labels
+            .entry((bank, address))
+            .or_insert_with(|| name.to_string());
+        self.names.insert(name.to_string(), (bank, address));
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns the label at the given address.
+    pub fn label(&self, bank: usize, address: u16) -> Option<&str> {
+        self.labels.get(&(bank, address)).map(String::as_str)
+    }
+
+    /// Returns the bank and address of a label.
+    pub fn resolve(&self, name: &str) -> Option<(usize, u16)> {
+        self.names.get(name).copied()
+    }
+
+    /// Returns the function containing the given address, along with the
+    /// offset from its start: the closest label at or before the address in
+    /// the same bank, ignoring local labels (`Function.loop`).
+    pub fn function(&self, bank: usize, address: u16) -> Option<(&str, u16)> {
+        self.labels
+            .range((bank, 0)..=(bank, address))
+            .rev()
+            .find(|(_, name)| !name.contains('.'))
+            .map(|((_, start), name)| (name.as_str(), address - start))
+    }
+
+    /// Iterate the labels in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u16, &str)> {
+        self.labels
+            .iter()
+            .map(|(&(bank, address), name)| (bank, address, name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symbols;
+
+    #[test]
+    fn parse() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             [labels]\n\
+             00:0150 Main\n\
+             01:4a2f Player_Update ; comment\n\
+             01:4a40 Player_Update.loop\n\
+             02:4a2f Enemy_Update\n\
+             [definitions]\n\
+             00000010 SPEED\n",
+        )
+        .unwrap();
+        assert_eq!(4, symbols.len());
+        assert_eq!(Some("Player_Update"), symbols.label(1, 0x4a2f));
+        assert_eq!(Some("Enemy_Update"), symbols.label(2, 0x4a2f));
+        assert_eq!(None, symbols.label(0, 0x4a2f));
+        assert_eq!(Some((1, 0x4a40)), symbols.resolve("Player_Update.loop"));
+        assert_eq!(None, symbols.resolve("SPEED"));
+        assert_eq!(Some(("Player_Update", 0x13)), symbols.function(1, 0x4a42));
+        assert_eq!(None, symbols.function(2, 0x4000));
+        assert!(Symbols::parse("01:zzzz Label").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! VGM (Video Game Music) logs, version 1.71.
+//!
+//! A VGM file is a stream of register writes to a sound chip, separated by
+//! waits (in samples at 44.1kHz), so chiptune players can play the music back
+//! without emulating the CPU. Only the Game Boy DMG chip is used: writes to
+//! 0xff10 to 0xff3f.
+//!
+//! The log is kept in memory, and encoded when it's saved.
+use std::{fs, io, path::Path};
+
+/// Samples per second of the timestamps.
+pub const SAMPLE_RATE: u64 = 44_100;
+
+// clock of the DMG chip, in Hz
+const CLOCK: u64 = 4_194_304;
+
+// header of version 1.71 (data starts right after it)
+const HEADER_LEN: usize = 0x100;
+
+const WRITE_DMG: u8 = 0xb3;
+const WAIT: u8 = 0x61;
+const WAIT_60HZ: u8 = 0x62;
+const WAIT_50HZ: u8 = 0x63;
+const WAIT_SHORT: u8 = 0x70;
+const END: u8 = 0x66;
+
+/// Log of APU register writes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VgmLog {
+    // commands (without the end of data)
+    data: Vec<u8>,
+    // clock ticks since the start of the log, and samples waited so far
+    ticks: u64,
+    samples: u64,
+    // offset in the data & sample of the loop point
+    loop_point: Option<(usize, u64)>,
+}
+
+impl VgmLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance the time of the log by the given number of clock ticks
+    /// (4194304 per second).
+    pub fn tick(&mut self, ticks: u64) {
+        self.ticks += ticks;
+    }
+
+    /// Returns the time of the log, in samples.
+    pub fn samples(&self) -> u64 {
+        self.ticks * SAMPLE_RATE / CLOCK
+    }
+
+    /// Log a write to an APU register (0xff10 to 0xff3f) at the current time.
+    /// Writes to other addresses are ignored.
+    pub fn write(&mut self, address: u16, data: u8) {
+        if let 0xff10..=0xff3f = address {
+            self.wait();
+            self.data
+                .extend_from_slice(&[WRITE_DMG, (address - 0xff10) as u8, data]);
+        }
+    }
+
+    /// Set the point the music loops back to, at the current time (replaces
+    /// the previous one).
+    pub fn set_loop(&mut self) {
+        self.wait();
+        self.loop_point = Some((self.data.len(), self.samples));
+    }
+
+    /// Returns the time of the loop point, in samples.
+    pub fn loop_point(&self) -> Option<u64> {
+        self.loop_point.map(|(_, sample)| sample)
+    }
+
+    /// Encode the log as a VGM file, ending at the current time.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let total = self.samples();
+        let mut data = self.data.clone();
+        waits(&mut data, total - self.samples);
+        data.push(END);
+
+        let mut file = vec![0; HEADER_LEN];
+        let mut set = |offset: usize, value: u32| {
+            file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        set(0x04, (HEADER_LEN + data.len() - 0x04) as u32);
+        set(0x08, 0x171);
+        set(0x18, total as u32);
+        if let Some((offset, sample)) = self.loop_point {
+            set(0x1c, (HEADER_LEN + offset - 0x1c) as u32);
+            set(0x20, (total - sample) as u32);
+        }
+        set(0x34, (HEADER_LEN - 0x34) as u32);
+        set(0x80, CLOCK as u32);
+        file[..4].copy_from_slice(b"Vgm ");
+        file.extend(data);
+        file
+    }
+
+    /// Write the VGM file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    // wait until the current time
+    fn wait(&mut self) {
+        let samples = self.samples();
+        waits(&mut self.data, samples - self.samples);
+        self.samples = samples;
+    }
+}
+
+fn waits(data: &mut Vec<u8>, mut samples: u64) {
+    while samples > 0 {
+        let (command, wait) = match samples {
+            735 => (vec![WAIT_60HZ], 735),
+            882 => (vec![WAIT_50HZ], 882),
+            1..=16 => (vec![WAIT_SHORT + samples as u8 - 1], samples),
+            _ => {
+                let wait = samples.min(0xffff);
+                let [lo, hi] = (wait as u16).to_le_bytes();
+                (vec![WAIT, lo, hi], wait)
+            }
+        };
+        data.extend(command);
+        samples -= wait;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VgmLog;
+
+    fn u32_at(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn vgm() {
+        let mut log = VgmLog::new();
+        log.write(0xff26, 0x80);
+        log.write(0xff40, 0x91);
+        // 1/60 s
+        log.tick(4_194_304 / 60 + 1);
+        log.set_loop();
+        log.write(0xff12, 0xf0);
+        log.tick(4_194_304 / 44_100 * 3 + 3);
+        log.write(0xff14, 0x87);
+        log.tick(4_194_304);
+
+        let file = log.to_bytes();
+        assert_eq!(b"Vgm ", &file[..4]);
+        assert_eq!(file.len() - 4, u32_at(&file, 0x04) as usize);
+        assert_eq!(0x171, u32_at(&file, 0x08));
+        assert_eq!(735 + 3 + 44_100, u32_at(&file, 0x18));
+        assert_eq!(0x100 + 3 + 1 - 0x1c, u32_at(&file, 0x1c));
+        assert_eq!(3 + 44_100, u32_at(&file, 0x20));
+        assert_eq!(0xcc, u32_at(&file, 0x34));
+        assert_eq!(4_194_304, u32_at(&file, 0x80));
+        assert_eq!(
+            [
+                0xb3, 0x16, 0x80, // NR52
+                0x62, // 735 samples
+                0xb3, 0x02, 0xf0, // NR12
+                0x72, // 3 samples
+                0xb3, 0x04, 0x87, // NR14
+                0x61, 0x44, 0xac, // 44100 samples
+                0x66,
+            ],
+            file[0x100..]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! 16-bit PCM WAV files.
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+// the RIFF chunk size (the file size minus 8 bytes) is 32 bits
+const MAX_LEN: u32 = u32::MAX - (HEADER_LEN - 8);
+
+/// Writes 16-bit PCM samples in the WAV format.
+///
+/// The sizes in the header are updated after every second of audio, and when
+/// the writer is finished (or dropped), so an interrupted recording is still a
+/// valid file.
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    // taken by `finish`
+    out: Option<W>,
+    channels: u16,
+    sample_rate: u32,
+    // bytes of sample data written so far, and when the header was updated
+    len: u32,
+    header_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        write_header(&mut out, channels, sample_rate, 0)?;
+        Ok(Self {
+            out: Some(out),
+            channels,
+            sample_rate,
+            len: 0,
+            header_len: 0,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the number of samples written (per channel).
+    pub fn samples(&self) -> u32 {
+        self.len / (2 * self.channels as u32)
+    }
+
+    /// Write a sample of every channel (interleaved).
+    ///
+    /// Fails without writing anything once the file reaches the 4GiB limit of
+    /// the format (about 6.7 hours of stereo audio at 44.1kHz).
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        debug_assert_eq!(self.channels as usize, samples.len());
+        let len = self
+            .len
+            .checked_add(2 * samples.len() as u32)
+            .filter(|len| *len <= MAX_LEN)
+            .ok_or_else(|| io::Error::other("WAV file size limit reached"))?;
+        let out = self.out.as_mut().unwrap();
+        for sample in samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        self.len = len;
+        if self.len - self.header_len >= self.sample_rate * self.channels as u32 * 2 {
+            self.update_header()?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the sizes of the header with the samples written so far.
+    pub fn update_header(&mut self) -> io::Result<()> {
+        let out = self.out.as_mut().unwrap();
+        out.seek(SeekFrom::Start(0))?;
+        write_header(out, self.channels, self.sample_rate, self.len)?;
+        out.seek(SeekFrom::End(0))?;
+        out.flush()?;
+        self.header_len = self.len;
+        Ok(())
+    }
+
+    /// Fill in the sizes of the header, and return the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.update_header()?;
+        Ok(self.out.take().unwrap())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if self.out.is_some() && self.len != self.header_len {
+            let _ = self.update_header();
+        }
+    }
+}
+
+fn write_header<W: Write>(
+    out: &mut W,
+    channels: u16,
+    sample_rate: u32,
+    len: u32,
+) -> io::Result<()> {
+    let block_align = 2 * channels;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_LEN - 8 + len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&len.to_le_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WavWriter, MAX_LEN};
+    use std::io::Cursor;
+
+    #[test]
+    fn wav() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 2, 44_100).unwrap();
+        wav.write(&[1, -1]).unwrap();
+        wav.write(&[0x100, 0]).unwrap();
+        assert_eq!(2, wav.samples());
+        let wav = wav.finish().unwrap().into_inner();
+        assert_eq!(44 + 8, wav.len());
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(
+            44 + 8 - 8,
+            u32::from_le_bytes(wav[4..8].try_into().unwrap())
+        );
+        assert_eq!(
+            44_100 * 4,
+            u32::from_le_bytes(wav[28..32].try_into().unwrap())
+        );
+        assert_eq!(8, u32::from_le_bytes(wav[40..44].try_into().unwrap()));
+        assert_eq!([1, 0, 0xff, 0xff, 0, 1, 0, 0], wav[44..]);
+
+        // the header is up to date after a second of audio, and on drop
+        let mut out = Cursor::new(Vec::new());
+        let mut wav = WavWriter::new(&mut out, 1, 4).unwrap();
+        wav.write(&[1]).unwrap();
+        assert_eq!(
+            0,
+            u32::from_le_bytes(
+                wav.out.as_ref().unwrap().get_ref()[40..44]
+                    .try_into()
+                    .unwrap()
+            )
+        );
+        for _ in 0..3 {
+            wav.write(&[1]).unwrap();
+        }
+        assert_eq!(
+            8,
+            u32::from_le_bytes(
+                wav.out.as_ref().unwrap().get_ref()[40..44]
+                    .try_into()
+                    .unwrap()
+            )
+        );
+        wav.write(&[1]).unwrap();
+        drop(wav);
+        assert_eq!(44 + 10, out.get_ref().len());
+        assert_eq!(
+            10,
+            u32::from_le_bytes(out.get_ref()[40..44].try_into().unwrap())
+        );
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut out = Cursor::new(Vec::new());
+        let mut wav = WavWriter::new(&mut out, 1, 44_100).unwrap();
+        wav.len = MAX_LEN - 2;
+        wav.header_len = wav.len;
+        wav.write(&[1]).unwrap();
+        assert!(wav.write(&[1]).is_err());
+        drop(wav);
+        assert_eq!(44 + 2, out.get_ref().len());
+        assert_eq!(
+            u32::MAX,
+            u32::from_le_bytes(out.get_ref()[4..8].try_into().unwrap())
+        );
+    }
+}